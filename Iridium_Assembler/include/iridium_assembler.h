@@ -0,0 +1,39 @@
+/*
+ * C interface to the Iridium assembler, exported by src/ffi.rs when the crate is built with `cargo build --release --features ffi`, which produces
+ * libiridium_assembler.so (or .dylib / .dll) in target/release. Keep this file in step with src/ffi.rs.
+ */
+
+#ifndef IRIDIUM_ASSEMBLER_H
+#define IRIDIUM_ASSEMBLER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The program was assembled and its words written to out_words. */
+#define IRIDIUM_OK 0
+/* The program could not be assembled, with the reason written to err_buf. */
+#define IRIDIUM_ERR_ASSEMBLY 1
+/* One of the pointers is null or the source is not valid UTF-8. */
+#define IRIDIUM_ERR_INVALID_ARGUMENT 2
+/* The assembler panicked, which is caught rather than unwinding into the caller. */
+#define IRIDIUM_ERR_PANIC 3
+
+/*
+ * Assembles the null-terminated program in source and on success stores a pointer to the words of its code section in out_words and their number in out_len,
+ * which must be freed with iridium_free_words. On failure, a null-terminated message truncated to err_len bytes is written to err_buf if it is not null, and
+ * out_words and out_len are set to NULL and 0. A program with a .data section is an assembly error, as only the code section can be returned.
+ */
+int iridium_assemble(const char *source, uint16_t **out_words, size_t *out_len, char *err_buf, size_t err_len);
+
+/* Frees the words returned by iridium_assemble. Does nothing if words is NULL. */
+void iridium_free_words(uint16_t *words, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IRIDIUM_ASSEMBLER_H */
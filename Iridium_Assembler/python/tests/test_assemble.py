@@ -0,0 +1,27 @@
+# Run with `maturin develop --features python && pytest python/tests` from the Iridium_Assembler directory.
+import pytest
+
+from iridium_assembler import AssembledProgram, AssemblyError, assemble
+
+
+def test_assemble():
+    program = assemble("start: ADDI $r1, $zero, 5\nLUI $r0, 500\n.data\ntable: .fill 0x10\n")
+    assert isinstance(program, AssembledProgram)
+    assert program.words == [0x2805, 0x65F4]
+    assert program.data == [0x0010]
+    assert program.labels == {"start": 0, "table": 0}
+
+
+def test_assembly_error_line():
+    with pytest.raises(AssemblyError) as err:
+        assemble("ADDI $r0, $zero, 1\n# a comment\nNAND $r0, $r1\n")
+
+    assert err.value.line == 3
+    assert "NAND $r0, $r1" in str(err.value)
+
+
+def test_assembly_error_without_line():
+    with pytest.raises(AssemblyError) as err:
+        assemble("MOVI $r1, @missing\n")
+
+    assert err.value.line is None
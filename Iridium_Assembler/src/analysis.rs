@@ -0,0 +1,197 @@
+use std::collections::{ BTreeMap, BTreeSet };
+use crate::AssembledProgram;
+use crate::diagnostics::{ Diagnostic, UNINITIALISED_REGISTER };
+use crate::encoder::{ Instruction, decode, register_name };
+use crate::labels::{ LabelKind, Section, get_label_kind, get_section_switch };
+use crate::parser::{ LABEL_REGEX, get_word_count };
+
+
+/// The register a syscall takes its argument in and gives its result in, which is `$r6`.
+const SYSCALL_REGISTER:u8 = 7;
+
+
+/// The registers a stretch of code reads and writes, leaving out `$zero` as reading it always gives 0 and writing it does nothing. A register is read before it is
+/// written if some instruction reads it before any instruction of the stretch has written it, in the order the instructions are written rather than the order a
+/// branch might run them in, and is given with the line of the first such read, counting from 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterUsage {
+    pub read: BTreeSet<u8>,
+    pub written: BTreeSet<u8>,
+    pub read_before_written: BTreeMap<u8, usize>
+}
+
+impl RegisterUsage {
+    /// Adds the registers an instruction on the given line reads and writes, taking the reads first so that an instruction such as `ADDI $r1, $r1, 1` reads its
+    /// register before writing it.
+    fn add(&mut self, instr:Instruction, line:usize) {
+        let (read, written) = instruction_registers(instr);
+        for reg in read.into_iter().filter(|reg| *reg != 0) {
+            if !self.written.contains(&reg) {
+                self.read_before_written.entry(reg).or_insert(line);
+            }
+
+            self.read.insert(reg);
+        }
+
+        self.written.extend(written.into_iter().filter(|reg| *reg != 0));
+    }
+
+
+    /// Describes the usage as the names of the registers read, written, and read before written, with the line of the first such read of each, or `none` for a
+    /// kind of use no register has.
+    pub fn describe(&self) -> String {
+        let list = |items:Vec<String>| if items.is_empty() { "none".to_owned() } else { items.join(", ") };
+        let names = |regs:&BTreeSet<u8>| list(regs.iter().map(|reg| register_name(*reg)).collect());
+        let uninitialised = list(self.read_before_written.iter().map(|(reg, line)| format!("{} (line {})", register_name(*reg), line)).collect());
+        format!("read: {}; written: {}; read before written: {}", names(&self.read), names(&self.written), uninitialised)
+    }
+}
+
+
+/// The registers used by a block of the code section, which starts at a label, or at the start of the section if it has no label, and runs up to the next label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockUsage {
+    pub label: Option<String>,
+    pub address: usize,
+    pub usage: RegisterUsage
+}
+
+
+/// The registers used by each block of the code section, in order of address, and by the whole program, as given by `analyse_registers`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterReport {
+    pub blocks: Vec<BlockUsage>,
+    pub program: RegisterUsage
+}
+
+impl RegisterReport {
+    /// Adds a block which ends just before `end`, unless it is the part of the code section before its first label and holds no words.
+    fn push_block(&mut self, block:BlockUsage, end:usize) {
+        if block.label.is_some() || block.address < end {
+            self.blocks.push(block);
+        }
+    }
+
+
+    /// Gives a warning for each register the program reads before any instruction before it has written it, on the line it is first read, which may be a value
+    /// left over from whatever ran before the program. `file` is the name of the source the program was assembled from.
+    pub fn uninitialised_warnings(&self, file:&str) -> Vec<Diagnostic> {
+        self.program.read_before_written.iter().map(|(reg, line)| {
+            Diagnostic::warning(UNINITIALISED_REGISTER, format!("Register {} is read before anything has been written to it", register_name(*reg)), file, Some(*line))
+        }).collect()
+    }
+}
+
+
+/// Gets the registers an instruction reads and those it writes, including `$zero`. A `LUI` reads its register as well as writing it, as it keeps the bottom 6 bits.
+/// A syscall which takes an argument reads it from `$r6` and one which gives a result writes it there, while the registers it uses itself are restored before it
+/// returns.
+pub fn instruction_registers(instr:Instruction) -> (Vec<u8>, Vec<u8>) {
+    match instr {
+        Instruction::Add { rd, ra, rb } | Instruction::Nand { rd, ra, rb } => (vec![ra, rb], vec![rd]),
+        Instruction::Addi { rd, ra, .. } | Instruction::Lw { rd, ra, .. } => (vec![ra], vec![rd]),
+        Instruction::Lui { rd, .. } => (vec![rd], vec![rd]),
+        Instruction::Sw { rd, ra, .. } => (vec![rd, ra], vec![]),
+        Instruction::Beq { rd, ra, rb } => (vec![rd, ra, rb], vec![]),
+        Instruction::Jal { rd, ra } => (vec![ra], vec![rd]),
+        Instruction::Syscall(0..=3) => (vec![SYSCALL_REGISTER], vec![]),
+        Instruction::Syscall(4 | 5) => (vec![], vec![SYSCALL_REGISTER]),
+        Instruction::Syscall(_) | Instruction::Data(_) => (vec![], vec![])
+    }
+}
+
+
+/// Finds the registers read and written by each label-delimited block of the code section of a program and by the program as a whole, from the instructions its
+/// words decode to. Each word is attributed to the line of the source it came from, so the two instructions a `MOVI` expands to are both on the `MOVI`'s line.
+/// Data in the code section, such as a `.text`, is not decoded, and a label on it does not start a block.
+pub fn analyse_registers(program:&AssembledProgram) -> RegisterReport {
+    let mut report = RegisterReport::default();
+    let mut block = BlockUsage { label: None, address: 0, usage: RegisterUsage::default() };
+    let mut section = Section::Code;
+    let mut address = 0;
+    for (index, line) in program.source_lines.iter().enumerate() {
+        if let Some(next_section) = get_section_switch(line) {
+            section = next_section;
+            continue;
+        } else if section == Section::Data {
+            continue;
+        }
+
+        let num_words = get_word_count(line);
+        if get_label_kind(line) == LabelKind::Code {
+            if let Some(label) = LABEL_REGEX.find(line) {
+                let next = BlockUsage { label: Some(label.as_str().trim_end_matches(':').to_owned()), address, usage: RegisterUsage::default() };
+                report.push_block(std::mem::replace(&mut block, next), address);
+            }
+
+            for word in &program.code[address..address + num_words] {
+                block.usage.add(decode(*word), index + 1);
+                report.program.add(decode(*word), index + 1);
+            }
+        }
+
+        address += num_words;
+    }
+
+    report.push_block(block, address);
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::{ assemble_file, assemble_str };
+
+
+    #[test]
+    fn test_instruction_registers() {
+        assert_eq!(instruction_registers(decode(0x2407)), (vec![0], vec![1])); // ADDI $r0, $zero, 7
+        assert_eq!(instruction_registers(Instruction::Sw { rd: 2, ra: 3, imm: 4 }), (vec![2, 3], vec![]));
+        assert_eq!(instruction_registers(Instruction::Lui { rd: 2, imm: 4 }), (vec![2], vec![2]));
+        assert_eq!(instruction_registers(Instruction::Syscall(2)), (vec![7], vec![]));
+        assert_eq!(instruction_registers(Instruction::Syscall(5)), (vec![], vec![7]));
+        assert_eq!(instruction_registers(Instruction::Syscall(6)), (vec![], vec![]));
+    }
+
+
+    #[test]
+    fn test_analyse_registers() {
+        let program = assemble_file(Path::new("test_files/test_reg_report.asm")).unwrap();
+        let report = analyse_registers(&program);
+        let labels:Vec<(Option<&str>, usize)> = report.blocks.iter().map(|block| (block.label.as_deref(), block.address)).collect();
+        assert_eq!(labels, vec![(Some("main"), 0), (Some("leaf"), 9), (Some("uses_garbage"), 11)]);
+
+        // the ADDI and LUI a MOVI expands to are both on its line, and the LUI reading its register is not a read before the ADDI writes it
+        let main = &report.blocks[0].usage;
+        assert_eq!(main.read, BTreeSet::from([1, 7]));
+        assert_eq!(main.written, BTreeSet::from([1, 6, 7]));
+        assert!(main.read_before_written.is_empty());
+
+        // a leaf routine reads its argument and return address, which the caller wrote
+        let leaf = &report.blocks[1].usage;
+        assert_eq!(leaf.read_before_written, BTreeMap::from([(1, 8), (6, 9)]));
+        assert_eq!(leaf.written, BTreeSet::from([1]));
+        assert_eq!(report.blocks[2].usage.read_before_written, BTreeMap::from([(1, 11), (3, 11), (6, 12)]));
+
+        // only $r2 is read before anything in the program writes it
+        assert_eq!(report.program.read_before_written, BTreeMap::from([(3, 11)]));
+        let warnings = report.uninitialised_warnings("test_reg_report.asm");
+        assert_eq!(warnings, vec![Diagnostic::warning(UNINITIALISED_REGISTER, "Register $r2 is read before anything has been written to it".to_owned(),
+            "test_reg_report.asm", Some(11))]);
+        assert_eq!(report.blocks[1].usage.describe(), "read: $r0, $r5; written: $r0; read before written: $r0 (line 8), $r5 (line 9)");
+    }
+
+
+    #[test]
+    fn test_analyse_registers_data_in_code() {
+        let program = assemble_str("MOVI $r6, @msg\n.syscall 1\n.syscall 6\nmsg: .text \"hi\"\nADD $r1, $r2, $r2\n").unwrap();
+        let report = analyse_registers(&program);
+        assert_eq!(report.blocks.len(), 1);
+        assert_eq!(report.blocks[0].label, None);
+        assert_eq!(report.blocks[0].usage.read, BTreeSet::from([3, 7]));
+        assert_eq!(report.blocks[0].usage.written, BTreeSet::from([2, 7]));
+        assert_eq!(report.program.read_before_written, BTreeMap::from([(3, 5)]));
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::BufRead;
+use std::path::Path;
+use std::rc::Rc;
+use crate::{ AssembledProgram, AssemblyError, StageTimings, assemble_reader, assemble_source, assemble_source_reporting, assemble_source_timed, assemble_sources,
+    assemble_sources_timed, count_words, read_source };
+use crate::diagnostics::DiagnosticSink;
+use crate::isa::{ DEFAULT_ISA_SPEC, IsaSpec };
+use crate::labels::DataPlacement;
+use crate::output::Endian;
+use crate::parser::{ InputEncoding, LineSource };
+use crate::stream::WordStream;
+
+
+/// The options a program is read, assembled, and written with. The defaults are those `assemble_file` and `assemble_str` use: the source must be valid UTF-8, tabs
+/// are allowed, each word is written high byte first, the instruction set is `isa::DEFAULT_ISA`, warnings do not stop a program being assembled, there is no
+/// scratch register, the data section is its own address space, `.buildinfo` assembles to 0, regions which write the same words are an error, and every string
+/// is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssemblerOptions {
+    /// Replace invalid UTF-8 in the source rather than rejecting it.
+    pub lossy: bool,
+    pub encoding: InputEncoding,
+    /// Reject any tab in the source.
+    pub no_tabs: bool,
+    /// The order of the bytes of each word given by `Assembler::to_bytes`.
+    pub endian: Endian,
+    /// The instruction set to validate and encode with in place of the default one.
+    pub isa: Option<Rc<IsaSpec>>,
+    /// Reject a program with any of the warnings given by `AssembledProgram::warnings`, or invalid UTF-8 replaced when reading lossily, giving the first of them as
+    /// the error.
+    pub strict: bool,
+    /// The register a jump to a label, such as `JAL $r5, @handler`, loads the address of the label into before jumping through it. Without one, such a jump is an
+    /// error.
+    pub scratch_register: Option<String>,
+    /// Where the data section is placed, which the labels in it resolve to.
+    pub data_placement: DataPlacement,
+    /// The word each `.buildinfo` assembles to.
+    pub build_id: u16,
+    /// Let a region started by `.org` write over the words before it rather than rejecting the program.
+    pub allow_overlap: bool,
+    /// Keep a single copy of each string written more than once with `.text`, pointing the labels of the others at it. This is not done by `Assembler::stream`,
+    /// which has no label table to point them with.
+    pub merge_strings: bool
+}
+
+impl AssemblerOptions {
+    /// The instruction set given by `isa`, or the default one if none was given.
+    pub fn isa_spec(&self) -> &IsaSpec {
+        self.isa.as_deref().unwrap_or(&DEFAULT_ISA_SPEC)
+    }
+}
+
+
+/// Assembles programs with a set of `AssemblerOptions`, built up one option at a time, such as
+/// `Assembler::new().no_tabs(true).endian(Endian::Little).assemble_str(source)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembler {
+    options: AssemblerOptions
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+
+    pub fn with_options(options:AssemblerOptions) -> Assembler {
+        Assembler { options }
+    }
+
+
+    pub fn options(&self) -> &AssemblerOptions {
+        &self.options
+    }
+
+
+    pub fn lossy(mut self, lossy:bool) -> Assembler {
+        self.options.lossy = lossy;
+        self
+    }
+
+
+    pub fn encoding(mut self, encoding:InputEncoding) -> Assembler {
+        self.options.encoding = encoding;
+        self
+    }
+
+
+    pub fn no_tabs(mut self, no_tabs:bool) -> Assembler {
+        self.options.no_tabs = no_tabs;
+        self
+    }
+
+
+    pub fn endian(mut self, endian:Endian) -> Assembler {
+        self.options.endian = endian;
+        self
+    }
+
+
+    pub fn isa(mut self, spec:IsaSpec) -> Assembler {
+        self.options.isa = Some(Rc::new(spec));
+        self
+    }
+
+
+    pub fn strict(mut self, strict:bool) -> Assembler {
+        self.options.strict = strict;
+        self
+    }
+
+
+    pub fn scratch_register(mut self, register:&str) -> Assembler {
+        self.options.scratch_register = Some(register.to_owned());
+        self
+    }
+
+
+    pub fn data_placement(mut self, placement:DataPlacement) -> Assembler {
+        self.options.data_placement = placement;
+        self
+    }
+
+
+    pub fn build_id(mut self, build_id:u16) -> Assembler {
+        self.options.build_id = build_id;
+        self
+    }
+
+
+    pub fn allow_overlap(mut self, allow_overlap:bool) -> Assembler {
+        self.options.allow_overlap = allow_overlap;
+        self
+    }
+
+
+    pub fn merge_strings(mut self, merge_strings:bool) -> Assembler {
+        self.options.merge_strings = merge_strings;
+        self
+    }
+
+
+    /// Reads and assembles the program from the given source in the same way as `crate::assemble_source`, with these options.
+    ///
+    /// Returns an `AssemblyError` if the source cannot be read or the program cannot be assembled.
+    pub fn assemble_source(&self, source:LineSource) -> Result<AssembledProgram, AssemblyError> {
+        assemble_source(source, &self.options)
+    }
+
+
+    /// Assembles a program in the same way as `assemble_source`, also giving how long each stage took as `crate::assemble_source_timed` does.
+    ///
+    /// Returns an `AssemblyError` if the source cannot be read or the program cannot be assembled.
+    pub fn assemble_source_timed(&self, source:LineSource) -> Result<(AssembledProgram, StageTimings), AssemblyError> {
+        assemble_source_timed(source, &self.options)
+    }
+
+
+    /// Reads and assembles a program from several sources as if they were one, in the same way as `crate::assemble_sources`, with these options.
+    ///
+    /// Returns an `AssemblyError` if a source cannot be read or the program cannot be assembled.
+    pub fn assemble_sources(&self, sources:&[LineSource]) -> Result<AssembledProgram, AssemblyError> {
+        assemble_sources(sources, &self.options)
+    }
+
+
+    /// Assembles a program from several sources in the same way as `assemble_sources`, also giving how long each stage took.
+    ///
+    /// Returns an `AssemblyError` if a source cannot be read or the program cannot be assembled.
+    pub fn assemble_sources_timed(&self, sources:&[LineSource]) -> Result<(AssembledProgram, StageTimings), AssemblyError> {
+        assemble_sources_timed(sources, &self.options)
+    }
+
+
+    /// Reads and assembles the program from the given source in the same way as `crate::assemble_source_reporting`, with these options, giving every warning and
+    /// error to `sink`.
+    pub fn assemble_source_reporting(&self, source:LineSource, sink:&mut dyn DiagnosticSink) -> Option<AssembledProgram> {
+        assemble_source_reporting(source, &self.options, sink)
+    }
+
+
+    /// Reads and assembles the program from any reader in the same way as `crate::assemble_reader`, with these options.
+    ///
+    /// Returns an `AssemblyError` if the reader fails or the program cannot be assembled.
+    pub fn assemble_reader(&self, reader:impl BufRead, name:&str) -> Result<AssembledProgram, AssemblyError> {
+        assemble_reader(reader, name, &self.options)
+    }
+
+
+    pub fn assemble_file(&self, input:&Path) -> Result<AssembledProgram, AssemblyError> {
+        self.assemble_source(LineSource::File(&input.to_string_lossy()))
+    }
+
+
+    pub fn assemble_str(&self, source:&str) -> Result<AssembledProgram, AssemblyError> {
+        self.assemble_source(LineSource::Str(source))
+    }
+
+
+    /// Counts the words of the code and data sections of the program from the given source without assembling it, as `crate::count_words` does, with these options.
+    ///
+    /// Returns an `AssemblyError` if the source cannot be read or is not valid.
+    pub fn count_words(&self, source:LineSource) -> Result<(usize, usize), AssemblyError> {
+        count_words(source, &self.options)
+    }
+
+
+    /// Reads the program from the given source and gives the words of its code section as `(address, word)` pairs, encoding each line only as the words before it
+    /// are taken rather than holding the whole program, as described by `WordStream`. An error is given as the last item of the stream.
+    pub fn stream(&self, source:LineSource) -> WordStream {
+        match read_source(source, &self.options) {
+            Ok((lines, _)) => WordStream::new(&lines, source.name(), &self.options),
+            Err(err) => WordStream::from_error(err)
+        }
+    }
+
+
+    /// Converts words into the bytes of a binary image, in the byte order of the options.
+    pub fn to_bytes(&self, words:&[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| self.options.endian.to_bytes(*word)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+    use crate::isa::DEFAULT_ISA;
+
+
+    #[test]
+    fn test_default_options() {
+        let source = "start: ADDI $r1, $zero, 5\n\tNAND $r2, $r1, $r1\n.data\ntable: .fill @start\n";
+        let program = Assembler::new().assemble_str(source).unwrap();
+        assert_eq!(program, assemble_str(source).unwrap());
+        assert_eq!(Assembler::new().to_bytes(&program.code[..1]), vec![0x28, 0x05]);
+    }
+
+
+    #[test]
+    fn test_options_reach_each_stage() {
+        let source = "ADDI $r1, $zero, 5\n\tNAND $r2, $r1, $r1\n";
+        assert!(Assembler::new().no_tabs(true).assemble_str(source).unwrap_err().0.contains("tab"));
+
+        let path = Path::new("test_files/test_invalid_utf8.asm");
+        assert!(Assembler::new().assemble_file(path).is_err());
+        assert_eq!(Assembler::new().lossy(true).assemble_file(path).unwrap().code.len(), 2);
+        assert_eq!(Assembler::new().encoding(InputEncoding::Latin1).assemble_file(path).unwrap().code.len(), 2);
+
+        let little = Assembler::new().endian(Endian::Little);
+        assert_eq!(little.to_bytes(&little.assemble_str(source).unwrap().code), vec![0x05, 0x28, 0x20, 0x4D]);
+
+        let spec = IsaSpec::from_toml(&(DEFAULT_ISA.to_owned() + "SUB = { format = \"RRR\", opcode = 0x0001 }\n")).unwrap();
+        assert_eq!(Assembler::new().isa(spec).assemble_str("SUB $r6, $r0, $zero").unwrap().code, vec![0x1C81]);
+        assert!(assemble_str("SUB $r6, $r0, $zero").is_err());
+
+        let source = "NOP\nversion: .buildinfo\nLW $r1, $zero, @version\n";
+        let program = Assembler::new().build_id(0xBEEF).assemble_str(source).unwrap();
+        assert_eq!((program.code[1], program.labels.get("version").unwrap().address), (0xBEEF, 1));
+        assert_eq!(program, Assembler::new().build_id(0xBEEF).assemble_str(source).unwrap());
+        assert_eq!(assemble_str(source).unwrap().code[1], 0);
+
+        let words:Vec<u16> = Assembler::new().build_id(0xBEEF).stream(LineSource::Str(source)).map(|word| word.unwrap().1).collect();
+        assert_eq!(words, program.code);
+    }
+
+
+    #[test]
+    fn test_strict() {
+        let path = Path::new("test_files/test_invalid_utf8.asm");
+        assert!(Assembler::new().lossy(true).assemble_file(path).is_ok());
+        assert!(Assembler::new().lossy(true).strict(true).assemble_file(path).unwrap_err().0.contains("UTF-8"));
+
+        let source = "start: ADDI $r1, $zero, 5\nJAL $zero, $zero\n";
+        assert!(Assembler::new().assemble_str(source).is_ok());
+        assert_eq!(Assembler::new().strict(true).assemble_str(source).unwrap_err().0, "Label start is never referenced on line 1");
+
+        let source = "ADDI $r1, $zero, 5\n.fill 7\n";
+        assert_eq!(Assembler::new().strict(true).assemble_str(source).unwrap_err().0, "Data at address 0x0001 is reached by falling through from the instruction \
+            before it, so will be executed: .fill 7");
+
+        let source = "NOP\nNOP\n.org 1\nNOP\n";
+        assert!(Assembler::new().allow_overlap(true).assemble_str(source).is_ok());
+        assert!(Assembler::new().allow_overlap(true).strict(true).assemble_str(source).unwrap_err().0.ends_with("written over the earlier ones on line 3"));
+    }
+
+
+    #[test]
+    fn test_scratch_register() {
+        let source = "start: JAL $r5, @handler+1\n.syscall 6\nhandler: NOP\nJAL $zero, $r5\n";
+        let err = Assembler::new().assemble_str(source).unwrap_err().0;
+        assert_eq!(err, "Jumping to @handler+1 needs a scratch register to hold its address in instruction start: JAL $r5, @handler+1");
+
+        let program = Assembler::new().scratch_register("$r6").assemble_str(source).unwrap();
+        let expected = assemble_str("start: MOVI $r6, @handler+1\nJAL $r5, $r6\n.syscall 6\nhandler: NOP\nJAL $zero, $r5\n").unwrap();
+        assert_eq!((&program.code, &program.relocations), (&expected.code, &expected.relocations));
+        assert_eq!(program.labels.get("handler").unwrap().defined_at.line, 3);
+
+        let words:Vec<u16> = Assembler::new().scratch_register("$r6").stream(LineSource::Str(source)).map(|word| word.unwrap().1).collect();
+        assert_eq!(words, program.code);
+        assert_eq!(Assembler::new().scratch_register("$r6").count_words(LineSource::Str(source)).unwrap(), (6, 0));
+
+        let err = Assembler::new().scratch_register("$r9").assemble_str(source).unwrap_err().0;
+        assert_eq!(err, "The scratch register $r9 is not a register of the instruction set");
+    }
+}
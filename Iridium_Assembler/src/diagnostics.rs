@@ -0,0 +1,129 @@
+use std::fmt;
+use log::{ error, warn };
+use serde::{ Deserialize, Serialize };
+use crate::AssemblyError;
+
+
+/// The code of the warning given when invalid UTF-8 in a source is replaced rather than rejected.
+pub const INVALID_UTF8:&str = "invalid-utf8";
+/// The code of the warning given for data in the code section which execution falls through into, as found by `lint::find_data_in_code`.
+pub const DATA_IN_CODE:&str = "data-in-code";
+/// The code of the warning given for a label nothing refers to, as found by `lint::find_unused_labels`.
+pub const UNUSED_LABEL:&str = "unused-label";
+/// The code of the opt-in warning given for a register read before anything has written it, as found by `analysis::analyse_registers`.
+pub const UNINITIALISED_REGISTER:&str = "uninitialised-register";
+/// The code of the warning given for an entry point other than word 0 in a program written in a format which does not record it, as found by
+/// `AssembledProgram::entry_warning`.
+pub const ENTRY_NOT_RECORDED:&str = "entry-not-recorded";
+/// The code of the warning given for a region started by `.org` which was allowed to write over the words before it, as found by
+/// `AssembledProgram::overlap_warnings`.
+pub const OVERLAPPING_REGIONS:&str = "overlapping-regions";
+/// The code of the error which stopped a program being assembled.
+pub const ASSEMBLY_ERROR:&str = "assembly-error";
+
+
+/// How serious a diagnostic is. A warning leaves the program assembled, while an error means it could not be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+
+/// A warning or error found while assembling a program, with the code naming what kind of problem it is, such as `UNUSED_LABEL`. It spans the whole of `line`,
+/// counting from 1, in the source named `file`, or has no line if the problem is not on one, such as a file which cannot be opened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub file: String,
+    pub line: Option<usize>
+}
+
+impl Diagnostic {
+    pub fn warning(code:&str, message:String, file:&str, line:Option<usize>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, code: code.to_owned(), message, file: file.to_owned(), line }
+    }
+
+
+    /// Gives the error which stopped the source named `file` being assembled, on the line its message ends by naming if there is one, and in the file named after
+    /// that line instead if there is one, as for a program assembled from several files.
+    pub fn from_error(err:&AssemblyError, file:&str) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, code: ASSEMBLY_ERROR.to_owned(), message: err.0.to_owned(), file: err.file().unwrap_or(file).to_owned(),
+            line: err.line() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "WARNING",
+            Severity::Error => "Error"
+        };
+
+        match self.line {
+            Some(line) => write!(f, "{} [{}] {}:{}: {}", severity, self.code, self.file, line, self.message),
+            None => write!(f, "{} [{}] {}: {}", severity, self.code, self.file, self.message)
+        }
+    }
+}
+
+
+/// Receives every warning and error found while assembling, in the order they are found, so that a tool embedding the assembler can show them however it likes
+/// rather than reading them from standard error.
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic:Diagnostic);
+}
+
+/// Collects the diagnostics to look at once assembly has finished.
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic:Diagnostic) {
+        self.push(diagnostic);
+    }
+}
+
+
+/// Prints each diagnostic to standard error as it is found, as the command line tool does.
+pub struct StderrSink;
+
+impl DiagnosticSink for StderrSink {
+    fn report(&mut self, diagnostic:Diagnostic) {
+        eprintln!("{}", diagnostic);
+    }
+}
+
+
+/// Passes each diagnostic to the `log` facade, at the warning or error level, which is where they go when no other sink is given.
+pub struct LogSink;
+
+impl DiagnosticSink for LogSink {
+    fn report(&mut self, diagnostic:Diagnostic) {
+        match diagnostic.severity {
+            Severity::Warning => warn!("{}", diagnostic),
+            Severity::Error => error!("{}", diagnostic)
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_diagnostic_display() {
+        let err = AssemblyError("Invalid instruction NAND $r0, $r1 on line 2".to_owned());
+        let diagnostic = Diagnostic::from_error(&err, "<string>");
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.to_string(), "Error [assembly-error] <string>:2: Invalid instruction NAND $r0, $r1 on line 2");
+
+        let err = AssemblyError("Invalid instruction NAND $r0, $r1 on line 2 of kernel.asm".to_owned());
+        assert_eq!((Diagnostic::from_error(&err, "boot.asm").file.as_str(), err.line()), ("kernel.asm", Some(2)));
+
+        let diagnostic = Diagnostic::warning(DATA_IN_CODE, "Data at address 0x0003 will be executed".to_owned(), "in.asm", None);
+        assert_eq!(diagnostic.to_string(), "WARNING [data-in-code] in.asm: Data at address 0x0003 will be executed");
+    }
+}
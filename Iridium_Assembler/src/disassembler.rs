@@ -0,0 +1,170 @@
+use std::error::Error;
+#[cfg(feature = "cli")]
+use std::fs;
+use crate::AssemblyError;
+use crate::encoder::{ Instruction, decode, register_name };
+use crate::output::Endian;
+#[cfg(feature = "cli")]
+use crate::output::write_file_atomically;
+
+
+/// Joins the bytes of a binary image into words in the given order.
+///
+/// Returns an `AssemblyError` if there is an odd number of bytes, so the last word is incomplete.
+pub fn read_words(bytes:&[u8], endian:Endian) -> Result<Vec<u16>, Box<dyn Error>> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(Box::new(AssemblyError(format!("Expected a whole number of 16-bit words but found {} bytes", bytes.len()))));
+    }
+
+    Ok(bytes.chunks(2).map(|pair| endian.from_bytes([pair[0], pair[1]])).collect())
+}
+
+
+/// Disassembles each word into a line of assembly in the canonical form given by `Instruction`'s `Display`, with its comment giving the word's address before the
+/// immediate field.
+pub fn disassemble(words:&[u16]) -> Vec<String> {
+    words.iter().enumerate().map(|(address, word)| {
+        let instr = decode(*word);
+        let imm = instr.imm_hex().map_or(String::new(), |hex| format!(", imm {}", hex));
+        format!("{:<27}# 0x{:04X}{}", instr.to_asm(), address, imm)
+    }).collect()
+}
+
+
+/// The mnemonic of each opcode, which is the top 3 bits of a word.
+const OPCODE_MNEMONICS:[&str; 8] = ["ADD", "ADDI", "NAND", "LUI", "SW", "LW", "BEQ", "JAL"];
+
+
+/// Describes the bits `high` down to `low` of a word as a row of the table given by `describe_word`, with their range, their value in binary, the name of the
+/// field, and what its value means.
+fn describe_field(word:u16, high:u32, low:u32, name:&str, meaning:&str) -> String {
+    let width = (high - low + 1) as usize;
+    let value = (word as u32 >> low) & ((1 << width) - 1);
+    let range = format!("{}-{}", high, low);
+    format!("bits {:<5}  {:<16}  {:<6}  {}", range, format!("{:0width$b}", value, width = width), name, meaning)
+}
+
+
+/// Breaks a single word down into a table of its fields, for checking an encoding by hand. The first line gives the word in hexadecimal and binary and the second
+/// the instruction it decodes to, as the disassembler would give it, followed by a row for each field with its range of bits, their value, and what they mean:
+/// registers are given by name and immediates as the value they are sign-extended to, if their field is signed. A word which does not encode any instruction is
+/// shown as a single field of data.
+pub fn describe_word(word:u16) -> Vec<String> {
+    let instr = decode(word);
+    let mut lines = vec![format!("0x{:04X} = 0b{:016b}", word, word), instr.to_asm().trim_end().to_owned()];
+    let opcode = describe_field(word, 15, 13, "opcode", OPCODE_MNEMONICS[(word >> 13) as usize]);
+    let reg = |high:u32, name:&str| describe_field(word, high, high - 2, name, &register_name((word >> (high - 2) & 0x7) as u8));
+    match instr {
+        Instruction::Add { .. } | Instruction::Nand { .. } | Instruction::Beq { .. } =>
+            lines.extend([opcode, reg(12, "rd"), reg(9, "ra"), reg(6, "rb"), describe_field(word, 3, 0, "unused", "always 0")]),
+        Instruction::Addi { imm, .. } | Instruction::Sw { imm, .. } | Instruction::Lw { imm, .. } =>
+            lines.extend([opcode, reg(12, "rd"), reg(9, "ra"), describe_field(word, 6, 0, "imm", &format!("{} (0x{:02X} sign-extended from 7 bits)", imm, word & 0x007F))]),
+        Instruction::Lui { imm, .. } =>
+            lines.extend([opcode, reg(12, "rd"), describe_field(word, 9, 0, "imm", &format!("{} (0x{:03X}), the upper 10 bits of rd", imm, imm))]),
+        Instruction::Jal { .. } => lines.extend([opcode, reg(12, "rd"), reg(9, "ra"), describe_field(word, 6, 0, "unused", "always 0")]),
+        Instruction::Syscall(code) => lines.extend([opcode, describe_field(word, 12, 10, "rd", "$r4, which marks a syscall"), reg(9, "ra"),
+            describe_field(word, 6, 0, "code", &format!("syscall {}", code))]),
+        Instruction::Data(_) => lines.push(describe_field(word, 15, 0, "data", &format!("{} (does not encode any instruction)", word)))
+    };
+
+    lines
+}
+
+
+/// Reads a binary image with its words in the given order and disassembles it, writing the lines of assembly to `output` if it is given, then returns the lines
+/// for the caller to show.
+///
+/// Returns an `AssemblyError` if the image cannot be read, has an odd number of bytes, or the output cannot be written.
+#[cfg(feature = "cli")]
+pub fn disassemble_file(input:&str, output:Option<&str>, endian:Endian) -> Result<Vec<String>, Box<dyn Error>> {
+    let bytes = match fs::read(input) {
+        Ok(val) => val,
+        Err(err) => return Err(Box::new(AssemblyError(format!("Could not read {}: {}", input, err))))
+    };
+
+    let lines = disassemble(&read_words(&bytes, endian)?);
+    if let Some(filename) = output {
+        let mut source = lines.join("\n");
+        source.push('\n');
+        write_file_atomically(filename, source.as_bytes())?;
+    }
+
+    Ok(lines)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+    #[cfg(feature = "cli")]
+    use std::{ env, path::Path };
+    #[cfg(feature = "cli")]
+    use crate::assemble_file;
+    #[cfg(feature = "cli")]
+    use crate::output::write_assembled_bytes;
+
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&[0x0420, 0x2DFF, 0x6BFF, 0xF403, 0xE780, 0x0421]);
+        assert_eq!(lines[0], "ADD      $r0, $zero, $r1   # 0x0000");
+        assert_eq!(lines[1], "ADDI     $r2, $r2, -1      # 0x0001, imm 0x7F");
+        assert_eq!(lines[2], "LUI      $r1, 1023         # 0x0002, imm 0x3FF");
+        assert_eq!(lines[3], ".syscall 3                 # 0x0003, imm 0x03");
+        assert_eq!(lines[4], "JAL      $r0, $r6          # 0x0004");
+        assert_eq!(lines[5], ".fill    1057              # 0x0005, imm 0x0421");
+    }
+
+
+    #[test]
+    fn test_describe_word() {
+        assert_eq!(describe_word(0x2807), vec![
+            "0x2807 = 0b0010100000000111",
+            "ADDI     $r1, $zero, 7",
+            "bits 15-13  001               opcode  ADDI",
+            "bits 12-10  010               rd      $r1",
+            "bits 9-7    000               ra      $zero",
+            "bits 6-0    0000111           imm     7 (0x07 sign-extended from 7 bits)"
+        ]);
+
+        assert_eq!(describe_word(0x2DFF)[5], "bits 6-0    1111111           imm     -1 (0x7F sign-extended from 7 bits)");
+        assert_eq!(describe_word(0x6BFF)[4], "bits 9-0    1111111111        imm     1023 (0x3FF), the upper 10 bits of rd");
+        assert_eq!(describe_word(0x4D20)[6], "bits 3-0    0000              unused  always 0");
+        assert_eq!(describe_word(0xF405)[5], "bits 6-0    0000101           code    syscall 5");
+        assert_eq!(describe_word(0x0421)[2..], ["bits 15-0   0000010000100001  data    1057 (does not encode any instruction)"]);
+    }
+
+
+    #[test]
+    fn test_disassemble_every_word() {
+        let words:Vec<u16> = (0..=u16::MAX).collect();
+        assert_eq!(assemble_str(&disassemble(&words).join("\n")).unwrap().code, words);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_read_words_odd_length() {
+        read_words(&[0x12, 0x34, 0x56], Endian::Big).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_disassemble_round_trip() {
+        for (endian, name) in [(Endian::Big, "iridium_test_round_trip_be"), (Endian::Little, "iridium_test_round_trip_le")] {
+            let program = assemble_file(Path::new("test_files/test_file_bios.asm")).unwrap();
+            let binary = env::temp_dir().join(format!("{}.bin", name)).to_str().unwrap().to_owned();
+            let source = env::temp_dir().join(format!("{}.asm", name)).to_str().unwrap().to_owned();
+            write_assembled_bytes(&binary, program.code.clone(), endian).unwrap();
+
+            assert_eq!(disassemble_file(&binary, Some(&source), endian).unwrap().len(), program.code.len());
+            let reassembled = assemble_str(&fs::read_to_string(&source).unwrap()).unwrap();
+            assert_eq!(reassembled.code, program.code);
+
+            fs::remove_file(&binary).unwrap();
+            fs::remove_file(&source).unwrap();
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use std::error::Error;
+use std::fmt;
+use serde::{ Deserialize, Serialize };
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use crate::{ AssemblyError, into_assembly_error, parse_immediate };
+use crate::isa::IsaSpec;
+use crate::lexer::{ LineKind, Token, get_line_kind, parse_line };
+use crate::parser::{ LABEL_REGEX, UINT_REGEX, get_imm_from_instr, get_mnemonic, parse_run };
+
+
+/// The fields of the word an instruction encodes to, as given by `Instruction::fields`, with the word and the opcode in its top 3 bits in hexadecimal, such as
+/// `0x2807` and `0x2000`. Registers are given by their 3-bit number and the immediate by its value, sign-extended if its field is signed, and any field the
+/// instruction does not have is `None` and left out when serialised.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstructionFields {
+    pub mnemonic: String,
+    pub word: String,
+    pub opcode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rd: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ra: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rb: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imm: Option<i64>
+}
+
+
+/// A single machine word, either an instruction or a data word placed with `.fill`. Registers are given by their 3-bit number, which is 0 for `$zero` and one more
+/// than the register's index for `$r0` to `$r6`, and immediates are masked to the width of their field when encoded, so a negative RRI-type immediate is stored in
+/// two's complement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Add { rd: u8, ra: u8, rb: u8 },
+    Addi { rd: u8, ra: u8, imm: i16 },
+    Nand { rd: u8, ra: u8, rb: u8 },
+    Lui { rd: u8, imm: u16 },
+    Sw { rd: u8, ra: u8, imm: i16 },
+    Lw { rd: u8, ra: u8, imm: i16 },
+    Beq { rd: u8, ra: u8, rb: u8 },
+    Jal { rd: u8, ra: u8 },
+    Syscall(u8),
+    Data(u16)
+}
+
+impl Instruction {
+    /// Encodes the instruction as a 16-bit word, with the opcode in the top 3 bits followed by the registers and then the immediate.
+    pub fn encode(&self) -> u16 {
+        let rrr = |opcode:u16, rd:u8, ra:u8, rb:u8| opcode | (rd as u16 & 0x7) << 10 | (ra as u16 & 0x7) << 7 | (rb as u16 & 0x7) << 4;
+        let rri = |opcode:u16, rd:u8, ra:u8, imm:i16| opcode | (rd as u16 & 0x7) << 10 | (ra as u16 & 0x7) << 7 | (imm as u16 & 0x007F);
+        match *self {
+            Instruction::Add { rd, ra, rb } => rrr(0x0000, rd, ra, rb),
+            Instruction::Addi { rd, ra, imm } => rri(0x2000, rd, ra, imm),
+            Instruction::Nand { rd, ra, rb } => rrr(0x4000, rd, ra, rb),
+            Instruction::Lui { rd, imm } => 0x6000 | (rd as u16 & 0x7) << 10 | (imm & 0x03FF),
+            Instruction::Sw { rd, ra, imm } => rri(0x8000, rd, ra, imm),
+            Instruction::Lw { rd, ra, imm } => rri(0xA000, rd, ra, imm),
+            Instruction::Beq { rd, ra, rb } => rrr(0xC000, rd, ra, rb),
+            Instruction::Jal { rd, ra } => rri(0xE000, rd, ra, 0),
+            Instruction::Syscall(code) => 0xF400 | (code as u16 & 0x007F), // a JAL with $r4 as its first register and the code as its immediate
+            Instruction::Data(word) => word
+        }
+    }
+
+
+    /// Gets the mnemonic the instruction is written with, which is `.syscall` for a syscall and `.fill` for data.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Instruction::Add { .. } => "ADD",
+            Instruction::Addi { .. } => "ADDI",
+            Instruction::Nand { .. } => "NAND",
+            Instruction::Lui { .. } => "LUI",
+            Instruction::Sw { .. } => "SW",
+            Instruction::Lw { .. } => "LW",
+            Instruction::Beq { .. } => "BEQ",
+            Instruction::Jal { .. } => "JAL",
+            Instruction::Syscall(_) => ".syscall",
+            Instruction::Data(_) => ".fill"
+        }
+    }
+
+
+    /// Gets the instruction as assembly without any comment, with its mnemonic padded to a fixed width followed by its operands, such as `ADDI     $r2, $r2, -1`.
+    /// Immediates are in decimal, with data of 0x8000 or more given as a negative number, as that is how `.fill` takes it.
+    pub(crate) fn to_asm(self) -> String {
+        let reg = register_name;
+        let operands = match self {
+            Instruction::Add { rd, ra, rb } | Instruction::Nand { rd, ra, rb } | Instruction::Beq { rd, ra, rb } => format!("{}, {}, {}", reg(rd), reg(ra), reg(rb)),
+            Instruction::Addi { rd, ra, imm } | Instruction::Sw { rd, ra, imm } | Instruction::Lw { rd, ra, imm } => format!("{}, {}, {}", reg(rd), reg(ra), imm),
+            Instruction::Lui { rd, imm } => format!("{}, {}", reg(rd), imm),
+            Instruction::Jal { rd, ra } => format!("{}, {}", reg(rd), reg(ra)),
+            Instruction::Syscall(code) => code.to_string(),
+            Instruction::Data(word) => (word as i16).to_string()
+        };
+
+        format!("{:<8} {}", self.mnemonic(), operands)
+    }
+
+
+    /// Breaks the instruction down into the fields of the word it encodes to, or gives `None` for data, which has no fields. A syscall is a `JAL` with `$r4` as its
+    /// first register and `$zero` as its second, so those are given along with its code as the immediate.
+    pub fn fields(self) -> Option<InstructionFields> {
+        let (rd, ra, rb, imm) = match self {
+            Instruction::Add { rd, ra, rb } | Instruction::Nand { rd, ra, rb } | Instruction::Beq { rd, ra, rb } => (Some(rd), Some(ra), Some(rb), None),
+            Instruction::Addi { rd, ra, imm } | Instruction::Sw { rd, ra, imm } | Instruction::Lw { rd, ra, imm } => (Some(rd), Some(ra), None, Some(imm as i64)),
+            Instruction::Lui { rd, imm } => (Some(rd), None, None, Some(imm as i64)),
+            Instruction::Jal { rd, ra } => (Some(rd), Some(ra), None, None),
+            Instruction::Syscall(code) => (Some(5), Some(0), None, Some(code as i64)),
+            Instruction::Data(_) => return None
+        };
+
+        let word = self.encode();
+        Some(InstructionFields { mnemonic: self.mnemonic().to_owned(), word: format!("0x{:04X}", word), opcode: format!("0x{:04X}", word & 0xE000), rd, ra, rb, imm })
+    }
+
+
+    /// Gets the bits of the instruction's immediate field in hexadecimal, padded to the width of the field, or `None` if it has no immediate.
+    pub(crate) fn imm_hex(self) -> Option<String> {
+        match self {
+            Instruction::Addi { imm, .. } | Instruction::Sw { imm, .. } | Instruction::Lw { imm, .. } => Some(format!("0x{:02X}", imm as u16 & 0x007F)),
+            Instruction::Lui { imm, .. } => Some(format!("0x{:03X}", imm & 0x03FF)),
+            Instruction::Syscall(code) => Some(format!("0x{:02X}", code & 0x7F)),
+            Instruction::Data(word) => Some(format!("0x{:04X}", word)),
+            _ => None
+        }
+    }
+}
+
+
+/// Writes the instruction in the canonical form, which always assembles back to the same word: an upper case mnemonic padded to a fixed width, its operands, and,
+/// if it has an immediate, a comment in a fixed column giving the immediate field in hexadecimal, such as `ADDI     $r2, $r2, -1       # 0x7F`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.imm_hex() {
+            Some(hex) => write!(f, "{:<27}# {}", self.to_asm(), hex),
+            None => write!(f, "{}", self.to_asm())
+        }
+    }
+}
+
+
+/// Gets the name of the register with the given 3-bit number as it is written in assembly, which is `$zero` for 0 and `$r0` to `$r6` for the rest.
+pub(crate) fn register_name(reg:u8) -> String {
+    match reg {
+        0 => "$zero".to_owned(),
+        _ => format!("$r{}", reg - 1)
+    }
+}
+
+
+/// Decodes a 16-bit word into the instruction it encodes, such that encoding the result always gives the same word. Every word decodes to some instruction, with
+/// those which no instruction encodes to, such as an `ADD` with its unused low bits set or a `JAL` with a non-zero immediate, decoded as `Data`. Two patterns are
+/// ambiguous and decode to the more specific instruction: a `JAL` with `$r4` as its first register and `$zero` as its second is the same word as `Syscall(0)`, and
+/// `Data` holding the encoding of an instruction is the same word as that instruction. `Syscall` codes above 7 are decoded as `Data`, as `.syscall` only takes 0
+/// to 7.
+pub fn decode(word:u16) -> Instruction {
+    let (rd, ra, rb) = ((word >> 10 & 0x7) as u8, (word >> 7 & 0x7) as u8, (word >> 4 & 0x7) as u8);
+    let imm = ((word & 0x007F) as i16) << 9 >> 9; // sign-extends the 7-bit immediate
+    let instr = match word >> 13 {
+        0 => Instruction::Add { rd, ra, rb },
+        1 => Instruction::Addi { rd, ra, imm },
+        2 => Instruction::Nand { rd, ra, rb },
+        3 => Instruction::Lui { rd, imm: word & 0x03FF },
+        4 => Instruction::Sw { rd, ra, imm },
+        5 => Instruction::Lw { rd, ra, imm },
+        6 => Instruction::Beq { rd, ra, rb },
+        _ if rd == 5 && ra == 0 && (0..=7).contains(&imm) => Instruction::Syscall(imm as u8),
+        _ => Instruction::Jal { rd, ra }
+    };
+
+    match instr.encode() == word {
+        true => instr,
+        false => Instruction::Data(word)
+    }
+}
+
+
+/// Gets the value of an immediate operand parsed by `parse_line`, which must fit in the given number of bits.
+///
+/// Returns an `AssemblyError` if the operand is a label, which should have been substituted by now, or does not fit.
+pub(crate) fn get_imm_operand(token:&Token, bits:u32, signed:bool, instr:&str) -> Result<i64, Box<dyn Error>> {
+    match *token {
+        Token::Immediate(val) => match parse_immediate(val, bits, signed) {
+            Ok(val) => Ok(val),
+            Err(err) => Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, instr))))
+        },
+
+        Token::Char(val) => Ok(val as i64),
+        Token::Expr(val) => Err(Box::new(AssemblyError(format!("Found label {} in instruction {} but labels are not accepted", val, instr)))),
+        _ => Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", instr))))
+    }
+}
+
+
+/// Takes a valid instruction, with or without a label, and parses it into an `Instruction` with `parse_line`. A line which is only a number is taken to be a data
+/// word. Machine instructions are encoded with the given instruction set and then decoded, so one outside the default set gives the same word as `Data`.
+///
+/// Returns an `AssemblyError` if the line is not a valid instruction, is a pseudo-instruction, or has an invalid immediate.
+pub fn parse_instruction(instr:&str, isa:&IsaSpec) -> Result<Instruction, Box<dyn Error>> {
+    let parsed = match parse_line(instr, isa) {
+        Ok(val) => val,
+        Err(err) => {
+            let without_label = LABEL_REGEX.replace(instr, "");
+            let data = without_label.trim();
+            if !UINT_REGEX.is_match(data) || get_line_kind(get_mnemonic(instr), isa).is_some() {
+                return Err(err);
+            }
+
+            return match get_imm_from_instr(data, 16, false, false, false)? {
+                Some(val) => Ok(Instruction::Data(val as u16)),
+                None => Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", data))))
+            };
+        }
+    };
+
+    let imm = |bits:u32, signed:bool| get_imm_operand(parsed.operands.last().unwrap(), bits, signed, instr);
+    let instruction = match (parsed.kind, parsed.mnemonic) {
+        (LineKind::Rrr | LineKind::Rri | LineKind::Ri | LineKind::Jal, _) => decode(isa.encode(&parsed, instr)?),
+        (LineKind::Syscall, _) => Instruction::Syscall(imm(7, false)? as u8),
+        (LineKind::Fill, _) => Instruction::Data(imm(16, false).or_else(|_| imm(16, true))? as u16), // negative values are stored in two's complement
+        _ => {
+            return Err(Box::new(AssemblyError(format!("{} is not a valid instruction for compilation. Note pseudoinstructions cannot be present at this stage", instr))));
+        }
+    };
+
+    Ok(instruction)
+}
+
+
+/// Takes a valid instruction and converts it to its binary equivalent as a word by parsing it with `parse_instruction` and encoding the result.
+///
+/// Returns an `AssemblyError` if the instruction cannot be parsed.
+pub fn convert_instr_to_binary(instr:&str, isa:&IsaSpec) -> Result<u16, Box<dyn Error>> {
+    Ok(parse_instruction(instr, isa)?.encode())
+}
+
+
+/// Converts a line of a section to binary, giving its word along with the number of words it stands for, which is the count of a `.run` and 1 for anything else.
+///
+/// Returns an `AssemblyError` if the instruction cannot be parsed.
+pub(crate) fn encode_line(line:&str, isa:&IsaSpec) -> Result<(u16, usize), AssemblyError> {
+    match parse_run(line) {
+        Some((_, count, value)) => Ok((value, count)),
+        None => Ok((convert_instr_to_binary(line, isa).map_err(into_assembly_error)?, 1))
+    }
+}
+
+
+/// Converts every line of a section to binary, giving the words in the same order as the lines, with a `.run` written out as each of its words. Each line is
+/// encoded exactly once with the given instruction set, and with the `parallel` feature the lines are spread across threads, which gives the same words as encoding
+/// them in order.
+///
+/// Returns an `AssemblyError` if any line cannot be converted, which is always the error of the first such line, whether or not they are encoded in parallel.
+pub fn assemble_section(lines:&[String], isa:&IsaSpec) -> Result<Vec<u16>, Box<dyn Error>> {
+    #[cfg(feature = "parallel")]
+    let words = encode_lines_in_parallel(lines, isa);
+    #[cfg(not(feature = "parallel"))]
+    let words = encode_lines_in_order(lines, isa);
+
+    Ok(words?)
+}
+
+
+/// Writes out the words of each encoded line in order, stopping at the first line which could not be converted.
+fn collect_words(encoded:impl Iterator<Item = Result<(u16, usize), AssemblyError>>) -> Result<Vec<u16>, AssemblyError> {
+    let mut words = Vec::new();
+    for result in encoded {
+        let (word, count) = result?;
+        words.resize(words.len() + count, word);
+    }
+
+    Ok(words)
+}
+
+
+/// Encodes each line in turn, stopping at the first which cannot be converted.
+#[cfg_attr(feature = "parallel", allow(dead_code))]
+fn encode_lines_in_order(lines:&[String], isa:&IsaSpec) -> Result<Vec<u16>, AssemblyError> {
+    collect_words(lines.iter().map(|line| encode_line(line, isa)))
+}
+
+
+/// Encodes the lines across the threads of rayon's pool, which all share the one instruction set. Every line is encoded before any error is returned, and the
+/// results are kept in the order of the lines so that the error given is that of the first line which cannot be converted, as with `encode_lines_in_order`.
+#[cfg(feature = "parallel")]
+fn encode_lines_in_parallel(lines:&[String], isa:&IsaSpec) -> Result<Vec<u16>, AssemblyError> {
+    let results:Vec<Result<(u16, usize), AssemblyError>> = lines.par_iter().map(|line| encode_line(line, isa)).collect();
+
+    collect_words(results.into_iter())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use std::collections::HashSet;
+    use regex::Regex;
+    use crate::parser::{ JAL_REGEX, RI_REGEX, RRI_REGEX, RRR_REGEX, SCALL_REGEX, get_line_vector, validate_assembly_lines };
+    use crate::expansion::substitute_pseudoinstrs;
+    use crate::labels::{ generate_label_table, substitute_labels };
+
+
+    #[test]
+    fn test_display_round_trip() {
+        let mut instrs:Vec<Instruction> = Vec::new();
+        for (rd, ra, rb) in [(0, 0, 0), (1, 7, 3), (7, 7, 7), (5, 0, 0)] {
+            instrs.extend([Instruction::Add { rd, ra, rb }, Instruction::Nand { rd, ra, rb }, Instruction::Beq { rd, ra, rb }, Instruction::Jal { rd, ra }]);
+            for imm in [-64, -1, 0, 1, 63] {
+                instrs.extend([Instruction::Addi { rd, ra, imm }, Instruction::Sw { rd, ra, imm }, Instruction::Lw { rd, ra, imm }]);
+            }
+
+            instrs.extend([0, 1, 0x01FF, 0x03FF].map(|imm| Instruction::Lui { rd, imm }));
+        }
+
+        instrs.extend([0, 7].map(Instruction::Syscall));
+        instrs.extend([0x0000, 0x7FFF, 0x8000, 0xFFFF].map(Instruction::Data));
+
+        for instr in instrs {
+            let text = instr.to_string();
+            assert_eq!(parse_instruction(&text, &DEFAULT_ISA_SPEC).unwrap().encode(), instr.encode(), "{}", text);
+        }
+
+        assert_eq!(Instruction::Addi { rd: 3, ra: 3, imm: -1 }.to_string(), "ADDI     $r2, $r2, -1      # 0x7F");
+        assert_eq!(Instruction::Beq { rd: 0, ra: 1, rb: 7 }.to_string(), "BEQ      $zero, $r0, $r6");
+        assert_eq!(Instruction::Data(0xC001).to_string(), ".fill    -16383            # 0xC001");
+    }
+
+
+    #[test]
+    fn test_decode_every_word() {
+        for word in 0..=u16::MAX {
+            assert_eq!(decode(word).encode(), word);
+        }
+    }
+
+
+    #[test]
+    fn test_decode_every_instruction() {
+        let mut instrs:Vec<Instruction> = Vec::new();
+        for (rd, ra, rb) in (0..512).map(|regs| ((regs >> 6) as u8, (regs >> 3 & 0x7) as u8, (regs & 0x7) as u8)) {
+            instrs.extend([Instruction::Add { rd, ra, rb }, Instruction::Nand { rd, ra, rb }, Instruction::Beq { rd, ra, rb }]);
+            if rb == 0 {
+                instrs.push(Instruction::Jal { rd, ra });
+                instrs.extend((-64..=63).flat_map(|imm| [Instruction::Addi { rd, ra, imm }, Instruction::Sw { rd, ra, imm }, Instruction::Lw { rd, ra, imm }]));
+            }
+
+            if ra == 0 && rb == 0 {
+                instrs.extend((0..=0x03FF).map(|imm| Instruction::Lui { rd, imm }));
+            }
+        }
+
+        instrs.extend((0..=7).map(Instruction::Syscall));
+        for instr in instrs {
+            match instr {
+                Instruction::Jal { rd: 5, ra: 0 } => assert_eq!(decode(instr.encode()), Instruction::Syscall(0)),
+                _ => assert_eq!(decode(instr.encode()), instr)
+            };
+        }
+
+        assert_eq!(decode(0x0421), Instruction::Data(0x0421));
+        assert_eq!(decode(0xE781), Instruction::Data(0xE781));
+    }
+
+
+    #[test]
+    fn test_convert_to_binary() {
+        assert_eq!(convert_instr_to_binary("ADD  $r0, $zero, $r1", &DEFAULT_ISA_SPEC).unwrap(), 0x0420_u16);
+        assert_eq!(convert_instr_to_binary("NAND $r2, $r3,   $r4", &DEFAULT_ISA_SPEC).unwrap(), 0x4E50_u16);
+        assert_eq!(convert_instr_to_binary("BEQ  $r5, $zero, $r6", &DEFAULT_ISA_SPEC).unwrap(), 0xD870_u16);
+
+        assert_eq!(convert_instr_to_binary("ADDI $r1, $zero,  7", &DEFAULT_ISA_SPEC).unwrap(),  0x2807_u16);
+        assert_eq!(convert_instr_to_binary("ADDI $r1, $zero, -7", &DEFAULT_ISA_SPEC).unwrap(),  0x2879_u16);
+        assert_eq!(convert_instr_to_binary("SW   $r1, $r2,   30", &DEFAULT_ISA_SPEC).unwrap(),  0x899E_u16);
+        assert_eq!(convert_instr_to_binary("LW   $r6, $r5,  -10", &DEFAULT_ISA_SPEC).unwrap(),  0xBF76_u16);
+
+        assert_eq!(convert_instr_to_binary("0x0455", &DEFAULT_ISA_SPEC).unwrap(), 0x0455_u16);
+        assert_eq!(convert_instr_to_binary("10000", &DEFAULT_ISA_SPEC).unwrap(),  0x2710_u16);
+
+        assert_eq!(convert_instr_to_binary("LUI $r0, 500", &DEFAULT_ISA_SPEC).unwrap(),  0x65F4_u16);
+
+        assert_eq!(convert_instr_to_binary(".syscall 5", &DEFAULT_ISA_SPEC).unwrap(),  0xF405_u16);
+        assert_eq!(convert_instr_to_binary("JAL $r5, $r6", &DEFAULT_ISA_SPEC).unwrap(),  0xFB80_u16);
+    }
+
+
+    #[test]
+    fn test_encode_instructions() {
+        assert_eq!(Instruction::Add { rd: 1, ra: 0, rb: 2 }.encode(), 0x0420_u16);
+        assert_eq!(Instruction::Nand { rd: 3, ra: 4, rb: 5 }.encode(), 0x4E50_u16);
+        assert_eq!(Instruction::Beq { rd: 6, ra: 0, rb: 7 }.encode(), 0xD870_u16);
+
+        assert_eq!(Instruction::Addi { rd: 2, ra: 0, imm: 7 }.encode(), 0x2807_u16);
+        assert_eq!(Instruction::Addi { rd: 2, ra: 0, imm: -7 }.encode(), 0x2879_u16);
+        assert_eq!(Instruction::Sw { rd: 2, ra: 3, imm: 30 }.encode(), 0x899E_u16);
+        assert_eq!(Instruction::Lw { rd: 7, ra: 6, imm: -10 }.encode(), 0xBF76_u16);
+
+        assert_eq!(Instruction::Data(0x0455).encode(), 0x0455_u16);
+        assert_eq!(Instruction::Lui { rd: 1, imm: 500 }.encode(), 0x65F4_u16);
+        assert_eq!(Instruction::Syscall(5).encode(), 0xF405_u16);
+        assert_eq!(Instruction::Jal { rd: 6, ra: 7 }.encode(), 0xFB80_u16);
+        assert_eq!(Instruction::Jal { rd: 0, ra: 7 }.encode(), 0xE380_u16);
+    }
+
+
+    #[test]
+    fn test_mnemonics_have_encodings() {
+        // the mnemonics each instruction regex accepts, taken from the regex itself so a mnemonic added to one is checked here without changing this test
+        let mnemonic_regex = Regex::new(r"^\^\(\[a-zA-Z_\]\+:\)\?\[\[:blank:\]\]\*\(?([.A-Za-z|\\]+)\)?").unwrap();
+        let samples = [(&*RRR_REGEX, "$r0, $r1, $r2"), (&*RRI_REGEX, "$r0, $r1, 1"), (&*RI_REGEX, "$r0, 1"), (&*JAL_REGEX, "$r0, $r1"), (&*SCALL_REGEX, "1")];
+
+        let mut opcodes = HashSet::new();
+        for (regex, operands) in samples {
+            let mnemonics = &mnemonic_regex.captures(regex.as_str()).unwrap()[1];
+            for mnemonic in mnemonics.replace('\\', "").split('|') {
+                let line = format!("{} {}", mnemonic, operands);
+                assert!(regex.is_match(&line), "{} is not matched by its own regex", line);
+
+                let instr = parse_instruction(&line, &DEFAULT_ISA_SPEC).unwrap_or_else(|err| panic!("{} cannot be encoded: {}", line, err));
+                assert!(!matches!(instr, Instruction::Data(_)), "{} was encoded as data", line);
+                assert!(opcodes.insert(instr.encode() >> 13) || mnemonic == ".syscall", "{} shares an opcode with another mnemonic", line);
+            }
+        }
+
+        // every opcode should be reachable from some mnemonic
+        assert_eq!(opcodes.len(), 8);
+    }
+
+
+    #[test]
+    fn test_parse_instruction() {
+        assert_eq!(parse_instruction("ADD  $r0, $zero, $r1", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Add { rd: 1, ra: 0, rb: 2 });
+        assert_eq!(parse_instruction("LW   $r6, $r5,  -10", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Lw { rd: 7, ra: 6, imm: -10 });
+        assert_eq!(parse_instruction("start: LUI $r0, 500", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Lui { rd: 1, imm: 500 });
+        assert_eq!(parse_instruction("JAL $zero, $r6", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Jal { rd: 0, ra: 7 });
+        assert_eq!(parse_instruction(".syscall 5", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Syscall(5));
+        assert_eq!(parse_instruction("ADD_TABLE: .fill 0x0004", &DEFAULT_ISA_SPEC).unwrap(), Instruction::Data(4));
+        assert!(parse_instruction("LUI 500", &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    fn test_instruction_fields() {
+        let fields = parse_instruction("ADDI $r0, $zero, 7", &DEFAULT_ISA_SPEC).unwrap().fields().unwrap();
+        assert_eq!(serde_json::to_string(&fields).unwrap(), r#"{"mnemonic":"ADDI","word":"0x2407","opcode":"0x2000","rd":1,"ra":0,"imm":7}"#);
+
+        let fields = Instruction::Nand { rd: 2, ra: 3, rb: 4 }.fields().unwrap();
+        assert_eq!((fields.rd, fields.ra, fields.rb, fields.imm), (Some(2), Some(3), Some(4), None));
+        assert_eq!(Instruction::Lw { rd: 1, ra: 2, imm: -3 }.fields().unwrap().imm, Some(-3));
+        assert_eq!(Instruction::Syscall(6).fields().unwrap(), InstructionFields { mnemonic: ".syscall".to_owned(), word: "0xF406".to_owned(),
+            opcode: "0xE000".to_owned(), rd: Some(5), ra: Some(0), rb: None, imm: Some(6) });
+        assert_eq!(Instruction::Data(0x1234).fields(), None);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_convert_invalid_instr_to_binary() {
+        convert_instr_to_binary("INVALID  $r0, $zero, $r1", &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_convert_invalid_register_to_binary() {
+        convert_instr_to_binary("ADD  $r0, $r9, $r1", &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_convert_jal_without_link() {
+        let lines = vec!["JAL $zero, $r6".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(convert_instr_to_binary(&lines[0], &DEFAULT_ISA_SPEC).unwrap(), 0xE380_u16);
+    }
+
+
+    #[test]
+    fn test_three_operand_jal() {
+        let lines = vec!["JAL $r1, $r2, 5".to_owned()];
+        let err = validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap_err().to_string();
+        assert!(err.contains("JAL takes exactly two registers"));
+    }
+
+
+    #[test]
+    fn test_convert_labels_containing_mnemonics() {
+        assert_eq!(convert_instr_to_binary("ADD_TABLE: .fill 0x0004", &DEFAULT_ISA_SPEC).unwrap(), 0x0004);
+        assert_eq!(convert_instr_to_binary("SWAP_LW: .fill 0x1234", &DEFAULT_ISA_SPEC).unwrap(), 0x1234);
+        assert_eq!(convert_instr_to_binary("do_JAL: .fill 0", &DEFAULT_ISA_SPEC).unwrap(), 0x0000);
+        assert_eq!(convert_instr_to_binary(".fill -2", &DEFAULT_ISA_SPEC).unwrap(), 0xFFFE);
+
+        let expected = convert_instr_to_binary("NAND $r0, $r1, $r2", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(convert_instr_to_binary("ADD_TABLE: NAND $r0, $r1, $r2", &DEFAULT_ISA_SPEC).unwrap(), expected);
+        assert_eq!(convert_instr_to_binary("SWAP_LW: NAND $r0, $r1, $r2", &DEFAULT_ISA_SPEC).unwrap(), expected);
+        assert_eq!(convert_instr_to_binary("do_JAL: NAND $r0, $r1, $r2", &DEFAULT_ISA_SPEC).unwrap(), expected);
+    }
+
+
+    #[test]
+    fn test_file_bios() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_file_bios.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        let mut assembled_lines = Vec::new();
+        for line in lines {
+            assembled_lines.push(convert_instr_to_binary(&line, &DEFAULT_ISA_SPEC).unwrap());
+        }
+
+        assert_eq!(assembled_lines[2], 0x280B);
+        assert_eq!(assembled_lines[3], 0x6800);
+    }
+
+
+    /// A section of 200,000 lines, more than fit in the address space but enough to time the encoder on.
+    fn generate_section() -> Vec<String> {
+        let sample = ["start: ADDI $r0, $zero, 5", "ADD $r1, $r0, $r2", "LUI $r3, 0x3FF", "loop: BEQ $r0, $r1, $r2", "SW $r0, $r6, -3", "JAL $zero, $r6",
+            "NAND $r4, $r4, $r5", ".fill 0x1234", "LW $r2, $r1, 63"];
+        sample.iter().cycle().take(200_000).map(|line| line.to_string()).collect()
+    }
+
+
+    #[test]
+    fn test_assemble_section_first_error() {
+        let mut lines = generate_section();
+        lines.truncate(1000);
+        assert_eq!(assemble_section(&lines, &DEFAULT_ISA_SPEC).unwrap(), encode_lines_in_order(&lines, &DEFAULT_ISA_SPEC).unwrap());
+
+        lines[700] = "ADDI $r0, $zero, 64".to_owned();
+        lines[300] = "LUI $r0, 0x400".to_owned();
+        assert!(assemble_section(&lines, &DEFAULT_ISA_SPEC).unwrap_err().to_string().contains("LUI $r0, 0x400"));
+    }
+
+
+    #[test]
+    fn test_assemble_section_runs() {
+        let lines:Vec<String> = ["ADDI $r1, $zero, 5", "buffer: .run 20 0x0000", ".run 16 0x0020", ".fill 0x0000"].iter().map(|line| line.to_string()).collect();
+        let words = assemble_section(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(words.len(), 38);
+        assert_eq!(words[0], 0x2805);
+        assert!(words[1..21].iter().all(|word| *word == 0));
+        assert!(words[21..37].iter().all(|word| *word == 0x0020));
+        assert_eq!(words[37], 0);
+    }
+
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_parallel_encoding_matches_in_order() {
+        use crate::isa::{ DEFAULT_ISA, IsaSpec };
+
+        let lines = generate_section();
+        assert_eq!(encode_lines_in_parallel(&lines, &DEFAULT_ISA_SPEC).unwrap(), encode_lines_in_order(&lines, &DEFAULT_ISA_SPEC).unwrap());
+
+        let mut lines:Vec<String> = lines.into_iter().take(5000).collect();
+        for index in [4000, 123, 2500] {
+            lines[index] = format!("ADDI $r0, $zero, {}", 100 + index);
+        }
+
+        assert_eq!(encode_lines_in_parallel(&lines, &DEFAULT_ISA_SPEC).unwrap_err().0, encode_lines_in_order(&lines, &DEFAULT_ISA_SPEC).unwrap_err().0);
+        assert!(encode_lines_in_parallel(&lines, &DEFAULT_ISA_SPEC).unwrap_err().0.contains("223"));
+
+        // the workers must all encode with the instruction set they are given rather than the default one
+        let spec = IsaSpec::from_toml(&(DEFAULT_ISA.to_owned() + "SUB = { format = \"RRR\", opcode = 0x0001 }\n")).unwrap();
+        let lines = vec!["SUB $r6, $r0, $zero".to_owned(); 5000];
+        let words = encode_lines_in_parallel(&lines, &spec).unwrap();
+        assert!(words.iter().all(|word| *word == 0x1C81));
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_assemble_section_large_input() {
+        let lines = generate_section();
+        assert_eq!(assemble_section(&lines, &DEFAULT_ISA_SPEC).unwrap(), encode_lines_in_order(&lines, &DEFAULT_ISA_SPEC).unwrap());
+    }
+}
@@ -0,0 +1,1323 @@
+use std::borrow::Cow;
+use std::collections::{ HashMap, HashSet };
+use std::collections::hash_map::Entry;
+use std::error::Error;
+use std::slice;
+use ascii_converter::string_to_decimals;
+use log::debug;
+use crate::{ AssemblyError, convert_to_i64, evaluate_expression };
+use crate::isa::IsaSpec;
+use crate::parser::{ ASSERT_SIZE_REGEX, AT_REGEX, CONSTANT_NAME_REGEX, EQU_REGEX, LABEL_ARG_REGEX, LABEL_NAME_REGEX, LABEL_REGEX, LITERAL_REGEX, OPERANDS_REGEX, ORG_REGEX, PREDEFINED_LABEL_REGEX, REGALIAS_REGEX, REGISTER_REGEX, TEXT_IMM_REGEX, get_imm_from_instr, get_mnemonic, get_word_count, is_reserved_word, parse_pattern, parse_run, parse_space, parse_text, split_operands, SpaceValue };
+use crate::labels::{ LabelKind, Section, get_label_kind, get_section_switch };
+use crate::layout::Overlap;
+use crate::lexer::{ LineKind, Token, parse_line };
+
+
+/// A `.assert_size` directive, which requires the number of words in the section it was written in to compare to `limit` as given by `comparison`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeAssertion {
+    section: Section,
+    comparison: String,
+    limit: i64,
+    line: String
+}
+
+
+/// A `.text` left out by `merge_strings` as a copy of an earlier one, given by the label defined on it, the line it was on, the label of the copy kept in its place,
+/// and the number of words it would have taken up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedString {
+    pub label: String,
+    pub line: usize,
+    pub kept: String,
+    pub words: usize
+}
+
+
+/// Finds the labels referred to as part of a larger expression anywhere in the program, such as `table` in `@table+1` or both labels in `@end-@start`, rather than
+/// on their own. A `@` inside a string literal is not a reference.
+fn find_label_arithmetic(lines:&[String]) -> HashSet<String> {
+    let mut labels:HashSet<String> = HashSet::new();
+    for line in lines {
+        let text = TEXT_IMM_REGEX.replace_all(line, "\"\"");
+        for expr in LABEL_ARG_REGEX.find_iter(&text).map(|val| val.as_str()) {
+            let names:Vec<&str> = LABEL_NAME_REGEX.captures_iter(expr).map(|caps| caps.get(1).unwrap().as_str()).collect();
+            if names.len() > 1 || expr.len() > names[0].len() + 1 {
+                labels.extend(names.into_iter().map(|name| name.to_owned()));
+            }
+        }
+    }
+
+    labels
+}
+
+
+/// Leaves out each labelled `.text` which holds the same null-terminated string as an earlier one in its section, replacing it with an empty line so that every
+/// line keeps its index, and gives the label of each one left out along with the label of the copy its label should point at instead. A `nonull` string is not
+/// merged, as it may be a fragment of a longer string continued by the words after it, and neither is a string without a label or whose label is used in an
+/// expression such as `@message+1`, as the words around it may then be reached by counting from another label.
+pub fn merge_strings(lines:&[String]) -> (Vec<String>, Vec<MergedString>) {
+    let in_arithmetic = find_label_arithmetic(lines);
+    let mut kept:HashMap<(Section, String, usize), String> = HashMap::new();
+    let mut merged:Vec<MergedString> = Vec::new();
+    let mut new_lines:Vec<String> = Vec::with_capacity(lines.len());
+    let mut section = Section::Code;
+    for (index, line) in lines.iter().enumerate() {
+        section = get_section_switch(line).unwrap_or(section);
+        let label = LABEL_REGEX.find(line).map(|val| val.as_str().trim_end_matches(':')).filter(|label| !in_arithmetic.contains(*label));
+        let string = match (label, get_mnemonic(line)) {
+            (Some(label), ".text") => parse_text(line).ok().filter(|(_, _, null_terminated)| *null_terminated).map(|(text, size, _)| (label, text, size)),
+            _ => None
+        };
+
+        let (label, text, size) = match string {
+            Some(val) => val,
+            None => {
+                new_lines.push(line.to_owned());
+                continue;
+            }
+        };
+
+        match kept.entry((section, text, size)) {
+            Entry::Occupied(entry) => {
+                debug!("Merged the string of {} into {}", label, entry.get());
+                merged.push(MergedString { label: label.to_owned(), line: index + 1, kept: entry.get().to_owned(), words: get_word_count(line) });
+                new_lines.push(String::new());
+            },
+
+            Entry::Vacant(entry) => {
+                entry.insert(label.to_owned());
+                new_lines.push(line.to_owned());
+            }
+        };
+    }
+
+    (new_lines, merged)
+}
+
+
+/// A run of words of a section written one after another from `start` up to but not including `end`, which begins at the start of the section, at an `.org`, or is
+/// the padding written before an `.org`, and is named in messages by what begins it and the first label defined in it.
+struct WrittenRegion {
+    start: usize,
+    end: usize,
+    name: String,
+    label: Option<String>
+}
+
+impl WrittenRegion {
+    fn describe(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{}, labelled {}", self.name, label),
+            None => self.name.clone()
+        }
+    }
+}
+
+
+/// Gets the number of words the region started by an `.org` writes, given the lines after it, along with the first label defined in it. The region takes in every
+/// line of its section up to the next `.org` there, so the lines of the other section are skipped over.
+fn measure_region(lines:&[String], mut section:Section) -> (usize, Option<String>) {
+    let region_section = section;
+    let (mut size, mut label) = (0, None);
+    for line in lines {
+        section = get_section_switch(line).unwrap_or(section);
+        if section != region_section || get_mnemonic(line) == ".at" {
+            continue;
+        } else if ORG_REGEX.is_match(line) {
+            break;
+        }
+
+        size += get_word_count(line);
+        label = label.or_else(|| LABEL_REGEX.find(line).map(|val| val.as_str().trim_end_matches(':').to_owned()));
+    }
+
+    (size, label)
+}
+
+
+/// Shortens a line which a later region writes over the end of to its first `keep` words, keeping its label. A `.run` of padding stays a shorter `.run`, the first
+/// word of a `MOVI` or `MASK` is kept as the instruction it expands to, and any other data is given as a `.space` of the values of the words kept. A jump to a
+/// label is only expanded through the scratch register once the sections are laid out, so it cannot be shortened and `None` is given instead.
+fn truncate_line(line:&str, keep:usize) -> Option<String> {
+    if let Some((label, _, value)) = parse_run(line) {
+        return Some(format!("{}.run {} 0x{:04X}", label, keep, value));
+    }
+
+    let expanded = substitute_pseudoinstrs(slice::from_ref(&line.to_owned()));
+    if get_label_kind(line) == LabelKind::Code {
+        return match expanded.len() == 1 && get_word_count(line) > 1 {
+            true => None,
+            false => Some(expanded[0].clone())
+        };
+    }
+
+    let label = LABEL_REGEX.find(line).map_or(String::new(), |val| val.as_str().to_owned() + " ");
+    let values:Vec<String> = expand_runs(&expanded).take(keep).map(|word| word.split_once(".fill ").map_or(String::new(), |(_, value)| value.to_owned())).collect();
+    Some(format!("{}.space {} [{}]", label, keep, values.join(", ")))
+}
+
+
+/// Replaces each `.org ADDR` with the zeros padding its section out to that address, counting from the start of the section it is written in, as a single `.run`
+/// however many words it takes, or with an empty line if the section already reaches the address. Every line keeps its index for error messages, so this is
+/// given the lines before empty lines are removed, and since the source is never expanded into `.run` lines before this, a `.run` in the lines it gives is
+/// always the padding of an `.org`.
+///
+/// An `.org` may only move back to an address the words before it already reach if the region it starts writes nothing, as otherwise the same words would be
+/// written twice. That is an error unless `allow_overlap` is set, in which case the overlap is given alongside the lines, and since the sections are still written in
+/// order, the words before the `.org` from its address onwards are dropped so that its region takes their place, even past the end of its own words.
+///
+/// Returns an `AssemblyError` if two regions write the same words when that is not allowed, a label would be dropped along with the words it is defined on, a
+/// jump to a label would be cut short, or the padding takes a section past the end of memory.
+pub fn substitute_org(lines:&[String], allow_overlap:bool) -> Result<(Vec<String>, Vec<Overlap>), Box<dyn Error>> {
+    let mut new_lines:Vec<String> = Vec::with_capacity(lines.len());
+    let mut placed:Vec<(Section, usize)> = Vec::with_capacity(lines.len());
+    let mut overlaps:Vec<Overlap> = Vec::new();
+    let start_region = |name:&str| WrittenRegion { start: 0, end: 0, name: format!("the region from the start of the {} section", name), label: None };
+    let (mut code, mut data) = ((0, vec![start_region("code")]), (0, vec![start_region("data")]));
+    let mut section = Section::Code;
+    for (index, line) in lines.iter().enumerate() {
+        section = get_section_switch(line).unwrap_or(section);
+        let (address, regions) = match section {
+            Section::Code => (&mut code.0, &mut code.1),
+            Section::Data => (&mut data.0, &mut data.1)
+        };
+
+        let target = match ORG_REGEX.captures(line) {
+            Some(caps) => convert_to_i64(&caps[1])? as usize,
+            None => {
+                let region = regions.last_mut().unwrap();
+                if get_mnemonic(line) != ".at" && region.label.is_none() {
+                    region.label = LABEL_REGEX.find(line).map(|val| val.as_str().trim_end_matches(':').to_owned());
+                }
+
+                region.end += get_word_count(line);
+                placed.push((section, *address));
+                new_lines.push(line.to_owned());
+                *address += get_word_count(line);
+                continue;
+            }
+        };
+
+        let (size, label) = measure_region(&lines[index + 1..], section);
+        let name = format!(".org 0x{:04X} on line {}", target, index + 1);
+        if target < *address && size > 0 {
+            let end = (target + size).min(*address);
+            let earlier = regions.iter().find(|region| region.start < end && region.end > target).map_or(String::new(), WrittenRegion::describe);
+            let later = WrittenRegion { start: target, end: target, name: format!("the region from {}", name), label }.describe();
+            let overlap = Overlap { section, start: target, end: end - 1, earlier, later, line: index + 1 };
+            if !allow_overlap {
+                return Err(Box::new(AssemblyError(overlap.message())));
+            }
+
+            // the lines of a section are placed in order, so only those at the end of it can reach past the address
+            for prev_index in (0..new_lines.len()).rev() {
+                let (prev_section, prev_address) = placed[prev_index];
+                let num_words = get_word_count(&new_lines[prev_index]);
+                if prev_section != section || num_words == 0 {
+                    continue;
+                } else if prev_address + num_words <= target {
+                    break;
+                } else if prev_address < target {
+                    new_lines[prev_index] = match truncate_line(&new_lines[prev_index], target - prev_address) {
+                        Some(val) => val,
+                        None => return Err(Box::new(AssemblyError(format!("The jump to a label on line {} is written over by the .org on line {}, but cannot be cut \
+                            short as it is only expanded once the sections are laid out", prev_index + 1, index + 1))))
+                    };
+                } else if let Some(label) = LABEL_REGEX.find(&new_lines[prev_index]) {
+                    return Err(Box::new(AssemblyError(format!("The words of {} on line {} are written over by the .org on line {}, so the label would no longer point \
+                        at them", label.as_str().trim_end_matches(':'), prev_index + 1, index + 1))));
+                } else {
+                    new_lines[prev_index] = String::new();
+                }
+            }
+
+            regions.iter_mut().for_each(|region| region.end = region.end.min(target));
+            regions.retain(|region| region.start < region.end);
+            overlaps.push(overlap);
+        } else if target > *address {
+            regions.push(WrittenRegion { start: *address, end: target, name: format!("the padding before {}", name), label: None });
+        }
+
+        placed.push((section, *address));
+        new_lines.push(match target.checked_sub(*address) {
+            Some(count) if count > 0 => format!(".run {} 0x0000", count),
+            _ => String::new()
+        });
+
+        if target >= *address || size > 0 {
+            *address = target;
+            regions.push(WrittenRegion { start: target, end: target, name: format!("the region from {}", name), label: None });
+        }
+    }
+
+    if let Some(size) = [code.0, data.0].into_iter().find(|size| *size > 0x10000) {
+        return Err(Box::new(AssemblyError(format!("The {} words of a section padded with .org do not fit in memory", size))));
+    }
+
+    Ok((new_lines, overlaps))
+}
+
+
+/// Replaces each `.buildinfo` with a `.fill` of the given build identifier, keeping any label defined on it, so the program holds the same word for every build
+/// given the same identifier.
+pub fn substitute_build_info(lines:&[String], build_id:u16) -> Vec<String> {
+    lines.iter().map(|line| match get_mnemonic(line) {
+        ".buildinfo" => line.replacen(".buildinfo", &format!(".fill 0x{:04X}", build_id), 1),
+        _ => line.to_owned()
+    }).collect()
+}
+
+
+/// Removes every `.assert_size` directive from the program, as they do not take up any space, and returns the remaining lines along with the assertions made, each
+/// tagged with the section it was written in.
+pub fn take_size_assertions(lines:&[String]) -> (Vec<String>, Vec<SizeAssertion>) {
+    let mut new_lines:Vec<String> = Vec::new();
+    let mut assertions:Vec<SizeAssertion> = Vec::new();
+    let mut section = Section::Code;
+    for line in lines {
+        section = get_section_switch(line).unwrap_or(section);
+        match ASSERT_SIZE_REGEX.captures(line) {
+            Some(caps) => assertions.push(SizeAssertion {
+                section,
+                comparison: caps[1].to_owned(),
+                limit: convert_to_i64(caps[2].trim()).unwrap_or(0),
+                line: line.to_owned()
+            }),
+
+            None => new_lines.push(line.to_owned())
+        };
+    }
+
+    (new_lines, assertions)
+}
+
+
+/// Checks every `.assert_size` against the final number of words in the code and data sections.
+///
+/// Returns an `AssemblyError` naming the assertion and the actual size if any assertion does not hold.
+pub fn check_size_assertions(assertions:&[SizeAssertion], code_size:usize, data_size:usize) -> Result<(), Box<dyn Error>> {
+    for assertion in assertions {
+        let size = match assertion.section {
+            Section::Code => code_size as i64,
+            Section::Data => data_size as i64
+        };
+
+        let holds = match assertion.comparison.as_str() {
+            "<=" => size <= assertion.limit,
+            "<" => size < assertion.limit,
+            _ => size == assertion.limit
+        };
+
+        if !holds {
+            return Err(Box::new(AssemblyError(format!("Assertion {} failed as the section is {} words long", assertion.line, size))));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Collects the constants defined with `.equ NAME, expression` and empties the lines defining them, so every line keeps its index for error messages, then evaluates any expression used as an immediate
+/// operand, such as `(BUF_SIZE*2)+1`, replacing it with its value so the line can be validated as usual. Each constant may use the constants defined before it.
+///
+/// Expressions containing labels cannot be evaluated until the label table has been generated, so only the constants in them are replaced and they are otherwise left
+/// for `substitute_labels`. The same applies to `__ADDR__` and `__END__`, which are rewritten as the labels `@__ADDR__` and `@__END__`. Operands which are already a single literal are left as they were written.
+/// The limit of a `.assert_size` and the address of a `.at` or `.org` are evaluated in the same way,
+/// as is a literal written with a radix suffix such as `0FFh`, which becomes its decimal value.
+///
+/// Returns an `AssemblyError` if a constant is defined twice, is named with a reserved word, or refers to a label, if the address of a `.at` refers to a label, or if
+/// an expression cannot be evaluated.
+pub fn substitute_constants(lines:&[String], isa:&IsaSpec) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut constants:HashMap<String, i64> = HashMap::new();
+    for caps in lines.iter().filter_map(|line| EQU_REGEX.captures(line)) {
+        if constants.contains_key(&caps[1]) {
+            return Err(Box::new(AssemblyError(format!("Found duplicate constant {}", &caps[1]))));
+        } else if is_reserved_word(&caps[1], isa) {
+            return Err(Box::new(AssemblyError(format!("Cannot define constant {} as it is a reserved word", &caps[1]))));
+        } else if caps[2].contains('@') {
+            return Err(Box::new(AssemblyError(format!("Constant {} cannot refer to a label as its value is needed before labels are known", &caps[1]))));
+        }
+
+        let value = evaluate_expression(&caps[2], &constants, &HashMap::new())?.value;
+        debug!("Defined constant {} as {}", &caps[1], value);
+        constants.insert(caps[1].to_owned(), value);
+    }
+
+    let mut new_lines:Vec<String> = Vec::new();
+    for line in lines {
+        if EQU_REGEX.is_match(line) {
+            new_lines.push(String::new());
+            continue;
+        } else if let Some(caps) = ASSERT_SIZE_REGEX.captures(line) {
+            let limit = evaluate_expression(&caps[2], &constants, &HashMap::new())?.value;
+            new_lines.push(format!(".assert_size {} {}", &caps[1], limit));
+            continue;
+        } else if let Some(caps) = AT_REGEX.captures(line) {
+            if caps[2].contains('@') {
+                return Err(Box::new(AssemblyError(format!("The address of {} cannot refer to a label as it must be fixed: {}", &caps[1], line))));
+            } else if LITERAL_REGEX.is_match(caps[2].trim()) {
+                new_lines.push(line.to_owned());
+            } else {
+                let address = evaluate_expression(&caps[2], &constants, &HashMap::new())?.value;
+                new_lines.push(format!("{}: .at {}", &caps[1], address));
+            }
+
+            continue;
+        }
+
+        let caps = match OPERANDS_REGEX.captures(line) {
+            Some(val) => val,
+            None => {
+                new_lines.push(line.to_owned());
+                continue;
+            }
+        };
+
+        // the count of a .space or .pattern is separated from its array by blanks rather than a comma, so the count and each value of the array are substituted
+        // separately
+        let mut changed = false;
+        let is_array = matches!(&caps[2], ".space" | ".pattern");
+        let (operands, suffix) = match (caps[3].find('['), caps[3].rfind(']')) {
+            (Some(open), Some(close)) if is_array && open < close => {
+                let mut elems:Vec<String> = Vec::new();
+                for elem in split_operands(&caps[3][open + 1..close]) {
+                    let elem = elem.trim();
+                    if elem.is_empty() || LITERAL_REGEX.is_match(elem) {
+                        elems.push(elem.to_owned());
+                        continue;
+                    }
+
+                    changed = true;
+                    if elem.contains('@') {
+                        elems.push(substitute_constant_names(elem, &constants)?);
+                        continue;
+                    }
+
+                    match evaluate_expression(elem, &constants, &HashMap::new()) {
+                        Ok(val) => elems.push(val.value.to_string()),
+                        Err(err) => return Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, line))))
+                    };
+                }
+
+                (&caps[3][..open], format!(" [{}]{}", elems.join(", "), &caps[3][close + 1..]))
+            },
+
+            _ => (&caps[3], String::new())
+        };
+
+        let mut new_operands:Vec<String> = Vec::new();
+        for operand in split_operands(operands) {
+            let operand = PREDEFINED_LABEL_REGEX.replace_all(operand.trim(), "$1@$2");
+            let operand = operand.as_ref();
+            if operand.starts_with('$') || LITERAL_REGEX.is_match(operand) {
+                new_operands.push(operand.to_owned());
+                continue;
+            }
+
+            changed = true;
+            if operand.contains('@') {
+                if is_array {
+                    return Err(Box::new(AssemblyError(format!("The size of a {} cannot depend on a label in instruction {}", &caps[2], line))));
+                }
+
+                new_operands.push(substitute_constant_names(operand, &constants)?);
+            } else {
+                match evaluate_expression(operand, &constants, &HashMap::new()) {
+                    Ok(val) => new_operands.push(val.value.to_string()),
+                    Err(err) => return Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, line))))
+                };
+            }
+        }
+
+        if !changed {
+            new_lines.push(line.to_owned());
+            continue;
+        }
+
+        let label = caps.get(1).map_or("".to_owned(), |val| val.as_str().to_owned() + " ");
+        new_lines.push(format!("{}{} {}{}", label, &caps[2], new_operands.join(", "), suffix));
+    }
+
+    Ok(new_lines)
+}
+
+
+/// Replaces `__LINE__` with the number of the source line it is on, counting from 1, and `__FILE__` with the name of the source file as a string literal, such that
+/// `.text __FILE__` stores the file name. Neither is replaced inside an existing string literal.
+///
+/// WARNING: must be called before empty lines are removed so that the line numbers match the source file.
+pub fn substitute_source_symbols(lines:&[String], filename:&str) -> Vec<String> {
+    lines.iter().enumerate().map(|(line_num, line)| {
+        line.split('"').enumerate().map(|(index, part)| {
+            if index % 2 == 1 {
+                return part.to_owned();
+            }
+
+            part.replace("__LINE__", &(line_num + 1).to_string()).replace("__FILE__", &format!("\"{}\"", filename))
+        }).collect::<Vec<String>>().join("\"")
+    }).collect()
+}
+
+
+/// Collects the register aliases defined with `.regalias NAME, $rN` and empties the lines defining them, so every line keeps its index for error messages, then
+/// replaces each use of an alias such as `$counter` with the register it names, so that it is validated and encoded exactly as that register. An alias can be
+/// used anywhere in the program, including before the line defining it, but is left as it is inside a string literal.
+///
+/// Returns an `AssemblyError` if an alias is defined twice, has the name of a register of `isa`, or names anything other than one of its registers.
+pub fn substitute_register_aliases(lines:&[String], isa:&IsaSpec) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut aliases:HashMap<String, String> = HashMap::new();
+    for caps in lines.iter().filter_map(|line| REGALIAS_REGEX.captures(line)) {
+        let alias = format!("${}", &caps[1]);
+        if isa.get_register(&alias).is_some() {
+            return Err(Box::new(AssemblyError(format!("Cannot define register alias {} as it is the name of a register", alias))));
+        } else if aliases.contains_key(&alias) {
+            return Err(Box::new(AssemblyError(format!("Found duplicate register alias {}", alias))));
+        } else if isa.get_register(&caps[2]).is_none() {
+            return Err(Box::new(AssemblyError(format!("Register alias {} names {}, which is not a register", alias, &caps[2]))));
+        }
+
+        debug!("Defined register alias {} as {}", alias, &caps[2]);
+        aliases.insert(alias, caps[2].to_owned());
+    }
+
+    Ok(lines.iter().map(|line| {
+        if REGALIAS_REGEX.is_match(line) {
+            return String::new();
+        }
+
+        // every other part of the line split on its quotes is inside a string literal, as in `substitute_source_symbols`
+        line.split('"').enumerate().map(|(index, part)| {
+            if index % 2 == 1 || aliases.is_empty() {
+                return part.to_owned();
+            }
+
+            REGISTER_REGEX.replace_all(part, |caps:&regex::Captures| aliases.get(&caps[0]).map_or_else(|| caps[0].to_owned(), |reg| reg.to_owned())).into_owned()
+        }).collect::<Vec<String>>().join("\"")
+    }).collect())
+}
+
+
+/// Replaces the names of constants in an expression which also contains labels with their values, and removes any blanks so that the expression is a single token
+/// for `substitute_labels` to evaluate.
+pub fn substitute_constant_names(expr:&str, constants:&HashMap<String, i64>) -> Result<String, Box<dyn Error>> {
+    let mut undefined = None;
+    let result = CONSTANT_NAME_REGEX.replace_all(expr, |caps:&regex::Captures| {
+        match constants.get(&caps[2]) {
+            Some(val) => format!("{}({})", &caps[1], val),
+            None => {
+                undefined = Some(caps[2].to_owned());
+                caps[0].to_owned()
+            }
+        }
+    });
+
+    if let Some(name) = undefined {
+        return Err(Box::new(AssemblyError(format!("Undefined constant {} in expression {}", name, expr))));
+    }
+
+    Ok(result.split_whitespace().collect())
+}
+
+
+/// Takes an instruction and the valid number of bits the operand can have as arguments. Checks the instruction for any immediates in number, character, and label form and
+/// returns them if there are any, or an `AssemblyError` if not. 
+pub fn get_imm_for_pseudoinstr(instr:&String, bits:u32) -> Result<String, Box<dyn Error>> {
+    let mut imm = None;
+    let mut label = None;
+    match get_imm_from_instr(instr, bits, false, false, true).unwrap() {
+        Some(val) => { imm = Some(val) },
+        None => {
+            label = Some (match LABEL_ARG_REGEX.find(instr) {
+                Some(val) => val.as_str(),
+                None => { return Err(Box::new(AssemblyError(format!("Could not find valid immediate for instruction {}", instr)))) }
+            });
+        }
+    };
+
+    match imm {
+        Some(val) => {
+            Ok(val.to_string())
+        },
+
+        None => {
+            Ok(label.unwrap_or_else(|| panic!("Could not find valid immediate for instruction {}", instr)).to_owned())
+        }
+    }
+}
+
+
+/// Expands a `MOVI` of the given immediate into an `ADDI` loading its bottom 6 bits followed by a `LUI` loading the top 10. An immediate which is an expression with
+/// a label is given to both, to be masked when the labels are substituted.
+fn expand_movi(label:&str, register:&str, imm:&str) -> [String; 2] {
+    match convert_to_i64(imm) {
+        Ok(val) => {
+            let lower_imm = val as u16 & 0x003F;
+            let upper_imm = (val as u16 & 0xFFC0) >> 6;
+            [format!("{}ADDI {}, $zero, {}", label, register, lower_imm), format!("LUI {}, {}", register, upper_imm)]
+        },
+
+        // the LUI is one word after the start of the MOVI, so __ADDR__ must be adjusted to still give the address of the MOVI
+        Err(_) => [format!("{}ADDI {}, $zero, {}", label, register, imm), format!("LUI {}, {}", register, imm.replace("@__ADDR__", "(@__ADDR__-1)"))]
+    }
+}
+
+
+/// Expands each jump to a label, such as `JAL $r5, @handler`, into a `MOVI` loading the address of the label into the scratch register, followed by the jump
+/// through that register. Any label defined on the jump is kept on the `MOVI`. As the jump becomes more than one line, this is given the lines once empty lines
+/// are removed, just before `substitute_pseudoinstrs` expands the `MOVI`.
+///
+/// Returns an `AssemblyError` if the scratch register is not a register of `isa`, or the program jumps to a label but there is no scratch register.
+pub fn substitute_label_jumps(lines:&[String], scratch:Option<&str>, isa:&IsaSpec) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(register) = scratch.filter(|register| isa.get_register(register).is_none()) {
+        return Err(Box::new(AssemblyError(format!("The scratch register {} is not a register of the instruction set", register))));
+    }
+
+    let mut new_lines:Vec<String> = Vec::with_capacity(lines.len());
+    for line in lines {
+        // only a line with a label in it can jump to one, so the others are not parsed
+        let parsed = match isa.get_kind(get_mnemonic(line)) == Some(LineKind::Jal) && line.contains('@') {
+            true => Some(parse_line(line, isa)?),
+            false => None
+        };
+
+        let target = match parsed.as_ref().and_then(|parsed| parsed.operands.last()) {
+            Some(Token::Expr(val)) => *val,
+            _ => {
+                new_lines.push(line.to_owned());
+                continue;
+            }
+        };
+
+        let scratch = match scratch {
+            Some(val) => val,
+            None => return Err(Box::new(AssemblyError(format!("Jumping to {} needs a scratch register to hold its address in instruction {}", target, line))))
+        };
+
+        let label = LABEL_REGEX.find(line).map_or(String::new(), |val| val.as_str().to_owned() + " ");
+        let register = REGISTER_REGEX.find(line).unwrap().as_str();
+        debug!("Expanded the jump to {} through {} in {}", target, scratch, line);
+        new_lines.push(format!("{}MOVI {}, {}", label, scratch, target));
+        new_lines.push(format!("{} {}, {}", get_mnemonic(line), register, scratch));
+    }
+
+    Ok(new_lines)
+}
+
+
+/// The fewest words holding the same value at the end of a `.space`, in the padding of a `.text`, or in a `.pattern` of a single value which are kept as a single
+/// `.run` line rather than a `.fill` each.
+const MIN_RUN_LENGTH:usize = 16;
+
+
+/// Adds `count` words holding `value`, with `label` before the first of them, as a single `.run` if there are at least `MIN_RUN_LENGTH` of them, or as a `.fill`
+/// each otherwise.
+fn push_repeated_fill(new_vec:&mut Vec<String>, label:&str, value:u16, count:usize) {
+    if count >= MIN_RUN_LENGTH {
+        new_vec.push(format!("{}.run {} 0x{:04X}", label, count, value));
+        return;
+    }
+
+    for elem_index in 0..count {
+        match elem_index {
+            0 => new_vec.push(format!("{}.fill 0x{:04X}", label, value)),
+            _ => new_vec.push(format!(".fill 0x{:04X}", value))
+        };
+    }
+}
+
+
+/// Gives the lines of a section with each `.run` written out as the `.fill` for each of its words, with its label on the first, so that there is one line per word
+/// of the section, exactly as if `substitute_pseudoinstrs` had not used a run. Each line of a run is only built as it is taken, so this is what the listings and
+/// written sources use, while every stage before them handles the run as a single line.
+pub fn expand_runs(lines:&[String]) -> impl Iterator<Item = Cow<'_, str>> {
+    lines.iter().flat_map(|line| {
+        let run = parse_run(line);
+        let count = run.map_or(1, |(_, count, _)| count);
+        (0..count).map(move |elem_index| match run {
+            Some((label, _, value)) if elem_index == 0 => Cow::Owned(format!("{}.fill 0x{:04X}", label, value)),
+            Some((_, _, value)) => Cow::Owned(format!(".fill 0x{:04X}", value)),
+            None => Cow::Borrowed(line.as_str())
+        })
+    })
+}
+
+
+/// Takes a vector of instructions and examines it for any pseudo-instructions. If it finds any, then it replaces it with 1-or-more regular instructions in its place,
+/// building the new vector in a single pass so that a large `.space` or `.text` takes time in proportion to its size. The zeros which fill out a `.space` and the
+/// spaces which pad a `.text` are given as a single `.run` line if there are many of them, which every later stage counts as that many words and `expand_runs`
+/// writes out in full. The new vector is returned.
+pub fn substitute_pseudoinstrs(lines:&[String]) -> Vec<String> {
+    let mut new_vec:Vec<String> = Vec::with_capacity(lines.len());
+    for instr in lines {
+        let label = match LABEL_REGEX.find(instr) {
+            Some(val) => val.as_str().to_owned() + " ",
+            None => "".to_owned()
+        };
+
+        let mnemonic = get_mnemonic(instr);
+        let start = new_vec.len();
+        if mnemonic == "NOP" {
+            new_vec.push(format!("{}ADD $zero, $zero, $zero", label));
+        } else if mnemonic == "LLI" {
+            let imm = get_imm_for_pseudoinstr(instr, 6).unwrap();
+            let register = REGISTER_REGEX.find(instr).unwrap().as_str();
+            new_vec.push(format!("{0}ADDI {1}, {1}, {2}", label, register, imm));
+        } else if mnemonic == "MOVI" {
+            let register = REGISTER_REGEX.find(instr).unwrap().as_str();
+            let imm = get_imm_for_pseudoinstr(instr, 16).unwrap();
+            new_vec.extend(expand_movi(&label, register, &imm));
+        } else if mnemonic == "MASK" {
+            let register = REGISTER_REGEX.find(instr).unwrap().as_str();
+            let bit = convert_to_i64(&get_imm_for_pseudoinstr(instr, 4).unwrap()).unwrap();
+            new_vec.extend(expand_movi(&label, register, &(1_i64 << bit).to_string()));
+        } else if mnemonic == ".space" {
+            let (total_elems, defined_elems) = parse_space(instr).unwrap();
+            let num_defined = defined_elems.len().min(total_elems);
+            new_vec.reserve(num_defined + 1);
+            for (elem_index, elem) in defined_elems.iter().take(num_defined).enumerate() {
+                let mut value_to_insert = match elem {
+                    SpaceValue::Value(val) => format!(".fill 0x{:04X}", *val as u16),
+                    SpaceValue::Expr(expr) => format!(".fill {}", expr) // resolved with the other labels once the label table is known
+                };
+
+                if elem_index == 0 {
+                    value_to_insert = label.to_owned() + &value_to_insert;
+                }
+
+                new_vec.push(value_to_insert);
+            }
+
+            let label = if num_defined == 0 { label.as_str() } else { "" };
+            push_repeated_fill(&mut new_vec, label, 0, total_elems - num_defined);
+        } else if mnemonic == ".pattern" {
+            let (total_elems, pattern) = parse_pattern(instr).unwrap();
+            if let [SpaceValue::Value(val)] = pattern.as_slice() {
+                push_repeated_fill(&mut new_vec, &label, *val as u16, total_elems);
+            } else {
+                new_vec.reserve(total_elems);
+                for (elem_index, elem) in pattern.iter().cycle().take(total_elems).enumerate() {
+                    let value_to_insert = match elem {
+                        SpaceValue::Value(val) => format!(".fill 0x{:04X}", *val as u16),
+                        SpaceValue::Expr(expr) => format!(".fill {}", expr)
+                    };
+
+                    new_vec.push(if elem_index == 0 { label.to_owned() + &value_to_insert } else { value_to_insert });
+                }
+            }
+        } else if mnemonic == ".text" {
+            let (text, size, null_terminated) = parse_text(instr).unwrap();
+            let text_ascii = string_to_decimals(&text).unwrap();
+            let num_chars = text_ascii.len();
+
+            new_vec.reserve(num_chars + 2);
+            for (elem_index, item) in text_ascii.into_iter().enumerate() {
+                let mut char_str = format!(".fill 0x{:04X}", item);
+                if elem_index == 0 {
+                    char_str = label.to_owned() + &char_str;
+                }
+
+                new_vec.push(char_str);
+            }
+
+            let label = if num_chars == 0 { label.as_str() } else { "" };
+            push_repeated_fill(&mut new_vec, label, b' ' as u16, size.saturating_sub(num_chars));
+            if null_terminated {
+                new_vec.push(".fill 0x0000".to_owned());
+            }
+        } else {
+            new_vec.push(instr.to_owned());
+            continue;
+        }
+
+        debug!("Expanded {} into {}", instr, new_vec[start..].join("; "));
+    }
+
+    new_vec
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use crate::{ assemble_str, into_assembly_error };
+    use crate::parser::{ get_line_vector, get_word_count, validate_assembly_lines };
+    use crate::labels::{ generate_label_table, substitute_labels };
+    use std::cell::RefCell;
+    use std::fs;
+    use log::{ Level, LevelFilter, Log, Metadata, Record };
+
+
+    /// Keeps the messages logged by each thread separately, so that the tests running alongside one another do not see each other's messages.
+    struct CapturingLogger;
+
+    thread_local! {
+        static CAPTURED:RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _:&Metadata) -> bool {
+            true
+        }
+
+
+        fn log(&self, record:&Record) {
+            CAPTURED.with(|captured| captured.borrow_mut().push((record.level(), record.args().to_string())));
+        }
+
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER:CapturingLogger = CapturingLogger;
+
+
+    /// Runs `stage` and gives every message it logged on this thread.
+    fn capture_logs(stage:impl FnOnce()) -> Vec<(Level, String)> {
+        // the logger can only be installed once, so it may already have been by another test
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+        CAPTURED.with(|captured| captured.borrow_mut().clear());
+        stage();
+        CAPTURED.with(|captured| captured.take())
+    }
+
+
+    /// The substitution as it was first written, inserting the expanded lines into a copy of the vector in place of each pseudo-instruction, which the single pass
+    /// must give exactly the same lines as.
+    #[allow(clippy::explicit_counter_loop)]
+    fn substitute_pseudoinstrs_by_insertion(lines:&[String]) -> Vec<String> {
+        let mut new_vec = lines.to_vec();
+        let mut index:usize = 0;
+        while index < new_vec.len() {
+            let instr = new_vec[index].to_owned();
+            let label = match LABEL_REGEX.find(&instr) {
+                Some(val) => val.as_str().to_owned() + " ",
+                None => "".to_owned()
+            };
+
+            let mnemonic = get_mnemonic(&instr);
+            if mnemonic == "NOP" {
+                new_vec.remove(index);
+                new_vec.insert(index, format!("{}ADD $zero, $zero, $zero", label));
+            } else if mnemonic == "LLI" {
+                let imm = get_imm_for_pseudoinstr(&instr, 6).unwrap();
+                let register = REGISTER_REGEX.find(&instr).unwrap().as_str();
+
+                new_vec.remove(index);
+                new_vec.insert(index, format!("{0}ADDI {1}, {1}, {2}", label, register, imm));
+            } else if mnemonic == "MOVI" {
+                new_vec.remove(index);
+
+                let register = REGISTER_REGEX.find(&instr).unwrap().as_str();
+                let imm = get_imm_for_pseudoinstr(&instr, 16).unwrap();
+                match convert_to_i64(&imm) {
+                    Ok(val) => {
+                        let lower_imm = val as u16 & 0x003F;
+                        let upper_imm = (val as u16 & 0xFFC0) >> 6;
+
+                        new_vec.insert(index, format!("{}ADDI {}, $zero, {}", label, register, lower_imm));
+                        new_vec.insert(index + 1, format!("LUI {}, {}", register, upper_imm));
+                    },
+
+                    Err(_) => {
+                        new_vec.insert(index, format!("{}ADDI {}, $zero, {}", label, register, imm));
+
+                        // the LUI is one word after the start of the MOVI, so __ADDR__ must be adjusted to still give the address of the MOVI
+                        new_vec.insert(index + 1, format!("LUI {}, {}", register, imm.replace("@__ADDR__", "(@__ADDR__-1)")));
+                    }
+                };
+
+                index += 1;
+            } else if mnemonic == ".space" {
+                new_vec.remove(index);
+
+                let (total_elems, defined_elems) = parse_space(&instr).unwrap();
+                if total_elems == 0 {
+                    continue;
+                }
+
+                for elem_index in 0..total_elems {
+                    let mut value_to_insert = match defined_elems.get(elem_index) {
+                        Some(SpaceValue::Value(val)) => format!(".fill 0x{:04X}", *val as u16),
+                        Some(SpaceValue::Expr(expr)) => format!(".fill {}", expr), // resolved with the other labels once the label table is known
+                        None => format!(".fill 0x{:04X}", 0)
+                    };
+
+                    if elem_index == 0 {
+                        value_to_insert = label.to_owned() + &value_to_insert;
+                    }
+
+                    new_vec.insert(index + elem_index, value_to_insert);
+                }
+
+                index += total_elems - 1;
+            } else if mnemonic == ".text" {
+                new_vec.remove(index);
+
+                let (text, size, null_terminated) = parse_text(&instr).unwrap();
+                let mut text_ascii = string_to_decimals(&text).unwrap();
+                text_ascii.resize(size, b' ');
+
+                let mut elem_index = 0;
+                for item in text_ascii {
+                    let mut char_str = format!(".fill 0x{:04X}", item);
+                    if elem_index == 0 {
+                        char_str = label.to_owned() + &char_str;
+                    }
+
+                    new_vec.insert(elem_index + index, char_str);
+                    elem_index += 1;
+                }
+
+                if null_terminated {
+                    new_vec.insert(elem_index + index, ".fill 0x0000".to_owned());
+                }
+            }
+
+            index += 1;
+        }
+
+        new_vec
+    }
+
+
+    fn expand_all_runs(lines:&[String]) -> Vec<String> {
+        expand_runs(lines).map(Cow::into_owned).collect()
+    }
+
+
+    #[test]
+    fn test_space_run() {
+        let lines:Vec<String> = ["buffer: .space 40000 []", "table: .space 20 [7]", "name: .text \"ab\" pad 18", ".space 15 []"].iter().map(|line| line.to_string()).collect();
+        let expanded = substitute_pseudoinstrs(&lines);
+        assert_eq!(expanded[0], "buffer: .run 40000 0x0000");
+        assert_eq!(expanded[1], "table: .fill 0x0007");
+        assert_eq!(expanded[2], ".run 19 0x0000");
+        assert_eq!(expanded[3], "name: .fill 0x0061");
+        assert_eq!(expanded[5], ".run 16 0x0020");
+        assert_eq!(expanded[6], ".fill 0x0000");
+        assert_eq!(expanded.len(), 22);
+
+        let words:Vec<String> = expand_all_runs(&expanded);
+        assert_eq!(words.len(), 40000 + 20 + 19 + 15);
+        assert_eq!(words[0], "buffer: .fill 0x0000");
+        assert_eq!(words[39999], ".fill 0x0000");
+        assert_eq!(words[40000], "table: .fill 0x0007");
+        assert_eq!(words[40020], "name: .fill 0x0061");
+        assert_eq!(words[40022], ".fill 0x0020");
+        assert_eq!(words.iter().map(|line| get_word_count(line)).sum::<usize>(), expanded.iter().map(|line| get_word_count(line)).sum::<usize>());
+    }
+
+
+    /// Expands the lines of each test file which can be expanded with both the single pass and the original substitution, checking that they give the same lines.
+    #[test]
+    fn test_pseudoinstrs_match_insertion() {
+        let mut num_files = 0;
+        for entry in fs::read_dir("test_files").unwrap() {
+            let lines = match get_line_vector(entry.unwrap().path().to_str().unwrap(), true) {
+                Ok(lines) => lines,
+                Err(_) => continue
+            };
+
+            let mut lines = match substitute_constants(&lines, &DEFAULT_ISA_SPEC) {
+                Ok(lines) => lines,
+                Err(_) => continue
+            };
+
+            lines.retain(|line| !line.is_empty());
+            if validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).is_ok() {
+                assert_eq!(expand_all_runs(&substitute_pseudoinstrs(&lines)), substitute_pseudoinstrs_by_insertion(&lines));
+                num_files += 1;
+            }
+        }
+
+        let edge_cases:Vec<String> = ["empty: .space 0 []", "str: .text \"a\"", "pad: .text \"ab\" pad 4", "big: .space 5 [1, @big]", "MOVI $r0, @__ADDR__", "x: LLI $r1, 3",
+            "zeros: .space 16 []", "tail: .space 40 [1, @tail]", "short: .space 17 [1, 2]", "wide: .text \"a\" pad 20", "name: .text \"ab\" pad 30"]
+            .iter().map(|line| line.to_string()).collect();
+        assert_eq!(expand_all_runs(&substitute_pseudoinstrs(&edge_cases)), substitute_pseudoinstrs_by_insertion(&edge_cases));
+        assert!(num_files > 5);
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_substitute_pseudoinstrs_large_input() {
+        let values:Vec<String> = (0..50_000).map(|val| (val % 100).to_string()).collect();
+        let lines = vec!["start: NOP".to_owned(), format!("table: .space 50000 [{}]", values.join(", ")), "MOVI $r0, @table".to_owned()];
+        let expanded = substitute_pseudoinstrs(&lines);
+        assert_eq!(substitute_pseudoinstrs_by_insertion(&lines), expanded);
+        assert_eq!(expanded.len(), 50_003);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_constant_named_reserved_word() {
+        let lines = vec![".equ Nand, 4".to_owned()];
+        substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_space_sub_relaxed() {
+        let lines = vec!["start: .space 3 [ 5,6, ]".to_owned(), ".space 0 []".to_owned(), "NOP".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        let lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines, vec!["start: .fill 0x0005", ".fill 0x0006", ".fill 0x0000", "ADD $zero, $zero, $zero"]);
+    }
+
+
+    #[test]
+    fn test_space_sub_negative() {
+        let lines = vec![".space 2 [-1, -32768]".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(substitute_pseudoinstrs(&lines), vec![".fill 0xFFFF", ".fill 0x8000"]);
+    }
+
+
+    #[test]
+    fn test_valid_pseudoinstr_substitutions() {
+        let mut lines = get_line_vector("test_files/test_valid_pseudo_subs.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        assert_eq!(lines[0], "ADDI $r0, $zero, 20");
+        assert_eq!(lines[1], "ADDI $r1, $r1, 20");
+        assert_eq!(lines[2], "ADD $zero, $zero, $zero");
+        assert_eq!(lines[3], "ADDI $r2, $zero, 20");
+        assert_eq!(lines[4], "labelA: ADDI $r2, $r2, 50");
+        assert_eq!(lines[5], "labelB: ADD $zero, $zero, $zero");
+        assert_eq!(lines[6], "labelC: ADDI $r1, $zero, 48");
+        assert_eq!(lines[7], "LUI $r1, 992");
+        assert_eq!(lines.len(), 8);
+    }
+
+
+    #[test]
+    fn test_mask_sub() {
+        let lines:Vec<String> = ["MASK $r0, 5", "bit: MASK $r1, 15", "MASK $r2, 0"].iter().map(|line| line.to_string()).collect();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(get_word_count(&lines[0]), 2);
+
+        let lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines, vec!["ADDI $r0, $zero, 32", "LUI $r0, 0", "bit: ADDI $r1, $zero, 0", "LUI $r1, 512", "ADDI $r2, $zero, 1", "LUI $r2, 0"]);
+        assert_eq!(assemble_str("MASK $r0, 5").unwrap().code, assemble_str("MOVI $r0, 0x0020").unwrap().code);
+        assert_eq!(assemble_str(".equ LED, 3\nMASK $r3, LED").unwrap().code, assemble_str("MOVI $r3, 8").unwrap().code);
+    }
+
+
+    #[test]
+    fn test_substitution_logging() {
+        let logs = capture_logs(|| {
+            let lines = substitute_pseudoinstrs(&["MOVI $r1, 300".to_owned(), "ADD $r1, $r1, $r1".to_owned()]);
+            assert_eq!(lines.len(), 3);
+        });
+
+        assert_eq!(logs, vec![(Level::Debug, "Expanded MOVI $r1, 300 into ADDI $r1, $zero, 44; LUI $r1, 4".to_owned())]);
+
+        let logs = capture_logs(|| { assemble_str("start: MOVI $r1, @start").unwrap(); });
+        assert!(logs.contains(&(Level::Debug, "Resolved @start to 0 in start: ADDI $r1, $zero, @start".to_owned())));
+        assert!(logs.contains(&(Level::Trace, "Classified start: MOVI $r1, @start as Load".to_owned())));
+        assert!(logs.iter().any(|(level, message)| *level == Level::Info && message == "Encoded 2 words of code and 0 of data"));
+    }
+
+
+    #[test]
+    fn test_pattern_sub() {
+        let lines:Vec<String> = ["buf: .pattern 6 [0xAA, 0x55]", ".pattern 3 [1, 2, 3, 4]", "ones: .pattern 20 [0xFFFF]", ".pattern 3 [@buf, -1]"].iter()
+            .map(|line| line.to_string()).collect();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(get_word_count(&lines[0]), 6);
+
+        let lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines[..6], ["buf: .fill 0x00AA", ".fill 0x0055", ".fill 0x00AA", ".fill 0x0055", ".fill 0x00AA", ".fill 0x0055"]);
+        assert_eq!(lines[6..9], [".fill 0x0001", ".fill 0x0002", ".fill 0x0003"]);
+        assert_eq!(lines[9], "ones: .run 20 0xFFFF");
+        assert_eq!(lines[10..], [".fill @buf", ".fill 0xFFFF", ".fill @buf"]);
+
+        assert_eq!(assemble_str(".equ N, 6\nbuf: .pattern N [0xAA, 0x55]\n").unwrap().code, vec![0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55]);
+        let code = assemble_str(".pattern 20 [7]\nend: .fill @end").unwrap().code;
+        assert_eq!((code.len(), code[19], code[20]), (21, 7, 20));
+    }
+
+
+    #[test]
+    fn test_invalid_mask() {
+        let err = validate_assembly_lines(&["MASK $r0, 16".to_owned()], &DEFAULT_ISA_SPEC).unwrap_err().to_string();
+        assert!(err.contains("outside the unsigned 4-bit range 0 to 15"));
+        assert!(validate_assembly_lines(&["MASK $r0, @start".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+        assert!(validate_assembly_lines(&["MASK $r0, -1".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_lli() {
+        let lines = vec!["LLI $r0, 86".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_pseudoinstr_names_in_labels() {
+        let lines = vec!["NOPE_handler: ADD $r0, $r1, $r2".to_owned(), "MOVI_LLI: NAND $r0, $r1, $r2".to_owned()];
+        assert_eq!(substitute_pseudoinstrs(&lines), lines);
+    }
+
+
+    #[test]
+    fn test_substitute_org() {
+        let lines:Vec<String> = ["MOVI $r1, 5", ".org 0x10", ".data", ".fill 1", ".org 1", ".code", "handler: NOP", ".org 0x11"].iter()
+            .map(|line| line.to_string()).collect();
+        let (new_lines, overlaps) = substitute_org(&lines, false).unwrap();
+        assert_eq!(new_lines, vec!["MOVI $r1, 5", ".run 14 0x0000", ".data", ".fill 1", "", ".code", "handler: NOP", ""]);
+        assert!(overlaps.is_empty());
+
+        // moving back is harmless if nothing is written there
+        let lines:Vec<String> = ["MOVI $r1, 5", ".org 2", "NOP", ".org 0x0002"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(substitute_org(&lines, false).unwrap(), (vec!["MOVI $r1, 5".to_owned(), String::new(), "NOP".to_owned(), String::new()], vec![]));
+
+        let lines:Vec<String> = [".org 0xFFFF", "MOVI $r1, 5"].iter().map(|line| line.to_string()).collect();
+        assert!(substitute_org(&lines, false).is_err());
+
+        let lines:Vec<String> = [".equ BASE, 0x20", ".org BASE + 2"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap()[1], ".org 34");
+    }
+
+
+    #[test]
+    fn test_substitute_org_overlap() {
+        let lines:Vec<String> = ["start: MOVI $r1, 5", "NOP", ".org 2", "handler: NOP", "NOP"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(into_assembly_error(substitute_org(&lines, false).unwrap_err()).0, "Word 0x0002 of the code section is written by both the region from the start of the code \
+            section, labelled start, and the region from .org 0x0002 on line 3, labelled handler");
+
+        let (new_lines, overlaps) = substitute_org(&lines, true).unwrap();
+        assert_eq!(new_lines, vec!["start: MOVI $r1, 5", "", "", "handler: NOP", "NOP"]);
+        assert_eq!(overlaps, vec![Overlap { section: Section::Code, start: 2, end: 2, earlier: "the region from the start of the code section, labelled start".to_owned(),
+            later: "the region from .org 0x0002 on line 3, labelled handler".to_owned(), line: 3 }]);
+
+        // the words reaching past the address are cut short, and the padding before an .org is written over like any other words
+        let lines:Vec<String> = ["MOVI $r1, 5", ".org 4", ".data", ".text \"abc\"", ".org 2", ".fill 7", ".code", ".org 1", "NOP"].iter().map(|line| line.to_string())
+            .collect();
+        let err = into_assembly_error(substitute_org(&lines, false).unwrap_err()).0;
+        assert_eq!(err, "Word 0x0002 of the data section is written by both the region from the start of the data section and the region from .org 0x0002 on line 5");
+
+        let (new_lines, overlaps) = substitute_org(&lines, true).unwrap();
+        assert_eq!(new_lines, vec!["ADDI $r1, $zero, 5", "", ".data", ".space 2 [0x0061, 0x0062]", "", ".fill 7", ".code", "", "NOP"]);
+        assert_eq!(overlaps.iter().map(|overlap| (overlap.section, overlap.start, overlap.end)).collect::<Vec<_>>(), vec![(Section::Data, 2, 2), (Section::Code, 1, 1)]);
+
+        let lines:Vec<String> = ["NOP", "lost: NOP", ".org 1", "NOP"].iter().map(|line| line.to_string()).collect();
+        let err = into_assembly_error(substitute_org(&lines, true).unwrap_err()).0;
+        assert_eq!(err, "The words of lost on line 2 are written over by the .org on line 3, so the label would no longer point at them");
+
+        let lines:Vec<String> = ["JAL $r5, @handler", ".org 1", "handler: NOP"].iter().map(|line| line.to_string()).collect();
+        let err = into_assembly_error(substitute_org(&lines, true).unwrap_err()).0;
+        assert!(err.starts_with("The jump to a label on line 1 is written over by the .org on line 2"), "{}", err);
+    }
+
+
+    #[test]
+    fn test_merge_strings() {
+        let lines:Vec<String> = ["greeting: .text \"OK!\"", "LW $r1, $zero, @again", ".data", "again: .text \"OK!\"", "other: .text \"No\"",
+            "last: .text \"OK!\"", "ADDI $r1, $r1, @last", "part: .text \"OK!\" nonull", ".code", "copy: .text \"OK!\"", "NOP"].iter()
+            .map(|line| line.to_string()).collect();
+        let (new_lines, merged) = merge_strings(&lines);
+        assert_eq!(new_lines.iter().filter(|line| line.is_empty()).count(), 2);
+        assert_eq!(merged, vec![MergedString { label: "last".to_owned(), line: 6, kept: "again".to_owned(), words: 4 },
+            MergedString { label: "copy".to_owned(), line: 10, kept: "greeting".to_owned(), words: 4 }]);
+
+        // a label used in an expression may be counted from, so its string is left where it is
+        let lines:Vec<String> = ["first: .text \"OK\"", "second: .text \"OK\"", "LW $r1, $zero, @second+1"].iter().map(|line| line.to_string()).collect();
+        assert!(merge_strings(&lines).1.is_empty());
+    }
+
+
+    #[test]
+    fn test_substitute_build_info() {
+        let lines:Vec<String> = ["version: .buildinfo", "NOP", "\t.buildinfo"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(substitute_build_info(&lines, 0), vec!["version: .fill 0x0000", "NOP", "\t.fill 0x0000"]);
+        assert_eq!(substitute_build_info(&lines, 0x1234), vec!["version: .fill 0x1234", "NOP", "\t.fill 0x1234"]);
+    }
+
+
+    #[test]
+    fn test_size_assertions() {
+        let lines:Vec<String> = [".equ LIMIT, 4", "NOP", ".assert_size <= LIMIT", ".data", ".fill 1", ".assert_size == 1", ".code", ".assert_size <3"].iter()
+            .map(|line| line.to_string()).collect();
+        let lines = substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        let (lines, assertions) = take_size_assertions(&lines);
+        assert_eq!(lines, vec!["", "NOP", ".data", ".fill 1", ".code"]);
+        assert_eq!(assertions[0], SizeAssertion { section: Section::Code, comparison: "<=".to_owned(), limit: 4, line: ".assert_size <= 4".to_owned() });
+        assert_eq!(assertions[1].section, Section::Data);
+
+        check_size_assertions(&assertions, 2, 1).unwrap();
+        assert!(check_size_assertions(&assertions, 3, 1).is_err());
+        assert!(check_size_assertions(&assertions, 2, 2).is_err());
+    }
+
+
+    #[test]
+    fn test_space_sub() {
+        let mut lines = get_line_vector("test_files/test_space_sub.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        assert_eq!(lines[0], "ADD $r0, $r1, $r2");
+        assert_eq!(lines[1], "start: .fill 0x0064");
+        assert_eq!(lines[2], ".fill 0xFFFE");
+        assert_eq!(lines[3], ".fill 0x0061");
+        assert_eq!(lines[4], ".fill 0x0000");
+        assert_eq!(lines[5], ".fill 0x0000");
+        assert_eq!(lines[6], "ADD $r0, $r1, $r3");
+    }
+
+
+    #[test]
+    fn test_text_sub() {
+        let mut lines = vec!["tag: .text \"Hell@ \"w0rld!\"".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        assert_eq!(lines[0], "tag: .fill 0x0048");
+        assert_eq!(lines[2], ".fill 0x006C");
+        assert_eq!(lines[4], ".fill 0x0040");
+        assert_eq!(lines[5], ".fill 0x0020");
+        assert_eq!(lines[6], ".fill 0x0022");
+        assert_eq!(lines[12], ".fill 0x0021");
+        assert_eq!(lines[13], ".fill 0x0000");
+        assert_eq!(lines.len(), 14);
+    }
+
+
+    #[test]
+    fn test_space_expressions_sub() {
+        let lines:Vec<String> = [".equ BASE, 4", "table: .space 4 [BASE, BASE*2+1, @end-BASE]"].iter().map(|line| line.to_string()).collect();
+        let lines = substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(lines[1], "table: .space 4 [4, 9, @end-(4)]");
+
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        let lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines[1], "table: .fill 0x0004");
+        assert_eq!(lines[2], ".fill 0x0009");
+        assert_eq!(lines[3], ".fill @end-(4)");
+        assert_eq!(lines[4], ".fill 0x0000");
+    }
+
+
+    #[test]
+    fn test_text_pad_sub() {
+        let mut lines = vec!["name: .text \"ab\" pad 4 # field".to_owned(), "ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(get_word_count(&lines[0]), 5);
+        lines = substitute_pseudoinstrs(&lines);
+
+        assert_eq!(lines[0], "name: .fill 0x0061");
+        assert_eq!(lines[1], ".fill 0x0062");
+        assert_eq!(lines[2], ".fill 0x0020");
+        assert_eq!(lines[3], ".fill 0x0020");
+        assert_eq!(lines[4], ".fill 0x0000");
+        assert_eq!(lines[5], "ADD $r0, $r1, $r2");
+    }
+
+
+    #[test]
+    fn test_text_nonull_sub() {
+        let mut lines = vec!["name: .text \"ab\" nonull # no terminator".to_owned(), "field: .text \"ab\" pad 3 nonull".to_owned(), "ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(get_word_count(&lines[0]), 2);
+        assert_eq!(get_word_count(&lines[1]), 3);
+        lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines, vec!["name: .fill 0x0061", ".fill 0x0062", "field: .fill 0x0061", ".fill 0x0062", ".fill 0x0020", "ADD $r0, $r1, $r2"]);
+
+        assert!(validate_assembly_lines(&[".text \"\" nonull".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+        assert!(validate_assembly_lines(&[".text \"ab\" nonull pad 3".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+        assert!(validate_assembly_lines(&[".text \"ab\" pad 3nonull".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_text_pad_too_short() {
+        validate_assembly_lines(&[".text \"abc\" pad 2".to_owned()], &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_constant_expressions() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_constant_expressions.asm", false).unwrap();
+        lines = substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines.retain(|line| !line.is_empty());
+
+        assert_eq!(lines[0], "ADDI $r0, $zero, 17");
+        assert_eq!(lines[1], "LUI $r1, 17");
+        assert_eq!(lines[2], "MOVI $r2, @buffer+(8)-1");
+        assert_eq!(lines[3], ".syscall 3");
+        assert_eq!(lines[4], "buffer: .space 8 []");
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[2], "ADDI $r2, $zero, 12");
+        assert_eq!(lines[3], "LUI $r2, 0");
+        assert_eq!(lines[13], ".fill 13");
+    }
+
+
+    #[test]
+    fn test_substitute_suffixed_literals() {
+        let lines:Vec<String> = [".equ MASK_BITS, 0Fh", "ADDI $r0, $zero, 10b", "LUI $r1, 17o", "MOVI $r2, @buffer+10h", ".org 20h", "buffer: .fill MASK_BITS"]
+            .iter().map(|line| line.to_string()).collect();
+        let lines = substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        assert_eq!(lines[1], "ADDI $r0, $zero, 2");
+        assert_eq!(lines[2], "LUI $r1, 15");
+        assert_eq!(lines[3], "MOVI $r2, @buffer+10h");
+        assert_eq!(lines[4], ".org 32");
+        assert_eq!(lines[5], "buffer: .fill 15");
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_undefined_constant() {
+        let lines = vec!["ADDI $r0, $zero, UNDEFINED + 1".to_owned()];
+        substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_register_aliases() {
+        let lines:Vec<String> = [".regalias counter, $r3", "loop: ADDI $counter, $counter, -1", ".text \"$counter\"", "BEQ $counter,$zero, $r6", "ADD $count, $r0, $r0"]
+            .iter().map(|line| line.to_string()).collect();
+        assert_eq!(substitute_register_aliases(&lines, &DEFAULT_ISA_SPEC).unwrap(), vec!["", "loop: ADDI $r3, $r3, -1", ".text \"$counter\"", "BEQ $r3,$zero, $r6", "ADD $count, $r0, $r0"]);
+
+        for invalid in [[".regalias r3, $r1", "NOP"], [".regalias zero, $r1", "NOP"], [".regalias x, $r1", ".regalias x, $r2"], [".regalias x, $r9", "NOP"]] {
+            let lines:Vec<String> = invalid.iter().map(|line| line.to_string()).collect();
+            assert!(substitute_register_aliases(&lines, &DEFAULT_ISA_SPEC).is_err(), "{:?}", invalid);
+        }
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_constant() {
+        let lines = vec![".equ SIZE, 1".to_owned(), ".equ SIZE, 2".to_owned()];
+        substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_constant_expression_division_by_zero() {
+        let lines = vec![".equ ZERO, 0".to_owned(), "ADDI $r0, $zero, 4 / ZERO".to_owned()];
+        substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+}
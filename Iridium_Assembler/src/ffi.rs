@@ -0,0 +1,168 @@
+use std::ffi::{ CStr, c_char, c_int };
+use std::panic::{ AssertUnwindSafe, catch_unwind };
+use std::ptr;
+use crate::{ AssemblyError, assemble_str };
+
+
+/// Returned by `iridium_assemble` when the program was assembled and its words written to `out_words`.
+pub const IRIDIUM_OK:c_int = 0;
+
+/// Returned by `iridium_assemble` when the program could not be assembled, with the reason written to `err_buf`.
+pub const IRIDIUM_ERR_ASSEMBLY:c_int = 1;
+
+/// Returned by `iridium_assemble` when one of its pointers is null or the source is not valid UTF-8.
+pub const IRIDIUM_ERR_INVALID_ARGUMENT:c_int = 2;
+
+/// Returned by `iridium_assemble` when the assembler panicked, which is caught rather than unwinding into the caller.
+pub const IRIDIUM_ERR_PANIC:c_int = 3;
+
+
+/// Copies as much of `msg` as fits into the buffer of `len` bytes at `buf`, followed by a null terminator. Nothing is written if `buf` is null or `len` is 0.
+unsafe fn write_error(buf:*mut c_char, len:usize, msg:&str) {
+    if buf.is_null() || len == 0 {
+        return;
+    }
+
+    let count = msg.len().min(len - 1);
+    ptr::copy_nonoverlapping(msg.as_ptr() as *const c_char, buf, count);
+    *buf.add(count) = 0;
+}
+
+
+/// Assembles the null-terminated program at `source` in the same way as `assemble_str`, and on success stores a pointer to the words of its code section in
+/// `out_words` and their number in `out_len`. The words must be freed with `iridium_free_words`. On failure, a null-terminated message is written to `err_buf`,
+/// truncated to fit in `err_len` bytes, and `out_words` and `out_len` are set to null and 0.
+///
+/// Returns `IRIDIUM_OK` on success, or one of the `IRIDIUM_ERR_` codes on failure. A program with a `.data` section is an assembly error, as only the code section
+/// can be returned.
+///
+/// # Safety
+///
+/// `source` must point to a null-terminated string, `out_words` and `out_len` must be valid for writes, and `err_buf` must either be null or be valid for writes of
+/// `err_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn iridium_assemble(source:*const c_char, out_words:*mut *mut u16, out_len:*mut usize, err_buf:*mut c_char, err_len:usize) -> c_int {
+    if source.is_null() || out_words.is_null() || out_len.is_null() {
+        write_error(err_buf, err_len, "A null pointer was passed to iridium_assemble");
+        return IRIDIUM_ERR_INVALID_ARGUMENT;
+    }
+
+    *out_words = ptr::null_mut();
+    *out_len = 0;
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(val) => val,
+        Err(err) => {
+            write_error(err_buf, err_len, &format!("The source is not valid UTF-8: {}", err));
+            return IRIDIUM_ERR_INVALID_ARGUMENT;
+        }
+    };
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let program = assemble_str(source)?;
+        if !program.data.is_empty() {
+            return Err(AssemblyError("The program has a .data section, which iridium_assemble cannot return".to_owned()));
+        }
+
+        Ok(program.code)
+    }));
+
+    match result {
+        Ok(Ok(words)) => {
+            let words = words.into_boxed_slice();
+            *out_len = words.len();
+            *out_words = Box::into_raw(words) as *mut u16;
+            IRIDIUM_OK
+        },
+
+        Ok(Err(err)) => {
+            write_error(err_buf, err_len, &err.0);
+            IRIDIUM_ERR_ASSEMBLY
+        },
+
+        Err(panic) => {
+            let msg = panic.downcast_ref::<String>().map(String::as_str).or_else(|| panic.downcast_ref::<&str>().copied()).unwrap_or("unknown panic");
+            write_error(err_buf, err_len, &format!("The assembler panicked: {}", msg));
+            IRIDIUM_ERR_PANIC
+        }
+    }
+}
+
+
+/// Frees the words returned by `iridium_assemble`. Does nothing if `words` is null.
+///
+/// # Safety
+///
+/// `words` and `len` must be exactly as returned by a successful call to `iridium_assemble`, and the words must not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn iridium_free_words(words:*mut u16, len:usize) {
+    if words.is_null() {
+        return;
+    }
+
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(words, len)));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::slice;
+
+
+    /// Calls `iridium_assemble` as a C caller would, giving the code, error message, and words it returned.
+    fn call_assemble(source:&CString) -> (c_int, String, Vec<u16>) {
+        let mut words:*mut u16 = ptr::null_mut();
+        let mut len:usize = 0;
+        let mut err_buf = [0 as c_char; 256];
+        unsafe {
+            let code = iridium_assemble(source.as_ptr(), &mut words, &mut len, err_buf.as_mut_ptr(), err_buf.len());
+            let msg = CStr::from_ptr(err_buf.as_ptr()).to_string_lossy().into_owned();
+            let result = if words.is_null() { Vec::new() } else { slice::from_raw_parts(words, len).to_vec() };
+            iridium_free_words(words, len);
+            (code, msg, result)
+        }
+    }
+
+
+    #[test]
+    fn test_ffi_assemble() {
+        let source = CString::new("ADDI $r1, $zero, 5\nLUI $r0, 500\n.syscall 5\n").unwrap();
+        let (code, msg, words) = call_assemble(&source);
+        assert_eq!(code, IRIDIUM_OK);
+        assert!(msg.is_empty());
+        assert_eq!(words, vec![0x2805, 0x65F4, 0xF405]);
+    }
+
+
+    #[test]
+    fn test_ffi_assembly_error() {
+        let source = CString::new("ADDI $r1, $zero, 500\n").unwrap();
+        let (code, msg, words) = call_assemble(&source);
+        assert_eq!(code, IRIDIUM_ERR_ASSEMBLY);
+        assert!(msg.contains("500"));
+        assert!(words.is_empty());
+    }
+
+
+    #[test]
+    fn test_ffi_truncated_error() {
+        let source = CString::new("NOT_AN_INSTRUCTION\n").unwrap();
+        let mut words:*mut u16 = ptr::null_mut();
+        let mut len:usize = 0;
+        let mut err_buf = [0x7F as c_char; 8];
+        let code = unsafe { iridium_assemble(source.as_ptr(), &mut words, &mut len, err_buf.as_mut_ptr(), err_buf.len()) };
+        assert_eq!(code, IRIDIUM_ERR_ASSEMBLY);
+        assert_eq!(err_buf[7], 0);
+        assert!(words.is_null());
+    }
+
+
+    #[test]
+    fn test_ffi_null_source() {
+        let mut words:*mut u16 = ptr::null_mut();
+        let mut len:usize = 0;
+        let code = unsafe { iridium_assemble(ptr::null(), &mut words, &mut len, ptr::null_mut(), 0) };
+        assert_eq!(code, IRIDIUM_ERR_INVALID_ARGUMENT);
+    }
+}
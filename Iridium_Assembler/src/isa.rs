@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use crate::AssemblyError;
+use crate::encoder::get_imm_operand;
+use crate::lexer::{ LineKind, ParsedLine, Token };
+
+
+/// The specification of the Iridium instruction set, which is used unless another is given with `Assembler::isa`.
+pub const DEFAULT_ISA:&str = include_str!("../isa/iridium.toml");
+
+/// The mnemonics of the pseudo-instructions, which are expanded before encoding and so cannot be defined by a specification.
+const PSEUDO_MNEMONICS:[&str; 4] = ["NOP", "LLI", "MOVI", "MASK"];
+
+
+/// A field of an instruction word holding an immediate, given by its shift from the least significant bit, its width, and whether it is signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub shift: u32,
+    pub bits: u32,
+    pub signed: bool
+}
+
+
+/// The layout of the fields of an instruction format, with the shift of each register field in the order the registers are written followed by the immediate
+/// field if it has one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatSpec {
+    pub registers: Vec<u32>,
+    pub immediate: Option<FieldSpec>
+}
+
+
+/// An instruction given by the name of its format and its opcode, which is the word it encodes to before its registers and immediate are placed in it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstrSpec {
+    pub format: String,
+    pub opcode: u16
+}
+
+
+/// An instruction set, giving the number of each register, the field layout of each format, and the format and opcode of each mnemonic. The formats are `RRR`,
+/// `RRI`, `RI`, and `RR`, which take three registers, two registers and an immediate, a register and an immediate, and two registers respectively.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsaSpec {
+    pub register_bits: u32,
+    pub registers: HashMap<String, u8>,
+    pub formats: HashMap<String, FormatSpec>,
+    pub instructions: HashMap<String, InstrSpec>
+}
+
+impl IsaSpec {
+    /// Parses a specification written in TOML, in the same form as `DEFAULT_ISA`.
+    ///
+    /// Returns an `AssemblyError` if it is not valid TOML of that form, or if any register, format, or instruction is invalid, such as an unknown format, a field
+    /// which overlaps another or the opcode, or a mnemonic which is already a pseudo-instruction.
+    pub fn from_toml(source:&str) -> Result<IsaSpec, Box<dyn Error>> {
+        let spec:IsaSpec = match toml::from_str(source) {
+            Ok(val) => val,
+            Err(err) => return Err(Box::new(AssemblyError(format!("Invalid instruction set specification: {}", err))))
+        };
+
+        let invalid = |msg:String| -> Result<IsaSpec, Box<dyn Error>> { Err(Box::new(AssemblyError(format!("Invalid instruction set specification: {}", msg)))) };
+        if !(1..=8).contains(&spec.register_bits) {
+            return invalid(format!("register_bits must be from 1 to 8 but is {}", spec.register_bits));
+        }
+
+        for (name, number) in &spec.registers {
+            if !name.starts_with('$') || name.len() < 2 || !name[1..].chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return invalid(format!("register {} must be a $ followed by letters, digits, and underscores", name));
+            } else if (*number as u32) >> spec.register_bits != 0 {
+                return invalid(format!("register {} is numbered {} which does not fit in {} bits", name, number, spec.register_bits));
+            }
+        }
+
+        for (name, format) in &spec.formats {
+            let shape = match name.as_str() {
+                "RRR" => (3, false),
+                "RRI" => (2, true),
+                "RI" => (1, true),
+                "RR" => (2, false),
+                _ => return invalid(format!("format {} is not one of RRR, RRI, RI, or RR", name))
+            };
+
+            if (format.registers.len(), format.immediate.is_some()) != shape {
+                return invalid(format!("format {} must have {} register fields and {} immediate field", name, shape.0, if shape.1 { "an" } else { "no" }));
+            } else if spec.get_field_mask(format).is_none() {
+                return invalid(format!("the fields of format {} overlap or do not fit in a 16-bit word", name));
+            }
+        }
+
+        for (mnemonic, instr) in &spec.instructions {
+            let format = match spec.formats.get(&instr.format) {
+                Some(val) => val,
+                None => return invalid(format!("instruction {} has the undefined format {}", mnemonic, instr.format))
+            };
+
+            if mnemonic.is_empty() || !mnemonic.chars().all(|c| c.is_ascii_alphabetic()) || PSEUDO_MNEMONICS.contains(&mnemonic.as_str()) {
+                return invalid(format!("instruction {} must be named with letters only and cannot be a pseudo-instruction", mnemonic));
+            } else if spec.get_field_mask(format).unwrap() & instr.opcode != 0 {
+                return invalid(format!("the opcode 0x{:04X} of instruction {} overlaps the fields of format {}", instr.opcode, mnemonic, instr.format));
+            }
+        }
+
+        Ok(spec)
+    }
+
+
+    /// Reads and parses a specification from a TOML file.
+    ///
+    /// Returns an `AssemblyError` if the file cannot be read or is not a valid specification.
+    pub fn from_file(filename:&str) -> Result<IsaSpec, Box<dyn Error>> {
+        match fs::read_to_string(filename) {
+            Ok(source) => IsaSpec::from_toml(&source),
+            Err(err) => Err(Box::new(AssemblyError(format!("Could not read {}: {}", filename, err))))
+        }
+    }
+
+
+    /// Gets the bits of a word covered by the fields of a format, or `None` if any field overlaps another or lies outside the word.
+    fn get_field_mask(&self, format:&FormatSpec) -> Option<u16> {
+        let fields = format.registers.iter().map(|shift| (*shift, self.register_bits)).chain(format.immediate.map(|imm| (imm.shift, imm.bits)));
+        let mut mask:u16 = 0;
+        for (shift, bits) in fields {
+            if bits == 0 || shift + bits > 16 {
+                return None;
+            }
+
+            let field = (((1_u32 << bits) - 1) << shift) as u16;
+            if mask & field != 0 {
+                return None;
+            }
+
+            mask |= field;
+        }
+
+        Some(mask)
+    }
+
+
+    /// Gets the number of a register from its name, such as `$r0`, or `None` if there is no such register.
+    pub fn get_register(&self, name:&str) -> Option<u8> {
+        self.registers.get(name).copied()
+    }
+
+
+    /// Gets the kind of line an instruction is from the name of its format, or `None` if there is no such instruction.
+    pub fn get_kind(&self, mnemonic:&str) -> Option<LineKind> {
+        match self.instructions.get(mnemonic)?.format.as_str() {
+            "RRR" => Some(LineKind::Rrr),
+            "RRI" => Some(LineKind::Rri),
+            "RI" => Some(LineKind::Ri),
+            _ => Some(LineKind::Jal)
+        }
+    }
+
+
+    /// Gets the immediate field of an instruction, or `None` if there is no such instruction or its format has no immediate.
+    pub fn get_immediate(&self, mnemonic:&str) -> Option<FieldSpec> {
+        self.formats.get(&self.instructions.get(mnemonic)?.format)?.immediate
+    }
+
+
+    /// Encodes an instruction parsed by `parse_line` by placing its registers and immediate in the fields of its format over its opcode.
+    ///
+    /// Returns an `AssemblyError` if its mnemonic is not an instruction of this set or its immediate does not fit in its field.
+    pub fn encode(&self, parsed:&ParsedLine, instr:&str) -> Result<u16, Box<dyn Error>> {
+        let (opcode, format) = match self.instructions.get(parsed.mnemonic) {
+            Some(val) => (val.opcode, &self.formats[&val.format]),
+            None => return Err(Box::new(AssemblyError(format!("{} is not an instruction of the instruction set: {}", parsed.mnemonic, instr))))
+        };
+
+        // a jump to a label must already have been expanded through the scratch register, as there is no field to hold the label in
+        if let (None, Some(Token::Expr(val))) = (format.immediate, parsed.operands.last()) {
+            return Err(Box::new(AssemblyError(format!("Found label {} in instruction {} but labels are not accepted", val, instr))));
+        }
+
+        let regs = parsed.operands.iter().filter_map(|token| match token { Token::Register(val) => Some(*val as u16), _ => None });
+        let mut word = opcode;
+        for (reg, shift) in regs.zip(format.registers.iter()) {
+            word |= (reg & ((1 << self.register_bits) - 1)) << shift;
+        }
+
+        if let Some(field) = format.immediate {
+            let imm = get_imm_operand(parsed.operands.last().unwrap(), field.bits, field.signed, instr)?;
+            word |= ((imm as u32 & ((1_u32 << field.bits) - 1)) << field.shift) as u16;
+        }
+
+        Ok(word)
+    }
+}
+
+impl Default for IsaSpec {
+    fn default() -> IsaSpec {
+        IsaSpec::from_toml(DEFAULT_ISA).unwrap()
+    }
+}
+
+
+lazy_static! {
+    /// The instruction set given by `DEFAULT_ISA`, which lines are validated and encoded with when no other is given.
+    pub static ref DEFAULT_ISA_SPEC:IsaSpec = IsaSpec::default();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use crate::{ Assembler, Instruction };
+    use crate::lexer::parse_line;
+
+
+    #[test]
+    fn test_default_isa_encodings() {
+        let isa = IsaSpec::default();
+        let mut instrs:Vec<Instruction> = Vec::new();
+        for (rd, ra, rb) in (0..512).map(|regs| ((regs >> 6) as u8, (regs >> 3 & 0x7) as u8, (regs & 0x7) as u8)) {
+            instrs.extend([Instruction::Add { rd, ra, rb }, Instruction::Nand { rd, ra, rb }, Instruction::Beq { rd, ra, rb }]);
+            if rb == 0 {
+                instrs.push(Instruction::Jal { rd, ra });
+                instrs.extend((-64..=63).flat_map(|imm| [Instruction::Addi { rd, ra, imm }, Instruction::Sw { rd, ra, imm }, Instruction::Lw { rd, ra, imm }]));
+            }
+
+            if ra == 0 && rb == 0 {
+                instrs.extend((0..=0x03FF).map(|imm| Instruction::Lui { rd, imm }));
+            }
+        }
+
+        for instr in instrs {
+            let text = instr.to_asm();
+            assert_eq!(isa.encode(&parse_line(&text, &DEFAULT_ISA_SPEC).unwrap(), &text).unwrap(), instr.encode(), "{}", text);
+        }
+    }
+
+
+    #[test]
+    fn test_custom_isa() {
+        // the low bits of an RRR-type instruction are unused, so an extra instruction can be placed there
+        let source = DEFAULT_ISA.to_owned() + "SUB = { format = \"RRR\", opcode = 0x0001 }\n";
+        let spec = IsaSpec::from_toml(&source).unwrap();
+        assert_eq!(spec.get_kind("SUB"), Some(LineKind::Rrr));
+
+        let program = Assembler::new().isa(spec).assemble_str("SUB $r6, $r0, $zero\nADD $r1, $r2, $r3").unwrap();
+        assert_eq!(program.code, vec![0x1C81, 0x09C0]);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_isa_opcode_overlapping_fields() {
+        IsaSpec::from_toml(&(DEFAULT_ISA.to_owned() + "SUB = { format = \"RRR\", opcode = 0x0010 }\n")).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_isa_redefining_pseudo_instruction() {
+        IsaSpec::from_toml(&(DEFAULT_ISA.to_owned() + "NOP = { format = \"RR\", opcode = 0xE001 }\n")).unwrap();
+    }
+}
@@ -0,0 +1,1512 @@
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::error::Error;
+use std::ops::Index;
+use log::debug;
+use serde::{ Deserialize, Serialize };
+use crate::{ AssemblyError, convert_to_i64, evaluate_expression };
+use crate::parser::{ AT_REGEX, LABEL_ARG_REGEX, LABEL_NAME_REGEX, LABEL_REGEX, LINKAGE_REGEX, PREDEFINED_SYMBOLS, SECTION_REGEX, TEXT_IMM_REGEX, find_comment_start, get_mnemonic, get_word_count };
+
+
+/// The memory a word is placed in. On a Harvard-architecture target the code and data memories are separate address spaces, each starting from 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    #[default]
+    Code,
+    Data
+}
+
+
+/// Where the data section is placed once the program is assembled. By default it is its own address space starting from 0, for Harvard-architecture targets, while
+/// a target with a single memory can have it follow the code section or start from a given address, with the labels in it resolving to their addresses there.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DataPlacement {
+    #[default]
+    Separate,
+    AfterCode,
+    At(u16)
+}
+
+impl DataPlacement {
+    /// Gets the address of the first word of the data section for a program whose code section is `code_size` words long.
+    pub fn base(self, code_size:usize) -> usize {
+        match self {
+            DataPlacement::Separate => 0,
+            DataPlacement::AfterCode => code_size,
+            DataPlacement::At(address) => address as usize
+        }
+    }
+}
+
+
+/// What a label points at, which is data if it is defined on a `.fill`, `.space`, or `.text` and code otherwise. This is separate from the `Section` the label is in,
+/// as data may also be placed in the code section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelKind {
+    Code,
+    Data,
+    /// A fixed address given by `.at`, such as a memory-mapped register, which is not part of the program and so is never relocated.
+    Absolute
+}
+
+
+/// Where something was written in the source, as a line number counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SourceLoc {
+    pub line: usize
+}
+
+
+/// An entry in the symbol table, giving the address of the label within the section it was defined in, whether it points at code or data, the line it was defined
+/// on, and whether it is exported for use outside the program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    pub section: Section,
+    pub kind: LabelKind,
+    pub defined_at: SourceLoc,
+    pub exported: bool
+}
+
+
+/// The labels of a program by name. Iterating over the table, and serialising it, gives the symbols in a fixed order whatever order they were added in: the labels
+/// of the code section come first, and each section is in order of address and then of name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<Symbol>", into = "Vec<Symbol>")]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+
+    /// Adds a symbol to the table, replacing and returning any symbol which already has its name.
+    pub fn insert(&mut self, symbol:Symbol) -> Option<Symbol> {
+        self.symbols.insert(symbol.name.clone(), symbol)
+    }
+
+
+    pub fn get(&self, name:&str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+
+    pub fn contains(&self, name:&str) -> bool {
+        self.symbols.contains_key(name)
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+
+    /// Gets the symbols in the fixed order described by `SymbolTable`.
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        let mut symbols:Vec<&Symbol> = self.symbols.values().collect();
+        symbols.sort_by_key(|symbol| (symbol.section == Section::Data, symbol.address, &symbol.name));
+        symbols.into_iter()
+    }
+
+
+    /// Gets the address of every symbol by name, in the form taken by `evaluate_expression`.
+    pub fn addresses(&self) -> HashMap<String, i64> {
+        self.symbols.iter().map(|(name, symbol)| (name.to_owned(), symbol.address as i64)).collect()
+    }
+
+
+    /// Moves the definition of each symbol to the line of `lines` which defines its label, counting from 1. The table is generated once the pseudo-instructions have
+    /// been expanded, which changes the number of lines, so this is given the lines before expansion to find where each label was written in the source.
+    pub fn locate_definitions(&mut self, lines:&[String]) {
+        for (index, line) in lines.iter().enumerate() {
+            let name = match LABEL_REGEX.find(line) {
+                Some(val) => val.as_str().trim_end_matches(':'),
+                None => continue
+            };
+
+            if let Some(symbol) = self.symbols.get_mut(name) {
+                symbol.defined_at = SourceLoc { line: index + 1 };
+            }
+        }
+    }
+}
+
+impl Index<&str> for SymbolTable {
+    type Output = Symbol;
+
+    /// Panics if there is no symbol with the given name.
+    fn index(&self, name:&str) -> &Symbol {
+        &self.symbols[name]
+    }
+}
+
+impl FromIterator<Symbol> for SymbolTable {
+    fn from_iter<I:IntoIterator<Item = Symbol>>(symbols:I) -> SymbolTable {
+        SymbolTable { symbols: symbols.into_iter().map(|symbol| (symbol.name.clone(), symbol)).collect() }
+    }
+}
+
+impl From<Vec<Symbol>> for SymbolTable {
+    fn from(symbols:Vec<Symbol>) -> SymbolTable {
+        symbols.into_iter().collect()
+    }
+}
+
+impl From<SymbolTable> for Vec<Symbol> {
+    fn from(table:SymbolTable) -> Vec<Symbol> {
+        table.iter().cloned().collect()
+    }
+}
+
+
+/// Gets the kind of label defined on a line, which is data for a `.fill`, `.space`, `.pattern`, `.text`, or `.buildinfo` and code for anything else. The
+/// pseudo-instructions expand to lines of the same kind, with a `.space`, `.pattern`, `.text`, or `.buildinfo` becoming `.fill`s, so a line gives the same kind
+/// before and after expansion.
+pub fn get_label_kind(line:&str) -> LabelKind {
+    match get_mnemonic(line) {
+        ".fill" | ".space" | ".pattern" | ".text" | ".run" | ".buildinfo" => LabelKind::Data,
+        _ => LabelKind::Code
+    }
+}
+
+
+/// How a relocated word holds the address it is relocated by, corresponding to the masking applied in `substitute_labels`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelocationKind {
+    /// The whole word is the address, as in a `.fill`.
+    Full16,
+    /// The immediate holds the bottom 6 bits of the address, as in an `ADDI`, `LW`, or `SW`.
+    Lo6,
+    /// The immediate holds the top 10 bits of the address, as in a `LUI`.
+    Hi10
+}
+
+impl RelocationKind {
+    /// Gets how the immediate of an instruction with the given mnemonic holds an address, which is the whole word for anything other than an `ADDI`, `LW`, `SW`,
+    /// or `LUI`.
+    pub fn of_mnemonic(mnemonic:&str) -> RelocationKind {
+        match mnemonic {
+            "ADDI" | "LW" | "SW" => RelocationKind::Lo6,
+            "LUI" => RelocationKind::Hi10,
+            _ => RelocationKind::Full16
+        }
+    }
+}
+
+
+/// A word which must be patched once the program is linked with others into a single image, as recorded by `LabelResolver`. The word is at `index` within its
+/// section, and is patched with the address of `symbol` plus `addend`, masked as given by `kind`. The symbol is either a label declared with `.extern`, whose
+/// address comes from the program exporting it, or `.code` or `.data` for an expression using the program's own labels, which is relocated by wherever that
+/// section is placed, as a label's address within its section is already part of the addend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRelocation {
+    pub section: Section,
+    pub index: usize,
+    pub symbol: String,
+    pub kind: RelocationKind,
+    pub addend: i64
+}
+
+impl LinkRelocation {
+    /// Checks whether the relocation is against a label declared with `.extern` rather than one of the program's own sections.
+    pub fn is_external(&self) -> bool {
+        !self.symbol.starts_with('.')
+    }
+}
+
+
+/// Goes through every line of the program and checks for labels. If it finds a label, it will substitute in the appropriate value in its place. The label may be
+/// part of an expression, such as `@table+4` or `@end-@start`, which is evaluated with the label's address before it is masked to fit the instruction.
+///
+/// The predefined symbols `@__ADDR__` and `@__END__` are also resolved here, to the address of the line they are used in and the address just past the last word of
+/// its section respectively.
+///
+/// WARNING: only works if the pseudo-instructions have already been substituted.
+///
+/// Returns an `AssemblyError` if an undefined label is encountered, an expression using a single label as an address, such as `@table+4`, gives an address outside
+/// the range 0 to 0xFFFF, or any other expression, such as the difference `@end-@start`, gives a value outside that range. The exception is a `.fill`, which may
+/// also hold a negative value down to -32768, stored in two's complement.
+pub fn substitute_labels(lines:&[String], label_table:&SymbolTable) -> Result<Vec<String>, Box<dyn Error>> {
+    substitute_placed_labels(lines, label_table, 0)
+}
+
+
+/// Substitutes the labels of a program in the same way as `substitute_labels`, for a program whose data section is placed at `data_base` by `place_data_labels`,
+/// so that `@__ADDR__` and `@__END__` in the data section also count from there.
+///
+/// Returns an `AssemblyError` for the same reasons as `substitute_labels`.
+pub fn substitute_placed_labels(lines:&[String], label_table:&SymbolTable, data_base:usize) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(substitute_linked_labels(lines, label_table, data_base, &[])?.0)
+}
+
+
+/// Substitutes the labels of a program in the same way as `substitute_placed_labels`, leaving each label of `externs`, which are declared with `.extern` to be
+/// defined by another program, for the linker to fill in. Along with the lines, this gives the `LinkRelocation` of every word the linker must patch, whether for
+/// an external label or for one of the program's own once its sections are moved.
+///
+/// Returns an `AssemblyError` for the same reasons as `substitute_labels`, or if an external label is used in an expression other than that label plus a constant.
+pub fn substitute_linked_labels(lines:&[String], label_table:&SymbolTable, data_base:usize, externs:&[String])
+    -> Result<(Vec<String>, Vec<LinkRelocation>), Box<dyn Error>> {
+    let (code_size, data_size) = section_sizes(lines);
+    let mut resolver = LabelResolver::new(label_table, code_size, data_size).with_data_base(data_base).for_linking(externs);
+    let lines = lines.iter().map(|line| resolver.resolve(line)).collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+    Ok((lines, resolver.take_link_relocations()))
+}
+
+
+/// Substitutes the labels of a program one line at a time, as `substitute_labels` does for the whole program, keeping track of the section and address of each
+/// line so that `@__ADDR__` and `@__END__` can be resolved. The lines must be given in order, including the section directives, and `code_size` and `data_size`
+/// are the number of words in each section, which `@__END__` resolves to.
+pub struct LabelResolver {
+    addresses: HashMap<String, i64>,
+    sections: HashMap<String, Section>,
+    externs: HashSet<String>,
+    linking: bool,
+    link_relocations: Vec<LinkRelocation>,
+    section: Section,
+    code_addr: i64,
+    data_addr: i64,
+    code_size: usize,
+    data_size: usize,
+    data_base: usize
+}
+
+impl LabelResolver {
+    pub fn new(label_table:&SymbolTable, code_size:usize, data_size:usize) -> LabelResolver {
+        let mut addresses = label_table.addresses();
+        addresses.insert("__ADDR__".to_owned(), 0);
+        addresses.insert("__END__".to_owned(), 0);
+
+        // the fixed addresses given by `.at` do not move when the program is linked
+        let sections = label_table.symbols.values().filter(|symbol| symbol.kind != LabelKind::Absolute).map(|symbol| (symbol.name.to_owned(), symbol.section)).collect();
+        LabelResolver { addresses, sections, externs: HashSet::new(), linking: false, link_relocations: Vec::new(), section: Section::Code, code_addr: 0,
+            data_addr: 0, code_size, data_size, data_base: 0 }
+    }
+
+
+    /// Counts the addresses of the data section from `data_base` rather than 0, as it is once placed there by `place_data_labels`.
+    pub fn with_data_base(mut self, data_base:usize) -> LabelResolver {
+        self.data_addr = data_base as i64;
+        self.data_base = data_base;
+        self
+    }
+
+
+    /// Records the `LinkRelocation` of each word referring to a label as it is resolved, for linking the program with others. Each of `externs`, the labels
+    /// declared with `.extern`, is resolved as if it were at address 0, so a word referring to one holds only the constant added to it until the linker adds the
+    /// label's address.
+    pub fn for_linking(mut self, externs:&[String]) -> LabelResolver {
+        self.linking = true;
+        for name in externs {
+            self.addresses.insert(name.to_owned(), 0);
+            self.externs.insert(name.to_owned());
+        }
+
+        self
+    }
+
+
+    /// Takes the relocations recorded for the lines resolved so far, in the order of the lines.
+    pub fn take_link_relocations(&mut self) -> Vec<LinkRelocation> {
+        std::mem::take(&mut self.link_relocations)
+    }
+
+
+    /// Substitutes the labels of the next line, which is returned unchanged if it is a section directive or does not refer to a label. Every expression in the
+    /// operands is found in a single scan of the line, each resolved with the label table, and the line rebuilt once with their values.
+    ///
+    /// Returns an `AssemblyError` for the same reasons as `substitute_labels`.
+    pub fn resolve(&mut self, line:&str) -> Result<String, Box<dyn Error>> {
+        if let Some(next_section) = get_section_switch(line) {
+            self.section = next_section;
+            return Ok(line.to_owned());
+        }
+
+        let (address, end) = match self.section {
+            Section::Code => (&mut self.code_addr, self.code_size),
+            Section::Data => (&mut self.data_addr, self.data_base + self.data_size)
+        };
+
+        let line_address = *address;
+        *address += get_word_count(line) as i64;
+
+        // most lines do not refer to a label, so they are not searched any further
+        if !line.contains('@') {
+            return Ok(line.to_owned());
+        }
+
+        let section = self.section;
+        let index = match section {
+            Section::Code => line_address as usize,
+            Section::Data => line_address as usize - self.data_base
+        };
+
+        // __ADDR__ and __END__ are resolved like labels, but their values depend on the line they are used in
+        for (name, value) in [("__ADDR__", line_address), ("__END__", end as i64)] {
+            if let Some(val) = self.addresses.get_mut(name) {
+                *val = value;
+            }
+        }
+
+        let operands_end = find_comment_start(line).unwrap_or(line.len());
+        let mnemonic = get_mnemonic(line);
+        let mut resolved = String::with_capacity(line.len());
+        let mut copied_to = 0;
+        for expr in LABEL_ARG_REGEX.find_iter(&line[..operands_end]) {
+            if self.linking {
+                if let Some((symbol, addend)) = self.find_link_target(expr.as_str(), line)? {
+                    self.link_relocations.push(LinkRelocation { section, index, symbol, kind: RelocationKind::of_mnemonic(mnemonic), addend });
+                }
+            }
+
+            let value = self.resolve_expr(expr.as_str(), mnemonic, line)?;
+            debug!("Resolved {} to {} in {}", expr.as_str(), value, line);
+            resolved.push_str(&line[copied_to..expr.start()]);
+            resolved.push_str(&value.to_string());
+            copied_to = expr.end();
+        }
+
+        resolved.push_str(&line[copied_to..]);
+        Ok(resolved)
+    }
+
+
+    /// Evaluates an expression with the label table, checks it is in range, and masks it to fit the immediate of the instruction with the given mnemonic.
+    fn resolve_expr(&self, expr:&str, mnemonic:&str, line:&str) -> Result<i64, Box<dyn Error>> {
+        let result = match evaluate_expression(expr, &HashMap::new(), &self.addresses) {
+            Ok(val) => val,
+            Err(err) => return Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, line))))
+        };
+
+        let mut address = result.value;
+        if LABEL_NAME_REGEX.captures_iter(expr).any(|caps| self.externs.contains(&caps[1])) {
+            // the linker checks the address once it adds the external label's address to the constant held here
+            address &= 0xFFFF;
+        } else if result.label_weight == 1 && !(0..=0xFFFF).contains(&address) {
+            return Err(Box::new(AssemblyError(format!("Address {} of {} is outside the range 0 to 0xFFFF in instruction {}", address, expr, line))));
+        } else if result.label_weight != 1 && !(0..=0xFFFF).contains(&address) {
+            if mnemonic != ".fill" {
+                return Err(Box::new(AssemblyError(format!("Found value {} of {} outside the range 0 to 0xFFFF in unsigned immediate field in instruction {}", address, expr, line))));
+            } else if !(-0x8000..=0xFFFF).contains(&address) {
+                return Err(Box::new(AssemblyError(format!("Found value {} of {} which does not fit in 16 bits in instruction {}", address, expr, line))));
+            }
+
+            address &= 0xFFFF;
+        }
+
+        if mnemonic == "ADDI" || mnemonic == "LW" || mnemonic == "SW" {
+            address &= 0x003F;
+        } else if mnemonic == "LUI" {
+            address = (address & 0xFFC0) >> 6;
+        }
+
+        Ok(address)
+    }
+
+
+    /// Finds what the word holding an expression on the line being resolved is relocated against when the program is linked, giving the symbol named by its
+    /// `LinkRelocation` and the addend. An expression moves with a section if adding 1 to the address of each of its labels in that section, including
+    /// `@__ADDR__` and `@__END__` for the line's own section, adds 1 to its value, so `@table+4` and `@__END__-@end+@start` both move while `@end-@start` does not.
+    /// An expression moving in any other way, such as `@table*2`, is left as it was resolved, while one using an external label must be that label plus a constant.
+    ///
+    /// Returns an `AssemblyError` if an external label is used in any other way. An undefined label or an expression which cannot be evaluated is left for
+    /// `resolve_expr` to report.
+    fn find_link_target(&self, expr:&str, line:&str) -> Result<Option<(String, i64)>, Box<dyn Error>> {
+        let mut names:Vec<&str> = LABEL_NAME_REGEX.captures_iter(expr).map(|caps| caps.get(1).unwrap().as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+
+        let mut values:HashMap<String, i64> = HashMap::new();
+        for name in &names {
+            match self.addresses.get(*name) {
+                Some(val) => values.insert(name.to_string(), *val),
+                None => return Ok(None)
+            };
+        }
+
+        let evaluate = |values:&HashMap<String, i64>| evaluate_expression(expr, &HashMap::new(), values).ok().map(|result| result.value);
+        let value = match evaluate(&values) {
+            Some(val) => val,
+            None => return Ok(None)
+        };
+
+        let section_of = |name:&str| match PREDEFINED_SYMBOLS.contains(&name) {
+            true => Some(self.section),
+            false => self.sections.get(name).copied()
+        };
+
+        let mut candidates:Vec<(String, i64, Vec<&str>)> = names.iter().filter(|name| self.externs.contains(**name)).map(|name| (name.to_string(), 0, vec![*name]))
+            .collect();
+        for (symbol, section, base) in [(".code", Section::Code, 0), (".data", Section::Data, self.data_base as i64)] {
+            candidates.push((symbol.to_owned(), base, names.iter().copied().filter(|name| section_of(name) == Some(section)).collect()));
+        }
+
+        let mut targets = Vec::new();
+        for (symbol, base, moved) in candidates.into_iter().filter(|(_, _, moved)| !moved.is_empty()) {
+            let mut shifted = values.clone();
+            for name in &moved {
+                *shifted.get_mut(*name).unwrap() += 1;
+            }
+
+            match evaluate(&shifted).map(|val| val - value) {
+                Some(0) => (),
+                shift => targets.push((symbol, base, shift == Some(1)))
+            };
+        }
+
+        let invalid_extern = targets.iter().find(|(symbol, _, moves_with)| !symbol.starts_with('.') && (!moves_with || targets.len() > 1));
+        if let Some((symbol, _, _)) = invalid_extern {
+            return Err(Box::new(AssemblyError(format!("External label @{} can only be used as an address plus a constant, unlike {} in instruction {}", symbol, expr,
+                line))));
+        }
+
+        match targets.as_slice() {
+            [(symbol, base, true)] => Ok(Some((symbol.to_owned(), value - base))),
+            _ => Ok(None)
+        }
+    }
+}
+
+
+/// Finds every word of the code section whose value will be an absolute address once labels are substituted, so that a loader placing the program at a base
+/// address other than 0 can add the base to them. These are the words with an operand such as `@label` or `@label+4` whose labels all belong to the code section,
+/// while differences like `@end-@start` and the fixed addresses given by `.at` are not relocated as they do not depend on where the program is placed.
+///
+/// WARNING: only works if the pseudo-instructions have already been substituted, and must be called before `substitute_labels`, which reports any undefined label.
+pub fn find_relocations(lines:&[String], label_table:&SymbolTable) -> Result<Vec<(usize, RelocationKind)>, Box<dyn Error>> {
+    let mut addresses = label_table.addresses();
+    let (code_lines, _) = split_sections(lines);
+    let (code_size, _) = section_sizes(lines);
+    addresses.insert("__END__".to_owned(), code_size as i64);
+
+    let mut relocations = Vec::new();
+    let mut next_address = 0;
+    for line in &code_lines {
+        let index = next_address;
+        next_address += get_word_count(line);
+        let expr = match LABEL_ARG_REGEX.find(line) {
+            Some(val) => val.as_str(),
+            None => continue
+        };
+
+        // an undefined label is reported by `substitute_labels`, and a label declared with `.extern` is relocated when linked rather than loaded
+        addresses.insert("__ADDR__".to_owned(), index as i64);
+        if LABEL_NAME_REGEX.captures_iter(expr).any(|caps| !addresses.contains_key(&caps[1])) {
+            continue;
+        }
+
+        let result = match evaluate_expression(expr, &HashMap::new(), &addresses) {
+            Ok(val) => val,
+            Err(err) => return Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, line))))
+        };
+
+        let refers_to_data = LABEL_NAME_REGEX.captures_iter(expr).any(|caps| {
+            label_table.get(&caps[1]).is_some_and(|symbol| symbol.section == Section::Data || symbol.kind == LabelKind::Absolute)
+        });
+
+        if result.label_weight != 1 || refers_to_data {
+            continue;
+        }
+
+        relocations.push((index, RelocationKind::of_mnemonic(get_mnemonic(line))));
+    }
+
+    Ok(relocations)
+}
+
+
+/// Goes through every line of the program looking for instructions with a label matching the regex `^[a-zA-Z_]+:`, each of which is added to the returned
+/// `SymbolTable` with its address, section, and kind. Each symbol is defined at its line within `lines` until `SymbolTable::locate_definitions` moves it to the
+/// source, and none is exported.
+///
+/// Each section has its own location counter starting from 0, and `.code`/`.data` lines switch between them without taking up an address themselves.
+///
+/// Returns an `AssemblyError` if a label is defined twice, has the name of a predefined symbol, or is at an address outside the range 0 to 0xFFFF.
+pub fn generate_label_table(lines:&[String]) -> Result<SymbolTable, Box<dyn Error>> {
+    let mut builder = LabelTableBuilder::new();
+    for line in lines {
+        builder.add_line(line)?;
+    }
+
+    Ok(builder.finish())
+}
+
+
+/// Builds a `SymbolTable` one line at a time, as `generate_label_table` does for the whole program, so that the table can be built without holding every line at
+/// once. The lines must be given in order, including the section directives.
+#[derive(Debug, Default)]
+pub struct LabelTableBuilder {
+    label_table: SymbolTable,
+    section: Section,
+    code_addr: usize,
+    data_addr: usize,
+    index: usize
+}
+
+impl LabelTableBuilder {
+    pub fn new() -> LabelTableBuilder {
+        LabelTableBuilder::default()
+    }
+
+
+    /// Adds the label defined on the next line to the table, if it defines one.
+    ///
+    /// Returns an `AssemblyError` for the same reasons as `generate_label_table`.
+    pub fn add_line(&mut self, line:&str) -> Result<(), Box<dyn Error>> {
+        self.index += 1;
+        if let Some(next_section) = get_section_switch(line) {
+            self.section = next_section;
+            return Ok(());
+        }
+
+        let address = match self.section {
+            Section::Code => &mut self.code_addr,
+            Section::Data => &mut self.data_addr
+        };
+
+        if let Some(val) = LABEL_REGEX.find(line) {
+            let label_name = val.as_str().replace(":", "");
+            if self.label_table.contains(&label_name) {
+                return Err(Box::new(AssemblyError(format!("Found duplicate key {}", label_name))));
+            } else if PREDEFINED_SYMBOLS.contains(&label_name.as_str()) {
+                return Err(Box::new(AssemblyError(format!("Cannot define label {} as it is a predefined symbol", label_name))));
+            }
+
+            let label_address = match u16::try_from(*address) {
+                Ok(val) => val,
+                Err(_) => return Err(Box::new(AssemblyError(format!("Label {} is at address {} outside the range 0 to 0xFFFF", label_name, address))))
+            };
+
+            let defined_at = SourceLoc { line: self.index };
+            self.label_table.insert(Symbol { name: label_name, address: label_address, section: self.section, kind: get_label_kind(line), defined_at, exported: false });
+        };
+
+        *address += get_word_count(line);
+        Ok(())
+    }
+
+
+    /// Gets the number of words in the code and data sections so far.
+    pub fn section_sizes(&self) -> (usize, usize) {
+        (self.code_addr, self.data_addr)
+    }
+
+
+    pub fn finish(self) -> SymbolTable {
+        self.label_table
+    }
+}
+
+
+/// Moves each label of the data section from its address within the section to its address once the section is placed at `data_base`, such as just after the
+/// code section for a target with a single memory. This must be done before `add_fixed_labels`, as a label given a fixed address by `.at` already has it.
+///
+/// Returns an `AssemblyError` if the `data_size` words of the section would not all fit below 0x10000 once placed.
+pub fn place_data_labels(label_table:&mut SymbolTable, data_base:usize, data_size:usize) -> Result<(), Box<dyn Error>> {
+    if data_base + data_size > 0x10000 {
+        return Err(Box::new(AssemblyError(format!("Placing the {} words of the data section at 0x{:04X} would take it past the 65536 words which can be addressed",
+            data_size, data_base))));
+    }
+
+    for symbol in label_table.symbols.values_mut().filter(|symbol| symbol.section == Section::Data) {
+        let address = symbol.address as usize + data_base;
+        symbol.address = match u16::try_from(address) {
+            Ok(val) => val,
+            Err(_) => return Err(Box::new(AssemblyError(format!("Label {} is at address {} outside the range 0 to 0xFFFF", symbol.name, address))))
+        };
+    }
+
+    Ok(())
+}
+
+
+/// A label given a fixed address by a `.at` directive, such as `IO_PORT: .at 0xF000`, rather than by where it is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedLabel {
+    name: String,
+    address: u16,
+    section: Section,
+    line: String
+}
+
+
+/// Removes every `.at` directive from the program, as they do not take up any space, and returns the remaining lines along with the labels they define, each tagged
+/// with the section it was written in.
+pub fn take_fixed_labels(lines:&[String]) -> (Vec<String>, Vec<FixedLabel>) {
+    let mut new_lines:Vec<String> = Vec::new();
+    let mut fixed:Vec<FixedLabel> = Vec::new();
+    let mut section = Section::Code;
+    for line in lines {
+        section = get_section_switch(line).unwrap_or(section);
+        match AT_REGEX.captures(line) {
+            Some(caps) => fixed.push(FixedLabel {
+                name: caps[1].to_owned(),
+                address: convert_to_i64(caps[2].trim()).unwrap_or(0) as u16,
+                section,
+                line: line.to_owned()
+            }),
+
+            None => new_lines.push(line.to_owned())
+        };
+    }
+
+    (new_lines, fixed)
+}
+
+
+/// Adds the labels taken by `take_fixed_labels` to the table, once it has been generated and the number of words in each section is known. The `.at` lines are
+/// not among the lines the table was generated from, so each label is defined on line 0 until `SymbolTable::locate_definitions` finds it in the source.
+///
+/// The data section starts from `data_base`, as placed by `place_data_labels`, which must already have moved the labels of the data section there.
+///
+/// Returns an `AssemblyError` if a label is defined twice or has the name of a predefined symbol, or if its address conflicts with one computed for the program by
+/// falling within the words of its section or being the address of another label in it.
+pub fn add_fixed_labels(label_table:&mut SymbolTable, fixed:&[FixedLabel], code_size:usize, data_size:usize, data_base:usize) -> Result<(), Box<dyn Error>> {
+    for label in fixed {
+        let (start, size) = match label.section {
+            Section::Code => (0, code_size),
+            Section::Data => (data_base, data_size)
+        };
+
+        if label_table.contains(&label.name) {
+            return Err(Box::new(AssemblyError(format!("Found duplicate key {}", label.name))));
+        } else if PREDEFINED_SYMBOLS.contains(&label.name.as_str()) {
+            return Err(Box::new(AssemblyError(format!("Cannot define label {} as it is a predefined symbol", label.name))));
+        } else if (start..start + size).contains(&(label.address as usize)) {
+            return Err(Box::new(AssemblyError(format!("Address 0x{:04X} of {} is within the {} words of its section: {}", label.address, label.name, size, label.line))));
+        }
+
+        let clash = label_table.iter().find(|symbol| symbol.section == label.section && symbol.address == label.address && symbol.kind != LabelKind::Absolute);
+        if let Some(symbol) = clash {
+            return Err(Box::new(AssemblyError(format!("Address 0x{:04X} of {} is already the address of label {}: {}", label.address, label.name, symbol.name,
+                label.line))));
+        }
+
+        label_table.insert(Symbol { name: label.name.to_owned(), address: label.address, section: label.section, kind: LabelKind::Absolute,
+            defined_at: SourceLoc { line: 0 }, exported: false });
+    }
+
+    Ok(())
+}
+
+
+/// The labels a program shares with the others it is linked with: those declared with `.extern`, which are defined by another program, and those declared with
+/// `.export`, which the program defines for others to use. The `entry` is the label declared with `.global`, where the program starts running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linkage {
+    pub externs: Vec<String>,
+    pub exports: Vec<String>,
+    pub entry: Option<String>
+}
+
+
+/// Collects the labels declared with `.extern NAME`, `.export NAME`, and `.global NAME` and empties the lines declaring them, so every line keeps its index for
+/// error messages.
+///
+/// Returns an `AssemblyError` if a label is declared with the same directive twice, is declared both external and exported, has the name of a predefined symbol,
+/// or a second entry point is declared.
+pub fn take_linkage(lines:&[String]) -> Result<(Vec<String>, Linkage), Box<dyn Error>> {
+    let mut linkage = Linkage::default();
+    let mut new_lines:Vec<String> = Vec::with_capacity(lines.len());
+    for line in lines {
+        let caps = match LINKAGE_REGEX.captures(line) {
+            Some(val) => val,
+            None => {
+                new_lines.push(line.to_owned());
+                continue;
+            }
+        };
+
+        let name = caps[2].to_owned();
+        if &caps[1] == "global" {
+            if let Some(entry) = &linkage.entry {
+                return Err(Box::new(AssemblyError(format!("The entry point is already declared as {}: {}", entry, line))));
+            } else if PREDEFINED_SYMBOLS.contains(&name.as_str()) {
+                return Err(Box::new(AssemblyError(format!("Cannot declare {} as it is a predefined symbol: {}", name, line))));
+            }
+
+            linkage.entry = Some(name);
+            new_lines.push(String::new());
+            continue;
+        }
+
+        let (declared, other) = match &caps[1] {
+            "extern" => (&mut linkage.externs, &linkage.exports),
+            _ => (&mut linkage.exports, &linkage.externs)
+        };
+
+        if declared.contains(&name) {
+            return Err(Box::new(AssemblyError(format!("Label {} is declared twice: {}", name, line))));
+        } else if other.contains(&name) {
+            return Err(Box::new(AssemblyError(format!("Label {} cannot be both external and exported: {}", name, line))));
+        } else if PREDEFINED_SYMBOLS.contains(&name.as_str()) {
+            return Err(Box::new(AssemblyError(format!("Cannot declare {} as it is a predefined symbol: {}", name, line))));
+        }
+
+        declared.push(name);
+        new_lines.push(String::new());
+    }
+
+    Ok((new_lines, linkage))
+}
+
+
+/// Marks each label the program exports as exported in its table, once the table has been generated.
+///
+/// Returns an `AssemblyError` if an exported label is not defined by the program, or an external label is, as it would then have two addresses.
+pub fn apply_linkage(label_table:&mut SymbolTable, linkage:&Linkage) -> Result<(), Box<dyn Error>> {
+    if let Some(name) = linkage.externs.iter().find(|name| label_table.contains(name)) {
+        return Err(Box::new(AssemblyError(format!("Label {} is declared with .extern but is also defined in the program", name))));
+    }
+
+    for name in &linkage.exports {
+        match label_table.symbols.get_mut(name) {
+            Some(symbol) => symbol.exported = true,
+            None => return Err(Box::new(AssemblyError(format!("Cannot export label {} as it is not defined in the program", name))))
+        };
+    }
+
+    Ok(())
+}
+
+
+/// Finds the label the program starts running from, as declared with `.global` or given with `--entry`, which must be an instruction in the code section.
+///
+/// Returns an `AssemblyError` if the label is not defined in the program, is a fixed address given by `.at`, or is on data such as a `.fill` or `.text`, in either
+/// section.
+pub fn resolve_entry(label_table:&SymbolTable, name:&str) -> Result<Symbol, Box<dyn Error>> {
+    let symbol = match label_table.get(name) {
+        Some(val) => val,
+        None => return Err(Box::new(AssemblyError(format!("Entry point {} is not defined in the program", name))))
+    };
+
+    match (symbol.section, symbol.kind) {
+        (_, LabelKind::Absolute) => Err(Box::new(AssemblyError(format!("Entry point {} is the fixed address 0x{:04X} given by .at rather than an instruction", name,
+            symbol.address)))),
+        (Section::Data, _) | (_, LabelKind::Data) => Err(Box::new(AssemblyError(format!("Entry point {} points at data at 0x{:04X} rather than an instruction on line {}",
+            name, symbol.address, symbol.defined_at.line)))),
+        _ => Ok(symbol.clone())
+    }
+}
+
+
+/// Finds every reference to a label which is not defined anywhere in the program, rather than stopping at the first as `substitute_labels` does, giving the line
+/// number of each counting from 1 and the name of the label. Lines are numbered by their index, so this must be given the lines before any are removed, and a `@`
+/// inside a string literal is not a reference. A label declared with `.extern` is defined by the program it is linked with, so is not unresolved.
+pub fn find_unresolved_labels(lines:&[String]) -> Vec<(usize, String)> {
+    let mut defined:Vec<&str> = lines.iter().filter_map(|line| LABEL_REGEX.find(line)).map(|val| val.as_str().trim_end_matches(':')).collect();
+    defined.extend(lines.iter().filter_map(|line| LINKAGE_REGEX.captures(line)).filter(|caps| &caps[1] == "extern").map(|caps| caps.get(2).unwrap().as_str()));
+
+    let mut unresolved:Vec<(usize, String)> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let line = TEXT_IMM_REGEX.replace_all(line, "\"\"");
+        for caps in LABEL_NAME_REGEX.captures_iter(&line) {
+            if !defined.contains(&&caps[1]) && !PREDEFINED_SYMBOLS.contains(&&caps[1]) {
+                unresolved.push((index + 1, caps[1].to_owned()));
+            }
+        }
+    }
+
+    unresolved
+}
+
+
+/// Finds the first label defined twice, giving its name and the lines of both definitions, counting from 1, so that both can be reported rather than only the
+/// label's name as `generate_label_table` does once the lines have been expanded. Lines are numbered by their index, so this must be given the lines before any
+/// are removed.
+pub fn find_duplicate_label(lines:&[String]) -> Option<(String, usize, usize)> {
+    let mut defined:HashMap<&str, usize> = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        let name = match LABEL_REGEX.find(line) {
+            Some(val) => val.as_str().trim_end_matches(':'),
+            None => continue
+        };
+
+        if let Some(first) = defined.insert(name, index + 1) {
+            return Some((name.to_owned(), first, index + 1));
+        }
+    }
+
+    None
+}
+
+
+/// A reference to a label, giving the file and line it is on, counting from 1, and the instruction it is in as written once its constants were substituted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelReference {
+    pub file: String,
+    pub line: usize,
+    pub instruction: String
+}
+
+
+/// The references to each label of a program by name, including an empty list for each label nothing refers to. Iterating over it gives the labels in order of
+/// name.
+pub type CrossReference = BTreeMap<String, Vec<LabelReference>>;
+
+
+/// Finds every reference to each label of `label_table` within `lines`, which come from `filename`. A line using a label more than once, such as
+/// `.fill @table+@table`, is one reference to it, and a `@` inside a string literal is not a reference. Lines are numbered by their index in the same way as for
+/// `SymbolTable::locate_definitions`, so this must be given the lines before any are removed or the pseudo-instructions are expanded, as an expansion such as a
+/// `MOVI` repeats the label on each line it expands to.
+pub fn find_references(lines:&[String], label_table:&SymbolTable, filename:&str) -> CrossReference {
+    let mut xref:CrossReference = label_table.iter().map(|symbol| (symbol.name.to_owned(), Vec::new())).collect();
+    for (index, line) in lines.iter().enumerate() {
+        let text = TEXT_IMM_REGEX.replace_all(line, "\"\"");
+        for caps in LABEL_NAME_REGEX.captures_iter(&text) {
+            let references = match xref.get_mut(&caps[1]) {
+                Some(val) => val,
+                None => continue
+            };
+
+            if references.last().is_some_and(|reference| reference.line == index + 1) {
+                continue;
+            }
+
+            references.push(LabelReference { file: filename.to_owned(), line: index + 1, instruction: line.trim().to_owned() });
+        }
+    }
+
+    xref
+}
+
+
+/// Returns the section a line switches to if it is a `.code` or `.data` directive, or the `.section text` and `.section data` spellings of them, or `None` for
+/// any other line.
+pub fn get_section_switch(line:&str) -> Option<Section> {
+    match SECTION_REGEX.captures(line) {
+        Some(caps) if caps[1].ends_with("data") => Some(Section::Data),
+        Some(_) => Some(Section::Code),
+        None => None
+    }
+}
+
+
+/// Counts the words in the code and data sections, which is the number of lines routed into each by `split_sections` with each `.run` counted as all of its words.
+pub fn section_sizes(lines:&[String]) -> (usize, usize) {
+    let mut section = Section::Code;
+    let (mut code_size, mut data_size) = (0, 0);
+    for line in lines {
+        match get_section_switch(line) {
+            Some(next_section) => section = next_section,
+            None if section == Section::Code => code_size += get_word_count(line),
+            None => data_size += get_word_count(line)
+        };
+    }
+
+    (code_size, data_size)
+}
+
+
+/// Routes each line into the code or data section according to the `.code` and `.data` directives preceding it, removing the directives themselves. Lines before
+/// the first directive belong to the code section, so a program without any directives is returned unchanged as the code section.
+pub fn split_sections(lines:&[String]) -> (Vec<String>, Vec<String>) {
+    let (mut code, mut data) = (Vec::new(), Vec::new());
+    let mut section = Section::Code;
+    for line in lines {
+        if let Some(next_section) = get_section_switch(line) {
+            section = next_section;
+            continue;
+        }
+
+        match section {
+            Section::Code => code.push(line.to_owned()),
+            Section::Data => data.push(line.to_owned())
+        };
+    }
+
+    (code, data)
+}
+
+
+/// Removes the label definition from the start of every line. Once the labels have been substituted nothing refers to them, so the program still assembles to the
+/// same words without them.
+pub fn strip_label_definitions(lines:&[String]) -> Vec<String> {
+    lines.iter().map(|line| LABEL_REGEX.replace(line, "").trim().to_owned()).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use crate::parser::{ get_line_vector, validate_assembly_lines };
+    use crate::expansion::{ substitute_constants, substitute_pseudoinstrs, substitute_source_symbols, take_size_assertions };
+    use crate::encoder::convert_instr_to_binary;
+    use std::fs;
+
+
+    /// Substitutes the labels as `substitute_labels` first did, searching each line for its first expression with the regex and then replacing its text, which the
+    /// single scan must give the same lines as for any line with at most one expression.
+    fn substitute_labels_by_first_match(lines:&[String], label_table:&SymbolTable) -> Result<Vec<String>, Box<dyn Error>> {
+        let (code_size, data_size) = section_sizes(lines);
+        let mut resolver = LabelResolver::new(label_table, code_size, data_size);
+        lines.iter().map(|line| {
+            if let Some(next_section) = get_section_switch(line) {
+                resolver.section = next_section;
+                return Ok(line.to_owned());
+            }
+
+            let (address, end) = match resolver.section {
+                Section::Code => (&mut resolver.code_addr, resolver.code_size),
+                Section::Data => (&mut resolver.data_addr, resolver.data_size)
+            };
+
+            resolver.addresses.insert("__ADDR__".to_owned(), *address);
+            resolver.addresses.insert("__END__".to_owned(), end as i64);
+            *address += get_word_count(line) as i64;
+
+            let expr = match LABEL_ARG_REGEX.find(line) {
+                Some(val) => val.as_str(),
+                None => return Ok(line.to_owned())
+            };
+
+            let value = resolver.resolve_expr(expr, get_mnemonic(line), line)?;
+            Ok(line.replace(expr, &value.to_string()))
+        }).collect()
+    }
+
+
+    /// Gives a distinct name for each index, made only of letters and underscores as a label name must be.
+    fn label_name(mut index:usize) -> String {
+        let mut name = "label_".to_owned();
+        loop {
+            name.push((b'a' + (index % 26) as u8) as char);
+            index /= 26;
+            if index == 0 {
+                return name;
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_resolve_every_expression() {
+        let symbol = |name:&str, address:u16| Symbol { name: name.to_owned(), address, section: Section::Code, kind: LabelKind::Code, defined_at: SourceLoc { line: 1 },
+            exported: false };
+        let label_table:SymbolTable = vec![symbol("first", 2), symbol("second", 0x1234)].into();
+        let mut resolver = LabelResolver::new(&label_table, 4, 0);
+
+        // no instruction takes two immediates yet, but each expression on a line is resolved and masked on its own
+        assert_eq!(resolver.resolve("PAIR @first, @second+1").unwrap(), "PAIR 2, 4661");
+        assert_eq!(resolver.resolve("ADDI @second, @first").unwrap(), "ADDI 52, 2");
+        assert_eq!(resolver.resolve(".fill @first # not @second").unwrap(), ".fill 2 # not @second");
+        assert_eq!(resolver.resolve("PAIR @__ADDR__, @__END__-@first").unwrap(), "PAIR 3, 2");
+        assert!(resolver.resolve("PAIR @first, @missing").unwrap_err().to_string().contains("missing"));
+    }
+
+
+    #[test]
+    fn test_resolve_matches_first_match() {
+        let mut num_files = 0;
+        for entry in fs::read_dir("test_files").unwrap() {
+            let lines = match get_line_vector(entry.unwrap().path().to_str().unwrap(), true).map(|lines| substitute_constants(&lines, &DEFAULT_ISA_SPEC)) {
+                Ok(Ok(lines)) if validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).is_ok() => lines,
+                _ => continue
+            };
+
+            let (lines, _) = take_size_assertions(&lines);
+            let (mut lines, _) = take_fixed_labels(&lines);
+            lines.retain(|line| !line.is_empty());
+            let lines = substitute_pseudoinstrs(&lines);
+            if let Ok(label_table) = generate_label_table(&lines) {
+                assert_eq!(substitute_labels(&lines, &label_table).ok(), substitute_labels_by_first_match(&lines, &label_table).ok());
+                num_files += 1;
+            }
+        }
+
+        assert!(num_files > 5);
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_substitute_labels_large_input() {
+        let mut lines:Vec<String> = Vec::new();
+        for index in 0..10_000 {
+            lines.push(format!("{}: ADDI $r1, $r1, @{}", label_name(index), label_name((index * 7) % 10_000)));
+            lines.push(format!(".fill @{}-@{}", label_name((index + 1) % 10_000), label_name(index)));
+            lines.push("ADD $r1, $r1, $r2".to_owned());
+        }
+
+        let label_table = generate_label_table(&lines).unwrap();
+        assert_eq!(label_table.addresses().len(), 10_000);
+        assert_eq!(substitute_labels(&lines, &label_table).unwrap(), substitute_labels_by_first_match(&lines, &label_table).unwrap());
+    }
+
+
+    #[test]
+    fn test_mnemonic_names_in_labels_masking() {
+        let far = Symbol { name: "far".to_owned(), address: 0x1234, section: Section::Code, kind: LabelKind::Code, defined_at: SourceLoc { line: 1 }, exported: false };
+        let label_table:SymbolTable = vec![far].into();
+        let lines = vec!["ADDI_ptr: LUI $r1, @far".to_owned(), "LUI_ptr: ADDI $r1, $r1, @far".to_owned()];
+        let lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[0], "ADDI_ptr: LUI $r1, 72");
+        assert_eq!(lines[1], "LUI_ptr: ADDI $r1, $r1, 52");
+    }
+
+
+    #[test]
+    fn test_label_table_generation() {
+        let mut lines = get_line_vector("test_files/test_label_table_generation.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        lines.retain(|line| !line.is_empty());
+        
+        let tags = generate_label_table(&lines).unwrap();
+        assert_eq!(tags["start"].address, 0);
+        assert_eq!(tags["something"].address, 3);
+        assert_eq!(tags["number"].address, 4);
+        assert_eq!(tags["hello"].address, 5);
+        assert_eq!(tags["more_text"].address, 11);
+    }
+
+
+    #[test]
+    fn test_label_kinds() {
+        let mut lines = get_line_vector("test_files/test_label_table_generation.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        let tags = generate_label_table(&lines).unwrap();
+        assert_eq!(tags["start"].kind, LabelKind::Code);
+        assert_eq!(tags["something"].kind, LabelKind::Code);
+        assert_eq!(tags["number"].kind, LabelKind::Data);
+        assert_eq!(tags["hello"].kind, LabelKind::Data);
+        assert_eq!(tags["more_text"].kind, LabelKind::Data);
+
+        let lines = substitute_pseudoinstrs(&["start: MOVI $r0, 300".to_owned(), "buffer: .space 2 []".to_owned()]);
+        let tags = generate_label_table(&lines).unwrap();
+        assert_eq!(tags["start"].kind, LabelKind::Code);
+        assert_eq!(tags["buffer"].kind, LabelKind::Data);
+    }
+
+
+    #[test]
+    fn test_section_label_table() {
+        let mut lines = get_line_vector("test_files/test_sections.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        let tags = generate_label_table(&lines).unwrap();
+        let symbols:Vec<(&str, u16, Section, LabelKind)> = tags.iter().map(|symbol| (symbol.name.as_str(), symbol.address, symbol.section, symbol.kind)).collect();
+        assert_eq!(symbols, vec![("start", 0, Section::Code, LabelKind::Code), ("loop", 3, Section::Code, LabelKind::Code), ("table", 0, Section::Data, LabelKind::Data),
+            ("message", 2, Section::Data, LabelKind::Data)]);
+        assert!(tags.iter().all(|symbol| !symbol.exported));
+    }
+
+
+    #[test]
+    fn test_symbol_definitions() {
+        let mut lines = get_line_vector("test_files/test_sections.asm", false).unwrap();
+        let source_lines = lines.clone();
+        lines.retain(|line| !line.is_empty());
+        lines = substitute_pseudoinstrs(&lines);
+
+        let mut tags = generate_label_table(&lines).unwrap();
+        assert_eq!(tags["loop"].defined_at, SourceLoc { line: 8 });
+
+        tags.locate_definitions(&source_lines);
+        assert_eq!(tags["start"].defined_at, SourceLoc { line: 1 });
+        assert_eq!(tags["table"].defined_at, SourceLoc { line: 5 });
+        assert_eq!(tags["loop"].defined_at, SourceLoc { line: 9 });
+        assert_eq!(tags["message"].defined_at, SourceLoc { line: 14 });
+    }
+
+
+    #[test]
+    fn test_fixed_labels() {
+        let lines:Vec<String> = ["IO_PORT: .at 0xF000", "start: NOP", ".data", "buffer: .fill 0", "END: .at 1"].iter().map(|line| line.to_string()).collect();
+        let (lines, fixed) = take_fixed_labels(&lines);
+        assert_eq!(lines, vec!["start: NOP", ".data", "buffer: .fill 0"]);
+        assert_eq!(fixed[1], FixedLabel { name: "END".to_owned(), address: 1, section: Section::Data, line: "END: .at 1".to_owned() });
+
+        let mut tags = generate_label_table(&lines).unwrap();
+        add_fixed_labels(&mut tags, &fixed, 1, 1, 0).unwrap();
+        assert_eq!((tags["IO_PORT"].address, tags["IO_PORT"].section, tags["IO_PORT"].kind), (0xF000, Section::Code, LabelKind::Absolute));
+        assert_eq!(find_relocations(&["MOVI $r0, @IO_PORT".to_owned()], &tags).unwrap(), vec![]);
+
+        let (_, conflicting) = take_fixed_labels(&["buffer: .at 0x10".to_owned(), "START: .at 0".to_owned()]);
+        assert!(add_fixed_labels(&mut generate_label_table(&lines).unwrap(), &conflicting[..1], 1, 1, 0).is_err());
+        assert!(add_fixed_labels(&mut generate_label_table(&lines).unwrap(), &conflicting[1..], 1, 1, 0).is_err());
+    }
+
+
+    #[test]
+    fn test_symbol_table_json() {
+        let mut lines = get_line_vector("test_files/test_sections.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        let tags = generate_label_table(&substitute_pseudoinstrs(&lines)).unwrap();
+
+        let json = serde_json::to_string(&tags).unwrap();
+        assert!(json.starts_with(r#"[{"name":"start","address":0,"section":"code","kind":"code","defined_at":{"line":1},"exported":false},"#), "{}", json);
+        assert_eq!(serde_json::from_str::<SymbolTable>(&json).unwrap(), tags);
+    }
+
+
+    #[test]
+    fn test_split_sections() {
+        let mut lines = get_line_vector("test_files/test_sections.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+        let (code, data) = split_sections(&lines);
+
+        assert_eq!(code[0], "start: ADDI $r0, $zero, 0");
+        assert_eq!(code[1], "LUI $r0, 0");
+        assert_eq!(code[4], "ADDI $r6, $zero, 3");
+        assert_eq!(code.len(), 7);
+
+        assert_eq!(data[0], "table: .fill 0x0010");
+        assert_eq!(data[2], "message: .fill 0x0068");
+        assert_eq!(data.len(), 5);
+    }
+
+
+    #[test]
+    fn test_split_without_sections() {
+        let lines = vec!["ADD $r0, $r1, $r2".to_owned(), ".fill 0x0001".to_owned()];
+        let (code, data) = split_sections(&lines);
+        assert_eq!(code, lines);
+        assert!(data.is_empty());
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_label() {
+        let mut lines = get_line_vector("test_files/test_duplicate_label.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        lines.retain(|line| !line.is_empty());
+
+        generate_label_table(&lines).unwrap();
+    }
+
+
+    #[test]
+    fn test_label_operands() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_label_operands.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[2], "move: ADDI $r6, $zero, 0");
+        assert_eq!(lines[5], "ADDI $r0, $zero, 2");
+        assert_eq!(lines[77], "after_text: ADDI $r6, $zero, 6");
+        assert_eq!(lines[78], "LUI $r6, 0");
+        assert_eq!(lines[79], "ADDI $r5, $zero, 13");
+        assert_eq!(lines[80], "LUI $r5, 1");
+    }
+
+
+    #[test]
+    fn test_fill_label_address() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_jump_table.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        // handler is at 303 = 0x12F, which is only stored in full if the address is not masked
+        assert_eq!(label_table["handler"].address, 303);
+        assert_eq!(lines[0], "table: .fill 2");
+        assert_eq!(lines[1], ".fill 303");
+        assert_eq!(convert_instr_to_binary(&lines[1], &DEFAULT_ISA_SPEC).unwrap(), 0x012F);
+    }
+
+
+    #[test]
+    fn test_label_arithmetic() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_label_arithmetic.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[0], "ADDI $r0, $zero, 22");
+        assert_eq!(lines[1], "LUI $r0, 0");
+        assert_eq!(lines[2], "LW $r1, $r0, 2");
+        assert_eq!(lines[5], ".fill 5");
+        // the zeros of the .space are kept as a single line, which still takes up the 100 words before the second MOVI
+        assert_eq!(lines[6], "buf: .run 100 0x0000");
+        assert_eq!(label_table["buf"].address, 6);
+        assert_eq!(lines[7], "ADDI $r0, $zero, 2");
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_arithmetic_overflow() {
+        let mut lines = vec!["NOP".to_owned(), "end: MOVI $r0, @end+0xFFFF".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_arithmetic_underflow() {
+        let mut lines = vec!["start: .fill @start-1".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    fn test_label_difference() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_label_difference.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[0], "ADDI $r1, $zero, 6");
+        assert_eq!(lines[1], "LUI $r1, 0");
+        assert_eq!(lines[17], "msg_end: .fill 6");
+        assert_eq!(lines[18], ".fill 65530");
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_oversized_computed_fill() {
+        let mut lines = vec!["start: .space 20 []".to_owned(), "end: .fill (@end-@start)*0x1000".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_undersized_computed_fill() {
+        let mut lines = vec!["start: .space 20 []".to_owned(), "end: .fill (@start-@end)*0x1000".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_negative_label_difference() {
+        let mut lines = vec!["start: NOP".to_owned(), "end: MOVI $r0, @start-@end".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_undefined_label_difference() {
+        let mut lines = vec!["start: MOVI $r0, @start-@nowhere".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        substitute_labels(&lines, &label_table).unwrap();
+    }
+
+
+    #[test]
+    fn test_predefined_symbols() {
+        let filename = "test_files/test_predefined_symbols.asm";
+        let mut lines:Vec<String> = substitute_source_symbols(&get_line_vector(filename, false).unwrap(), filename);
+        lines.retain(|line| !line.is_empty());
+        lines = substitute_constants(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        assert_eq!(lines[0], "start: ADDI $r0, $zero, 1");
+        assert_eq!(lines[1], "ADDI $r6, $zero, 45");
+        assert_eq!(lines[2], "LUI $r6, 0");
+        assert_eq!(lines[3], "here: .fill 3");
+        assert_eq!(lines[4], "ADDI $r1, $zero, 5");
+        assert_eq!(lines[5], "LUI $r1, 0");
+        assert_eq!(lines[6], ".fill 0x0074");
+        assert_eq!(lines.len(), 45);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_predefined_symbol_as_label() {
+        let lines = vec!["__END__: NOP".to_owned()];
+        generate_label_table(&lines).unwrap();
+    }
+
+
+    #[test]
+    fn test_find_unresolved_labels() {
+        let lines:Vec<String> = ["start: MOVI $r1, @table", "", "BEQ $r1, $zero, @missing", ".fill @end-@start", ".text \"@quoted\"", ".fill @__ADDR__",
+            "table: .fill 0"].iter().map(|line| line.to_string()).collect();
+
+        assert_eq!(find_unresolved_labels(&lines), vec![(3, "missing".to_owned()), (4, "end".to_owned())]);
+        assert_eq!(find_unresolved_labels(&lines[..2]), vec![(1, "table".to_owned())]);
+    }
+
+
+    #[test]
+    fn test_find_references() {
+        let filename = "test_files/test_xref.asm";
+        let mut lines = get_line_vector(filename, false).unwrap();
+        let source_lines = lines.clone();
+        lines.retain(|line| !line.is_empty());
+        lines = substitute_pseudoinstrs(&lines);
+
+        let mut tags = generate_label_table(&lines).unwrap();
+        tags.locate_definitions(&source_lines);
+        let xref = find_references(&source_lines, &tags, filename);
+        assert_eq!(xref.keys().collect::<Vec<&String>>(), vec!["end", "loop", "start", "table"]);
+
+        // a forward reference, a backward one, and one from the data section, each on the line it was written on rather than the lines the MOVI expands to
+        let reference = |line:usize, instruction:&str| LabelReference { file: filename.to_owned(), line, instruction: instruction.to_owned() };
+        assert_eq!(tags["loop"].defined_at, SourceLoc { line: 3 });
+        assert_eq!(xref["loop"], vec![reference(1, "start: MOVI $r6, @loop"), reference(4, "MOVI $r6, @loop"), reference(9, "table: .fill @loop")]);
+        assert_eq!(xref["table"], vec![reference(10, ".fill @table+@table")]);
+        assert!(xref["start"].is_empty());
+        assert!(xref["end"].is_empty());
+
+        let lines:Vec<String> = ["start: NOP", ".text \"@start\"", ".fill @__ADDR__"].iter().map(|line| line.to_string()).collect();
+        let xref = find_references(&lines, &generate_label_table(&lines).unwrap(), "<string>");
+        assert!(xref["start"].is_empty());
+    }
+
+
+    #[test]
+    fn test_find_relocations() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_relocations.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        let relocations = find_relocations(&lines, &label_table).unwrap();
+
+        assert_eq!(relocations, vec![
+            (0, RelocationKind::Lo6),
+            (1, RelocationKind::Hi10),
+            (2, RelocationKind::Lo6),
+            (5, RelocationKind::Full16)
+        ]);
+    }
+
+
+    #[test]
+    fn test_take_linkage() {
+        let lines:Vec<String> = [".extern print", "start: NOP", ".export start", ".extern  table"].iter().map(|line| line.to_string()).collect();
+        let (new_lines, linkage) = take_linkage(&lines).unwrap();
+        assert_eq!(new_lines, vec!["", "start: NOP", "", ""]);
+        assert_eq!(linkage, Linkage { externs: vec!["print".to_owned(), "table".to_owned()], exports: vec!["start".to_owned()], entry: None });
+
+        let mut label_table = generate_label_table(&["start: NOP".to_owned()]).unwrap();
+        apply_linkage(&mut label_table, &linkage).unwrap();
+        assert!(label_table["start"].exported);
+
+        for invalid in [[".extern a", ".extern a"], [".extern a", ".export a"], [".export __END__", "NOP"], [".global a", ".global b"], [".global __END__", "NOP"]] {
+            let lines:Vec<String> = invalid.iter().map(|line| line.to_string()).collect();
+            assert!(take_linkage(&lines).is_err(), "{:?}", invalid);
+        }
+
+        // an exported label must be defined by the program, and an external one must not
+        let exports_undefined = Linkage { exports: vec!["end".to_owned()], ..Linkage::default() };
+        assert!(apply_linkage(&mut label_table.clone(), &exports_undefined).is_err());
+        let defines_extern = Linkage { externs: vec!["start".to_owned()], ..Linkage::default() };
+        assert!(apply_linkage(&mut label_table, &defines_extern).is_err());
+
+        let lines:Vec<String> = [".extern print", "MOVI $r6, @print", ".fill @other"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(find_unresolved_labels(&lines), vec![(3, "other".to_owned())]);
+    }
+
+
+    #[test]
+    fn test_link_relocations() {
+        let symbol = |name:&str, address:u16, section| Symbol { name: name.to_owned(), address, section, kind: LabelKind::Code, defined_at: SourceLoc { line: 1 },
+            exported: false };
+        let label_table:SymbolTable = vec![symbol("start", 2, Section::Code), symbol("end", 6, Section::Code), symbol("table", 1, Section::Data)].into();
+        let externs = vec!["print".to_owned()];
+        let lines:Vec<String> = [".fill @start+1", "ADDI $r1, $zero, @end-@start", "LUI $r1, @print+3", ".fill @__ADDR__", ".data", ".fill @table*2",
+            ".fill @end-@start+@table"].iter().map(|line| line.to_string()).collect();
+        let (resolved, relocations) = substitute_linked_labels(&lines, &label_table, 0, &externs).unwrap();
+        assert_eq!(resolved, vec![".fill 3", "ADDI $r1, $zero, 4", "LUI $r1, 0", ".fill 3", ".data", ".fill 2", ".fill 5"]);
+
+        // a difference does not move, and a label multiplied cannot be relocated, while the rest move with their section or the external label
+        let relocation = |section, index, symbol:&str, kind, addend| LinkRelocation { section, index, symbol: symbol.to_owned(), kind, addend };
+        assert_eq!(relocations, vec![
+            relocation(Section::Code, 0, ".code", RelocationKind::Full16, 3),
+            relocation(Section::Code, 2, "print", RelocationKind::Hi10, 3),
+            relocation(Section::Code, 3, ".code", RelocationKind::Full16, 3),
+            relocation(Section::Data, 1, ".data", RelocationKind::Full16, 5)
+        ]);
+
+        // the data section counts from where it is placed, but its relocations are within it
+        let mut placed = label_table.clone();
+        placed.insert(symbol("table", 0x101, Section::Data));
+        let (_, relocations) = substitute_linked_labels(&lines[4..], &placed, 0x100, &externs).unwrap();
+        assert_eq!(relocations[0], relocation(Section::Data, 1, ".data", RelocationKind::Full16, 5));
+
+        for invalid in [".fill @print-@start", ".fill @print*2", "ADDI $r1, $zero, @end-@print"] {
+            let err = substitute_linked_labels(&[invalid.to_owned()], &label_table, 0, &externs).unwrap_err();
+            assert!(err.to_string().contains("External label @print can only be used as an address plus a constant"), "{}", err);
+        }
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_non_existent_label_operand() {
+        let mut _lines = vec!["MOVI $r1, @nowhere".to_owned()];
+        _lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&_lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        _lines = substitute_pseudoinstrs(&_lines);
+
+        let label_table = generate_label_table(&_lines).unwrap();
+        _lines = substitute_labels(&_lines, &label_table).unwrap();
+    }
+}
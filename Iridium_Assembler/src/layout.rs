@@ -0,0 +1,204 @@
+use serde::{ Deserialize, Serialize };
+use crate::AssembledProgram;
+use crate::labels::{ LabelKind, Section, get_label_kind, get_section_switch };
+use crate::parser::{ LABEL_REGEX, get_mnemonic, get_word_count, parse_space };
+
+
+/// What the words of a region of memory were written as: instructions, data such as a `.fill`, `.text`, or the values given to a `.space`, the zeros filling out
+/// a `.space` past its values, or the zeros an `.org` skips over to reach its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    Code,
+    Data,
+    Padding,
+    Gap
+}
+
+impl RegionKind {
+    /// Gets the name the kind is given in the memory map: `CODE`, `DATA`, `PADDING`, or `GAP`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegionKind::Code => "CODE",
+            RegionKind::Data => "DATA",
+            RegionKind::Padding => "PADDING",
+            RegionKind::Gap => "GAP"
+        }
+    }
+}
+
+
+/// A run of consecutive words of one section which were all written as the same kind of thing, from `start` to `end` inclusive, along with the labels defined
+/// at its first word.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryRegion {
+    pub section: Section,
+    pub kind: RegionKind,
+    pub start: usize,
+    pub end: usize,
+    pub size: usize,
+    pub labels: Vec<String>
+}
+
+
+/// Two regions of a section which write the same words, as found by `expansion::substitute_org` when an `.org` moves back to an address the words before it already
+/// reach. `earlier` and `later` describe the regions by what starts them and the first label in them, and the words from `start` to `end` inclusive are written by
+/// both. The `.org` starting the later region is on `line`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overlap {
+    pub section: Section,
+    pub start: usize,
+    pub end: usize,
+    pub earlier: String,
+    pub later: String,
+    pub line: usize
+}
+
+impl Overlap {
+    /// Gives the words written twice and the regions writing them, such as `Words 0x0004-0x0005 of the code section are written by both the region from the start
+    /// of the code section, labelled start, and the region from .org 0x0004 on line 6, labelled handler`.
+    pub fn message(&self) -> String {
+        let (words, verb) = match self.start == self.end {
+            true => (format!("Word 0x{:04X}", self.start), "is"),
+            false => (format!("Words 0x{:04X}-0x{:04X}", self.start, self.end), "are")
+        };
+
+        let section = match self.section {
+            Section::Code => "code",
+            Section::Data => "data"
+        };
+
+        // a comma closes the label of the earlier region, so it is not read as part of the and
+        let separator = match self.earlier.contains(", labelled") {
+            true => ",",
+            false => ""
+        };
+        format!("{} of the {} section {} written by both {}{} and {}", words, section, verb, self.earlier, separator, self.later)
+    }
+}
+
+
+/// Splits the words of a line into the kinds of region they belong to, in the order they are written, with each given as its kind and number of words. Only an
+/// `.org` is written as a `.run` in the source lines of a program, as `expansion::substitute_org` gives them.
+fn line_regions(line:&str) -> Vec<(RegionKind, usize)> {
+    match (get_mnemonic(line), get_label_kind(line)) {
+        (".run", _) => vec![(RegionKind::Gap, get_word_count(line))],
+        (".space", _) => match parse_space(line) {
+            Ok((size, values)) => vec![(RegionKind::Data, values.len().min(size)), (RegionKind::Padding, size.saturating_sub(values.len()))],
+            Err(_) => vec![(RegionKind::Data, get_word_count(line))]
+        },
+        (_, LabelKind::Data) => vec![(RegionKind::Data, get_word_count(line))],
+        _ => vec![(RegionKind::Code, get_word_count(line))]
+    }
+}
+
+
+/// Finds the regions of memory a program takes up, in the order they are written in each section, with those of the code section first. Words of the same kind
+/// next to each other form a single region even where a label is defined part way through, so only the labels on the first word of each region are given. The
+/// regions of the data section start from the program's `data_base`, and a fixed address given by `.at` is not part of any region.
+pub fn memory_map(program:&AssembledProgram) -> Vec<MemoryRegion> {
+    let mut regions:Vec<MemoryRegion> = Vec::new();
+    let mut labels:Vec<String> = Vec::new();
+    let mut section = Section::Code;
+    let (mut code_address, mut data_address) = (0, program.data_base);
+    for line in &program.source_lines {
+        if let Some(next_section) = get_section_switch(line) {
+            section = next_section;
+            labels.clear();
+            continue;
+        } else if get_mnemonic(line) == ".at" {
+            continue;
+        }
+
+        if let Some(label) = LABEL_REGEX.find(line) {
+            labels.push(label.as_str().trim_end_matches(':').to_owned());
+        }
+
+        let address = match section {
+            Section::Code => &mut code_address,
+            Section::Data => &mut data_address
+        };
+
+        for (kind, size) in line_regions(line).into_iter().filter(|(_, size)| *size > 0) {
+            let last = regions.iter().rposition(|region| region.section == section);
+            match last.filter(|index| regions[*index].kind == kind && regions[*index].end + 1 == *address) {
+                Some(index) => {
+                    regions[index].end += size;
+                    regions[index].size += size;
+                    labels.clear();
+                },
+
+                None => regions.push(MemoryRegion { section, kind, start: *address, end: *address + size - 1, size, labels: std::mem::take(&mut labels) })
+            };
+
+            *address += size;
+        }
+    }
+
+    regions.sort_by_key(|region| region.section == Section::Data);
+    regions
+}
+
+
+/// Formats the memory map with one line per region giving its kind, the addresses of its first and last words, its size, and the labels at its start, such as
+/// `CODE     0x0000-0x0005      6 words  start`. The regions of the data section follow a `.data` line, as in the symbol map.
+pub fn format_memory_map(regions:&[MemoryRegion]) -> String {
+    let mut map = String::new();
+    let mut section = Section::Code;
+    for region in regions {
+        if region.section != section {
+            section = region.section;
+            map.push_str(".data\n");
+        }
+
+        let labels = match region.labels.is_empty() {
+            true => String::new(),
+            false => format!("  {}", region.labels.join(", "))
+        };
+
+        map.push_str(&format!("{:7}  0x{:04X}-0x{:04X}  {:>5} words{}\n", region.kind.name(), region.start, region.end, region.size, labels));
+    }
+
+    map
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ assemble_file, assemble_str };
+    use std::path::Path;
+
+
+    #[test]
+    fn test_memory_map() {
+        let program = assemble_file(Path::new("test_files/test_memory_map.asm")).unwrap();
+        let regions = memory_map(&program);
+        let region = |section, kind, start, end, labels:&[&str]| MemoryRegion { section, kind, start, end, size: end + 1 - start,
+            labels: labels.iter().map(|label| label.to_string()).collect() };
+        assert_eq!(regions, vec![
+            region(Section::Code, RegionKind::Code, 0, 4, &["start"]),
+            region(Section::Code, RegionKind::Data, 5, 7, &["table"]),
+            region(Section::Code, RegionKind::Code, 8, 10, &["next"]),
+            region(Section::Code, RegionKind::Gap, 11, 0x1F, &[]),
+            region(Section::Code, RegionKind::Code, 0x20, 0x21, &["handler"]),
+            region(Section::Data, RegionKind::Data, 0, 1, &["buffer"]),
+            region(Section::Data, RegionKind::Padding, 2, 9, &[])
+        ]);
+
+        assert_eq!(format_memory_map(&regions), "CODE     0x0000-0x0004      5 words  start\nDATA     0x0005-0x0007      3 words  table\n\
+            CODE     0x0008-0x000A      3 words  next\nGAP      0x000B-0x001F     21 words\nCODE     0x0020-0x0021      2 words  handler\n.data\n\
+            DATA     0x0000-0x0001      2 words  buffer\nPADDING  0x0002-0x0009      8 words\n");
+
+        let json = serde_json::to_string(&regions[3]).unwrap();
+        assert_eq!(json, r#"{"section":"code","kind":"gap","start":11,"end":31,"size":21,"labels":[]}"#);
+    }
+
+
+    #[test]
+    fn test_memory_map_sections() {
+        let program = assemble_str(".data\ncount: .fill 3\n.code\nmain: NOP\n.org 4\nJAL $zero, $r5\n").unwrap();
+        assert_eq!(memory_map(&program).iter().map(|region| (region.kind, region.start, region.size)).collect::<Vec<_>>(),
+            vec![(RegionKind::Code, 0, 1), (RegionKind::Gap, 1, 3), (RegionKind::Code, 4, 1), (RegionKind::Data, 0, 1)]);
+    }
+}
@@ -0,0 +1,519 @@
+use std::error::Error;
+use log::trace;
+use crate::{ AssemblyError, convert_to_i64 };
+use crate::isa::IsaSpec;
+use crate::parser::{ parse_pattern, parse_space, parse_text };
+
+
+/// A single token of the operands of a line of assembly. Operands are split on the blanks and commas between them, and each is classified by its form, with anything which is not a
+/// valid operand kept as `Other` so the parser can reject it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// A register by its number in the instruction set the line is parsed with, which by default is 0 for `$zero` and one more than the register's index for `$r0` to `$r6`.
+    Register(u8),
+    /// A decimal, binary, or hexadecimal integer as it was written.
+    Immediate(&'a str),
+    Char(char),
+    /// A string literal including its quotes.
+    Str(&'a str),
+    /// An expression containing at least one label, such as `@table+2`.
+    Expr(&'a str),
+    Comma,
+    /// A comment without its `#`.
+    Comment(&'a str),
+    Other(&'a str)
+}
+
+
+/// The kind of a line, which decides the operands it takes and how it is expanded and encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    /// An instruction of the RRR format, such as `ADD`, taking three registers.
+    Rrr,
+    /// An instruction of the RRI format, such as `ADDI`, taking two registers and an immediate.
+    Rri,
+    /// An instruction of the RI format, such as `LUI`, taking a register and an immediate.
+    Ri,
+    /// An instruction of the RR format, such as `JAL`, taking two registers, or a register and a label to jump to through the scratch register.
+    Jal,
+    Nop,
+    /// `LLI`, `MOVI`, or `MASK`, taking a register and an unsigned immediate.
+    Load,
+    Fill,
+    Space,
+    /// `.pattern`, which repeats the values in its brackets to fill the given number of words.
+    Pattern,
+    Text,
+    Syscall,
+    Section,
+    AssertSize,
+    /// `.at`, which gives its label a fixed address rather than taking up any words.
+    At,
+    /// `.org`, which pads its section out to the given address.
+    Org,
+    /// `.buildinfo`, which takes up a single word holding the build identifier given to the assembler.
+    BuildInfo
+}
+
+
+/// A line of assembly parsed into its label, mnemonic, and operands, along with the kind of line it is and the number of words it takes up once expanded, as
+/// given by `parser::get_word_count`. The operands of a `.space`, `.pattern`, or `.assert_size` are checked by their own rules and not split into tokens, and
+/// those of a `.text` are its string literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedLine<'a> {
+    pub label: Option<&'a str>,
+    pub mnemonic: &'a str,
+    pub kind: LineKind,
+    pub operands: Vec<Token<'a>>,
+    pub num_words: usize
+}
+
+
+/// Checks whether a character is a blank, which is a space or a tab.
+fn is_blank(c:char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+
+/// Checks whether a word is an integer in one of the forms accepted as an immediate, optionally preceded by zeros, with a sign allowed on a decimal only if `signed` is
+/// set. Binary and hexadecimal integers must use the lower case prefixes `0b` and `0x`.
+pub fn is_integer(word:&str, signed:bool) -> bool {
+    let is_core = |word:&str| {
+        if let Some(digits) = word.strip_prefix("0b") {
+            return !digits.is_empty() && digits.chars().all(|c| c == '0' || c == '1');
+        } else if let Some(digits) = word.strip_prefix("0x") {
+            return !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit());
+        }
+
+        let digits = match signed {
+            true => word.strip_prefix(['+', '-']).unwrap_or(word),
+            false => word
+        };
+
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+
+    let zeros = word.len() - word.trim_start_matches('0').len();
+    (0..=zeros).any(|start| is_core(&word[start..]))
+}
+
+
+/// Checks whether a word is an expression containing at least one label, made up only of the characters which may appear in an expression.
+fn is_label_expr(word:&str) -> bool {
+    let valid_chars = word.chars().all(|c| c.is_ascii_alphanumeric() || "-+*/%&|^<>~()_@".contains(c));
+    valid_chars && word.split('@').skip(1).any(|after| after.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_'))
+}
+
+
+/// Classifies a single operand word which is not a character literal.
+fn classify_word<'a>(word:&'a str, isa:&IsaSpec) -> Token<'a> {
+    let register = match word.starts_with('$') {
+        true => isa.get_register(word),
+        false => None
+    };
+
+    match register {
+        Some(val) => Token::Register(val),
+        None if is_integer(word, true) => Token::Immediate(word),
+        None if is_label_expr(word) => Token::Expr(word),
+        None => Token::Other(word)
+    }
+}
+
+
+/// Splits the operands of a line, everything after its mnemonic, into tokens. A comment runs from a `#` outside a character literal to the end of the line.
+pub fn tokenise_operands<'a>(operands:&'a str, isa:&IsaSpec) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < operands.len() {
+        let rest = &operands[index..];
+        let c = rest.chars().next().unwrap();
+        if is_blank(c) {
+            index += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            index += 1;
+        } else if c == '#' {
+            tokens.push(Token::Comment(&rest[1..]));
+            break;
+        } else if let Some(val) = rest.strip_prefix('\'').and_then(|val| val.chars().next()).filter(|val| val.is_ascii() && rest[2..].starts_with('\'')) {
+            tokens.push(Token::Char(val));
+            index += 3;
+        } else {
+            let end = rest.find(|c:char| is_blank(c) || c == ',' || c == '#').unwrap_or(rest.len());
+            tokens.push(classify_word(&rest[..end], isa));
+            index += end;
+        }
+    }
+
+    tokens
+}
+
+
+/// Checks that a comment only contains printable ASCII characters.
+fn is_valid_comment(comment:&str) -> bool {
+    comment.chars().all(|c| (' '..='~').contains(&c))
+}
+
+
+/// Finds the end of the string literal of a `.text`, which must be a single string of ASCII characters, optionally followed by `pad` and the length to pad it to and
+/// then by `nonull`, then by blanks and a comment. The string ends at the last quote which leaves a valid tail, so it may itself contain quotes.
+fn find_text_end(operands:&str) -> Option<usize> {
+    if !operands.starts_with('"') {
+        return None;
+    }
+
+    let is_valid_tail = |tail:&str| {
+        let (padding, comment) = tail.split_once('#').map_or((tail, None), |(padding, comment)| (padding, Some(comment)));
+        let padding = padding.trim_matches(is_blank);
+        let padding = match padding.strip_suffix("nonull") {
+            Some(val) if val.is_empty() || val.ends_with(is_blank) => val.trim_end_matches(is_blank),
+            _ => padding
+        };
+
+        let valid_padding = padding.is_empty() || padding.strip_prefix("pad").is_some_and(|val| val.starts_with(is_blank));
+        valid_padding && comment.is_none_or(is_valid_comment)
+    };
+
+    operands.match_indices('"').skip(1).map(|(end, _)| end).filter(|end| *end > 1 && operands[..=*end].is_ascii() && is_valid_tail(&operands[end + 1..])).last()
+}
+
+
+/// Gets the kind of line for a mnemonic, along with the operands it takes as the checks which each must pass, or `None` if the mnemonic is not known. Machine
+/// instructions are looked up in the given instruction set, and the pseudo-instructions and directives are fixed.
+#[allow(clippy::type_complexity)]
+pub(crate) fn get_line_kind(mnemonic:&str, isa:&IsaSpec) -> Option<(LineKind, Vec<fn(&Token) -> bool>)> {
+    let register:fn(&Token) -> bool = |token| matches!(token, Token::Register(_));
+    let signed_imm:fn(&Token) -> bool = |token| matches!(token, Token::Immediate(_) | Token::Expr(_));
+    let unsigned_imm:fn(&Token) -> bool = |token| match token {
+        Token::Immediate(val) => is_integer(val, false),
+        Token::Expr(_) => true,
+        _ => false
+    };
+
+    let fill:fn(&Token) -> bool = |token| matches!(token, Token::Immediate(_) | Token::Expr(_) | Token::Char(_));
+    let syscall:fn(&Token) -> bool = |token| matches!(token, Token::Immediate(val) if val.len() == 1 && ('0'..='7').contains(&val.chars().next().unwrap()));
+    let address:fn(&Token) -> bool = |token| matches!(token, Token::Immediate(val) if is_integer(val, false));
+    let jump_target:fn(&Token) -> bool = |token| matches!(token, Token::Register(_) | Token::Expr(_));
+
+    let imm = match isa.get_immediate(mnemonic) {
+        Some(field) if !field.signed => unsigned_imm,
+        _ => signed_imm
+    };
+
+    let kind = match (mnemonic, isa.get_kind(mnemonic)) {
+        (_, Some(LineKind::Rrr)) => (LineKind::Rrr, vec![register, register, register]),
+        (_, Some(LineKind::Rri)) => (LineKind::Rri, vec![register, register, imm]),
+        (_, Some(LineKind::Ri)) => (LineKind::Ri, vec![register, imm]),
+        (_, Some(LineKind::Jal)) => (LineKind::Jal, vec![register, jump_target]),
+        (_, Some(kind)) => (kind, vec![register, register]),
+        ("NOP", _) => (LineKind::Nop, vec![]),
+        ("LLI" | "MOVI" | "MASK", _) => (LineKind::Load, vec![register, unsigned_imm]),
+        (".fill", _) => (LineKind::Fill, vec![fill]),
+        (".syscall", _) => (LineKind::Syscall, vec![syscall]),
+        (".space", _) => (LineKind::Space, vec![]),
+        (".pattern", _) => (LineKind::Pattern, vec![]),
+        (".text", _) => (LineKind::Text, vec![]),
+        (".code" | ".data" | ".section", _) => (LineKind::Section, vec![]),
+        (".assert_size", _) => (LineKind::AssertSize, vec![]),
+        (".at", _) => (LineKind::At, vec![address]),
+        (".org", _) => (LineKind::Org, vec![address]),
+        (".buildinfo", _) => (LineKind::BuildInfo, vec![]),
+        _ => return None
+    };
+
+    Some(kind)
+}
+
+
+/// Parses a line of assembly into its label, mnemonic, kind, and operands in a single pass. A label must start the line, blanks are required between the mnemonic and
+/// its operands and allowed around the commas separating them, and the line may end with a comment. Section directives and `.assert_size` cannot have a label or be
+/// indented, a `.section` must name the `text` or `data` section, `.at` must have a label, and `.org` must not. The machine instructions and registers are
+/// those of `isa`.
+///
+/// Returns an `AssemblyError` if the line is not a valid instruction, with a specific message for a `JAL` without exactly two registers or an invalid `.space` or
+/// padded `.text`.
+pub fn parse_line<'a>(line:&'a str, isa:&IsaSpec) -> Result<ParsedLine<'a>, Box<dyn Error>> {
+    let invalid = || -> Box<dyn Error> { Box::new(AssemblyError(format!("Line did not match any valid instructions patterns: {}", line))) };
+
+    let name_len = line.find(|c:char| !(c.is_ascii_alphabetic() || c == '_')).unwrap_or(line.len());
+    let (label, rest) = match line[name_len..].starts_with(':') && name_len > 0 {
+        true => (Some(&line[..name_len]), &line[name_len + 1..]),
+        false => (None, line)
+    };
+
+    let start = rest.len() - rest.trim_start_matches(is_blank).len();
+    let rest = &rest[start..];
+    let mnemonic_len = rest.find(|c:char| is_blank(c) || c == '#').unwrap_or(rest.len());
+    let (mnemonic, operands) = rest.split_at(mnemonic_len);
+
+    let (kind, expected) = get_line_kind(mnemonic, isa).ok_or_else(invalid)?;
+    trace!("Classified {} as {:?}", line, kind);
+    let indented = label.is_some() || start > 0;
+    let mut num_words = match (kind, mnemonic) {
+        // the padding of an .org depends on where it is, so it is added by `expansion::substitute_org` once the whole section is known
+        (LineKind::Section | LineKind::AssertSize | LineKind::At | LineKind::Org, _) => 0,
+        (LineKind::Load, "MOVI" | "MASK") => 2,
+        _ => 1
+    };
+
+    let valid = match kind {
+        LineKind::Section if mnemonic == ".section" => {
+            !indented && operands.starts_with(is_blank) && matches!(operands.trim_matches(is_blank), "text" | "data")
+        },
+
+        LineKind::Section => !indented && operands.chars().all(is_blank),
+        LineKind::AssertSize => {
+            let limit = operands.trim_start_matches(is_blank);
+            let limit = ["<=", "<", "=="].iter().find_map(|comparison| limit.strip_prefix(comparison)).map(|val| val.trim_start_matches(is_blank));
+            !indented && operands.starts_with(is_blank) && limit.is_some_and(|val| !val.is_empty())
+        },
+
+        LineKind::At => label.is_some(),
+        LineKind::Org => label.is_none(),
+        LineKind::Space => {
+            num_words = parse_space(line)?.0;
+            true
+        },
+
+        LineKind::Pattern => {
+            num_words = parse_pattern(line)?.0;
+            true
+        },
+
+        LineKind::Text => {
+            let valid = operands.starts_with(is_blank) && find_text_end(operands.trim_start_matches(is_blank)).is_some();
+            if valid {
+                let (_, size, null_terminated) = parse_text(line)?;
+                num_words = size + null_terminated as usize;
+            }
+
+            valid
+        },
+        _ => true
+    };
+
+    if !valid {
+        return Err(invalid());
+    } else if kind == LineKind::Text {
+        let text = operands.trim_start_matches(is_blank);
+        let end = find_text_end(text).unwrap();
+        return Ok(ParsedLine { label, mnemonic, kind, operands: vec![Token::Str(&text[..=end])], num_words });
+    } else if matches!(kind, LineKind::Section | LineKind::AssertSize | LineKind::Space | LineKind::Pattern) {
+        return Ok(ParsedLine { label, mnemonic, kind, operands: Vec::new(), num_words });
+    }
+
+    let mut tokens = tokenise_operands(operands, isa);
+    if let Some(Token::Comment(comment)) = tokens.last() {
+        if !is_valid_comment(comment) {
+            return Err(invalid());
+        }
+
+        tokens.pop();
+    }
+
+    // the operands must alternate with the commas between them, and be separated from the mnemonic by at least one blank
+    let values:Vec<Token> = tokens.iter().step_by(2).copied().collect();
+    let well_formed = tokens.iter().skip(1).step_by(2).all(|token| *token == Token::Comma) && tokens.len() == (2 * values.len()).saturating_sub(1)
+        && (expected.is_empty() || operands.starts_with(is_blank));
+
+    if !well_formed || values.len() != expected.len() || !values.iter().zip(expected.iter()).all(|(token, check)| check(token)) {
+        if mnemonic == "JAL" {
+            return Err(Box::new(AssemblyError(format!("JAL takes exactly two registers, the register to save the return address to and the register holding the \
+                address to jump to, such as JAL $zero, $r6 to jump without saving the return address, or a label to jump to in place of the second register when \
+                assembling with a scratch register: {}", line))));
+        }
+
+        // an operand which starts like a hexadecimal or binary literal but is not one, such as 0xG, is most likely a mistyped literal
+        let malformed = values.iter().find_map(|token| match token {
+            Token::Other(word) if word.starts_with("0x") || word.starts_with("0b") => convert_to_i64(word).err(),
+            _ => None
+        });
+
+        return Err(match malformed {
+            Some(err) => Box::new(AssemblyError(format!("{} in instruction {}", err.0, line))),
+            None => invalid()
+        });
+    }
+
+    // a jump to a label becomes a MOVI of its address into the scratch register followed by a JAL through it
+    if kind == LineKind::Jal && matches!(values.last(), Some(Token::Expr(_))) {
+        num_words = 3;
+    }
+
+    Ok(ParsedLine { label, mnemonic, kind, operands: values, num_words })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use std::fs;
+    use lazy_static::lazy_static;
+    use regex::{ Regex, RegexSet };
+    use crate::parser::{ ASSERT_SIZE_REGEX, DATA_REGEX, FILL_REGEX, JAL_REGEX, NOP_REGEX, ORG_REGEX, PSEUDO_TEXT_REGEX, RI_REGEX, RRI_REGEX, RRR_REGEX, SCALL_REGEX,
+        SECTION_REGEX, get_line_vector, get_mnemonic };
+
+
+    lazy_static! {
+        /// The regexes lines were matched against one by one before `parse_line`, in the order they were tried, with the kind of line each matches.
+        static ref KIND_REGEXES:[(&'static Regex, LineKind); 12] = [(&*RRR_REGEX, LineKind::Rrr), (&*JAL_REGEX, LineKind::Jal), (&*NOP_REGEX, LineKind::Nop),
+            (&*RRI_REGEX, LineKind::Rri), (&*RI_REGEX, LineKind::Ri), (&*DATA_REGEX, LineKind::Load), (&*FILL_REGEX, LineKind::Fill),
+            (&*PSEUDO_TEXT_REGEX, LineKind::Text), (&*SCALL_REGEX, LineKind::Syscall), (&*SECTION_REGEX, LineKind::Section), (&*ASSERT_SIZE_REGEX, LineKind::AssertSize),
+            (&*ORG_REGEX, LineKind::Org)];
+        static ref KIND_SET:RegexSet = RegexSet::new(KIND_REGEXES.iter().map(|(regex, _)| regex.as_str())).unwrap();
+    }
+
+
+    /// Classifies a line by trying each of the `KIND_REGEXES` in turn, as lines were before `parse_line`.
+    fn classify_sequentially(line:&str) -> Option<LineKind> {
+        if get_mnemonic(line) == ".space" {
+            return parse_space(line).ok().map(|_| LineKind::Space);
+        }
+
+        KIND_REGEXES.iter().find(|(regex, _)| regex.is_match(line)).map(|(_, kind)| *kind)
+    }
+
+
+    /// Classifies a line by matching it against all of the `KIND_REGEXES` at once, taking the first which matches.
+    fn classify_with_set(line:&str) -> Option<LineKind> {
+        if get_mnemonic(line) == ".space" {
+            return parse_space(line).ok().map(|_| LineKind::Space);
+        }
+
+        KIND_SET.matches(line).iter().next().map(|index| KIND_REGEXES[index].1)
+    }
+
+
+    /// Classifies a line by its leading mnemonic, then matching it against only the one of the `KIND_REGEXES` for that kind of line, so an unknown mnemonic is
+    /// rejected without trying any regex.
+    fn classify_by_mnemonic(line:&str) -> Option<LineKind> {
+        let (kind, _) = get_line_kind(get_mnemonic(line), &DEFAULT_ISA_SPEC)?;
+        if kind == LineKind::Space {
+            return parse_space(line).ok().map(|_| LineKind::Space);
+        }
+
+        KIND_REGEXES.iter().find(|(_, regex_kind)| *regex_kind == kind).filter(|(regex, _)| regex.is_match(line)).map(|_| kind)
+    }
+
+
+    #[test]
+    fn test_parse_line() {
+        let parsed = parse_line("loop: ADDI $r0, $zero, -5 # count down", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(parsed.label, Some("loop"));
+        assert_eq!(parsed.mnemonic, "ADDI");
+        assert_eq!(parsed.kind, LineKind::Rri);
+        assert_eq!(parsed.operands, vec![Token::Register(1), Token::Register(0), Token::Immediate("-5")]);
+
+        let parsed = parse_line("\t.fill 'a'", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!((parsed.label, parsed.kind, parsed.operands), (None, LineKind::Fill, vec![Token::Char('a')]));
+
+        let parsed = parse_line(r#"msg: .text "say "hi"" # greeting"#, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(parsed.operands, vec![Token::Str(r#""say "hi"""#)]);
+
+        let parsed = parse_line("LUI $r6,@table+2", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(parsed.operands, vec![Token::Register(7), Token::Expr("@table+2")]);
+    }
+
+
+    #[test]
+    fn test_parse_line_org() {
+        let parsed = parse_line(".org 0x0100", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!((parsed.kind, parsed.operands, parsed.num_words), (LineKind::Org, vec![Token::Immediate("0x0100")], 0));
+        assert!(parse_line("handler: .org 0x0100", &DEFAULT_ISA_SPEC).is_err());
+        assert!(parse_line(".org @handler", &DEFAULT_ISA_SPEC).is_err());
+        assert!(parse_line(".org 0x10000", &DEFAULT_ISA_SPEC).is_ok()); // the address is range checked by `parser::validate_assembly_line`
+    }
+
+
+    #[test]
+    fn test_parse_line_buildinfo() {
+        let parsed = parse_line("version: .buildinfo", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!((parsed.label, parsed.kind, parsed.operands, parsed.num_words), (Some("version"), LineKind::BuildInfo, vec![], 1));
+        assert!(parse_line(".buildinfo 5", &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    fn test_parse_line_at() {
+        let parsed = parse_line("IO_PORT: .at 0xF000", &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!((parsed.label, parsed.kind, parsed.operands), (Some("IO_PORT"), LineKind::At, vec![Token::Immediate("0xF000")]));
+        assert!(parse_line(".at 0xF000", &DEFAULT_ISA_SPEC).is_err());
+        assert!(parse_line("IO_PORT: .at @table", &DEFAULT_ISA_SPEC).is_err());
+        assert!(parse_line("IO_PORT: .at -1", &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    fn test_parse_line_malformed_literal() {
+        let err = parse_line("ADDI $r0, $zero, 0xG", &DEFAULT_ISA_SPEC).unwrap_err().to_string();
+        assert!(err.contains("'0xG' is not a valid hexadecimal literal in instruction ADDI $r0, $zero, 0xG"), "{}", err);
+        assert!(parse_line(".fill 0b", &DEFAULT_ISA_SPEC).unwrap_err().to_string().contains("'0b' is not a valid binary literal"));
+        assert!(parse_line("ADDI $r0, $zero, five", &DEFAULT_ISA_SPEC).unwrap_err().to_string().contains("did not match any valid instructions patterns"));
+    }
+
+
+    #[test]
+    fn test_tokenise_operands() {
+        assert_eq!(tokenise_operands(" $r1 ,0x1F,'#' # note", &DEFAULT_ISA_SPEC), vec![Token::Register(2), Token::Comma, Token::Immediate("0x1F"), Token::Comma, Token::Char('#'),
+            Token::Comment(" note")]);
+        assert_eq!(tokenise_operands("$r7, foo", &DEFAULT_ISA_SPEC), vec![Token::Other("$r7"), Token::Comma, Token::Other("foo")]);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_line_missing_comma() {
+        parse_line("ADD $r0 $r1, $r2", &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_line_indented_section() {
+        parse_line("  .data", &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_parse_line_matches_regexes() {
+        let mut lines:Vec<String> = Vec::new();
+        for entry in fs::read_dir("test_files").unwrap() {
+            lines.extend(get_line_vector(entry.unwrap().path().to_str().unwrap(), true).unwrap());
+        }
+
+        let variants = [
+            "ADD $r0,$r1,$r2", "ADD\t$r0 ,\t$r1 , $r2\t", "ADD $r0, $r1", "ADD $r0, $r1, $r2,", "ADD$r0, $r1, $r2", "add $r0, $r1, $r2", "ADD $r0, $r1, $r7",
+            "ADDI $r0, $r1, +5", "ADDI $r0, $r1, 007", "ADDI $r0, $r1, 0xfF", "ADDI $r0, $r1, 0X1F", "ADDI $r0, $r1, 0b", "ADDI $r0, $r1, @a-@b", "ADDI $r0, $r1, @1",
+            "LUI $r0, -1", "LUI $r0, 00x10", "LLI $r0, @_start", "MOVI $r0, 65535 #", "JAL $r0", "JAL $r0, $r1, $r2", "JAL $r5, @handler+1", "JAL $r5, 4", "NOP", "NOP $r0", "  NOP # idle",
+            "label: NOP", "_: NOP", "la bel: NOP", "1abel: NOP", ".fill 'a'", ".fill ''", ".fill -3", ".fill @end", ".syscall 8", ".syscall 07", ".text \"\"",
+            ".text \"a\" extra", ".text\"a\"", "  .text \"a # b\" # c", ".code", ".data  ", "x: .data", ".section data", ".section\ttext ", ".section", ".section bss", ".sectiondata", ".assert_size <= 10", ".assert_size<10", ".assert_size ==", ".org 0x10", "x: .org 4", ".org",
+            ".space 4", ".space 4, 1", ".space -1", "ADD $r0, $r1, $r2 # caf\u{e9}", "", "   ", "# comment", "FOO $r0"
+        ];
+
+        lines.extend(variants.iter().map(|line| line.to_string()));
+        for line in lines {
+            let kind = classify_sequentially(&line);
+            assert_eq!(classify_with_set(&line), kind, "{}", line);
+            assert_eq!(classify_by_mnemonic(&line), kind, "{}", line);
+            assert_eq!(parse_line(&line, &DEFAULT_ISA_SPEC).ok().map(|parsed| parsed.kind), kind, "{}", line);
+        }
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_parse_line_large_input() {
+        let sample = ["start: ADDI $r0, $zero, 5", "ADD $r1, $r0, $r2 # sum", "LUI $r3, 0x3FF", "loop: BEQ $r0, $r1, $r2", "SW $r0, $r6, -3", "JAL $zero, $r6",
+            "NOP", ".fill 'x'", ".text \"hello\"", ".syscall 3", "MOVI $r4, @start+2"];
+        let lines:Vec<&str> = sample.iter().cycle().take(50_000).copied().collect();
+        for line in lines {
+            let kind = classify_sequentially(line);
+            assert!(kind.is_some(), "{}", line);
+            assert_eq!(classify_with_set(line), kind, "{}", line);
+            assert_eq!(classify_by_mnemonic(line), kind, "{}", line);
+            assert_eq!(parse_line(line, &DEFAULT_ISA_SPEC).ok().map(|parsed| parsed.kind), kind, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,1027 @@
+use std::{ fmt, error::Error };
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::num::IntErrorKind;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use ascii_converter::string_to_decimals;
+
+pub mod lexer;
+pub mod parser;
+pub mod expansion;
+pub mod labels;
+pub mod encoder;
+pub mod output;
+pub mod disassembler;
+pub mod isa;
+pub mod assembler;
+pub mod writer;
+pub mod object;
+pub mod linker;
+pub mod stream;
+pub mod repl;
+pub mod lint;
+pub mod diagnostics;
+pub mod analysis;
+pub mod layout;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
+
+pub use assembler::{ Assembler, AssemblerOptions };
+pub use encoder::{ Instruction, decode };
+
+use diagnostics::{ Diagnostic, DiagnosticSink, ENTRY_NOT_RECORDED, OVERLAPPING_REGIONS, UNUSED_LABEL };
+use expansion::MergedString;
+use isa::IsaSpec;
+use labels::{ CrossReference, LinkRelocation, RelocationKind, SourceLoc, Symbol, SymbolTable };
+use layout::Overlap;
+use parser::{ EncodingAnnotation, InputEncoding, LineSource, SourceMap };
+
+
+lazy_static! {
+    static ref CHAR_REGEX:Regex = Regex::new(r"'[[:ascii:]]'").unwrap();
+}
+
+
+#[derive(Debug)]
+pub struct AssemblyError(pub String);
+
+impl AssemblyError {
+    /// Gets the line of the source the error is on, counting from 1, if the message ends by giving one as errors in an instruction do.
+    pub fn line(&self) -> Option<usize> {
+        self.location().map(|(line, _)| line)
+    }
+
+
+    /// Gets the file the error is in if the message ends by giving it after the line, such as `on line 3 of kernel.asm`, as it does when the program was assembled
+    /// from several files by `assemble_sources`.
+    pub fn file(&self) -> Option<&str> {
+        self.location().and_then(|(_, file)| file)
+    }
+
+
+    /// Splits the end of the message into the line it gives and the file after it, if there is one.
+    fn location(&self) -> Option<(usize, Option<&str>)> {
+        let (_, location) = self.0.rsplit_once(" on line ")?;
+        let (line, file) = match location.split_once(" of ") {
+            Some((line, file)) => (line, Some(file)),
+            None => (location, None)
+        };
+
+        line.parse().ok().map(|line| (line, file))
+    }
+}
+
+impl Error for AssemblyError {}
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "AssemblyError: {}", self.0)
+    }
+}
+
+
+/// Checks whether the error from parsing an integer was because it is too large or too small for an `i64`, rather than not being an integer at all.
+fn is_overflow(kind:&IntErrorKind) -> bool {
+    matches!(kind, IntErrorKind::PosOverflow | IntErrorKind::NegOverflow)
+}
+
+
+/// Converts the digits after the prefix of a binary or hexadecimal literal, such as the `1F` of `0x1F`, where `name` names the base in the message given if the
+/// digits are missing or invalid.
+///
+/// Returns an error distinguishing a malformed literal, such as `0xG` or a lone `0x`, from one which does not fit in an `i64`.
+fn convert_prefixed(raw_string:&str, prefix:&str, radix:u32, name:&str) -> Result<i64, AssemblyError> {
+    match i64::from_str_radix(raw_string.trim_start_matches(prefix), radix) {
+        Ok(val) => Ok(val),
+        Err(e) if is_overflow(e.kind()) => Err(AssemblyError(format!("{} is outside the range of a 64-bit integer", raw_string))),
+        Err(_) => Err(AssemblyError(format!("'{}' is not a valid {} literal", raw_string, name)))
+    }
+}
+
+
+/// Converts a literal written with a trailing radix suffix as in other assemblers, which is `h` or `H` for hexadecimal such as `0FFh`, `b` for binary such as
+/// `1010b`, and `o` or `q` for octal such as `17o`. The digits must start with a decimal digit, so that a name such as `FFh` is not taken for a number, and must
+/// all be valid in the radix, so `10b` is the binary 2 while `1Bh` is the hexadecimal 27. A literal starting with the prefix `0x` or `0b`, after any leading zeros,
+/// is never read as suffixed, so `0x1b` is still the prefixed hexadecimal 27 and `0b` and `0b1h` are still malformed binary literals.
+///
+/// Returns `None` if the literal is not in this form, or an error if it is but does not fit in an `i64`.
+fn convert_suffixed(raw_string:&str) -> Option<Result<i64, AssemblyError>> {
+    let body = raw_string.trim_start_matches('0');
+    if body.len() < raw_string.len() && body.starts_with(['x', 'b']) {
+        return None;
+    }
+
+    let (digits, radix) = match raw_string.char_indices().last()? {
+        (end, 'h' | 'H') => (&raw_string[..end], 16),
+        (end, 'b') => (&raw_string[..end], 2),
+        (end, 'o' | 'q') => (&raw_string[..end], 8),
+        _ => return None
+    };
+
+    if !digits.starts_with(|c:char| c.is_ascii_digit()) || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    Some(i64::from_str_radix(digits, radix).map_err(|_| AssemblyError(format!("{} is outside the range of a 64-bit integer", raw_string))))
+}
+
+
+/// Takes a string formatted either as a decimal (signed or unsigned), binary (prefixed with "0b" or suffixed with "b"), octal (suffixed with "o" or "q"), or
+/// hexadecimal (prefixed with "0x" or suffixed with "h"), and outputs it as an `i64`. It may also take a character as an input which conforms to the RegEx
+/// r"^'[[:ascii:]]'$" and will output the ASCII value of that character.
+///
+/// Returns an error if the value passed is not a decimal, hexadecimal, octal, or binary integer or not a single character in single quotes, with a specific message
+/// for a prefixed literal with missing or invalid digits and for an integer too large for an `i64`.
+pub fn convert_to_i64(raw_string:&str) -> Result<i64, AssemblyError> {
+    if let Some(imm) = convert_suffixed(raw_string) {
+        return imm;
+    }
+
+    let imm:i64;
+    if raw_string.contains("0x") {  // hexadecimal number
+        imm = convert_prefixed(raw_string, "0x", 16, "hexadecimal")?;
+    } else if raw_string.contains("0b") { // binary number
+        imm = convert_prefixed(raw_string, "0b", 2, "binary")?;
+    } else {
+        imm = match raw_string.parse::<i64>() {
+            Ok(val) => val,
+            Err(e) if is_overflow(e.kind()) => return Err(AssemblyError(format!("{} is outside the range of a 64-bit integer", raw_string))),
+            Err(_) => {
+                if CHAR_REGEX.find(raw_string).is_none() {
+                    return Err(AssemblyError(format!("Could not convert from {} to i64", raw_string)))
+                }
+
+                match string_to_decimals(&raw_string[1..2]) {
+                    Ok(val) => *val.first().unwrap() as i64,
+                    Err(_) => { return Err(AssemblyError(format!("Could not convert from {} to i64", raw_string))) }
+                }
+            }
+        };
+    }
+
+    Ok(imm)
+}
+
+
+/// Takes a literal in any of the forms accepted by `convert_to_i64` and checks that it fits in an immediate field of the given number of bits, which holds values from
+/// 0 to 2^bits - 1 if unsigned, or from -2^(bits - 1) to 2^(bits - 1) - 1 if signed.
+///
+/// Returns an error if the literal cannot be converted or is outside the range of the field.
+pub fn parse_immediate(raw_string:&str, bits:u32, signed:bool) -> Result<i64, AssemblyError> {
+    let imm = convert_to_i64(raw_string)?;
+    let (min, max) = if signed { (-(2_i64.pow(bits) / 2), (2_i64.pow(bits) / 2) - 1) } else { (0, 2_i64.pow(bits) - 1) };
+
+    if !signed && imm < 0 {
+        return Err(AssemblyError(format!("Found negative immediate {} in unsigned immediate field", imm)));
+    } else if signed && imm > max && imm < 2_i64.pow(bits) {
+        // the bit pattern fits the field, so this is most likely someone expecting the top half of the field to be positive
+        return Err(AssemblyError(format!("Found immediate {} outside the signed {}-bit range {} to {}; values above {} would wrap to {}, so write {} if that is \
+            what you meant", imm, bits, min, max, max, imm - 2_i64.pow(bits), imm - 2_i64.pow(bits))));
+    } else if imm < min || imm > max {
+        let kind = if signed { "signed" } else { "unsigned" };
+        return Err(AssemblyError(format!("Found immediate {} outside the {} {}-bit range {} to {}", imm, kind, bits, min, max)));
+    }
+
+    Ok(imm)
+}
+
+
+/// The result of evaluating an expression. `label_weight` counts the labels in the expression, with subtracted labels counting as -1, so an expression with a weight
+/// of 1 such as `@table+2` is an address, and one with a weight of 0 such as `@end-@start` is a plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprValue {
+    pub value: i64,
+    pub label_weight: i64
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExprToken {
+    Number(i64),
+    Constant(String),
+    Label(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen
+}
+
+
+/// Splits an expression into numbers, character literals such as `'A'` (as their ASCII values), constant names, `@` labels, operators, and parentheses.
+fn tokenise_expression(expr:&str) -> Result<Vec<ExprToken>, AssemblyError> {
+    let chars:Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let start = index;
+        let c = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' || c == '@' {
+            index += 1;
+            while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '_') {
+                index += 1;
+            }
+
+            let word:String = chars[start..index].iter().collect();
+            tokens.push(if c.is_ascii_digit() {
+                match convert_to_i64(&word) {
+                    Ok(val) => ExprToken::Number(val),
+                    Err(err) => return Err(AssemblyError(format!("Invalid number {} in expression {}: {}", word, expr, err.0)))
+                }
+            } else if let Some(name) = word.strip_prefix('@') {
+                if name.is_empty() || name.starts_with(|first:char| first.is_ascii_digit()) {
+                    return Err(AssemblyError(format!("Invalid label {} in expression {}", word, expr)));
+                }
+
+                ExprToken::Label(name.to_owned())
+            } else {
+                ExprToken::Constant(word)
+            });
+
+            continue;
+        }
+
+        if c == '\'' {
+            if index + 2 >= chars.len() || chars[index + 2] != '\'' {
+                return Err(AssemblyError(format!("Unterminated character literal in expression {}", expr)));
+            }
+
+            let literal:String = chars[index..index + 3].iter().collect();
+            match convert_to_i64(&literal) {
+                Ok(val) => tokens.push(ExprToken::Number(val)),
+                Err(_) => return Err(AssemblyError(format!("Invalid character {} in expression {}", literal, expr)))
+            };
+
+            index += 3;
+            continue;
+        }
+
+        let two_chars:String = chars[index..(index + 2).min(chars.len())].iter().collect();
+        let token = match (c, two_chars.as_str()) {
+            (_, "<<") => { index += 1; ExprToken::Operator("<<") },
+            (_, ">>") => { index += 1; ExprToken::Operator(">>") },
+            ('+', _) => ExprToken::Operator("+"),
+            ('-', _) => ExprToken::Operator("-"),
+            ('*', _) => ExprToken::Operator("*"),
+            ('/', _) => ExprToken::Operator("/"),
+            ('%', _) => ExprToken::Operator("%"),
+            ('&', _) => ExprToken::Operator("&"),
+            ('|', _) => ExprToken::Operator("|"),
+            ('^', _) => ExprToken::Operator("^"),
+            ('~', _) => ExprToken::Operator("~"),
+            ('(', _) => ExprToken::OpenParen,
+            (')', _) => ExprToken::CloseParen,
+            _ => return Err(AssemblyError(format!("Unexpected character '{}' in expression {}", c, expr)))
+        };
+
+        tokens.push(token);
+        index += 1;
+    }
+
+    Ok(tokens)
+}
+
+
+/// A recursive-descent parser which evaluates an expression as it parses it. Operators bind in the same order as in C, from loosest to tightest: `|`, `^`, `&`,
+/// `<<` and `>>`, `+` and `-`, `*`, `/` and `%`, and finally the unary operators `-`, `+`, and `~`.
+struct ExprParser<'a> {
+    expr: &'a str,
+    tokens: Vec<ExprToken>,
+    pos: usize,
+    constants: &'a HashMap<String, i64>,
+    labels: &'a HashMap<String, i64>
+}
+
+impl<'a> ExprParser<'a> {
+    /// The binary operators at each level of precedence, from loosest to tightest.
+    const PRECEDENCE:[&'static [&'static str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+
+    fn error(&self, msg:&str) -> AssemblyError {
+        AssemblyError(format!("{} in expression {}", msg, self.expr))
+    }
+
+
+    fn overflow(&self) -> AssemblyError {
+        self.error("Arithmetic overflow")
+    }
+
+
+    fn parse_binary(&mut self, level:usize) -> Result<ExprValue, AssemblyError> {
+        if level == Self::PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some(ExprToken::Operator(op)) = self.tokens.get(self.pos) {
+            let op = *op;
+            if !Self::PRECEDENCE[level].contains(&op) {
+                break;
+            }
+
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+
+    fn apply(&self, op:&str, lhs:ExprValue, rhs:ExprValue) -> Result<ExprValue, AssemblyError> {
+        let (a, b) = (lhs.value, rhs.value);
+        let value = match op {
+            "+" => a.checked_add(b),
+            "-" => a.checked_sub(b),
+            "*" => a.checked_mul(b),
+            "/" | "%" if b == 0 => return Err(self.error("Division by zero")),
+            "/" => a.checked_div(b),
+            "%" => a.checked_rem(b),
+            "&" => Some(a & b),
+            "|" => Some(a | b),
+            "^" => Some(a ^ b),
+            "<<" | ">>" if !(0..64).contains(&b) => return Err(self.error(&format!("Cannot shift by {}", b))),
+            "<<" => a.checked_shl(b as u32).filter(|val| val >> b == a),
+            ">>" => Some(a >> b),
+            _ => unreachable!()
+        }.ok_or_else(|| self.overflow())?;
+
+        let label_weight = match op {
+            "+" => lhs.label_weight + rhs.label_weight,
+            "-" => lhs.label_weight - rhs.label_weight,
+            _ => 0
+        };
+
+        Ok(ExprValue { value, label_weight })
+    }
+
+
+    fn parse_unary(&mut self) -> Result<ExprValue, AssemblyError> {
+        let op = match self.tokens.get(self.pos) {
+            Some(ExprToken::Operator(op @ ("-" | "+" | "~"))) => *op,
+            _ => return self.parse_primary()
+        };
+
+        self.pos += 1;
+        let operand = self.parse_unary()?;
+        match op {
+            "-" => Ok(ExprValue { value: operand.value.checked_neg().ok_or_else(|| self.overflow())?, label_weight: -operand.label_weight }),
+            "~" => Ok(ExprValue { value: !operand.value, label_weight: 0 }),
+            _ => Ok(operand)
+        }
+    }
+
+
+    fn parse_primary(&mut self) -> Result<ExprValue, AssemblyError> {
+        let token = match self.tokens.get(self.pos) {
+            Some(val) => val.clone(),
+            None => return Err(self.error("Unexpected end"))
+        };
+
+        self.pos += 1;
+        match token {
+            ExprToken::Number(value) => Ok(ExprValue { value, label_weight: 0 }),
+            ExprToken::Constant(name) => match self.constants.get(&name) {
+                Some(value) => Ok(ExprValue { value: *value, label_weight: 0 }),
+                None => Err(self.error(&format!("Undefined constant {}", name)))
+            },
+
+            ExprToken::Label(name) => match self.labels.get(&name) {
+                Some(value) => Ok(ExprValue { value: *value, label_weight: 1 }),
+                None => Err(self.error(&format!("Could not find label @{}", name)))
+            },
+
+            ExprToken::OpenParen => {
+                let inner = self.parse_binary(0)?;
+                if self.tokens.get(self.pos) != Some(&ExprToken::CloseParen) {
+                    return Err(self.error("Expected ')'"));
+                }
+
+                self.pos += 1;
+                Ok(inner)
+            },
+
+            _ => Err(self.error(&format!("Unexpected {:?}", token)))
+        }
+    }
+}
+
+
+/// Evaluates an expression made up of integer and character literals in any of the forms accepted by `convert_to_i64`, names from `constants`, `@` label references resolved with
+/// `labels`, parentheses, and the operators `+ - * / % & | ^ << >> ~`.
+///
+/// Returns an error if the expression is malformed, refers to an undefined constant or label, divides by zero, or overflows an `i64`.
+pub fn evaluate_expression(expr:&str, constants:&HashMap<String, i64>, labels:&HashMap<String, i64>) -> Result<ExprValue, AssemblyError> {
+    let mut parser = ExprParser { expr, tokens: tokenise_expression(expr)?, pos: 0, constants, labels };
+    let result = parser.parse_binary(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.error(&format!("Unexpected {:?}", parser.tokens[parser.pos])));
+    }
+
+    Ok(result)
+}
+
+
+/// How long each stage of assembly took, by the name of the stage, in the order they ran.
+pub type StageTimings = Vec<(&'static str, Duration)>;
+
+
+/// A program assembled by `assemble_source`. Each section is given as its words along with the lines they were assembled from, once pseudo-instructions have been
+/// expanded and labels resolved. A long run of words holding the same value, such as the zeros filling out a large `.space`, is kept as a single `.run` line, so
+/// the `i`th line given by `expansion::expand_runs(&code_lines)` is the source of `code[i]`. The `xref` gives the source lines referring to each label, as found
+/// by `labels::find_references`, and `source_lines` are the lines of the source once its constants were substituted, with the line numbered `n` at index `n - 1`.
+/// The word `data[i]` is at address `data_base + i`, where `data_base` is 0 unless the data section was placed elsewhere with a `labels::DataPlacement`. The
+/// `link_relocations` are the words to patch when the program is linked with others, including every reference to a label declared with `.extern`, which is
+/// resolved as if it were at address 0 until then. The `sources` trace each of the `source_lines`, and so the line each label is defined on, back to the file it
+/// was read from, which matters for a program assembled from several files by `assemble_sources`. The `entry` is the label the program starts running from, if
+/// one was declared with `.global` or set with `set_entry`, and otherwise the program starts from word 0 of the code section. The `overlaps` are the regions
+/// started by `.org` which wrote over the words before them, which is only allowed by `Assembler::allow_overlap`, and the `merged_strings` are the copies of
+/// strings left out by `Assembler::merge_strings`, whose labels point at the copy kept instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledProgram {
+    pub code: Vec<u16>,
+    pub data: Vec<u16>,
+    pub code_lines: Vec<String>,
+    pub data_lines: Vec<String>,
+    pub labels: SymbolTable,
+    pub relocations: Vec<(usize, RelocationKind)>,
+    pub xref: CrossReference,
+    pub source_lines: Vec<String>,
+    pub data_base: usize,
+    pub link_relocations: Vec<LinkRelocation>,
+    pub sources: SourceMap,
+    pub entry: Option<Symbol>,
+    pub overlaps: Vec<Overlap>,
+    pub merged_strings: Vec<MergedString>
+}
+
+impl AssembledProgram {
+    /// Gets the names of the labels declared with `.extern` which the program refers to, in the order they are first referred to, which must be defined by another
+    /// program before this one can run.
+    pub fn external_labels(&self) -> Vec<&str> {
+        let mut names:Vec<&str> = Vec::new();
+        for relocation in self.link_relocations.iter().filter(|relocation| relocation.is_external()) {
+            if !names.contains(&relocation.symbol.as_str()) {
+                names.push(&relocation.symbol);
+            }
+        }
+
+        names
+    }
+
+
+    /// Sets the label the program starts running from, replacing any declared with `.global`.
+    ///
+    /// Returns an `AssemblyError` if the label is not defined in the program or is not an instruction in the code section, as for `labels::resolve_entry`.
+    pub fn set_entry(&mut self, name:&str) -> Result<(), AssemblyError> {
+        self.entry = Some(labels::resolve_entry(&self.labels, name).map_err(into_assembly_error)?);
+        Ok(())
+    }
+
+
+    /// Gives the warning for an entry point other than word 0 when the program is written in a format which does not record it, such as a raw binary image, as a
+    /// loader of such an image can only start running it from word 0. The warning is on the line defining the entry point's label, in the source named `file`.
+    pub fn entry_warning(&self, file:&str) -> Option<Diagnostic> {
+        let entry = self.entry.as_ref().filter(|entry| entry.address != 0)?;
+        let message = format!("Entry point {} is at 0x{:04X} rather than word 0, which the output format does not record, so the program will not start there",
+            entry.name, entry.address);
+        Some(self.sources.locate_diagnostic(Diagnostic::warning(ENTRY_NOT_RECORDED, message, file, Some(entry.defined_at.line))))
+    }
+
+
+    /// Gives a warning for each region which was allowed to write over the words before it, on the line of the `.org` starting it in the source named `file`.
+    pub fn overlap_warnings(&self, file:&str) -> Vec<Diagnostic> {
+        self.overlaps.iter().map(|overlap| {
+            let message = format!("{}, so the later words are written over the earlier ones", overlap.message());
+            self.sources.locate_diagnostic(Diagnostic::warning(OVERLAPPING_REGIONS, message, file, Some(overlap.line)))
+        }).collect()
+    }
+
+
+    /// Gives every warning about the program in the source named `file`: the `overlap_warnings`, then each block of data in the code section which execution
+    /// falls through into and each label nothing refers to, as found by `lint::find_data_in_code` with the instruction set it was assembled with and
+    /// `lint::find_unused_labels`.
+    pub fn warnings(&self, file:&str, isa:&IsaSpec) -> Vec<Diagnostic> {
+        let mut warnings = self.overlap_warnings(file);
+        warnings.extend(lint::find_data_in_code(&self.code_lines, isa).iter().map(|found| found.warning(file, false)));
+        warnings.extend(lint::find_unused_labels(&self.xref, &self.labels).into_iter().map(|name| {
+            Diagnostic::warning(UNUSED_LABEL, format!("Label {} is never referenced", name), file, Some(self.labels[name].defined_at.line))
+        }));
+
+        warnings
+    }
+}
+
+
+/// Converts any error from a stage of the assembler into an `AssemblyError`, keeping its message.
+pub(crate) fn into_assembly_error(err:Box<dyn Error>) -> AssemblyError {
+    match err.downcast::<AssemblyError>() {
+        Ok(val) => *val,
+        Err(err) => AssemblyError(err.to_string())
+    }
+}
+
+
+/// Assembles the lines of a program as returned by `parser::get_source_lines`, running every stage of the assembler from substituting the source symbols through to
+/// encoding each section and checking the encodings the lines are annotated with, and calling `end_stage` with the name of each stage as it finishes. `filename`
+/// is the name `__FILE__` is replaced with, and each stage is run with the `options` it depends on.
+///
+/// Returns an `AssemblyError` if any stage fails, a `.assert_size` does not hold, a line does not assemble to the words it is annotated with, or the program has a
+/// warning in strict mode.
+fn assemble_lines(lines:&[String], annotations:&[EncodingAnnotation], filename:&str, options:&AssemblerOptions, end_stage:&mut dyn FnMut(&'static str))
+        -> Result<AssembledProgram, AssemblyError> {
+    let isa = options.isa_spec();
+    let mut lines = expansion::substitute_source_symbols(lines, filename);
+    lines = expansion::substitute_register_aliases(&lines, isa).map_err(into_assembly_error)?;
+    let (lines_without_linkage, linkage) = labels::take_linkage(&lines).map_err(into_assembly_error)?;
+    lines = expansion::substitute_constants(&lines_without_linkage, isa).map_err(into_assembly_error)?;
+    parser::validate_assembly_lines(&lines, isa).map_err(into_assembly_error)?;
+    let (merged_lines, merged_strings) = match options.merge_strings {
+        true => expansion::merge_strings(&lines),
+        false => (lines, Vec::new())
+    };
+
+    let (org_lines, overlaps) = expansion::substitute_org(&merged_lines, options.allow_overlap).map_err(into_assembly_error)?;
+    lines = org_lines;
+    lines = expansion::substitute_build_info(&lines, options.build_id);
+    info!("Validated {} lines of {}", lines.len(), filename);
+    end_stage("validation");
+
+    let source_lines = lines.clone();
+    lines.retain(|line| !line.is_empty());
+    let (lines_without_assertions, size_assertions) = expansion::take_size_assertions(&lines);
+    let (lines_without_fixed, fixed_labels) = labels::take_fixed_labels(&lines_without_assertions);
+    let lines_with_jumps = expansion::substitute_label_jumps(&lines_without_fixed, options.scratch_register.as_deref(), isa).map_err(into_assembly_error)?;
+    lines = expansion::substitute_pseudoinstrs(&lines_with_jumps);
+    info!("Expanded the pseudo-instructions of {} lines into {} lines", lines_with_jumps.len(), lines.len());
+    end_stage("pseudo-instruction expansion");
+
+    let mut label_table = labels::generate_label_table(&lines).map_err(into_assembly_error)?;
+    for merged in &merged_strings {
+        let kept = label_table[merged.kept.as_str()].clone();
+        label_table.insert(Symbol { name: merged.label.clone(), defined_at: SourceLoc { line: merged.line }, ..kept });
+    }
+
+    let (code_size, data_size) = labels::section_sizes(&lines);
+    let data_base = options.data_placement.base(code_size);
+    labels::place_data_labels(&mut label_table, data_base, data_size).map_err(into_assembly_error)?;
+    labels::add_fixed_labels(&mut label_table, &fixed_labels, code_size, data_size, data_base).map_err(into_assembly_error)?;
+    labels::apply_linkage(&mut label_table, &linkage).map_err(into_assembly_error)?;
+    label_table.locate_definitions(&source_lines);
+    let entry = linkage.entry.as_deref().map(|name| labels::resolve_entry(&label_table, name)).transpose().map_err(into_assembly_error)?;
+    let xref = labels::find_references(&source_lines, &label_table, filename);
+    let relocations = labels::find_relocations(&lines, &label_table).map_err(into_assembly_error)?;
+    info!("Found {} labels and {} relocations, with {} words of code and {} of data", label_table.len(), relocations.len(), code_size, data_size);
+    end_stage("label table generation");
+
+    let (resolved_lines, link_relocations) = labels::substitute_linked_labels(&lines, &label_table, data_base, &linkage.externs).map_err(into_assembly_error)?;
+    lines = resolved_lines;
+    let (code_lines, data_lines) = labels::split_sections(&lines);
+    expansion::check_size_assertions(&size_assertions, code_size, data_size).map_err(into_assembly_error)?;
+    end_stage("label substitution");
+
+    let code = encoder::assemble_section(&code_lines, isa).map_err(into_assembly_error)?;
+    let data = encoder::assemble_section(&data_lines, isa).map_err(into_assembly_error)?;
+    parser::check_encoding_annotations(&source_lines, annotations, &code, &data).map_err(into_assembly_error)?;
+    info!("Encoded {} words of code and {} of data", code.len(), data.len());
+    end_stage("encoding");
+
+    let mut sources = SourceMap::new();
+    sources.push(filename, source_lines.len());
+    let program = AssembledProgram { code, data, code_lines, data_lines, labels: label_table, relocations, xref, source_lines, data_base,
+        link_relocations, sources, entry, overlaps, merged_strings };
+
+    // in strict mode the first warning stops the program being assembled, in the same order as `assemble_source_reporting` gives them
+    let first_warning = match options.strict {
+        true => program.warnings(filename, isa).into_iter().next(),
+        false => None
+    };
+
+    if let Some(warning) = first_warning {
+        return Err(AssemblyError(match warning.line {
+            Some(line) => format!("{} on line {}", warning.message, line),
+            None => warning.message
+        }));
+    }
+
+    Ok(program)
+}
+
+
+/// Reads the lines of the given source as the options say to decode it and removes their comments, checking for tabs if the options reject them, along with the
+/// encodings any lines are annotated with. In strict mode invalid UTF-8 is rejected even when reading lossily, rather than replaced with a warning.
+///
+/// Returns an `AssemblyError` if the source cannot be read, contains a tab when they are not allowed, or has an annotation which is not a list of words.
+fn read_source(source:LineSource, options:&AssemblerOptions) -> Result<(Vec<String>, Vec<EncodingAnnotation>), AssemblyError> {
+    let raw_lines = parser::read_lines(source, options.lossy && !options.strict, options.encoding).map_err(into_assembly_error)?;
+    clean_lines(&raw_lines, options.no_tabs)
+}
+
+
+/// Removes the comments from the lines read from a source, first checking for tabs if `no_tabs` is set, and gives the encodings found in the comments starting
+/// with `#=` alongside the lines.
+///
+/// Returns an `AssemblyError` if a line contains a tab when they are not allowed, the last line is continued, or an annotation is not a list of words.
+fn clean_lines(raw_lines:&[String], no_tabs:bool) -> Result<(Vec<String>, Vec<EncodingAnnotation>), AssemblyError> {
+    if no_tabs {
+        parser::check_no_tabs(raw_lines).map_err(into_assembly_error)?;
+    }
+
+    let lines = parser::clean_source_lines(raw_lines).map_err(into_assembly_error)?;
+    let annotations = parser::find_encoding_annotations(raw_lines).map_err(into_assembly_error)?;
+    Ok((lines, annotations))
+}
+
+
+/// Reads and assembles the program from the given source with the given options, which say how the source is decoded, whether a tab in it is an error, and how
+/// the program is laid out. Errors in an instruction give its line number within the source.
+///
+/// Returns an `AssemblyError` if the source cannot be read, contains a tab when they are not allowed, or the program cannot be assembled.
+pub fn assemble_source(source:LineSource, options:&AssemblerOptions) -> Result<AssembledProgram, AssemblyError> {
+    // nothing here may read the clock, as `Instant::now` panics on targets without one such as WebAssembly
+    let (lines, annotations) = read_source(source, options)?;
+    assemble_lines(&lines, &annotations, source.name(), options, &mut |_| {})
+}
+
+
+/// Reads and assembles the program from any reader in the same way as `assemble_source`, such as from a network stream or a `Cursor` in memory. `name` is used in
+/// messages and for `__FILE__`.
+///
+/// Returns an `AssemblyError` if the reader fails, the source contains a tab when they are not allowed, or the program cannot be assembled.
+pub fn assemble_reader(reader:impl BufRead, name:&str, options:&AssemblerOptions) -> Result<AssembledProgram, AssemblyError> {
+    let raw_lines = parser::read_lines_from(reader, name, options.lossy && !options.strict, options.encoding).map_err(into_assembly_error)?;
+    let (lines, annotations) = clean_lines(&raw_lines, options.no_tabs)?;
+    assemble_lines(&lines, &annotations, name, options, &mut |_| {})
+}
+
+
+/// Assembles a program in the same way as `assemble_source`, also giving how long each stage of assembly took, in the order they ran, for finding which stage is
+/// slow on large programs.
+///
+/// Returns an `AssemblyError` if the program cannot be read or assembled.
+pub fn assemble_source_timed(source:LineSource, options:&AssemblerOptions) -> Result<(AssembledProgram, StageTimings), AssemblyError> {
+    let mut timings = Vec::new();
+    let mut start = Instant::now();
+    let mut end_stage = |stage:&'static str| {
+        timings.push((stage, start.elapsed()));
+        start = Instant::now();
+    };
+
+    let (lines, annotations) = read_source(source, options)?;
+    end_stage("reading");
+
+    let program = assemble_lines(&lines, &annotations, source.name(), options, &mut end_stage)?;
+    Ok((program, timings))
+}
+
+
+/// Reads and assembles a program from several sources as if they were one, joining their lines in the order given so that they share one namespace of labels,
+/// constants, and register aliases, and calling `end_stage` with the name of each stage as it finishes. `__FILE__` and `__LINE__` give the source and line each
+/// was written on, as do the `xref` and every error naming a line, while the `sources` of the program trace its `source_lines` back to their sources. A source
+/// carries on in the section the one before it ended in, so one following a source which ends in its data section must switch back with `.code` to hold code.
+///
+/// Returns an `AssemblyError` if a source cannot be read, a label is defined twice, naming where both definitions are, a label is referred to but defined by no
+/// source, or the program cannot be assembled.
+fn assemble_joined(sources:&[LineSource], options:&AssemblerOptions, end_stage:&mut dyn FnMut(&'static str))
+        -> Result<AssembledProgram, AssemblyError> {
+    let mut lines:Vec<String> = Vec::new();
+    let mut annotations:Vec<EncodingAnnotation> = Vec::new();
+    let mut source_map = SourceMap::new();
+    for source in sources {
+        let (source_lines, source_annotations) = match read_source(*source, options) {
+            Ok(val) => val,
+            Err(err) if err.0.contains(source.name()) => return Err(err),
+            Err(err) => return Err(AssemblyError(format!("In {}: {}", source.name(), err.0)))
+        };
+
+        annotations.extend(source_annotations.into_iter().map(|annotation| EncodingAnnotation { line: annotation.line + lines.len(), ..annotation }));
+        lines.extend(expansion::substitute_source_symbols(&source_lines, source.name()));
+        source_map.push(source.name(), source_lines.len());
+    }
+
+    end_stage("reading");
+    if let Some((name, first, second)) = labels::find_duplicate_label(&lines) {
+        return Err(AssemblyError(format!("Label {} is defined on {} and again on {}", name, source_map.describe(first), source_map.describe(second))));
+    } else if let Some((line, name)) = labels::find_unresolved_labels(&lines).first() {
+        return Err(source_map.locate_error(AssemblyError(format!("Found undefined label @{} on line {}", name, line))));
+    }
+
+    let names:Vec<&str> = sources.iter().map(|source| source.name()).collect();
+    let mut program = assemble_lines(&lines, &annotations, &names.join(", "), options, end_stage).map_err(|err| source_map.locate_error(err))?;
+    for reference in program.xref.values_mut().flatten() {
+        if let Some((name, line)) = source_map.locate(reference.line) {
+            (reference.file, reference.line) = (name.to_owned(), line);
+        }
+    }
+
+    program.sources = source_map;
+    Ok(program)
+}
+
+
+/// Reads and assembles a program from several sources as if they were one, as described by `assemble_joined`, such as the files of a program split into a boot
+/// loader, a kernel, and its data.
+///
+/// Returns an `AssemblyError` if a source cannot be read, a label is defined twice, a label is referred to but never defined, or the program cannot be assembled.
+pub fn assemble_sources(sources:&[LineSource], options:&AssemblerOptions) -> Result<AssembledProgram, AssemblyError> {
+    assemble_joined(sources, options, &mut |_| {})
+}
+
+
+/// Assembles a program from several sources in the same way as `assemble_sources`, also giving how long each stage of assembly took as `assemble_source_timed`
+/// does.
+///
+/// Returns an `AssemblyError` if a source cannot be read or the program cannot be assembled.
+pub fn assemble_sources_timed(sources:&[LineSource], options:&AssemblerOptions) -> Result<(AssembledProgram, StageTimings), AssemblyError> {
+    let mut timings = Vec::new();
+    let mut start = Instant::now();
+    let program = assemble_joined(sources, options, &mut |stage| {
+        timings.push((stage, start.elapsed()));
+        start = Instant::now();
+    })?;
+
+    Ok((program, timings))
+}
+
+
+/// Reads and assembles the program from the given source in the same way as `assemble_source`, giving every warning found and the error which stopped it being
+/// assembled, if there is one, to `sink` rather than returning them. Once the program is assembled, each block of data in the code section which execution falls
+/// through into and each label nothing refers to is given as a warning, as found by `lint::find_data_in_code` and `lint::find_unused_labels`.
+///
+/// Returns the program if it could be assembled, whether or not there were any warnings.
+pub fn assemble_source_reporting(source:LineSource, options:&AssemblerOptions, sink:&mut dyn DiagnosticSink) -> Option<AssembledProgram> {
+    let result = parser::read_lines_reporting(source, options.lossy, options.encoding, sink).map_err(into_assembly_error)
+        .and_then(|raw_lines| clean_lines(&raw_lines, options.no_tabs))
+        .and_then(|(lines, annotations)| assemble_lines(&lines, &annotations, source.name(), options, &mut |_| {}));
+
+    let program = match result {
+        Ok(val) => val,
+        Err(err) => {
+            sink.report(Diagnostic::from_error(&err, source.name()));
+            return None;
+        }
+    };
+
+    for warning in program.warnings(source.name(), options.isa_spec()) {
+        sink.report(warning);
+    }
+
+    Some(program)
+}
+
+
+/// Reads the given source and finds every reference to an undefined label with `labels::find_unresolved_labels`, without assembling it, so that all of them can be
+/// reported at once.
+///
+/// Returns an `AssemblyError` if the source cannot be read.
+pub fn list_unresolved_labels(source:LineSource, lossy:bool, encoding:InputEncoding) -> Result<Vec<(usize, String)>, AssemblyError> {
+    Ok(labels::find_unresolved_labels(&read_source(source, &AssemblerOptions { lossy, encoding, ..Default::default() })?.0))
+}
+
+
+/// Reads the given source and counts the words of its code and data sections without assembling it, for a quick estimate of its size. The lines are validated
+/// after their constants are substituted as when assembling, which counts the words each takes up from the same parse, so the lines are never expanded, the labels
+/// never resolved, and nothing is encoded. An error which would only be found by those stages, such as an undefined label, is not reported.
+///
+/// Returns an `AssemblyError` if the source cannot be read or is not valid.
+pub fn count_words(source:LineSource, options:&AssemblerOptions) -> Result<(usize, usize), AssemblyError> {
+    let lines = expansion::substitute_source_symbols(&read_source(source, options)?.0, source.name());
+    let lines = expansion::substitute_register_aliases(&lines, options.isa_spec()).map_err(into_assembly_error)?;
+    let (lines, _) = labels::take_linkage(&lines).map_err(into_assembly_error)?;
+    let lines = expansion::substitute_constants(&lines, options.isa_spec()).map_err(into_assembly_error)?;
+    let sizes = parser::validate_and_count_words(&lines, options.isa_spec()).map_err(into_assembly_error)?;
+
+    // the padding of an .org depends on where it is and a merged string takes up no words, so the sections are counted again once they have been laid out
+    match options.merge_strings || lines.iter().any(|line| parser::get_mnemonic(line) == ".org") {
+        true => {
+            let lines = if options.merge_strings { expansion::merge_strings(&lines).0 } else { lines };
+            Ok(labels::section_sizes(&expansion::substitute_org(&lines, options.allow_overlap).map_err(into_assembly_error)?.0))
+        },
+
+        false => Ok(sizes)
+    }
+}
+
+
+/// Reads and assembles the given source file, which must be valid UTF-8.
+///
+/// Returns an `AssemblyError` if the file cannot be read or the program cannot be assembled.
+pub fn assemble_file(input:&Path) -> Result<AssembledProgram, AssemblyError> {
+    assemble_source(LineSource::File(&input.to_string_lossy()), &AssemblerOptions::default())
+}
+
+
+/// Assembles a program held in a string in the same way as `assemble_file`, with `__FILE__` replaced by `<string>`.
+///
+/// Returns an `AssemblyError` if the program cannot be assembled.
+pub fn assemble_str(source:&str) -> Result<AssembledProgram, AssemblyError> {
+    assemble_source(LineSource::Str(source), &AssemblerOptions::default())
+}
+
+
+/// Assembles a program held in a string in the same way as `assemble_str`, giving the program if it could be assembled along with every warning and error
+/// collected by `assemble_source_reporting`.
+pub fn assemble_str_with_diagnostics(source:&str) -> (Option<AssembledProgram>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let program = assemble_source_reporting(LineSource::Str(source), &AssemblerOptions::default(), &mut diagnostics);
+    (program, diagnostics)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_convert_to_i64() {
+        assert_eq!(convert_to_i64("100").unwrap(), 100);
+        assert_eq!(convert_to_i64("-100").unwrap(), -100);
+        assert_eq!(convert_to_i64("0x0F4").unwrap(), 244);
+        assert_eq!(convert_to_i64("0b0110").unwrap(), 6);
+        assert_eq!(convert_to_i64("'c'").unwrap(), 99);
+        assert_eq!(convert_to_i64("'&''").unwrap(), 38);
+    }
+
+
+    #[test]
+    fn test_convert_to_i64_suffixed() {
+        assert_eq!(convert_to_i64("0FFh").unwrap(), 255);
+        assert_eq!(convert_to_i64("0FFH").unwrap(), 255);
+        assert_eq!(convert_to_i64("1Bh").unwrap(), 27);
+        assert_eq!(convert_to_i64("1010b").unwrap(), 10);
+        assert_eq!(convert_to_i64("10b").unwrap(), 2);
+        assert_eq!(convert_to_i64("17o").unwrap(), 15);
+        assert_eq!(convert_to_i64("17q").unwrap(), 15);
+
+        // the prefixed forms and characters keep their meaning, even where they end in a suffix letter
+        assert_eq!(convert_to_i64("0x1b").unwrap(), 27);
+        assert_eq!(convert_to_i64("0b10").unwrap(), 2);
+        assert_eq!(convert_to_i64("0B1h").unwrap(), 177);
+        assert_eq!(convert_to_i64("'h'").unwrap(), 104);
+        assert_eq!(convert_to_i64("0b").unwrap_err().0, "'0b' is not a valid binary literal");
+        assert_eq!(convert_to_i64("0b1h").unwrap_err().0, "'0b1h' is not a valid binary literal");
+        assert!(convert_to_i64("00b").is_err());
+
+        assert!(convert_to_i64("FFh").is_err());
+        assert!(convert_to_i64("12b").is_err());
+        assert!(convert_to_i64("18o").is_err());
+        assert!(convert_to_i64("h").is_err());
+        assert_eq!(convert_to_i64("1FFFFFFFFFFFFFFFFh").unwrap_err().0, "1FFFFFFFFFFFFFFFFh is outside the range of a 64-bit integer");
+    }
+
+
+    #[test]
+    fn test_convert_to_i64_malformed_literal() {
+        assert_eq!(convert_to_i64("0xG").unwrap_err().0, "'0xG' is not a valid hexadecimal literal");
+        assert_eq!(convert_to_i64("0x").unwrap_err().0, "'0x' is not a valid hexadecimal literal");
+        assert_eq!(convert_to_i64("0b102").unwrap_err().0, "'0b102' is not a valid binary literal");
+        assert_eq!(convert_to_i64("0b").unwrap_err().0, "'0b' is not a valid binary literal");
+        assert_eq!(convert_to_i64("0x10000000000000000").unwrap_err().0, "0x10000000000000000 is outside the range of a 64-bit integer");
+        assert_eq!(convert_to_i64("-99999999999999999999").unwrap_err().0, "-99999999999999999999 is outside the range of a 64-bit integer");
+        assert!(evaluate_expression("0xG+1", &HashMap::new(), &HashMap::new()).unwrap_err().0.ends_with("'0xG' is not a valid hexadecimal literal"));
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_convert_to_i64_non_ascii_char() {
+        assert_eq!(convert_to_i64("'Ж'").unwrap(), 100);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_convert_to_i64_malformed_char() {
+        assert_eq!(convert_to_i64("a'").unwrap(), 100);
+    }
+
+
+    #[test]
+    fn test_parse_immediate() {
+        assert_eq!(parse_immediate("63", 7, true).unwrap(), 63);
+        assert_eq!(parse_immediate("-64", 7, true).unwrap(), -64);
+        assert_eq!(parse_immediate("0x3FF", 10, false).unwrap(), 1023);
+        assert_eq!(parse_immediate("0b111111", 6, false).unwrap(), 63);
+        assert_eq!(parse_immediate("'a'", 16, true).unwrap(), 97);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_immediate_signed_too_large() {
+        parse_immediate("64", 7, true).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_immediate_negative_unsigned() {
+        parse_immediate("-1", 10, false).unwrap();
+    }
+
+
+    #[test]
+    fn test_parse_immediate_range_messages() {
+        let err = parse_immediate("100", 7, true).unwrap_err().0;
+        assert_eq!(err, "Found immediate 100 outside the signed 7-bit range -64 to 63; values above 63 would wrap to -28, so write -28 if that is what you meant");
+
+        let err = parse_immediate("200", 7, true).unwrap_err().0;
+        assert_eq!(err, "Found immediate 200 outside the signed 7-bit range -64 to 63");
+
+        let err = parse_immediate("1024", 10, false).unwrap_err().0;
+        assert_eq!(err, "Found immediate 1024 outside the unsigned 10-bit range 0 to 1023");
+    }
+
+
+    #[test]
+    fn test_evaluate_expression() {
+        let constants = HashMap::from([("BUF_SIZE".to_owned(), 16)]);
+        let labels = HashMap::from([("start".to_owned(), 4), ("end".to_owned(), 20)]);
+        let eval = |expr:&str| evaluate_expression(expr, &constants, &labels).unwrap();
+
+        assert_eq!(eval("1 + 2 * 3").value, 7);
+        assert_eq!(eval("(1 + 2) * 3").value, 9);
+        assert_eq!(eval("1 << 2 + 1").value, 8);
+        assert_eq!(eval("0xF0 | 0b1010 & 12").value, 0xF8);
+        assert_eq!(eval("10 - 4 - 3").value, 3);
+        assert_eq!(eval("-7 / 2").value, -3);
+        assert_eq!(eval("-7 % 4").value, -3);
+        assert_eq!(eval("~0 & 0xFF").value, 0xFF);
+        assert_eq!(eval("5 ^ 3").value, 6);
+        assert_eq!(eval("0x100 >> 4").value, 16);
+        assert_eq!(eval("(BUF_SIZE*2)+1").value, 33);
+        assert_eq!(eval("@start + BUF_SIZE"), ExprValue { value: 20, label_weight: 1 });
+        assert_eq!(eval("@end - @start"), ExprValue { value: 16, label_weight: 0 });
+    }
+
+
+    #[test]
+    fn test_evaluate_char_literals() {
+        let eval = |expr:&str| evaluate_expression(expr, &HashMap::new(), &HashMap::new()).unwrap().value;
+
+        assert_eq!(eval("'Z' - 'A'"), 25);
+        assert_eq!(eval("'Z'-'A'+1"), 26);
+        assert_eq!(eval("'a' - ('A' - 1)"), 33);
+        assert_eq!(eval("' ' | 0x40"), 0x60);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_unterminated_char() {
+        evaluate_expression("'A - 1", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_division_by_zero() {
+        evaluate_expression("4 / (2 - 2)", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_overflow() {
+        evaluate_expression("0x7FFFFFFFFFFFFFFF + 1", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_shift_overflow() {
+        evaluate_expression("1 << 64", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_unbalanced_parens() {
+        evaluate_expression("(1 + 2", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_trailing_operator() {
+        evaluate_expression("1 +", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_evaluate_undefined_constant() {
+        evaluate_expression("UNDEFINED * 2", &HashMap::new(), &HashMap::new()).unwrap();
+    }
+}
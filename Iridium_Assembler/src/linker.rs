@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use crate::{ AssembledProgram, AssemblyError };
+use crate::encoder::{ Instruction, decode };
+use crate::labels::{ CrossReference, DataPlacement, LabelKind, RelocationKind, Section, Symbol, SymbolTable };
+use crate::object::ObjectFile;
+use crate::output::label_kind_name;
+use crate::parser::SourceMap;
+
+
+/// An object once placed by `link`, giving the name it was linked under, where each of its sections starts in the linked program and how many words it holds,
+/// and its labels at their linked addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedObject {
+    pub name: String,
+    pub code_base: usize,
+    pub code_size: usize,
+    pub data_base: usize,
+    pub data_size: usize,
+    pub symbols: Vec<Symbol>
+}
+
+
+/// A program linked from object files by `link`, along with where each object was placed. The program's label table holds only the exported labels, as the labels
+/// each object keeps to itself may share their names with those of other objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedProgram {
+    pub program: AssembledProgram,
+    pub objects: Vec<PlacedObject>
+}
+
+impl LinkedProgram {
+    /// Formats the map of the final layout, starting with the entry point if one of the objects declared it, and then giving for each object in the order they
+    /// were placed where each of its sections starts and how many words it holds, followed by each of its labels as in the symbol map with `EXPORT` after those
+    /// it exports, such as:
+    /// ```text
+    /// entry: main 0x0000
+    /// main.o: code 0x0000 (6 words), data 0x0000 (1 words)
+    ///   main  0x0000  CODE
+    ///   once  0x0004  CODE  EXPORT
+    /// ```
+    pub fn format_map(&self) -> String {
+        let mut map = String::new();
+        if let Some(entry) = &self.program.entry {
+            map.push_str(&format!("entry: {} 0x{:04X}\n", entry.name, entry.address));
+        }
+
+        for object in &self.objects {
+            map.push_str(&format!("{}: code 0x{:04X} ({} words), data 0x{:04X} ({} words)\n", object.name, object.code_base, object.code_size, object.data_base,
+                object.data_size));
+
+            let name_width = object.symbols.iter().map(|symbol| symbol.name.len()).max().unwrap_or(0);
+            for symbol in &object.symbols {
+                let export = if symbol.exported { "  EXPORT" } else { "" };
+                map.push_str(&format!("  {:name_width$}  0x{:04X}  {}{}\n", symbol.name, symbol.address, label_kind_name(symbol.kind), export));
+            }
+        }
+
+        map
+    }
+}
+
+
+/// Patches the immediate of a word with an address, held in the way given by `kind`, with the immediate fields where the Iridium instruction set places them in
+/// the low bits of the word: a `lo6` word keeps all but its bottom 6 bits, and a `hi10` word all but its bottom 10.
+pub fn patch_word(word:u16, kind:RelocationKind, address:u16) -> u16 {
+    match kind {
+        RelocationKind::Full16 => address,
+        RelocationKind::Lo6 => (word & !0x003F) | (address & 0x003F),
+        RelocationKind::Hi10 => (word & !0x03FF) | (address >> 6)
+    }
+}
+
+
+/// Links object files, each given with the name it is reported by, into a single program. The code sections are placed one after another from address 0 in the
+/// order the objects are given, and the data sections likewise from where `placement` puts the data section of a program with that much code. Each label declared
+/// with `.extern` is resolved to the label of that name exported by another object, and then every relocation of each object is applied to its words. A word of
+/// the code section holding the address of a label in the code section is given as a relocation of the linked program, as `labels::find_relocations` gives them
+/// for a program assembled as a whole. The entry point of the linked program is that of the object declaring one, at its linked address.
+///
+/// Returns an `AssemblyError` if the sections do not fit in memory, a label is exported by two objects, an external label is not exported by any object, a
+/// relocated address is outside the range 0 to 0xFFFF, or more than one object declares an entry point.
+pub fn link(objects:&[(String, ObjectFile)], placement:DataPlacement) -> Result<LinkedProgram, AssemblyError> {
+    let code_size:usize = objects.iter().map(|(_, object)| object.code.len()).sum();
+    let data_size:usize = objects.iter().map(|(_, object)| object.data.len()).sum();
+    let data_start = placement.base(code_size);
+    if code_size > 0x10000 {
+        return Err(AssemblyError(format!("The {} words of code in the linked program do not fit in memory", code_size)));
+    } else if data_start + data_size > 0x10000 {
+        return Err(AssemblyError(format!("The {} words of data in the linked program placed from 0x{:04X} do not fit in memory", data_size, data_start)));
+    }
+
+    let mut placed:Vec<PlacedObject> = Vec::new();
+    let (mut code_base, mut data_base) = (0, data_start);
+    for (name, object) in objects {
+        let symbols = object.symbols.iter().cloned().map(|mut symbol| {
+            symbol.address = match (symbol.section, symbol.kind) {
+                (_, LabelKind::Absolute) => symbol.address,
+                (Section::Code, _) => (symbol.address as usize + code_base) as u16,
+                (Section::Data, _) => (symbol.address as usize + data_base) as u16
+            };
+
+            symbol
+        }).collect();
+
+        placed.push(PlacedObject { name: name.to_owned(), code_base, code_size: object.code.len(), data_base, data_size: object.data.len(), symbols });
+        code_base += object.code.len();
+        data_base += object.data.len();
+    }
+
+    let mut exports:HashMap<&str, (&str, &Symbol)> = HashMap::new();
+    for object in &placed {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.exported) {
+            if let Some((other, _)) = exports.insert(&symbol.name, (&object.name, symbol)) {
+                return Err(AssemblyError(format!("Label {} is exported by both {} and {}", symbol.name, other, object.name)));
+            }
+        }
+    }
+
+    let mut entry:Option<(&str, Symbol)> = None;
+    for ((_, object), placed_object) in objects.iter().zip(&placed) {
+        let name = match &object.entry {
+            Some(val) => val,
+            None => continue
+        };
+
+        let symbol = match placed_object.symbols.iter().find(|symbol| &symbol.name == name) {
+            Some(val) => val,
+            None => return Err(AssemblyError(format!("Entry point {} of {} is not one of its labels", name, placed_object.name)))
+        };
+
+        if let Some((other, _)) = &entry {
+            return Err(AssemblyError(format!("An entry point is declared by both {} and {}", other, placed_object.name)));
+        }
+
+        entry = Some((&placed_object.name, symbol.clone()));
+    }
+
+    let (mut code, mut data) = (Vec::with_capacity(code_size), Vec::with_capacity(data_size));
+    let mut relocations = Vec::new();
+    for ((_, object), placed_object) in objects.iter().zip(&placed) {
+        let (mut object_code, mut object_data) = (object.code.clone(), object.data.clone());
+        for relocation in &object.relocations {
+            let (target, in_code) = match relocation.symbol.as_str() {
+                ".code" => (placed_object.code_base, true),
+                ".data" => (placed_object.data_base, false),
+                name => match exports.get(name) {
+                    Some((_, symbol)) => (symbol.address as usize, symbol.section == Section::Code && symbol.kind != LabelKind::Absolute),
+                    None => return Err(AssemblyError(format!("Label {} referred to by {} is not exported by any object", name, placed_object.name)))
+                }
+            };
+
+            let address = target as i64 + relocation.addend;
+            if !(0..=0xFFFF).contains(&address) {
+                return Err(AssemblyError(format!("Address {} of {}{:+} referred to by {} is outside the range 0 to 0xFFFF", address, relocation.symbol, relocation.addend,
+                    placed_object.name)));
+            }
+
+            let words = match relocation.section {
+                Section::Code => &mut object_code,
+                Section::Data => &mut object_data
+            };
+
+            match words.get_mut(relocation.index) {
+                Some(word) => *word = patch_word(*word, relocation.kind, address as u16),
+                None => return Err(AssemblyError(format!("Relocation of word {} is outside the {:?} section of {}", relocation.index, relocation.section,
+                    placed_object.name)))
+            };
+
+            if relocation.section == Section::Code && in_code {
+                relocations.push((placed_object.code_base + relocation.index, relocation.kind));
+            }
+        }
+
+        code.extend(object_code);
+        data.extend(object_data);
+    }
+
+    let labels:SymbolTable = exports.values().map(|(_, symbol)| (*symbol).clone()).collect();
+    let code_lines = code.iter().map(|word| decode(*word).to_asm()).collect();
+    let data_lines = data.iter().map(|word| Instruction::Data(*word).to_asm()).collect();
+    let program = AssembledProgram { code, data, code_lines, data_lines, labels, relocations, xref: CrossReference::new(), source_lines: Vec::new(),
+        data_base: data_start, link_relocations: Vec::new(), sources: SourceMap::new(), entry: entry.map(|(_, symbol)| symbol),
+        overlaps: Vec::new(), merged_strings: Vec::new() };
+    Ok(LinkedProgram { program, objects: placed })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+
+    /// Assembles the source into an object file.
+    fn object(source:&str) -> ObjectFile {
+        ObjectFile::from_program(&assemble_str(source).unwrap())
+    }
+
+
+    #[test]
+    fn test_patch_word() {
+        assert_eq!(patch_word(0x3C02, RelocationKind::Lo6, 0x1234), 0x3C34);
+        assert_eq!(patch_word(0x7C00, RelocationKind::Hi10, 0x1234), 0x7C48);
+        assert_eq!(patch_word(0x0002, RelocationKind::Full16, 0x1234), 0x1234);
+    }
+
+
+    #[test]
+    fn test_link() {
+        let first = object(".extern twice\n.export once\nmain: MOVI $r6, @twice\nJAL $r5, $r6\n.syscall 6\nonce: ADDI $r1, $r1, 1\nJAL $zero, $r5\n\
+            .data\nptr: .fill @twice\n");
+        let second = object(".extern once\n.export twice\ntwice: MOVI $r6, @once\nJAL $r3, $r6\nJAL $zero, $r5\n.data\ncount: .fill 3\nref: .fill @ref\n");
+        let linked = link(&[("first.o".to_owned(), first.clone()), ("second.o".to_owned(), second.clone())], DataPlacement::Separate).unwrap();
+
+        // each calls the routine defined in the other, giving the same words as assembling them as one program
+        let whole = assemble_str("main: MOVI $r6, @twice\nJAL $r5, $r6\n.syscall 6\nonce: ADDI $r1, $r1, 1\nJAL $zero, $r5\ntwice: MOVI $r6, @once\nJAL $r3, $r6\n\
+            JAL $zero, $r5\n.data\nptr: .fill @twice\ncount: .fill 3\nref: .fill @ref\n").unwrap();
+        assert_eq!((linked.program.code[0], linked.program.code[6]), (0x3C06, 0x3C04)); // ADDI $r6, $zero, 6 and ADDI $r6, $zero, 4
+        assert_eq!(linked.program.code, whole.code);
+        assert_eq!(linked.program.data, whole.data);
+        assert_eq!(linked.program.relocations, whole.relocations);
+        assert_eq!(linked.program.labels.iter().map(|symbol| (symbol.name.as_str(), symbol.address)).collect::<Vec<(&str, u16)>>(), vec![("once", 4), ("twice", 6)]);
+        assert_eq!(linked.format_map(), "first.o: code 0x0000 (6 words), data 0x0000 (1 words)\n  main  0x0000  CODE\n  once  0x0004  CODE  EXPORT\n\
+            \x20 ptr   0x0000  DATA\nsecond.o: code 0x0006 (4 words), data 0x0001 (2 words)\n  twice  0x0006  CODE  EXPORT\n  count  0x0001  DATA\n\
+            \x20 ref    0x0002  DATA\n");
+
+        // the data section can follow the code instead
+        let linked = link(&[("first.o".to_owned(), first.clone()), ("second.o".to_owned(), second.clone())], DataPlacement::AfterCode).unwrap();
+        assert_eq!((linked.program.data_base, linked.program.data[2]), (10, 12));
+
+        let err = link(&[("first.o".to_owned(), first.clone()), ("again.o".to_owned(), first.clone())], DataPlacement::Separate).unwrap_err();
+        assert_eq!(err.0, "Label once is exported by both first.o and again.o");
+        let err = link(&[("first.o".to_owned(), first)], DataPlacement::Separate).unwrap_err();
+        assert_eq!(err.0, "Label twice referred to by first.o is not exported by any object");
+    }
+
+
+    #[test]
+    fn test_link_entry() {
+        let library = object(".export halt
+halt: .syscall 6
+");
+        let main = object(".extern halt
+.global main
+.data
+count: .fill 3
+.code
+main: MOVI $r6, @halt
+JAL $r5, $r6
+");
+        let linked = link(&[("library.o".to_owned(), library.clone()), ("main.o".to_owned(), main.clone())], DataPlacement::Separate).unwrap();
+        let entry = linked.program.entry.as_ref().unwrap();
+        assert_eq!((entry.name.as_str(), entry.address), ("main", 1));
+        assert!(linked.format_map().starts_with("entry: main 0x0001\nlibrary.o: code 0x0000 (1 words)"), "{}", linked.format_map());
+
+        let err = link(&[("main.o".to_owned(), main.clone()), ("again.o".to_owned(), object(".global start
+start: NOP
+"))], DataPlacement::Separate).unwrap_err();
+        assert_eq!(err.0, "An entry point is declared by both main.o and again.o");
+    }
+}
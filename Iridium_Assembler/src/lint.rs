@@ -0,0 +1,147 @@
+use crate::diagnostics::{ DATA_IN_CODE, Diagnostic };
+use crate::isa::IsaSpec;
+use crate::labels::{ CrossReference, LabelKind, SymbolTable, get_label_kind };
+use crate::lexer::{ Token, parse_line };
+use crate::output::get_display_address;
+use crate::parser::get_word_count;
+
+
+/// A block of data in the code section which execution reaches by falling through from the instruction before it, or by starting at it if it is at the start of
+/// the section, so the CPU will try to execute it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataInCode {
+    /// The address of the first word of the block.
+    pub address: usize,
+    /// The line the first word of the block was encoded from.
+    pub line: String
+}
+
+impl DataInCode {
+    /// Gives the warning about the block in the source named `file`, with its address as a byte offset if `byte_addresses` is set.
+    pub fn warning(&self, file:&str, byte_addresses:bool) -> Diagnostic {
+        Diagnostic::warning(DATA_IN_CODE, format!("Data at address 0x{:04X} is reached by falling through from the instruction before it, so will be executed: {}",
+            get_display_address(self.address, byte_addresses), self.line), file, None)
+    }
+}
+
+
+/// Checks whether execution can continue to the word after an instruction. It cannot after a plain jump, which is a `JAL` discarding its return address or a `BEQ`
+/// comparing a register with itself, or after the halt and error syscalls, while a `JAL` saving its return address is a call which returns to the next word.
+///
+/// WARNING: only works once the pseudo-instructions have been substituted.
+pub fn falls_through(line:&str, isa:&IsaSpec) -> bool {
+    let parsed = match parse_line(line, isa) {
+        Ok(val) => val,
+        Err(_) => return true
+    };
+
+    let registers:Vec<u8> = parsed.operands.iter().filter_map(|token| match token {
+        Token::Register(val) => Some(*val),
+        _ => None
+    }).collect();
+
+    match parsed.mnemonic {
+        "JAL" => registers.first() != isa.get_register("$zero").as_ref(),
+        "BEQ" => registers.len() < 2 || registers[0] != registers[1],
+        ".syscall" => !matches!(parsed.operands.first(), Some(Token::Immediate("6" | "7"))),
+        _ => true
+    }
+}
+
+
+/// Finds each block of data in the code section which execution can reach by falling through from the instruction before it, rather than only by a jump to one
+/// of its labels, as happens when a `.fill` or `.text` is placed in the middle of a code path without a jump around it. Only the first word of each block is
+/// given, as the rest of the block is reached by falling through the data before it.
+///
+/// WARNING: only works once the pseudo-instructions have been substituted, such as on the `code_lines` of an `AssembledProgram`.
+pub fn find_data_in_code(lines:&[String], isa:&IsaSpec) -> Vec<DataInCode> {
+    let mut found = Vec::new();
+    let mut address = 0;
+    let mut reached = true;
+    for line in lines {
+        match get_label_kind(line) {
+            LabelKind::Data if reached => {
+                found.push(DataInCode { address, line: line.to_owned() });
+                reached = false;
+            },
+
+            LabelKind::Data | LabelKind::Absolute => (),
+            LabelKind::Code => reached = falls_through(line, isa)
+        };
+
+        address += get_word_count(line);
+    }
+
+    found
+}
+
+
+/// Finds the labels which nothing in the program refers to, in order of name, from the references given by `labels::find_references`. An exported label is never
+/// unused, as it is there for other programs to refer to.
+pub fn find_unused_labels<'a>(xref:&'a CrossReference, label_table:&SymbolTable) -> Vec<&'a str> {
+    xref.iter()
+        .filter(|(name, references)| references.is_empty() && !label_table.get(name).is_some_and(|symbol| symbol.exported))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use crate::assemble_str;
+
+
+    /// Gives the addresses of the data blocks found in the code section of a program.
+    fn data_in_code_addresses(source:&str) -> Vec<usize> {
+        find_data_in_code(&assemble_str(source).unwrap().code_lines, &DEFAULT_ISA_SPEC).iter().map(|found| found.address).collect()
+    }
+
+
+    #[test]
+    fn test_falls_through() {
+        assert!(falls_through("ADDI $r1, $zero, 5", &DEFAULT_ISA_SPEC));
+        assert!(falls_through("JAL $r5, $r6", &DEFAULT_ISA_SPEC));
+        assert!(!falls_through("JAL $zero, $r6", &DEFAULT_ISA_SPEC));
+        assert!(falls_through("loop: BEQ $r0, $r1, $r6", &DEFAULT_ISA_SPEC));
+        assert!(!falls_through("loop: BEQ $r0, $r0, $r6", &DEFAULT_ISA_SPEC));
+        assert!(falls_through(".syscall 3", &DEFAULT_ISA_SPEC));
+        assert!(!falls_through(".syscall 6", &DEFAULT_ISA_SPEC));
+        assert!(!falls_through(".syscall 7 # error", &DEFAULT_ISA_SPEC));
+    }
+
+
+    #[test]
+    fn test_find_data_in_code() {
+        let found = find_data_in_code(&assemble_str("MOVI $r6, @msg\n.syscall 1\nmsg: .text \"hi\"\n").unwrap().code_lines, &DEFAULT_ISA_SPEC);
+        assert_eq!(found, vec![DataInCode { address: 3, line: "msg: .fill 0x0068".to_owned() }]);
+
+        // jumped over, after a halt, and after a branch which is always taken
+        assert!(data_in_code_addresses("MOVI $r6, @main\nJAL $zero, $r6\ntable: .fill 1\n.fill 2\nmain: .syscall 6\n.space 40 []\n").is_empty());
+        assert!(data_in_code_addresses("MOVI $r6, @main\nBEQ $r0, $r0, $r6\ntable: .space 2 [1]\nmain: NOP\n").is_empty());
+
+        // a branch which may not be taken and a call both continue to the next word, as does the start of the section
+        assert_eq!(data_in_code_addresses("MOVI $r6, @main\nBEQ $r0, $r1, $r6\ntable: .fill 1\nmain: NOP\n"), vec![3]);
+        assert_eq!(data_in_code_addresses("MOVI $r6, @sub\nJAL $r5, $r6\n.fill 1\nsub: JAL $zero, $r5\n"), vec![3]);
+        assert_eq!(data_in_code_addresses(".fill 1\nNOP\n.syscall 6\n.fill 2\n"), vec![0]);
+
+        // each block is given once, and data reached only by a jump to its label is not given
+        assert_eq!(data_in_code_addresses("NOP\n.space 100 []\n.text \"abc\"\nJAL $zero, $r6\n.fill 1\nNOP\n.fill 2\n"), vec![1, 108]);
+        assert!(data_in_code_addresses("JAL $zero, $r6\n.data\ntable: .fill 1\n").is_empty());
+    }
+
+
+    #[test]
+    fn test_find_unused_labels() {
+        let program = assemble_str("start: MOVI $r6, @loop\nloop: NOP\nJAL $zero, $r6\nmessage: .text \"hi\"\nend: .fill @loop\n").unwrap();
+        assert_eq!(find_unused_labels(&program.xref, &program.labels), vec!["end", "message", "start"]);
+
+        let mut labels = program.labels.clone();
+        let mut start = labels["start"].clone();
+        start.exported = true;
+        labels.insert(start);
+        assert_eq!(find_unused_labels(&program.xref, &labels), vec!["end", "message"]);
+    }
+}
@@ -1,947 +1,1418 @@
-use std::{ env, fmt };
-use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::iter;
+use std::fs::File;
+use std::process;
 use std::error::Error;
-use std::fs::OpenOptions;
-use std::io::{ BufReader, BufRead, Write };
-use lazy_static::lazy_static;
-use regex::Regex;
-use ascii_converter::string_to_decimals;
-
-
-lazy_static! {
-    static ref RI_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)LUI[[:blank:]]*(((\$(zero|r[0-6])),)[[:blank:]]*)(0*([0-9]+|0b[01]+|0x[[:xdigit:]]+|@[a-zA-Z_]+))[[:blank:]]*(#[[:blank:]]*[[:print:]]+)?$").unwrap();
-    static ref RRR_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)(ADD|NAND|BEQ)[[:blank:]]+(((\$(zero|r[0-6])),)([[:blank:]]*))(((\$(zero|r[0-6])),)([[:blank:]]*))(\$(zero|r[0-6]))([[:blank:]]*)(#([[:blank:]]*)[[:print:]]+)?$").unwrap();
-    static ref RRI_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)(ADDI|SW|LW|JAL)[[:blank:]]+(((\$(zero|r[0-6])),)[[:blank:]]*)(((\$(zero|r[0-6])),)[[:blank:]]*)(0*((-|\+)?[0-9]+|0b[01]+|0x[[:xdigit:]]+)|@[a-zA-Z_]+)[[:blank:]]*(#[[:blank:]]*[[:print:]]+)?$").unwrap();
-    static ref JAL_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)JAL[[:blank:]]*(\$(zero|r[0-6]),)[[:blank:]]*(\$(zero|r[0-6]))[[:blank:]]*(#[[:print:]]*)?$").unwrap();
-    static ref NOP_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)NOP([[:blank:]]*)(#[[:print:]]*)?$").unwrap();
-    static ref INT_REGEX:Regex = Regex::new(r"[[:blank:]](0b[01]+|0x[[:xdigit:]]+|((\+|-)?[0-9]+))").unwrap();
-    static ref ELEM_REGEX:Regex = Regex::new(r"0b[01]+|0x[[:xdigit:]]+|((\+|-)?[0-9]+|'[[:ascii:]]')").unwrap();
-    static ref CHAR_REGEX:Regex = Regex::new(r"'[[:ascii:]]'").unwrap();
-    static ref UINT_REGEX:Regex = Regex::new(r"0b[01]+|0x[[:xdigit:]]+|([0-9]+)").unwrap();
-    static ref DATA_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*)(LLI|MOVI)([[:blank:]]*)(\$(zero|r[0-6])),([[:blank:]]*)(0*([0-9]+|0b[01]+|0x[[:xdigit:]]+|@[a-zA-Z_]+))([[:blank:]]*)(#[[:print:]]*)?$").unwrap();
-    static ref FILL_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*).fill[[:blank:]]*('[[:ascii:]]'|(0*((\+|-)?[0-9]+|0b[01]+|0x[[:xdigit:]]+)))([[:blank:]]*)(#[[:print:]]*)?$").unwrap();
-    static ref INSTR_REGEX:Regex = Regex::new("ADDI|NAND|LUI|SW|LW|BEQ|JAL|ADD|.syscall").unwrap();
-    static ref SPACE_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*).space[[:blank:]]+[0-9]+[[:blank:]]+\[([[:blank:]]*((\+|-)?[0-9]+|0x[[:xdigit:]]+|0b[01]+|'[[:ascii:]]'),[[:blank:]]*)*([0-9]+|0x[[:xdigit:]]+|0b[01]+|'[[:ascii:]]')?][[:blank:]]*(#[[:print:]]+)?$").unwrap();
-    static ref SCALL_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?([[:blank:]]*).syscall [0-7]$").unwrap();
-    static ref LABEL_REGEX:Regex = Regex::new(r"^[a-zA-Z_]+:").unwrap();
-    static ref REGISTER_REGEX:Regex = Regex::new(r"\$(r[0-6]|zero)").unwrap();
-    static ref TEXT_IMM_REGEX:Regex = Regex::new(r#""[[:ascii:]]+""#).unwrap();
-    static ref LABEL_ARG_REGEX:Regex = Regex::new(r"@[a-zA-Z_]+").unwrap();
-    static ref PSEUDO_TEXT_REGEX:Regex = Regex::new(r#"^([a-zA-Z_]+:)?([[:blank:]]*).text[[:blank:]]+"[[:ascii:]]+"$"#).unwrap();
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use iridium_assembler::{ AssembledProgram, Assembler, AssemblyError, convert_to_i64, list_unresolved_labels };
+use iridium_assembler::parser::{ InputEncoding, LineSource };
+use iridium_assembler::analysis::{ RegisterReport, analyse_registers };
+use iridium_assembler::diagnostics::{ Diagnostic, DiagnosticSink, StderrSink };
+use iridium_assembler::disassembler::{ describe_word, disassemble_file };
+use iridium_assembler::expansion::expand_runs;
+use iridium_assembler::isa::IsaSpec;
+use iridium_assembler::labels::DataPlacement;
+use iridium_assembler::layout::memory_map;
+use iridium_assembler::linker::link;
+use iridium_assembler::lint::{ find_data_in_code, find_unused_labels };
+use iridium_assembler::object::ObjectFile;
+use iridium_assembler::output::{ Endian, ImmRadix, check_source_file, format_source_file, get_display_address, render_immediates, write_assembled_bytes, write_expanded_lines,
+    write_file_atomically, write_relocations, write_resolved_source, write_stream, write_symbol_json, write_symbol_map, write_test_vectors, write_text_listing,
+    write_encoding_json, write_memory_map, write_memory_map_json };
+use iridium_assembler::repl::{ assemble_instr, run_repl };
+use iridium_assembler::writer::WriterRegistry;
+
+
+/// The usage printed along with an error in the command line arguments. Every flag is described in the README.
+const USAGE:&str = "Usage: iridium_assembler <input> <output> [flags]";
+
+
+/// The command line arguments given to the assembler. The code image is written to `code_output`, which is either the second positional argument or the file
+/// given by `--code`. If the code image is named by `-o` rather than by a positional argument, every positional argument is an input, and the files after the
+/// first are given in `extra_inputs` to be assembled as one program with it. The data image is written to `data_output` if `--data` is given. The relocation
+/// table is written to `reloc_output` if `--reloc` is given. The encoding of each instruction is written to `vectors_output` if `--export-vectors` is given.
+/// The encoding of each instruction with its fields picked out is written as JSON to `encode_json_output` if `--encode-json` is given. A plain listing of the
+/// code section is written to `listing_output` if `--text-listing` is given. The program with its labels resolved is written to `resolved_output` if
+/// `--resolve-labels` is given. The lines each word was encoded from, with their labels still defined, are written to `expanded_output` if `--emit-expanded` is
+/// given. The label table is written to `symbols_output` if `--symbols` is given. The label table is written as JSON to `symbols_json_output` if
+/// `--symbols-json` is given. The code, data, padding, and `.org` gaps of the program are written as a memory map to `memory_map_output` if `--memory-map` is
+/// given. The same memory map is written as JSON to `memory_map_json_output` if `--memory-map-json` is given. The code image is written in the `format` named
+/// by `--format`, which is looked up in the built-in `WriterRegistry`, or as a raw binary image if it is not given, and a program referring to a label declared
+/// with `.extern` can only be written in a relocatable format such as `obj`, which also holds the data section. If `no_clobber` is set by `--no-clobber`,
+/// nothing is written if any of the output files already exists, other than the file given to be formatted in place.
+///
+/// If `lossy` is set by `--lossy`, invalid UTF-8 in the input is replaced with a warning instead of being an error. The input is decoded as Latin-1 if
+/// `input_encoding` is set to it by `--input-encoding latin1|utf8`. If `no_tabs` is set by `--no-tabs`, a tab anywhere in the input is an error. The
+/// instruction set is loaded from `isa` if it is given by `--isa`, and the default set is used otherwise. The bytes of each word are read and written in the
+/// `endian` order given by `--endian big|little`. The data section is placed as given by `data_placement`, which `--data-base` sets to either an address or
+/// `after-code` to follow the code section. The label given by `entry` with `--entry` is set as the entry point in place of any declared with `.global`, and a
+/// warning is printed if the entry point is not word 0 but the output format does not record it. Each `.buildinfo` assembles to the `build_id` given by
+/// `--build-id`, either as a number or as `timestamp` for the low 16 bits of the current Unix time in seconds, and to 0 if it is not given. If `allow_overlap`
+/// is set by `--allow-overlap`, a region started by `.org` which writes over the words before it is a warning rather than an error. If `merge_strings` is set
+/// by `--merge-strings`, only the first copy of each string written more than once with `.text` is kept. If `stream` is set by `--stream`, each word of the
+/// code image is written as it is assembled rather than once the whole program has been, which only a raw binary image of a program without a data section can
+/// be, so no output needing the whole program may be asked for alongside it.
+///
+/// The immediates in the dump of each section are printed in the `imm_radix` set by `--imm-radix hex|dec`. If `byte_addresses` is set by `--byte-addresses`,
+/// the dump and listing give addresses as byte offsets rather than word indices. Only the words from `dump_from` to `dump_to`, inclusive, are printed in the
+/// dump of each section if they are given by `--dump-from` and `--dump-to`, which leaves the output files unchanged. If `profile` is set by `--profile`, the
+/// time taken by each stage of assembly is printed at the end. If `verbose` is set by `--verbose`, what each stage of the assembler does is logged down to the
+/// debug level, unless `RUST_LOG` chooses the level instead. If `xref` is set by `--xref`, every label is printed with its address, the line defining it, and
+/// the lines referring to it once the program is assembled. If `reg_report` is set by `--reg-report`, the registers each label-delimited block of the code
+/// section reads and writes are printed. If `warn_uninitialised` is set by `--warn-uninitialised`, a warning is printed for each register the program reads
+/// before writing it. If `warn_data_in_code` is set by `--warn-data-in-code`, a warning is printed for each block of data in the code section which execution
+/// reaches by falling through from the instruction before it.
+///
+/// If `format_source` is given by `--format-source`, that file is rewritten in the canonical layout, and the input and output may be left empty if nothing is
+/// to be assembled. If `check` is set by `--check`, that file is only checked to be in the canonical layout and is left unchanged. If `disassemble` is given by
+/// `--disassemble`, that binary image is disassembled instead, and the input and output may again be left empty. The disassembly is written to
+/// `disassembly_output` if `-o` is given, and printed otherwise. If `list_unresolved` is set by `--list-unresolved`, every reference to an undefined label in
+/// the input is listed before anything is assembled, and the output may be left empty to only list them. If `repl` is set by `--repl`, instructions are read
+/// from the terminal and assembled one at a time instead, and no input or output may be given. If `decode` is given by `--decode`, the fields of that word are
+/// printed as a table, and the input and output may be left empty. If `count_only` is set by `--count-only`, only the number of words the input assembles to is
+/// printed, without assembling it, and no output may be given. If `instr` is given by `--instr`, only the words that one line assembles to are printed, and no
+/// input or output may be given. If `link` is given by the `link` subcommand, those object files are linked into a program written to `code_output` instead of
+/// assembling an input. The map of where each linked object was placed is written to `map_output` if `--map` is given.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CliArgs {
+    input: String,
+    extra_inputs: Vec<String>,
+    code_output: String,
+    data_output: Option<String>,
+    reloc_output: Option<String>,
+    format_source: Option<String>,
+    lossy: bool,
+    input_encoding: InputEncoding,
+    imm_radix: ImmRadix,
+    vectors_output: Option<String>,
+    encode_json_output: Option<String>,
+    listing_output: Option<String>,
+    resolved_output: Option<String>,
+    byte_addresses: bool,
+    symbols_output: Option<String>,
+    symbols_json_output: Option<String>,
+    memory_map_output: Option<String>,
+    memory_map_json_output: Option<String>,
+    disassemble: Option<String>,
+    disassembly_output: Option<String>,
+    endian: Endian,
+    no_tabs: bool,
+    profile: bool,
+    isa: Option<String>,
+    list_unresolved: bool,
+    format: Option<String>,
+    expanded_output: Option<String>,
+    repl: bool,
+    decode: Option<u16>,
+    count_only: bool,
+    instr: Option<String>,
+    warn_data_in_code: bool,
+    check: bool,
+    verbose: bool,
+    xref: bool,
+    dump_from: Option<usize>,
+    dump_to: Option<usize>,
+    reg_report: bool,
+    warn_uninitialised: bool,
+    data_placement: DataPlacement,
+    link: Vec<String>,
+    map_output: Option<String>,
+    stream: bool,
+    entry: Option<String>,
+    no_clobber: bool,
+    build_id: Option<u16>,
+    allow_overlap: bool,
+    merge_strings: bool
 }
 
 
-#[derive(Debug)]
-struct AssemblyError(String);
+/// Parses the command line arguments, which take the form `<input> [output] [--code <file>] [--data <file>] [--reloc <file>] [--export-vectors <file>]
+/// [--encode-json <file>] [--text-listing <file>] [--resolve-labels <file>] [--emit-expanded <file>] [--symbols <file>] [--symbols-json <file>]
+/// [--memory-map <file>] [--memory-map-json <file>] [--disassemble <file> [-o <file>]] [--endian big|little] [--no-tabs] [--profile] [--isa <file>]
+/// [--list-unresolved] [--format <name>] [--repl] [--instr <line>] [--decode <word>] [--count-only] [--warn-data-in-code] [--verbose] [--xref]
+/// [--dump-from <address>] [--dump-to <address>] [--reg-report] [--warn-uninitialised] [--data-base <address>|after-code] [--lossy]
+/// [--input-encoding latin1|utf8] [--imm-radix hex|dec] [--byte-addresses] [--stream] [--entry <label>] [--no-clobber] [--build-id <id>|timestamp]
+/// [--allow-overlap] [--merge-strings]`, where the output may instead be given as `-o <file>` after any number of inputs. The positional output and `--code`
+/// both name the code image, so exactly one of them must be given. `link <object>... -o <file> [--data <file>] [--map <file>]` links object files instead of
+/// assembling an input. `--format-source <file>` may be given on its own to only format that file, and `fmt <file>` and `--fmt <file>` are the same as it.
+/// `--check` may follow it to only check that the file is formatted. `--disassemble <file>` may be given on its own to only disassemble that file. The output
+/// may be left out if `--list-unresolved` is given to only list the undefined labels of the input. `--repl` is given without an input or output, optionally
+/// with `--isa`, to assemble instructions typed at the terminal. `--instr <line>` is given in the same way to assemble only the line given. `--decode <word>`
+/// may be given on its own to only describe that word. The output must be left out if `--count-only` is given.
+///
+/// Returns an `AssemblyError` for an unknown combination of arguments, a missing or invalid value after a flag, or a `--dump-from` after the `--dump-to`.
+fn parse_args(args:&[String]) -> Result<CliArgs, Box<dyn Error>> {
+    let mut positionals:Vec<String> = Vec::new();
+    let mut code_output = None;
+    let mut data_output = None;
+    let mut reloc_output = None;
+    let mut format_source = None;
+    let mut lossy = false;
+    let mut input_encoding = InputEncoding::Utf8;
+    let mut imm_radix = ImmRadix::Source;
+    let mut vectors_output = None;
+    let mut encode_json_output = None;
+    let mut listing_output = None;
+    let mut resolved_output = None;
+    let mut byte_addresses = false;
+    let mut symbols_output = None;
+    let mut symbols_json_output = None;
+    let mut memory_map_output = None;
+    let mut memory_map_json_output = None;
+    let mut disassemble = None;
+    let mut disassembly_output = None;
+    let mut endian = Endian::Big;
+    let mut no_tabs = false;
+    let mut profile = false;
+    let mut isa = None;
+    let mut list_unresolved = false;
+    let mut format = None;
+    let mut expanded_output = None;
+    let mut repl = false;
+    let mut decode = None;
+    let mut count_only = false;
+    let mut instr = None;
+    let mut warn_data_in_code = false;
+    let mut check = false;
+    let mut verbose = false;
+    let mut xref = false;
+    let mut dump_from = None;
+    let mut dump_to = None;
+    let mut reg_report = false;
+    let mut warn_uninitialised = false;
+    let mut data_placement = DataPlacement::Separate;
+    let mut map_output = None;
+    let mut stream = false;
+    let mut entry = None;
+    let mut no_clobber = false;
+    let mut build_id = None;
+    let mut allow_overlap = false;
+    let mut merge_strings = false;
+
+    let mut index = 1;
+    while index < args.len() {
+        match args[index].as_str() {
+            flag @ ("--code" | "--data" | "--reloc" | "--format-source" | "--fmt" | "--export-vectors" | "--encode-json" | "--text-listing" | "--resolve-labels" | "--emit-expanded" | "--symbols" | "--symbols-json" | "--memory-map" | "--memory-map-json" | "--disassemble" | "-o" | "--isa" | "--map") => {
+                let value = match args.get(index + 1) {
+                    Some(val) => val.to_owned(),
+                    None => return Err(Box::new(AssemblyError(format!("Expected a file name after {}", flag))))
+                };
+
+                match flag {
+                    "--code" => code_output = Some(value),
+                    "--data" => data_output = Some(value),
+                    "--reloc" => reloc_output = Some(value),
+                    "--export-vectors" => vectors_output = Some(value),
+                    "--encode-json" => encode_json_output = Some(value),
+                    "--text-listing" => listing_output = Some(value),
+                    "--resolve-labels" => resolved_output = Some(value),
+                    "--emit-expanded" => expanded_output = Some(value),
+                    "--symbols" => symbols_output = Some(value),
+                    "--symbols-json" => symbols_json_output = Some(value),
+                    "--memory-map" => memory_map_output = Some(value),
+                    "--memory-map-json" => memory_map_json_output = Some(value),
+                    "--disassemble" => disassemble = Some(value),
+                    "-o" => disassembly_output = Some(value),
+                    "--isa" => isa = Some(value),
+                    "--map" => map_output = Some(value),
+                    _ => format_source = Some(value)
+                };
+
+                index += 1;
+            },
 
-impl Error for AssemblyError {}
-impl fmt::Display for AssemblyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "AssemblyError: {}", self.0)
-    }
-}
+            "--input-encoding" => {
+                input_encoding = match args.get(index + 1).map(|arg| arg.as_str()) {
+                    Some("latin1") => InputEncoding::Latin1,
+                    Some("utf8") => InputEncoding::Utf8,
+                    _ => return Err(Box::new(AssemblyError("Expected latin1 or utf8 after --input-encoding".to_owned())))
+                };
 
+                index += 1;
+            },
 
-/// Takes a valid instruction and converts it to its binary equivalent as a byte, or returns an `AssemblyError` or panics if it cannot.
-fn convert_instr_to_binary(instr:&String) -> Result<u16, Box<dyn Error>> {
-    let opcodes = HashMap::from([
-        ("ADD", 0x0000), ("ADDI", 0x2000), ("NAND", 0x4000), ("LUI", 0x6000), 
-        ("SW",  0x8000), ("LW",   0xA000), ("BEQ",  0xC000), ("JAL", 0xE000),
-        (".syscall", 0xE000)
-    ]);
-
-    let registers = HashMap::from([
-        ("$zero", 0x00), ("$r0", 0x01), ("$r1", 0x02), ("$r2", 0x03), ("$r3", 0x04), ("$r4", 0x05), ("$r5", 0x06), ("$r6", 0x07)
-    ]);
-    
-    // let opcode:u16 = match opcodes.get(INSTR_REGEX.find(instr).unwrap().as_str()) {
-    let opcode:u16 = match INSTR_REGEX.find(instr) {
-        Some(val) => *opcodes.get(val.as_str()).unwrap(),
-        None => {
-            if !UINT_REGEX.is_match(instr) {
-                return Err(Box::new(AssemblyError(format!("{} is not a valid instruction for compilation. Note pseudoinstructions cannot be present at this stage", instr))));
-            }
+            "--imm-radix" => {
+                imm_radix = match args.get(index + 1).map(|arg| arg.as_str()) {
+                    Some("hex") => ImmRadix::Hex,
+                    Some("dec") => ImmRadix::Dec,
+                    _ => return Err(Box::new(AssemblyError("Expected hex or dec after --imm-radix".to_owned())))
+                };
 
-            let data_byte = get_imm_from_instr(&instr, 16, false, false, false)?.unwrap() as u16;
-            return Ok(data_byte);
-        }
-    };
+                index += 1;
+            },
 
-    let registers:Vec<u16> = REGISTER_REGEX.find_iter(&instr).map(|reg| *registers.get(reg.as_str()).unwrap() as u16).collect();
-    let instr_binary = match opcode {
-        0x0000 | 0x4000 | 0xC000 => {
-            let mut result = opcode;
-            if registers.len() != 3 {
-                return Err(Box::new(AssemblyError(format!("{} does not have 3 registers as is required", instr))));
-            }
+            "--endian" => {
+                endian = match args.get(index + 1).map(|arg| arg.as_str()) {
+                    Some("big") => Endian::Big,
+                    Some("little") => Endian::Little,
+                    _ => return Err(Box::new(AssemblyError("Expected big or little after --endian".to_owned())))
+                };
 
-            let (reg_a, reg_b, reg_c) = (
-                registers[0] << 10,
-                registers[1] << 7,
-                registers[2] << 4
-            );
+                index += 1;
+            },
 
-            result |= reg_a;
-            result |= reg_b;
-            result |= reg_c;
+            "--format" => {
+                format = match args.get(index + 1) {
+                    Some(val) => {
+                        WriterRegistry::with_builtin_writers(Endian::default()).get(val)?;
+                        Some(val.to_owned())
+                    },
 
-            result
-        },
+                    None => return Err(Box::new(AssemblyError("Expected the name of an output format after --format".to_owned())))
+                };
 
-        0x2000 | 0x8000 | 0xA000 => {
-            let mut result = opcode;
-            let immediate = get_imm_from_instr(instr, 7, true, false, false).unwrap().unwrap() as u16 & 0x007F;
-            if registers.len() != 2 {
-                return Err(Box::new(AssemblyError(format!("{} does not have 2 registers as is required", instr))));
-            }
+                index += 1;
+            },
+
+            "--decode" => {
+                decode = match args.get(index + 1).map(|arg| convert_to_i64(arg)) {
+                    Some(Ok(val)) if (0..=0xFFFF).contains(&val) => Some(val as u16),
+                    _ => return Err(Box::new(AssemblyError("Expected a 16-bit word such as 0x2807 after --decode".to_owned())))
+                };
+
+                index += 1;
+            },
+
+            "--dump-from" | "--dump-to" => {
+                let address = match args.get(index + 1).map(|arg| convert_to_i64(arg)) {
+                    Some(Ok(val)) if val >= 0 => val as usize,
+                    _ => return Err(Box::new(AssemblyError(format!("Expected an address such as 0x0010 after {}", args[index]))))
+                };
+
+                match args[index].as_str() {
+                    "--dump-from" => dump_from = Some(address),
+                    _ => dump_to = Some(address)
+                };
 
-            let (reg_a, reg_b) = (
-                registers[0] << 10,
-                registers[1] << 7
-            );
+                index += 1;
+            },
+
+            "--data-base" => {
+                data_placement = match args.get(index + 1).map(|arg| (arg.as_str(), convert_to_i64(arg))) {
+                    Some(("after-code", _)) => DataPlacement::AfterCode,
+                    Some((_, Ok(val))) if (0..=0xFFFF).contains(&val) => DataPlacement::At(val as u16),
+                    _ => return Err(Box::new(AssemblyError("Expected an address such as 0x4000 or after-code after --data-base".to_owned())))
+                };
+
+                index += 1;
+            },
+
+            "--entry" => {
+                entry = match args.get(index + 1) {
+                    Some(val) => Some(val.to_owned()),
+                    None => return Err(Box::new(AssemblyError("Expected the label the program starts running from after --entry".to_owned())))
+                };
+
+                index += 1;
+            },
+
+            "--build-id" => {
+                build_id = match args.get(index + 1).map(|arg| (arg.as_str(), convert_to_i64(arg))) {
+                    Some(("timestamp", _)) => Some(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as u16)),
+                    Some((_, Ok(val))) if (0..=0xFFFF).contains(&val) => Some(val as u16),
+                    _ => return Err(Box::new(AssemblyError("Expected a build identifier from 0 to 0xFFFF or timestamp after --build-id".to_owned())))
+                };
+
+                index += 1;
+            },
+
+            "--instr" => {
+                instr = match args.get(index + 1) {
+                    Some(val) => Some(val.to_owned()),
+                    None => return Err(Box::new(AssemblyError("Expected an instruction such as \"ADDI $r0, $zero, 7\" after --instr".to_owned())))
+                };
+
+                index += 1;
+            },
+
+            "--lossy" => lossy = true,
+            "--byte-addresses" => byte_addresses = true,
+            "--no-tabs" => no_tabs = true,
+            "--profile" => profile = true,
+            "--list-unresolved" => list_unresolved = true,
+            "--repl" => repl = true,
+            "--count-only" => count_only = true,
+            "--warn-data-in-code" => warn_data_in_code = true,
+            "--check" => check = true,
+            "--verbose" => verbose = true,
+            "--xref" => xref = true,
+            "--reg-report" => reg_report = true,
+            "--warn-uninitialised" => warn_uninitialised = true,
+            "--stream" => stream = true,
+            "--no-clobber" => no_clobber = true,
+            "--allow-overlap" => allow_overlap = true,
+            "--merge-strings" => merge_strings = true,
+            arg => positionals.push(arg.to_owned())
+        };
+
+        index += 1;
+    }
+
+    if positionals.first().map(|arg| arg.as_str()) == Some("fmt") {
+        format_source = match positionals.get(1) {
+            Some(val) => Some(val.to_owned()),
+            None => return Err(Box::new(AssemblyError("Expected a file name after fmt".to_owned())))
+        };
 
-            result |= reg_a;
-            result |= reg_b;
-            result |= immediate;
+        positionals.drain(..2);
+    }
 
-            result
+    if positionals.first().map(|arg| arg.as_str()) == Some("link") {
+        let link:Vec<String> = positionals.drain(..).skip(1).collect();
+        if link.is_empty() {
+            return Err(Box::new(AssemblyError("Expected the object files to link after link".to_owned())));
         }
 
-        0x6000 => {
-            let mut result = opcode;
-            let immediate = get_imm_from_instr(instr, 10, false, false, false).unwrap().unwrap() as u16 & 0x03FF;
-            let reg_a = registers[0] << 10;
-            if registers.len() != 1 {
-                return Err(Box::new(AssemblyError(format!("{} does not have 1 register as is required", instr))));
-            }
+        let code_output = match disassembly_output {
+            Some(val) => val,
+            None => return Err(Box::new(AssemblyError("No output file given for the linked program with -o".to_owned())))
+        };
 
-            result |= reg_a;
-            result |= immediate;
-
-            result
-        }
-
-        0xE000 => {
-            let mut result = opcode;
-            if instr.contains(".syscall") {
-                let immediate = get_imm_from_instr(instr, 7, false, false, false).unwrap().unwrap() as u16 & 0x007F;
-                let reg_a = 0x1400; // 0b0001 0100 0000 0000
-
-                result |= reg_a;
-                result |= immediate;
-            } 
-            
-            else {
-                if registers.len() != 2 {
-                    return Err(Box::new(AssemblyError(format!("{} does not have 2 registers as is required", instr))));
-                }
-    
-                let (reg_a, reg_b) = (
-                    registers[0] << 10,
-                    registers[1] << 7
-                );
-    
-                result |= reg_a;
-                result |= reg_b;
-            }
+        if entry.is_some() {
+            return Err(Box::new(AssemblyError("--entry cannot be given with link, as the entry point of a linked program is declared with .global".to_owned())));
+        }
 
-            result
+        if build_id.is_some() {
+            return Err(Box::new(AssemblyError("--build-id cannot be given with link, as .buildinfo is filled in when each object is assembled".to_owned())));
+        } else if allow_overlap {
+            return Err(Box::new(AssemblyError("--allow-overlap cannot be given with link, as each .org is laid out when its object is assembled".to_owned())));
+        } else if merge_strings {
+            return Err(Box::new(AssemblyError("--merge-strings cannot be given with link, as the strings of each object are merged when it is assembled".to_owned())));
         }
 
-        _ => { 
-            return Err(Box::new(AssemblyError(format!("{} does not contain a valid opcode", instr)))) 
+        if memory_map_output.is_some() || memory_map_json_output.is_some() {
+            return Err(Box::new(AssemblyError("--memory-map and --memory-map-json describe a program assembled from source so cannot be given with link".to_owned())));
         }
-    };
 
-    Ok(instr_binary)
-}
+        return Ok(CliArgs { code_output, data_output, reloc_output, symbols_output, symbols_json_output, endian, format, verbose, data_placement, link, map_output,
+            no_clobber, ..Default::default() });
+    }
 
+    if map_output.is_some() {
+        return Err(Box::new(AssemblyError("--map names the map of the objects linked by link so can only be given with it".to_owned())));
+    }
 
-/// Goes through every line of the program and checks for labels. If it finds a label, it will substitute in the appropriate value in its place.
-///
-/// WARNING: only works if the pseudo-instructions have already been substituted.
-///
-/// Panics if an undefined label is encountered.
-fn substitute_labels(lines:&Vec<String>, label_table:&HashMap<String, i32>) -> Vec<String> {
-    let mut new_lines:Vec<String> = Vec::new();
-    for line in lines {
-        let label:String = match LABEL_ARG_REGEX.find(line) {
-            Some(val) => val.as_str().to_owned(),
-            None => {
-                new_lines.append(&mut vec![line.to_owned()]);
-                continue;
-            }
-        };
+    if check && format_source.is_none() {
+        return Err(Box::new(AssemblyError("--check checks the layout of the file given with fmt or --format-source so can only be given with it".to_owned())));
+    }
 
-        let mut address = *label_table.get(&label[1..]).expect(&format!("Could not find label {} in instruction {}", label, line));
-        if line.contains("ADDI") || line.contains("LW") || line.contains("SW") {
-            address = address & 0x003F;
-        } else if line.contains("LUI") {
-            address = (address & 0xFFC0) >> 6;
-        }
+    // without --disassemble, -o names the code image, and every positional argument is then an input
+    let output = match disassemble {
+        Some(_) => None,
+        None => disassembly_output.take()
+    };
 
-        new_lines.append(&mut vec![line.replace(&label, &address.to_string()).to_owned()]);
+    if repl {
+        return match positionals.first().or(output.as_ref()) {
+            Some(val) => Err(Box::new(AssemblyError(format!("Unexpected argument {}, as --repl reads instructions from the terminal", val)))),
+            None => Ok(CliArgs { isa, repl, verbose, build_id, ..Default::default() })
+        };
     }
 
-    new_lines
-}
+    if instr.is_some() {
+        return match positionals.first().or(output.as_ref()) {
+            Some(val) => Err(Box::new(AssemblyError(format!("Unexpected argument {}, as --instr only assembles the line given with it", val)))),
+            None => Ok(CliArgs { isa, instr, verbose, build_id, ..Default::default() })
+        };
+    }
 
+    if (format_source.is_some() || disassemble.is_some() || decode.is_some()) && positionals.is_empty() {
+        return Ok(CliArgs { format_source, disassemble, disassembly_output, endian, decode, check, verbose, no_clobber, ..Default::default() });
+    }
 
-/// Goes through every line of the program looking for instructions with a label matching the regex `^[a-zA-Z_]+:`. This is then added to a `HashMap` with the label's
-/// name as the key and its line number as the value - this hashmap is the return value.
-fn generate_label_table(lines:&Vec<String>) -> Result<HashMap<String, i32>, Box<dyn Error>> {
-    let mut label_table:HashMap<String, i32> = HashMap::new();
-    let mut line_num = 0;
-    for line in lines {
-        match LABEL_REGEX.find(line) {
-            Some(val) => { 
-                let label_name = val.as_str().replace(":", "");
-                if label_table.keys().collect::<Vec<&String>>().contains(&&label_name) {
-                    return Err(Box::new(AssemblyError(format!("Found duplicate key {}", label_name))));
-                }
-
-                label_table.insert(label_name, line_num);
-            },
+    let input = match positionals.first() {
+        Some(val) => val.to_owned(),
+        None => return Err(Box::new(AssemblyError("No input file given".to_owned())))
+    };
 
-            None => (),
+    if count_only {
+        return match (positionals.get(1), code_output.or(output)) {
+            (None, None) => Ok(CliArgs { input, lossy, input_encoding, no_tabs, isa, count_only, verbose, ..Default::default() }),
+            _ => Err(Box::new(AssemblyError("--count-only only counts the words of the input so cannot be given an output".to_owned())))
         };
-        
-        line_num += 1;
     }
 
-    Ok(label_table)
-}
+    if list_unresolved && positionals.len() == 1 && code_output.is_none() && output.is_none() {
+        return Ok(CliArgs { input, lossy, input_encoding, list_unresolved, verbose, ..Default::default() });
+    }
+
+    let (code_output, extra_inputs) = match (positionals.get(1), code_output, output) {
+        (_, Some(_), Some(_)) => return Err(Box::new(AssemblyError("The code output was given both with --code and with -o".to_owned()))),
+        (_, None, Some(val)) => (val, positionals[1..].to_vec()),
+        (Some(_), Some(_), None) => return Err(Box::new(AssemblyError("The code output was given both as a positional argument and with --code".to_owned()))),
+        (Some(val), None, None) => (val.to_owned(), Vec::new()),
+        (None, Some(val), None) => (val, Vec::new()),
+        (None, None, None) => return Err(Box::new(AssemblyError("No output file given".to_owned())))
+    };
 
+    if extra_inputs.is_empty() && positionals.len() > 2 {
+        return Err(Box::new(AssemblyError(format!("Unexpected argument {}, as the output must be given with -o to assemble several inputs", positionals[2]))));
+    } else if list_unresolved && !extra_inputs.is_empty() {
+        return Err(Box::new(AssemblyError("--list-unresolved only lists the undefined labels of a single input".to_owned())));
+    }
 
-/// Takes an instruction and the valid number of bits the operand can have as arguments. Checks the instruction for any immediates in number, character, and label form and
-/// returns them if there are any, or an `AssemblyError` if not. 
-fn get_imm_for_pseudoinstr(instr:&String, bits:u32) -> Result<String, Box<dyn Error>> {
-    let mut imm = None;
-    let mut label = None;
-    match get_imm_from_instr(&instr, bits, false, false, true).unwrap() {
-        Some(val) => { imm = Some(val) },
-        None => {
-            label = Some (match LABEL_ARG_REGEX.find(&instr) {
-                Some(val) => val.as_str(),
-                None => { return Err(Box::new(AssemblyError(format!("Could not find valid immediate for instruction {}", instr)))) }
-            });
+    if stream {
+        let whole_program_flags = [(data_output.is_some(), "--data"), (reloc_output.is_some(), "--reloc"), (vectors_output.is_some(), "--export-vectors"),
+            (encode_json_output.is_some(), "--encode-json"), (listing_output.is_some(), "--text-listing"), (resolved_output.is_some(), "--resolve-labels"),
+            (expanded_output.is_some(), "--emit-expanded"), (symbols_output.is_some(), "--symbols"), (symbols_json_output.is_some(), "--symbols-json"),
+            (memory_map_output.is_some(), "--memory-map"), (memory_map_json_output.is_some(), "--memory-map-json"),
+            (format.as_deref().is_some_and(|name| name != "bin"), "--format"), (!extra_inputs.is_empty(), "several inputs"), (list_unresolved, "--list-unresolved"),
+            (warn_data_in_code, "--warn-data-in-code"), (xref, "--xref"), (reg_report, "--reg-report"), (warn_uninitialised, "--warn-uninitialised"),
+            (data_placement != DataPlacement::Separate, "--data-base"), (profile, "--profile"), (dump_from.is_some() || dump_to.is_some(), "--dump-from or --dump-to"),
+            (entry.is_some(), "--entry"), (allow_overlap, "--allow-overlap"),
+            (merge_strings, "--merge-strings")];
+        if let Some((_, flag)) = whole_program_flags.iter().find(|(given, _)| *given) {
+            return Err(Box::new(AssemblyError(format!("--stream writes each word of the code image as it is assembled, so cannot be given with {}", flag))));
         }
-    };
+    }
+
+    if let (Some(from), Some(to)) = (dump_from, dump_to) {
+        if from > to {
+            return Err(Box::new(AssemblyError(format!("--dump-from 0x{:04X} is after --dump-to 0x{:04X}", from, to))));
+        }
+    }
 
-    match imm {
-        Some(val) => {
-            return Ok(val.to_string());
-        },
+    Ok(CliArgs { input, extra_inputs, code_output, data_output, reloc_output, format_source, lossy, input_encoding, imm_radix, vectors_output, encode_json_output, listing_output,
+        resolved_output, byte_addresses, symbols_output, symbols_json_output, disassemble, disassembly_output, endian, no_tabs, profile, isa, list_unresolved,
+        memory_map_output, memory_map_json_output, format, expanded_output, repl, decode, count_only, instr, warn_data_in_code, check, verbose, xref, dump_from, dump_to, reg_report,
+        warn_uninitialised, data_placement, link: Vec::new(), map_output, stream, entry, no_clobber, build_id, allow_overlap, merge_strings })
+}
 
-        None => {
-            return Ok(label.expect(&format!("Could not find valid immediate for instruction {}", instr)).to_owned());
+
+/// Prints each word of a section starting at address `base` whose address is within `range` alongside its address and source line, with immediates shown in the
+/// given radix and addresses given as byte offsets if `byte_addresses` is set, in which case `range` is in bytes too.
+fn print_section(lines:&[String], words:&[u16], base:usize, radix:ImmRadix, byte_addresses:bool, range:&RangeInclusive<usize>) {
+    for (index, (line, word)) in expand_runs(lines).zip(words.iter()).enumerate() {
+        let address = get_display_address(base + index, byte_addresses);
+        if range.contains(&address) {
+            println!("0x{:04X}:\t {:32} \t 0x{:04X}", address, render_immediates(&line, radix), word);
         }
     }
 }
 
 
-/// Takes a vector of instructions and examines it for any pseudo-instructions. If it finds any, then it replaces it with 1-or-more regular instructions which are inserted
-/// into the vector in its place. The vector at the end of this process is returned.
-fn substitute_pseudoinstrs(lines:&Vec<String>) -> Vec<String> {
-    let mut new_vec = lines.clone();
-    let mut index:usize = 0;
-    while index < new_vec.len() {
-        let instr = new_vec[index].to_owned();
-        let label = match LABEL_REGEX.find(&instr) {
-            Some(val) => val.as_str().to_owned() + " ",
-            None => "".to_owned()
-        };
+/// Prints every label of a program in order of name, with its address, the line it is defined on, and each line referring to it, noting the labels nothing
+/// refers to. Addresses are given as byte offsets if `byte_addresses` is set.
+fn print_xref(program:&AssembledProgram, byte_addresses:bool) {
+    let unused = find_unused_labels(&program.xref, &program.labels);
+    println!("Cross-reference:");
+    for (name, references) in &program.xref {
+        let symbol = &program.labels[name.as_str()];
+        println!("  {:20} 0x{:04X}  defined on {}", name, get_display_address(symbol.address as usize, byte_addresses),
+            program.sources.describe(symbol.defined_at.line));
+        for reference in references {
+            println!("      {}:{}: {}", reference.file, reference.line, reference.instruction);
+        }
 
-        if instr.contains("NOP") {
-            new_vec.remove(index);
-            new_vec.insert(index, format!("{}ADD $zero, $zero, $zero", label));
-        } else if instr.contains("LLI") {
-            let imm = get_imm_for_pseudoinstr(&instr, 6).unwrap();
-            let register = REGISTER_REGEX.find(&instr).unwrap().as_str();
-
-            new_vec.remove(index);
-            new_vec.insert(index, format!("{0}ADDI {1}, {1}, {2}", label, register, imm));
-        } else if instr.contains("MOVI") {
-            new_vec.remove(index);
-
-            let register = REGISTER_REGEX.find(&instr).unwrap().as_str();
-            let imm = get_imm_for_pseudoinstr(&instr, 16).unwrap();
-            match convert_to_i64(&imm) {
-                Ok(val) => {
-                    let lower_imm = val as u16 & 0x003F;
-                    let upper_imm = (val as u16 & 0xFFC0) >> 6;
-
-                    new_vec.insert(index, format!("{}ADDI {}, $zero, {}", label, register, lower_imm));
-                    new_vec.insert(index + 1, format!("LUI {}, {}", register, upper_imm));
-                },
-
-                Err(_) => {
-                    println!("Imm: {}", imm);
-                    new_vec.insert(index, format!("{}ADDI {}, $zero, {}", label, register, imm));
-                    new_vec.insert(index + 1, format!("LUI {}, {}", register, imm));
-                }
-            };
+        if unused.contains(&name.as_str()) {
+            println!("      never referenced");
+        }
+    }
+}
 
-            index += 1;
-        } else if instr.contains(".space") {
-            new_vec.remove(index);
-            
-            let defined_elems:Vec<u16> = ELEM_REGEX.find_iter(&instr).map(|item| convert_to_i64(item.as_str()).unwrap() as u16).collect::<Vec<u16>>()[1..].to_vec();
-            let total_elems = ELEM_REGEX.find_iter(&instr).map(|item| convert_to_i64(item.as_str()).unwrap() as u16).collect::<Vec<u16>>()[0];
 
-            for elem_index in 0..total_elems {
-                let mut value_to_insert = format!(".fill 0x{:04X}", 0);
-                if elem_index < defined_elems.len() as u16 {
-                    value_to_insert = format!(".fill 0x{:04X}", defined_elems[elem_index as usize]);
-                }
+/// Prints the registers read, written, and read before written by each block of the code section, and then by the whole program, with the address of each block
+/// given as a byte offset if `byte_addresses` is set.
+fn print_register_report(report:&RegisterReport, byte_addresses:bool) {
+    println!("Register usage:");
+    for block in &report.blocks {
+        println!("  {:20} 0x{:04X}  {}", block.label.as_deref().unwrap_or("(start)"), get_display_address(block.address, byte_addresses), block.usage.describe());
+    }
 
-                if elem_index == 0 {
-                    value_to_insert = label.to_owned() + &value_to_insert;
-                }
+    println!("  {:20}         {}", "(program)", report.program.describe());
+}
 
-                new_vec.insert(index + elem_index as usize, value_to_insert);
-            }
 
-            index += total_elems as usize - 1;
-        } else if instr.contains(".text") {
-            new_vec.remove(index);
+/// Prints how long each stage of assembly took in milliseconds, along with the total.
+fn print_profile(timings:&[(&str, Duration)]) {
+    println!("Profile:");
+    for (stage, duration) in timings {
+        println!("  {:30} {:>10.3} ms", stage, duration.as_secs_f64() * 1000.0);
+    }
 
-            let text = TEXT_IMM_REGEX.find(&instr).unwrap().as_str();
-            let cleaned_text = text[1..text.len() - 1].to_owned();
-            let text_ascii = string_to_decimals(&cleaned_text).unwrap().into_iter().map(|item| format!(".fill 0x{:04X}", item)).collect::<Vec<String>>();
+    println!("  {:30} {:>10.3} ms", "total", timings.iter().map(|(_, duration)| duration.as_secs_f64() * 1000.0).sum::<f64>());
+}
 
-            let mut elem_index = 0;
-            for mut char_str in text_ascii {
-                if elem_index == 0 {
-                    char_str = label.to_owned() + &char_str;
-                }
 
-                new_vec.insert(elem_index + index as usize, char_str);
-                elem_index += 1;
+/// Finds the first of the files the arguments would write which already exists, for `--no-clobber` to refuse to overwrite. The file given to `--format-source` is
+/// rewritten in place, so it is not counted.
+fn find_clobbered_output(cli_args:&CliArgs) -> Option<&str> {
+    let outputs = [&cli_args.data_output, &cli_args.reloc_output, &cli_args.vectors_output, &cli_args.encode_json_output, &cli_args.listing_output,
+        &cli_args.resolved_output, &cli_args.expanded_output, &cli_args.symbols_output, &cli_args.symbols_json_output, &cli_args.disassembly_output,
+        &cli_args.map_output, &cli_args.memory_map_output, &cli_args.memory_map_json_output];
+    iter::once(cli_args.code_output.as_str()).filter(|filename| !filename.is_empty()).chain(outputs.into_iter().flatten().map(String::as_str))
+        .find(|filename| Path::new(filename).exists())
+}
+
+
+/// Links the object files given with the `link` subcommand and writes the linked program, along with its data section, map, and label table if they were asked
+/// for. Exits with an error naming the object at fault if an object cannot be read or the objects cannot be linked.
+fn link_objects(cli_args:&CliArgs) {
+    let mut objects:Vec<(String, ObjectFile)> = Vec::new();
+    for filename in &cli_args.link {
+        let object = File::open(filename).map_err(|err| AssemblyError(format!("Could not open {}: {}", filename, err))).and_then(ObjectFile::read);
+        match object {
+            Ok(val) => objects.push((filename.to_owned(), val)),
+            Err(err) => {
+                StderrSink.report(Diagnostic::from_error(&err, filename));
+                process::exit(1);
             }
+        };
+    }
 
-            new_vec.insert(elem_index + index as usize, ".fill 0x0000".to_owned());
+    println!("Linking {} --> {}", cli_args.link.join(", "), cli_args.code_output);
+    let linked = match link(&objects, cli_args.data_placement) {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("Error: {}", err.0);
+            process::exit(1);
         }
+    };
 
-        index += 1;
+    let program = &linked.program;
+    let writers = WriterRegistry::with_builtin_writers(cli_args.endian);
+    let writer = writers.get(cli_args.format.as_deref().unwrap_or("bin")).unwrap();
+    if !program.data.is_empty() && cli_args.data_output.is_none() && !writer.is_relocatable() {
+        let err = AssemblyError("The linked program has a .data section but no data output file was given with --data".to_owned());
+        StderrSink.report(Diagnostic::from_error(&err, &cli_args.link.join(", ")));
+        process::exit(1);
     }
 
-    new_vec
-}
+    if !writer.records_entry() {
+        // the line the entry point is defined on is in the source of an object rather than in any of the files being linked
+        if let Some(warning) = program.entry_warning(&cli_args.link.join(", ")) {
+            StderrSink.report(Diagnostic { line: None, ..warning });
+        }
+    }
+
+    let mut image:Vec<u8> = Vec::new();
+    let num_bytes = match writer.write(program, &mut image) {
+        Ok(val) => val,
+        Err(err) => exit_with_error(Box::new(err), &cli_args.code_output)
+    };
 
+    if let Err(err) = write_file_atomically(&cli_args.code_output, &image) {
+        exit_with_error(err, &cli_args.code_output);
+    }
 
-/// Takes a string formatted either as a decimal (signed or unsigned), binary (prefixed with "0b"), or hexadecimal (prefixed with "0x"), and outputs it as an `i64`. It
-/// may also take a character as an input which conforms to the RegEx r"^'[[:ascii:]]'$" and will output the ASCII value of that character.
-///
-/// Returns an error if the value passed is not a decimal, hexadecimal, or binary integer or not a single character in single quotes.
-fn convert_to_i64(raw_string:&str) -> Result<i64, Box<dyn Error>> {
-    let imm:i64;
-    if raw_string.contains("0x") {  // hexadecimal number
-        imm = match i64::from_str_radix(raw_string.trim_start_matches("0x"), 16) {
+    println!("Successfully linked {} bytes from {} objects", num_bytes, objects.len());
+
+    if let Some(data_output) = &cli_args.data_output {
+        let num_bytes = match write_assembled_bytes(data_output, program.data.clone(), cli_args.endian) {
             Ok(val) => val,
-            Err(_) => { return Err(Box::new(AssemblyError(format!("Could not convert from {} to i64", raw_string)))) }
+            Err(err) => exit_with_error(err, data_output)
         };
-    } else if raw_string.contains("0b") { // binary number
-        imm = match i64::from_str_radix(raw_string.trim_start_matches("0b"), 2) {
+
+        println!("Wrote {} bytes of data to {}", num_bytes, data_output);
+    }
+
+    if let Some(map_output) = &cli_args.map_output {
+        if let Err(err) = write_file_atomically(map_output, linked.format_map().as_bytes()) {
+            exit_with_error(err, map_output);
+        }
+
+        println!("Wrote the map of {} objects to {}", linked.objects.len(), map_output);
+    }
+
+    if let Some(reloc_output) = &cli_args.reloc_output {
+        let num_relocations = match write_relocations(reloc_output, &program.relocations) {
             Ok(val) => val,
-            Err(_) => { return Err(Box::new(AssemblyError(format!("Could not convert from {} to i64", raw_string)))) }
+            Err(err) => exit_with_error(err, reloc_output)
         };
-    } else {
-        imm = match raw_string.parse() {
+
+        println!("Wrote {} relocations to {}", num_relocations, reloc_output);
+    }
+
+    if let Some(symbols_output) = &cli_args.symbols_output {
+        let num_symbols = match write_symbol_map(symbols_output, &program.labels) {
             Ok(val) => val,
-            Err(_) => {
-                if CHAR_REGEX.find(raw_string) == None {
-                    return Err(Box::new(AssemblyError(format!("Could not convert from {} to i64", raw_string))))
-                }
-
-                match string_to_decimals(&raw_string[1..2]) {
-                    Ok(val) => *val.get(0).unwrap() as i64,
-                    Err(_) => { return Err(Box::new(AssemblyError(format!("Could not convert from {} to i64", raw_string)))) }
-                }
-            }
+            Err(err) => exit_with_error(err, symbols_output)
         };
-    }
 
-    Ok(imm)
-}
+        println!("Wrote {} symbols to {}", num_symbols, symbols_output);
+    }
 
+    if let Some(symbols_json_output) = &cli_args.symbols_json_output {
+        let num_symbols = match write_symbol_json(symbols_json_output, &program.labels) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, symbols_json_output)
+        };
 
-/// Takes an instruction and returns a result containing either any immediate it finds if successful, or an error if it could not find one. If it finds a label immediate,
-/// then it will return `None`.
-///
-/// Panics if an immediate outside the valid range is found.
-fn get_imm_from_instr(instr:&str, bits:u32, signed:bool, accept_char:bool, accept_label:bool) -> Result<Option<i16>, Box<dyn Error>> {
-    match LABEL_ARG_REGEX.find(&instr) {
-        Some(val) => {
-            if accept_label {
-                return Ok(None);
-            }
+        println!("Wrote {} symbols as JSON to {}", num_symbols, symbols_json_output);
+    }
+}
 
-            return Err(Box::new(AssemblyError(format!("Found label {} in instruction {} but labels are not accepted", val.as_str(), instr))));
-        },
 
-        None => {}
+/// Reports an error which stops the assembler through `StderrSink` as an error in the named file, then exits.
+fn exit_with_error(err:Box<dyn Error>, file:&str) -> ! {
+    let err = match err.downcast::<AssemblyError>() {
+        Ok(val) => *val,
+        Err(err) => AssemblyError(err.to_string())
     };
 
-    // prepended space needed to ensure that regex can tell the difference between a number such as the one6 in "$r6" and an actual immediate as Rust Regex does not support
-    // negative lookbehinds to check for "$r".
-    let instr_with_prepended_space = " ".to_owned() + instr;
+    StderrSink.report(Diagnostic::from_error(&err, file));
+    process::exit(1);
+}
 
-    let imm_str:&str = match INT_REGEX.find_iter(&instr_with_prepended_space).map(|num| num.as_str()).collect::<Vec<&str>>().get(0) {
-        Some(val) => val.trim(),
-        None => {
-            if !accept_char {
-                return Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", instr))))
-            }
 
-            match CHAR_REGEX.find_iter(&instr).map(|num| num.as_str()).collect::<Vec<&str>>().get(0) {
-                Some(val) => return Ok(Some(*string_to_decimals(&val[1..2]).unwrap().get(0).unwrap() as i16)),
-                None      => return Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", instr))))
-            }
+fn main() {
+    let args:Vec<String> = env::args().collect();
+    let cli_args = match parse_args(&args) {
+        Ok(val) => val,
+        Err(err) => {
+            match err.downcast::<AssemblyError>() {
+                Ok(err) => eprintln!("Error: {}", err.0),
+                Err(err) => eprintln!("Error: {}", err)
+            };
+
+            eprintln!("{}", USAGE);
+            process::exit(1);
         }
     };
 
-    let imm:i64 = convert_to_i64(imm_str).unwrap();
-
-    if !signed && (imm < 0 || imm > 2_i64.pow(bits) - 1) {
-        return Err(Box::new(AssemblyError(format!("Found negative immediate {} in unsigned immediate field in instruction {}", imm, instr))));
-    } else if signed && (imm < -(2_i64.pow(bits) / 2) || imm > (2_i64.pow(bits) / 2) - 1) {
-        return Err(Box::new(AssemblyError(format!("Found immediate {} outside valid range in instruction {}", imm, instr))));
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(if cli_args.verbose { "debug" } else { "warn" })).init();
+    if let Some(filename) = find_clobbered_output(&cli_args).filter(|_| cli_args.no_clobber) {
+        eprintln!("Error: {} already exists, and --no-clobber does not overwrite an existing output", filename);
+        process::exit(1);
     }
 
-    return Ok(Some(imm as i16))
-}
+    if let Some(filename) = &cli_args.format_source {
+        if cli_args.check {
+            let num_changed = match check_source_file(filename) {
+                Ok(val) => val,
+                Err(err) => exit_with_error(err, filename)
+            };
+
+            if num_changed > 0 {
+                eprintln!("{} is not formatted ({} lines would change)", filename, num_changed);
+                process::exit(1);
+            }
 
+            println!("{} is formatted", filename);
+        } else {
+            let num_changed = match format_source_file(filename) {
+                Ok(val) => val,
+                Err(err) => exit_with_error(err, filename)
+            };
 
-/// Validating .space will not work with the get_imm_from_instr() function due to Rust RegEx not implementing lookarounds. Therefore, this function validates them instead.
-///
-/// Panics if the input is not a valid statement.
-fn validate_space(instr:&str) -> Result<(), Box<dyn Error>> {
-    let elems:Vec<&str> = ELEM_REGEX.find_iter(instr).map(|item| item.as_str()).collect();
-    let array_len:i64 = elems.get(0).unwrap().parse().expect(&format!("Could not get length of array in instruction {}", instr));
-    if elems.len() > (array_len + 1) as usize {
-        return Err(Box::new(AssemblyError(format!("Array is not long enough for data in instruction {}", instr))));
+            println!("Formatted {} ({} lines changed)", filename, num_changed);
+        }
     }
 
-    for elem in elems {
-        let val = match elem.parse::<i64>() {
+    if let Some(filename) = &cli_args.disassemble {
+        let lines = match disassemble_file(filename, cli_args.disassembly_output.as_deref(), cli_args.endian) {
             Ok(val) => val,
-            Err(_) => {
-                let val:i64;
-                if elem.contains("0x") {  // hexadecimal number
-                    val = i64::from_str_radix(elem.trim_start_matches("0x"), 16).unwrap();
-                } else if elem.contains("0b") { // binary number
-                    val = i64::from_str_radix(elem.trim_start_matches("0b"), 2).unwrap();
-                } else { // elem is a character
-                    continue;
-                }
-
-                val
-            }
+            Err(err) => exit_with_error(err, filename)
         };
 
-        if val > 65535 {
-            return Err(Box::new(AssemblyError(format!("Value {} is out of the range 0 <= value < 65536 in instruction {}", val, instr).to_owned())));
+        match &cli_args.disassembly_output {
+            Some(disassembly_output) => println!("Disassembled {} words from {} --> {}", lines.len(), filename, disassembly_output),
+            None => lines.iter().for_each(|line| println!("{}", line))
+        };
+    }
+
+    if let Some(word) = cli_args.decode {
+        for line in describe_word(word) {
+            println!("{}", line);
         }
     }
 
-    Ok(())
-}
+    if cli_args.repl {
+        let mut assembler = Assembler::new().build_id(cli_args.build_id.unwrap_or(0));
+        if let Some(filename) = &cli_args.isa {
+            let spec = match IsaSpec::from_file(filename) {
+                Ok(val) => val,
+                Err(err) => exit_with_error(err, filename)
+            };
 
+            assembler = assembler.isa(spec);
+        }
 
-/// Go line-by-line through each instruction in the file, skips if it is empty, and otherwise compares against a set of regular expressions to determine the type of
-/// the instruction or pseudo-instruction, then performs other checks such as validating the range of immediate values.
-///
-/// Panics if an invalid instruction is found, otherwise returns `Ok()`
-fn validate_assembly_lines(lines:&Vec<String>) -> Result<(), Box<dyn Error>> {
-    for line in lines {
-        if line.is_empty() {
-            continue;
-        }
-
-        if RRR_REGEX.is_match(&line) {
-            continue;
-        } else if RRI_REGEX.is_match(&line) {
-            get_imm_from_instr(line, 7, true, false, true).unwrap();
-            continue;
-        } else if RI_REGEX.is_match(&line) {
-            get_imm_from_instr(line, 10, false, false, true).unwrap();
-            continue;
-        } else if JAL_REGEX.is_match(&line) {
-            continue;
-        } else if NOP_REGEX.is_match(&line) {
-            continue;
-        } else if DATA_REGEX.is_match(&line) {
-            if line.contains("LLI") {
-                get_imm_from_instr(line, 6, false, false, true).unwrap();
-            } else if line.contains("MOVI") {
-                get_imm_from_instr(line, 16, false, false, true).unwrap();
-            }
+        if let Err(err) = run_repl(io::stdin().lock(), io::stdout(), &assembler) {
+            exit_with_error(Box::new(err), "<stdin>");
+        }
 
-            continue;
-        } else if FILL_REGEX.is_match(&line) {
-            get_imm_from_instr(line, 16, true, true, false).unwrap();
-            continue;
-        } else if SPACE_REGEX.is_match(&line) {
-            validate_space(&line).unwrap();
-            continue;
-        } else if PSEUDO_TEXT_REGEX.is_match(&line) {
-            continue;
-        } else if SCALL_REGEX.is_match(&line) {
-            continue;
-        } else {
-            return Err(Box::new(AssemblyError(format!("Line did not match any valid instructions patterns: {}", line))));
+        return;
+    }
+
+    if let Some(instr) = &cli_args.instr {
+        let mut assembler = Assembler::new().build_id(cli_args.build_id.unwrap_or(0));
+        if let Some(filename) = &cli_args.isa {
+            let spec = match IsaSpec::from_file(filename) {
+                Ok(val) => val,
+                Err(err) => exit_with_error(err, filename)
+            };
+
+            assembler = assembler.isa(spec);
         }
+
+        match assemble_instr(instr, &assembler) {
+            Ok(words) => words.iter().for_each(|word| println!("0x{:04X}", word)),
+            Err(err) => {
+                eprintln!("Error: {}", err.0);
+                process::exit(1);
+            }
+        };
+
+        return;
     }
 
-    Ok(())
-}
+    if !cli_args.link.is_empty() {
+        link_objects(&cli_args);
+        return;
+    }
 
+    if cli_args.input.is_empty() {
+        return;
+    }
 
-/// Iterates through each line in the given file and returns a vector containing all the lines, then removes any '#' symbols and everythig after them, and finally
-/// trims the resulting string.
-/// 
-/// Panics if a line cannot be read or the file cannot be found.
-fn get_line_vector(filename: &str) -> Vec<String> {
-    let input_file = OpenOptions::new().read(true).open(filename).expect(&format!("ERROR: Could not open file: {}", filename));
-    let reader = BufReader::new(input_file);
-    let lines:Vec<String> = {
-        let mut line_num = 0;
-        let mut result:Vec<String> = Vec::new();
+    if cli_args.list_unresolved {
+        let unresolved = match list_unresolved_labels(LineSource::File(&cli_args.input), cli_args.lossy, cli_args.input_encoding) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(Box::new(err), &cli_args.input)
+        };
 
-        for line in reader.lines() {
-            let mut ln = line.expect(&format!("ERROR: Could not read line {}", line_num)).trim().to_owned();
-            ln = ln[..ln.find('#').unwrap_or(ln.len())].trim().to_owned(); // strip comments out of all lines
+        for (line_num, name) in &unresolved {
+            println!("Undefined label @{} on line {}", name, line_num);
+        }
 
-            result.push(ln);
-            line_num += 1;
+        if !unresolved.is_empty() {
+            eprintln!("Found {} references to undefined labels in {}", unresolved.len(), cli_args.input);
+            process::exit(1);
         }
 
-        result
-    };
+        println!("No undefined labels in {}", cli_args.input);
+        if cli_args.code_output.is_empty() {
+            return;
+        }
+    }
 
-    lines
-}
+    let mut assembler = Assembler::new().lossy(cli_args.lossy).encoding(cli_args.input_encoding).no_tabs(cli_args.no_tabs).endian(cli_args.endian)
+        .data_placement(cli_args.data_placement).build_id(cli_args.build_id.unwrap_or(0)).allow_overlap(cli_args.allow_overlap)
+        .merge_strings(cli_args.merge_strings);
+    if let Some(filename) = &cli_args.isa {
+        let spec = match IsaSpec::from_file(filename) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, filename)
+        };
 
+        assembler = assembler.isa(spec);
+    }
 
-/// Takes a vector containing the processed and assembled instructions and writes them to the specified file as 2 bytes (16 bits), creating the file if it does not
-/// already exist and then returns the number of bytes written.
-fn write_assembled_bytes(filename: &str, instrs: Vec<u16>) -> usize {
-    let mut output_file = OpenOptions::new().write(true).create(true).open(filename).expect(&format!("ERROR: Could not open file: {}", filename));
+    if cli_args.count_only {
+        let (code_size, data_size) = match assembler.count_words(LineSource::File(&cli_args.input)) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(Box::new(err), &cli_args.input)
+        };
 
-    let mut bytes:Vec<u8> = Vec::new();
-    for instr in instrs {
-        bytes.push(((instr & 0xFF00) >> 8) as u8);
-        bytes.push((instr & 0x00FF) as u8);
+        println!("{} words ({} bytes)", code_size + data_size, 2 * (code_size + data_size));
+        return;
     }
 
-    output_file.write_all(&bytes.as_slice()).unwrap();
-    return bytes.len();
-}
+    if cli_args.stream {
+        println!("Streaming {} --> {}", cli_args.input, cli_args.code_output);
+        match write_stream(&cli_args.code_output, assembler.stream(LineSource::File(&cli_args.input)), cli_args.endian) {
+            Ok(num_bytes) => println!("Successfully assembled {} bytes", num_bytes),
+            Err(err) => {
+                match err.downcast::<AssemblyError>() {
+                    Ok(err) => StderrSink.report(Diagnostic::from_error(&err, &cli_args.input)),
+                    Err(err) => eprintln!("Error: {}", err)
+                };
+
+                process::exit(1);
+            }
+        };
 
+        return;
+    }
 
-fn main() {
-    let args:Vec<String> = env::args().collect();
-    println!("Assembling {} --> {}", args[1], args[2]);
+    let writers = WriterRegistry::with_builtin_writers(cli_args.endian);
+    let writer = writers.get(cli_args.format.as_deref().unwrap_or("bin")).unwrap();
+
+    let inputs:Vec<LineSource> = iter::once(&cli_args.input).chain(&cli_args.extra_inputs).map(|filename| LineSource::File(filename)).collect();
+    let input_names = iter::once(cli_args.input.as_str()).chain(cli_args.extra_inputs.iter().map(|filename| filename.as_str())).collect::<Vec<&str>>().join(", ");
+    println!("Assembling {} --> {}", input_names, cli_args.code_output);
 
-    let mut lines:Vec<String> = get_line_vector(&args[1]);
-    lines = lines.into_iter().filter(|line| !line.is_empty()).collect();
-    validate_assembly_lines(&lines).unwrap();
-    lines = substitute_pseudoinstrs(&lines);
+    let result = match inputs.len() {
+        1 => assembler.assemble_source_timed(inputs[0]),
+        _ => assembler.assemble_sources_timed(&inputs)
+    };
 
-    let label_table = generate_label_table(&lines).unwrap();
-    lines = substitute_labels(&lines, &label_table);
+    let (mut program, mut timings) = match result {
+        Ok(val) => val,
+        Err(err) => {
+            StderrSink.report(Diagnostic::from_error(&err, &input_names));
+            process::exit(1);
+        }
+    };
 
-    let mut assembled_lines = Vec::new();
-    let mut index = 0;
-    for line in lines {
-        assembled_lines.push(convert_instr_to_binary(&line).unwrap());
-        println!("0x{:04X}:\t {:32} \t 0x{:04X}", index, line, convert_instr_to_binary(&line).unwrap());
-        index += 1;
+    if let Some(entry) = &cli_args.entry {
+        if let Err(err) = program.set_entry(entry) {
+            StderrSink.report(Diagnostic::from_error(&err, &input_names));
+            process::exit(1);
+        }
     }
 
-    let num_bytes = write_assembled_bytes(&args[2], assembled_lines);
-    println!("Successfully assembled {} bytes", num_bytes);
-}
+    if !writer.records_entry() {
+        if let Some(warning) = program.entry_warning(&input_names) {
+            StderrSink.report(warning);
+        }
+    }
+
+    for warning in program.overlap_warnings(&input_names) {
+        StderrSink.report(warning);
+    }
 
+    if cli_args.merge_strings {
+        let words_saved:usize = program.merged_strings.iter().map(|string| string.words).sum();
+        println!("Merged {} repeated strings into earlier copies, saving {} words", program.merged_strings.len(), words_saved);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    let output_start = Instant::now();
+    let mut final_lines = program.code_lines.clone();
+    if !program.data_lines.is_empty() {
+        final_lines.push(".data".to_owned());
+        final_lines.extend(program.data_lines.iter().cloned());
+    }
 
+    if let Some(resolved_output) = &cli_args.resolved_output {
+        let num_lines = match write_resolved_source(resolved_output, &final_lines) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, resolved_output)
+        };
 
-    #[test]
-    fn test_line_vector_generation() {
-        let lines = get_line_vector("test_files/test_line_vec_gen.asm");
-        assert_eq!(lines[0], "start: ADDI $r0, $r0, 5");
-        assert_eq!(lines[1], "ADDI $r0, $r1, 2");
-        assert_eq!(lines[2], "NAND $r0, $r0, $r0");
-        assert_eq!(lines[3], "NOP");
-        assert_eq!(lines[4], "ADDI $r0, $r6, 1");
-        assert_eq!(lines[5], "ADD $r0, $r0, $r1");
-        assert_eq!(lines[6], "MOVI $r0, @start");
-        assert_eq!(lines.len(), 7);
+        println!("Wrote {} lines of resolved source to {}", num_lines, resolved_output);
     }
 
+    if let Some(expanded_output) = &cli_args.expanded_output {
+        let num_lines = match write_expanded_lines(expanded_output, &final_lines) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, expanded_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn test_line_vector_gen_invalid_file() {
-        let _lines = get_line_vector("test_files/does_not_exist.asm");
+        println!("Wrote {} expanded lines to {}", num_lines, expanded_output);
     }
 
+    if !program.data_lines.is_empty() && cli_args.data_output.is_none() && !writer.is_relocatable() {
+        let err = AssemblyError("The program has a .data section but no data output file was given with --data".to_owned());
+        StderrSink.report(Diagnostic::from_error(&err, &input_names));
+        process::exit(1);
+    }
 
-    #[test]
-    fn test_valid_instrs() {
-        let lines = get_line_vector("test_files/test_valid_instrs.asm");
-        validate_assembly_lines(&lines).unwrap();
+    let external_labels = program.external_labels();
+    if !external_labels.is_empty() && !writer.is_relocatable() {
+        let err = AssemblyError(format!("The program refers to the external labels {}, so can only be written as an object file with --format obj",
+            external_labels.join(", ")));
+        StderrSink.report(Diagnostic::from_error(&err, &input_names));
+        process::exit(1);
     }
 
+    if cli_args.warn_data_in_code {
+        for found in find_data_in_code(&program.code_lines, assembler.options().isa_spec()) {
+            StderrSink.report(found.warning(&input_names, cli_args.byte_addresses));
+        }
+    }
 
-    #[test]
-    #[should_panic]
-    fn test_invalid_rrr() {
-        let lines = vec!["ADD $zero $r1 $r1".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    if cli_args.warn_uninitialised {
+        for warning in analyse_registers(&program).uninitialised_warnings(&input_names) {
+            StderrSink.report(program.sources.locate_diagnostic(warning));
+        }
     }
 
+    let dump_range = cli_args.dump_from.unwrap_or(0)..=cli_args.dump_to.unwrap_or(usize::MAX);
+    print_section(&program.code_lines, &program.code, 0, cli_args.imm_radix, cli_args.byte_addresses, &dump_range);
+    let mut image:Vec<u8> = Vec::new();
+    let num_bytes = match writer.write(&program, &mut image) {
+        Ok(val) => val,
+        Err(err) => exit_with_error(Box::new(err), &cli_args.code_output)
+    };
 
-    #[test]
-    fn test_get_imm_from_instr() {
-        let mut imm = get_imm_from_instr("ADDI $r0, $r1, 10", 7, true, true, true).unwrap();
-        assert_eq!(imm.unwrap(), 10);
+    if let Err(err) = write_file_atomically(&cli_args.code_output, &image) {
+        exit_with_error(err, &cli_args.code_output);
+    }
 
-        imm = get_imm_from_instr("ADDI $r0, $r1, -10", 7, true, true, true).unwrap();
-        assert_eq!(imm.unwrap(), -10);
+    println!("Successfully assembled {} bytes", num_bytes);
 
-        imm = get_imm_from_instr("ADDI $r0, $r1, 0x03A", 7, true, true, true).unwrap();
-        assert_eq!(imm.unwrap(), 0x3A);
+    if let Some(data_output) = &cli_args.data_output {
+        println!("Assembling data section --> {}", data_output);
+        print_section(&program.data_lines, &program.data, program.data_base, cli_args.imm_radix, cli_args.byte_addresses, &dump_range);
+        let num_bytes = match write_assembled_bytes(data_output, program.data.clone(), cli_args.endian) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, data_output)
+        };
 
-        imm = get_imm_from_instr("ADDI $r0, $r1, 0b011010", 7, true, true, true).unwrap();
-        assert_eq!(imm.unwrap(), 0b11010);
+        println!("Successfully assembled {} bytes", num_bytes);
+    }
 
-        imm = get_imm_from_instr(".fill 'a'", 16, true, true, false).unwrap();
-        assert_eq!(imm.unwrap(), 97);
+    if let Some(reloc_output) = &cli_args.reloc_output {
+        let num_relocations = match write_relocations(reloc_output, &program.relocations) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, reloc_output)
+        };
 
-        imm = get_imm_from_instr("ADDI $r0, $r1, @label", 16, true, true, true).unwrap();
-        assert_eq!(imm, None);
+        println!("Wrote {} relocations to {}", num_relocations, reloc_output);
     }
 
+    if let Some(vectors_output) = &cli_args.vectors_output {
+        let num_vectors = match write_test_vectors(vectors_output, &program.code_lines, &program.code) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, vectors_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn test_invalid_label_imm() {
-        let imm = get_imm_from_instr("ADDI $r0, $r1, @label", 16, true, true, false).unwrap();
-        assert_eq!(imm, None);
+        println!("Wrote {} test vectors to {}", num_vectors, vectors_output);
     }
 
+    if let Some(encode_json_output) = &cli_args.encode_json_output {
+        let num_encodings = match write_encoding_json(encode_json_output, &program.code_lines, &program.code) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, encode_json_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn test_negative_unsigned_imm() {
-        let _imm = get_imm_from_instr("ADDI $r0, $r1, -10", 7, false, false, true).unwrap();
+        println!("Wrote {} encodings as JSON to {}", num_encodings, encode_json_output);
     }
 
+    if let Some(listing_output) = &cli_args.listing_output {
+        let words:Vec<u16> = program.code.iter().chain(program.data.iter()).copied().collect();
+        let num_words = match write_text_listing(listing_output, &final_lines, &words, program.data_base, cli_args.byte_addresses) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, listing_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn unsigned_imm_out_of_range() {
-        let _imm = get_imm_from_instr("ADDI $r0, $r1, 128", 7, false, false, true).unwrap();
+        println!("Wrote a listing of {} words to {}", num_words, listing_output);
     }
 
+    if let Some(symbols_output) = &cli_args.symbols_output {
+        let num_symbols = match write_symbol_map(symbols_output, &program.labels) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, symbols_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn signed_imm_to_large() {
-        let _imm = get_imm_from_instr("ADDI $r0, $r1, 64", 7, true, false, true).unwrap();
+        println!("Wrote {} symbols to {}", num_symbols, symbols_output);
     }
 
+    if let Some(symbols_json_output) = &cli_args.symbols_json_output {
+        let num_symbols = match write_symbol_json(symbols_json_output, &program.labels) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, symbols_json_output)
+        };
 
-    #[test]
-    #[should_panic]
-    fn signed_imm_too_small() {
-        let _imm = get_imm_from_instr("ADDI $r0, $r1, -65", 7, true, false, true).unwrap();
+        println!("Wrote {} symbols as JSON to {}", num_symbols, symbols_json_output);
     }
 
+    if let Some(memory_map_output) = &cli_args.memory_map_output {
+        let num_regions = match write_memory_map(memory_map_output, &memory_map(&program)) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, memory_map_output)
+        };
+
+        println!("Wrote {} memory regions to {}", num_regions, memory_map_output);
+    }
 
-    #[test]
-    #[should_panic]
-    fn test_unsigned_imm_too_large() {
-        let lines = vec!["ADDI $r0, $r1, 100000".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    if let Some(memory_map_json_output) = &cli_args.memory_map_json_output {
+        let num_regions = match write_memory_map_json(memory_map_json_output, &memory_map(&program)) {
+            Ok(val) => val,
+            Err(err) => exit_with_error(err, memory_map_json_output)
+        };
+
+        println!("Wrote {} memory regions as JSON to {}", num_regions, memory_map_json_output);
     }
 
+    if cli_args.xref {
+        print_xref(&program, cli_args.byte_addresses);
+    }
 
-    #[test]
-    #[should_panic]
-    fn test_signed_imm_too_large() {
-        let lines = vec!["ADDI $r0, $r1, 100".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    if cli_args.reg_report {
+        print_register_report(&analyse_registers(&program), cli_args.byte_addresses);
     }
 
+    if cli_args.profile {
+        timings.push(("output", output_start.elapsed()));
+        print_profile(&timings);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
 
     #[test]
-    #[should_panic]
-    fn test_non_ascii_char_fill() {
-        let lines = vec![".fill 'д'".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), ..Default::default() });
+
+        let args:Vec<String> = ["asm", "in.asm", "--code", "out.bin", "--data", "data.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), data_output: Some("data.bin".to_owned()), ..Default::default() });
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--reloc", "out.reloc"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().reloc_output, Some("out.reloc".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--lossy"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().lossy);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--imm-radix", "hex"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().imm_radix, ImmRadix::Hex);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--input-encoding", "latin1"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().input_encoding, InputEncoding::Latin1);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--export-vectors", "out.vec"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().vectors_output, Some("out.vec".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--encode-json", "out.json"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().encode_json_output, Some("out.json".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--text-listing", "out.lst"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().listing_output, Some("out.lst".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--resolve-labels", "out.asm"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().resolved_output, Some("out.asm".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--byte-addresses"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().byte_addresses);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--symbols", "out.sym"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().symbols_output, Some("out.sym".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--emit-expanded", "out.expanded.asm"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().expanded_output, Some("out.expanded.asm".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--format", "bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().format, Some("bin".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--format", "hex"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap_err().to_string().contains("Unknown output format hex, expected one of: bin"));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--symbols-json", "out.json"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().symbols_json_output, Some("out.json".to_owned()));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--memory-map", "out.map", "--memory-map-json", "out.map.json"].iter().map(|arg| arg.to_string()).collect();
+        let cli_args = parse_args(&args).unwrap();
+        assert_eq!((cli_args.memory_map_output, cli_args.memory_map_json_output), (Some("out.map".to_owned()), Some("out.map.json".to_owned())));
+
+        let args:Vec<String> = ["asm", "link", "a.obj", "-o", "out.bin", "--memory-map", "out.map"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--endian", "little"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().endian, Endian::Little);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--no-tabs"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().no_tabs);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--profile"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().profile);
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--isa", "custom.toml"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().isa, Some("custom.toml".to_owned()));
     }
 
 
     #[test]
     #[should_panic]
-    fn test_invalid_fill_integer() {
-        let lines = vec![".fill -100000".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_invalid_imm_radix() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--imm-radix", "oct"].iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).unwrap();
     }
 
 
     #[test]
-    fn test_validate_space() {
-        validate_space(".space 10 [100, 200, 0xFF, 0b001100, 'a', 'b']").unwrap();
+    #[should_panic]
+    fn test_parse_args_invalid_input_encoding() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--input-encoding", "utf16"].iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).unwrap();
     }
 
 
     #[test]
     #[should_panic]
-    fn test_validate_invalid_space() {
-        validate_space(".space 10 [100, 200, 0xFFFFF, 0b001100, 'a', 'b']").unwrap();
+    fn test_parse_args_two_code_outputs() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--code", "other.bin"].iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).unwrap();
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_array_too_small() {
-        validate_space(".space 3 [100, 200, 50, 20]").unwrap();
+    fn test_parse_args_format_source() {
+        let args:Vec<String> = ["asm", "--format-source", "in.asm"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { format_source: Some("in.asm".to_owned()), ..Default::default() });
+
+        let args:Vec<String> = ["asm", "fmt", "in.asm", "--check"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { format_source: Some("in.asm".to_owned()), check: true, ..Default::default() });
+
+        let args:Vec<String> = ["asm", "--fmt", "in.asm"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { format_source: Some("in.asm".to_owned()), ..Default::default() });
+
+        let args:Vec<String> = ["asm", "fmt"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--check"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_invalid_syscall_code() {
-        let lines = vec![".syscall 18".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_disassemble() {
+        let args:Vec<String> = ["asm", "--disassemble", "in.bin", "-o", "out.asm", "--endian", "little"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { disassemble: Some("in.bin".to_owned()), disassembly_output: Some("out.asm".to_owned()),
+            endian: Endian::Little, ..Default::default() });
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_label_with_space() {
-        let lines = vec!["hello world: ADD $r0, $r1, $r2".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_list_unresolved() {
+        let args:Vec<String> = ["asm", "in.asm", "--list-unresolved"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), list_unresolved: true, ..Default::default() });
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--list-unresolved"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), list_unresolved: true, ..Default::default() });
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_label_with_non_alphabet_char() {
-        let lines = vec!["he**world: ADD $r0, $r1, $r2".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_warn_data_in_code() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--warn-data-in-code"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), warn_data_in_code: true,
+            ..Default::default() });
     }
 
 
     #[test]
-    fn test_valid_pseudoinstr_substitutions() {
-        let mut lines = get_line_vector("test_files/test_valid_pseudo_subs.asm");
-        validate_assembly_lines(&lines).unwrap();
-        lines = substitute_pseudoinstrs(&lines);
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_verbose() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--verbose"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), verbose: true, ..Default::default() });
 
-        assert_eq!(lines[0], "ADDI $r0, $zero, 20");
-        assert_eq!(lines[1], "ADDI $r1, $r1, 20");
-        assert_eq!(lines[2], "ADD $zero, $zero, $zero");
-        assert_eq!(lines[3], "ADDI $r2, $zero, 20");
-        assert_eq!(lines[4], "labelA: ADDI $r2, $r2, 50");
-        assert_eq!(lines[5], "labelB: ADD $zero, $zero, $zero");
-        assert_eq!(lines[6], "labelC: ADDI $r1, $zero, 48");
-        assert_eq!(lines[7], "LUI $r1, 992");
-        assert_eq!(lines.len(), 8);
+        let args:Vec<String> = ["asm", "--repl", "--verbose"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { repl: true, verbose: true, ..Default::default() });
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_invalid_lli() {
-        let lines = vec!["LLI $r0, 86".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_reg_report() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--reg-report"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), reg_report: true, ..Default::default() });
     }
 
 
     #[test]
-    fn test_convert_to_i64() {
-        assert_eq!(convert_to_i64("100").unwrap(), 100);
-        assert_eq!(convert_to_i64("-100").unwrap(), -100);
-        assert_eq!(convert_to_i64("0x0F4").unwrap(), 244);
-        assert_eq!(convert_to_i64("0b0110").unwrap(), 6);
-        assert_eq!(convert_to_i64("'c'").unwrap(), 99);
-        assert_eq!(convert_to_i64("'&''").unwrap(), 38);
+    fn test_parse_args_warn_uninitialised() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--warn-uninitialised"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), warn_uninitialised: true,
+            ..Default::default() });
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_convert_to_i64_non_ascii_char() {
-        assert_eq!(convert_to_i64("'Ж'").unwrap(), 100);
+    fn test_parse_args_xref() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--xref"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), xref: true, ..Default::default() });
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_convert_to_i64_malformed_char() {
-        assert_eq!(convert_to_i64("a'").unwrap(), 100);
+    fn test_parse_args_repl() {
+        let args:Vec<String> = ["asm", "--repl", "--isa", "custom.toml"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { isa: Some("custom.toml".to_owned()), repl: true, ..Default::default() });
+
+        let args:Vec<String> = ["asm", "--repl", "in.asm"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    fn test_space_sub() {
-        let mut lines = get_line_vector("test_files/test_space_sub.asm");
-        validate_assembly_lines(&lines).unwrap();
-        lines = substitute_pseudoinstrs(&lines);
+    fn test_parse_args_instr() {
+        let args:Vec<String> = ["asm", "--instr", "ADDI $r0, $zero, 7"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { instr: Some("ADDI $r0, $zero, 7".to_owned()), ..Default::default() });
+
+        let args:Vec<String> = ["asm", "--instr", "MOVI $r0, 32", "--isa", "custom.toml"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { instr: Some("MOVI $r0, 32".to_owned()), isa: Some("custom.toml".to_owned()), ..Default::default() });
 
-        assert_eq!(lines[0], "ADD $r0, $r1, $r2");
-        assert_eq!(lines[1], "start: .fill 0x0064");
-        assert_eq!(lines[2], ".fill 0xFFFE");
-        assert_eq!(lines[3], ".fill 0x0061");
-        assert_eq!(lines[4], ".fill 0x0000");
-        assert_eq!(lines[5], ".fill 0x0000");
-        assert_eq!(lines[6], "ADD $r0, $r1, $r3");
+        let args:Vec<String> = ["asm", "--instr", "ADDI $r0, $zero, 7", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
+
+        let args:Vec<String> = ["asm", "--instr"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    fn test_text_sub() {
-        let mut lines = vec!["tag: .text \"Hell@ \"w0rld!\"".to_owned()];
-        validate_assembly_lines(&lines).unwrap();
-        lines = substitute_pseudoinstrs(&lines);
+    fn test_parse_args_decode() {
+        let args:Vec<String> = ["asm", "--decode", "0x2807"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { decode: Some(0x2807), ..Default::default() });
 
-        assert_eq!(lines[0], "tag: .fill 0x0048");
-        assert_eq!(lines[2], ".fill 0x006C");
-        assert_eq!(lines[4], ".fill 0x0040");
-        assert_eq!(lines[5], ".fill 0x0020");
-        assert_eq!(lines[6], ".fill 0x0022");
-        assert_eq!(lines[12], ".fill 0x0021");
-        assert_eq!(lines[13], ".fill 0x0000");
-        assert_eq!(lines.len(), 14);
+        let args:Vec<String> = ["asm", "--decode", "0x10000"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    fn test_label_table_generation() {
-        let mut lines = get_line_vector("test_files/test_label_table_generation.asm");
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_dump_range() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--dump-from", "0x0010", "--dump-to", "32"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), dump_from: Some(0x10), dump_to: Some(32),
+            ..Default::default() });
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--dump-to", "0x0008"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().dump_to, Some(8));
 
-        lines = substitute_pseudoinstrs(&lines);
-        lines = lines.into_iter().filter(|line| !line.is_empty()).collect();
-        
-        let tags = generate_label_table(&lines).unwrap();
-        assert_eq!(tags["start"], 0);
-        assert_eq!(tags["something"], 3);
-        assert_eq!(tags["number"], 4);
-        assert_eq!(tags["hello"], 5);
-        assert_eq!(tags["more_text"], 11);
+        for invalid in [vec!["--dump-from", "0x20", "--dump-to", "0x10"], vec!["--dump-from", "-1"], vec!["--dump-to", "end"], vec!["--dump-from"]] {
+            let args:Vec<String> = ["asm", "in.asm", "out.bin"].iter().chain(invalid.iter()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_duplicate_label() {
-        let mut lines = get_line_vector("test_files/test_duplicate_label.asm");
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_data_base() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--data-base", "0x4000"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().data_placement, DataPlacement::At(0x4000));
 
-        lines = substitute_pseudoinstrs(&lines);
-        lines = lines.into_iter().filter(|line| !line.is_empty()).collect();
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--data-base", "after-code"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().data_placement, DataPlacement::AfterCode);
 
-        generate_label_table(&lines).unwrap();
+        for invalid in [vec!["--data-base", "0x10000"], vec!["--data-base", "-1"], vec!["--data-base", "end"], vec!["--data-base"]] {
+            let args:Vec<String> = ["asm", "in.asm", "out.bin"].iter().chain(invalid.iter()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
     }
 
 
     #[test]
-    fn test_label_operands() {
-        let mut lines:Vec<String> = get_line_vector("test_files/test_label_operands.asm");
-        lines = lines.into_iter().filter(|line| !line.is_empty()).collect();
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_link() {
+        let args:Vec<String> = ["asm", "link", "a.o", "b.o", "-o", "prog.bin", "--map", "prog.map", "--data-base", "after-code"].iter().map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { code_output: "prog.bin".to_owned(), link: vec!["a.o".to_owned(), "b.o".to_owned()],
+            map_output: Some("prog.map".to_owned()), data_placement: DataPlacement::AfterCode, ..Default::default() });
+
+        for invalid in [vec!["link", "-o", "prog.bin"], vec!["link", "a.o"], vec!["in.asm", "out.bin", "--map", "prog.map"],
+            vec!["link", "a.o", "-o", "prog.bin", "--format", "bogus"]] {
+            let args:Vec<String> = ["asm"].iter().chain(invalid.iter()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
+    }
 
-        lines = substitute_pseudoinstrs(&lines);
 
-        let label_table = generate_label_table(&lines).unwrap();
-        lines = substitute_labels(&lines, &label_table);
+    #[test]
+    fn test_parse_args_count_only() {
+        let args:Vec<String> = ["asm", "in.asm", "--count-only", "--no-tabs"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), no_tabs: true, count_only: true, ..Default::default() });
 
-        assert_eq!(lines[2], "move: ADDI $r6, $zero, 0");
-        assert_eq!(lines[5], "ADDI $r0, $zero, 2");
-        assert_eq!(lines[77], "after_text: ADDI $r6, $zero, 6");
-        assert_eq!(lines[78], "LUI $r6, 0");
-        assert_eq!(lines[79], "ADDI $r5, $zero, 13");
-        assert_eq!(lines[80], "LUI $r5, 1");
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--count-only"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_non_existent_label_operand() {
-        let mut _lines = vec!["MOVI $r1, @nowhere".to_owned()];
-        _lines = _lines.into_iter().filter(|line| !line.is_empty()).collect();
-        validate_assembly_lines(&_lines).unwrap();
+    fn test_parse_args_several_inputs() {
+        let args:Vec<String> = ["asm", "boot.asm", "kernel.asm", "data.asm", "-o", "rom.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "boot.asm".to_owned(), extra_inputs: vec!["kernel.asm".to_owned(), "data.asm".to_owned()],
+            code_output: "rom.bin".to_owned(), ..Default::default() });
+
+        // -o names the code image on its own too, with the positional output left out
+        let args:Vec<String> = ["asm", "in.asm", "-o", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), ..Default::default() });
+
+        for invalid in [vec!["boot.asm", "kernel.asm", "rom.bin"], vec!["boot.asm", "kernel.asm", "-o", "rom.bin", "--list-unresolved"],
+                vec!["boot.asm", "-o", "rom.bin", "--count-only"], vec!["--repl", "-o", "rom.bin"]] {
+            let args:Vec<String> = ["asm"].iter().chain(invalid.iter()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
+    }
 
-        _lines = substitute_pseudoinstrs(&_lines);
 
-        let label_table = generate_label_table(&_lines).unwrap();
-        _lines = substitute_labels(&_lines, &label_table);
+    #[test]
+    fn test_parse_args_stream() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--stream", "--endian", "little", "--format", "bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.bin".to_owned(), stream: true, endian: Endian::Little,
+            format: Some("bin".to_owned()), ..Default::default() });
+
+        for invalid in [vec!["--data", "data.bin"], vec!["--format", "obj"], vec!["--symbols", "out.sym"], vec!["--xref"], vec!["--data-base", "after-code"]] {
+            let args:Vec<String> = ["asm", "in.asm", "out.bin", "--stream"].iter().chain(invalid.iter()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).unwrap_err().to_string().contains("--stream writes each word"), "{:?}", invalid);
+        }
     }
 
 
     #[test]
-    fn test_convert_to_binary() {
-        assert_eq!(convert_instr_to_binary(&"ADD  $r0, $zero, $r1".to_owned()).unwrap(), 0x0420_u16);
-        assert_eq!(convert_instr_to_binary(&"NAND $r2, $r3,   $r4".to_owned()).unwrap(), 0x4E50_u16);
-        assert_eq!(convert_instr_to_binary(&"BEQ  $r5, $zero, $r6".to_owned()).unwrap(), 0xD870_u16);
+    fn test_parse_args_entry() {
+        let args:Vec<String> = ["asm", "in.asm", "out.o", "--entry", "start", "--format", "obj"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), CliArgs { input: "in.asm".to_owned(), code_output: "out.o".to_owned(), entry: Some("start".to_owned()),
+            format: Some("obj".to_owned()), ..Default::default() });
+
+        for invalid in [vec!["in.asm", "out.bin", "--entry"], vec!["in.asm", "out.bin", "--entry", "start", "--stream"], vec!["link", "a.o", "-o", "out.bin", "--entry", "start"]] {
+            let args:Vec<String> = iter::once("asm").chain(invalid.iter().copied()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
+    }
 
-        assert_eq!(convert_instr_to_binary(&"ADDI $r1, $zero,  7".to_owned()).unwrap(),  0x2807_u16);
-        assert_eq!(convert_instr_to_binary(&"ADDI $r1, $zero, -7".to_owned()).unwrap(),  0x2879_u16);
-        assert_eq!(convert_instr_to_binary(&"SW   $r1, $r2,   30".to_owned()).unwrap(),  0x899E_u16);
-        assert_eq!(convert_instr_to_binary(&"LW   $r6, $r5,  -10".to_owned()).unwrap(),  0xBF76_u16);
 
-        assert_eq!(convert_instr_to_binary(&"0x0455".to_owned()).unwrap(), 0x0455_u16);
-        assert_eq!(convert_instr_to_binary(&"10000".to_owned()).unwrap(),  0x2710_u16);
+    #[test]
+    fn test_parse_args_build_id() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--build-id", "0x2A01"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().build_id, Some(0x2A01));
+
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--build-id", "timestamp"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().build_id.is_some());
 
-        assert_eq!(convert_instr_to_binary(&"LUI $r0, 500".to_owned()).unwrap(),  0x65F4_u16);
+        let args:Vec<String> = ["asm", "in.asm", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap().build_id, None);
 
-        assert_eq!(convert_instr_to_binary(&".syscall 5".to_owned()).unwrap(),  0xF405_u16);
-        assert_eq!(convert_instr_to_binary(&"JAL $r5, $r6".to_owned()).unwrap(),  0xFB80_u16);
+        for invalid in [vec!["in.asm", "out.bin", "--build-id"], vec!["in.asm", "out.bin", "--build-id", "0x10000"], vec!["in.asm", "out.bin", "--build-id", "-1"],
+            vec!["link", "a.o", "-o", "out.bin", "--build-id", "5"]] {
+            let args:Vec<String> = iter::once("asm").chain(invalid.iter().copied()).map(|arg| arg.to_string()).collect();
+            assert!(parse_args(&args).is_err(), "{:?}", invalid);
+        }
     }
 
 
     #[test]
-    #[should_panic]
-    fn test_convert_invalid_instr_to_binary() {
-        convert_instr_to_binary(&"INVALID  $r0, $zero, $r1".to_owned()).unwrap();
-    }
+    fn test_parse_args_allow_overlap() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--allow-overlap"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().allow_overlap);
 
+        let args:Vec<String> = ["asm", "in.asm", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        assert!(!parse_args(&args).unwrap().allow_overlap);
 
-    #[test]
-    #[should_panic]
-    fn test_convert_invalid_register_to_binary() {
-        convert_instr_to_binary(&"ADD  $r0, $r9, $r1".to_owned()).unwrap();
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--allow-overlap", "--stream"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
     }
 
 
     #[test]
-    fn test_file_bios() {
-        let mut lines:Vec<String> = get_line_vector("test_files/test_file_bios.asm");
-        lines = lines.into_iter().filter(|line| !line.is_empty()).collect();
-        validate_assembly_lines(&lines).unwrap();
+    fn test_parse_args_merge_strings() {
+        let args:Vec<String> = ["asm", "in.asm", "out.bin", "--merge-strings"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).unwrap().merge_strings);
 
-        lines = substitute_pseudoinstrs(&lines);
-        let label_table = generate_label_table(&lines).unwrap();
+        let args:Vec<String> = ["asm", "link", "a.o", "-o", "out.bin", "--merge-strings"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err());
+    }
 
-        lines = substitute_labels(&lines, &label_table);
 
-        let mut assembled_lines = Vec::new();
-        for line in lines {
-            assembled_lines.push(convert_instr_to_binary(&line).unwrap());
-        }
+    #[test]
+    fn test_no_clobber() {
+        let existing = env::temp_dir().join("iridium_test_clobber.bin").to_str().unwrap().to_owned();
+        let missing = env::temp_dir().join("iridium_test_clobber_missing.sym").to_str().unwrap().to_owned();
+        let _ = std::fs::remove_file(&missing);
+        std::fs::write(&existing, [0x12, 0x34, 0x56, 0x78]).unwrap();
+
+        let args:Vec<String> = ["asm", "in.asm", missing.as_str(), "--symbols", existing.as_str(), "--no-clobber"].iter().map(|arg| arg.to_string()).collect();
+        let cli_args = parse_args(&args).unwrap();
+        assert!(cli_args.no_clobber);
+        assert_eq!(find_clobbered_output(&cli_args), Some(existing.as_str()));
+
+        // the file being formatted is rewritten in place rather than clobbered
+        let args:Vec<String> = ["asm", "fmt", existing.as_str(), "--no-clobber"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(find_clobbered_output(&parse_args(&args).unwrap()), None);
+        std::fs::remove_file(&existing).unwrap();
+    }
+
 
-        assert_eq!(assembled_lines[2], 0x280B);
-        assert_eq!(assembled_lines[3], 0x6800);
+    #[test]
+    #[should_panic]
+    fn test_parse_args_output_with_code() {
+        let args:Vec<String> = ["asm", "in.asm", "--code", "out.bin", "-o", "out.asm"].iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).unwrap();
     }
 }
-
@@ -0,0 +1,158 @@
+use std::io;
+use serde::{ Deserialize, Serialize };
+use crate::{ AssembledProgram, AssemblyError };
+use crate::labels::{ LabelKind, LinkRelocation, Section, SymbolTable };
+use crate::writer::OutputWriter;
+
+
+/// The name every object file gives as its format, so that some other JSON file is not mistaken for one.
+pub const OBJECT_FORMAT:&str = "iridium-object";
+/// The version of the layout of the object files written by this assembler, which is raised whenever a field is changed so an older object is rejected rather
+/// than misread.
+pub const OBJECT_VERSION:u32 = 1;
+
+
+/// A program assembled on its own to be linked with others later, written by `--format obj` as a single JSON object. It holds the words of the code and data
+/// sections, each with addresses counting from 0, the label table with the labels declared with `.export` marked as exported, and the `LinkRelocation` of every
+/// word the linker must patch once it has placed the sections and found the address of each label declared with `.extern`. The `entry` names the label the
+/// program starts running from if it has one, and may be left out of the file, so objects written before it was added can still be read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectFile {
+    pub format: String,
+    pub version: u32,
+    pub code: Vec<u16>,
+    pub data: Vec<u16>,
+    pub symbols: SymbolTable,
+    pub relocations: Vec<LinkRelocation>,
+    #[serde(default)]
+    pub entry: Option<String>
+}
+
+impl ObjectFile {
+    /// Creates the object file of an assembled program. If its data section was placed at an address other than 0 with a `labels::DataPlacement`, the labels in
+    /// it are moved back to count from 0, as the linker decides where the section goes.
+    pub fn from_program(program:&AssembledProgram) -> ObjectFile {
+        let symbols = program.labels.iter().cloned().map(|mut symbol| {
+            if symbol.section == Section::Data && symbol.kind != LabelKind::Absolute {
+                symbol.address -= program.data_base as u16;
+            }
+
+            symbol
+        }).collect();
+
+        ObjectFile { format: OBJECT_FORMAT.to_owned(), version: OBJECT_VERSION, code: program.code.clone(), data: program.data.clone(), symbols,
+            relocations: program.link_relocations.clone(), entry: program.entry.as_ref().map(|entry| entry.name.clone()) }
+    }
+
+
+    /// Writes the object file to `sink` as JSON, and then returns the number of bytes written.
+    pub fn write(&self, sink:&mut dyn io::Write) -> io::Result<u64> {
+        let mut json = serde_json::to_vec(self)?;
+        json.push(b'\n');
+        sink.write_all(&json)?;
+        Ok(json.len() as u64)
+    }
+
+
+    /// Reads an object file written by `write`.
+    ///
+    /// Returns an `AssemblyError` if the reader fails, what it gives is not an object file, or the object file has a version other than `OBJECT_VERSION`.
+    pub fn read(reader:impl io::Read) -> Result<ObjectFile, AssemblyError> {
+        let object:ObjectFile = match serde_json::from_reader(reader) {
+            Ok(val) => val,
+            Err(err) => return Err(AssemblyError(format!("Could not read object file: {}", err)))
+        };
+
+        if object.format != OBJECT_FORMAT {
+            return Err(AssemblyError(format!("Expected an object file of format {} but found {}", OBJECT_FORMAT, object.format)));
+        } else if object.version != OBJECT_VERSION {
+            return Err(AssemblyError(format!("Object file has version {} but only version {} can be read", object.version, OBJECT_VERSION)));
+        }
+
+        Ok(object)
+    }
+}
+
+
+/// Writes a program as the relocatable `ObjectFile` for linking it with others, which is the only format a program referring to a label declared with `.extern`
+/// can be written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectWriter;
+
+impl OutputWriter for ObjectWriter {
+    fn write(&self, program:&AssembledProgram, sink:&mut dyn io::Write) -> io::Result<u64> {
+        ObjectFile::from_program(program).write(sink)
+    }
+
+
+    fn extension(&self) -> &str {
+        "o"
+    }
+
+
+    fn is_relocatable(&self) -> bool {
+        true
+    }
+
+
+    fn records_entry(&self) -> bool {
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+    use crate::labels::RelocationKind;
+
+
+    #[test]
+    fn test_object_round_trip() {
+        let program = assemble_str(".extern print\n.extern table\n.export main\nmain: MOVI $r6, @print\nJAL $r5, $r6\nLW $r1, $zero, @table+2\nMOVI $r2, @handler\n\
+            .syscall 6\n.data\nhandler: .fill @print\n").unwrap();
+        assert_eq!(program.external_labels(), vec!["print", "table"]);
+
+        let mut bytes:Vec<u8> = Vec::new();
+        let num_bytes = ObjectWriter.write(&program, &mut bytes).unwrap();
+        assert_eq!(num_bytes as usize, bytes.len());
+
+        let object = ObjectFile::read(bytes.as_slice()).unwrap();
+        assert_eq!(object, ObjectFile::from_program(&program));
+        assert_eq!((object.code.len(), object.data.clone(), object.code[0]), (7, vec![0], 0x3C00)); // ADDI $r6, $zero, 0
+        assert!(object.symbols["main"].exported);
+        assert!(!object.symbols["handler"].exported);
+
+        // one relocation for each way an external label is held, and one against the data section for the program's own label
+        let relocation = |section, index, symbol:&str, kind, addend| LinkRelocation { section, index, symbol: symbol.to_owned(), kind, addend };
+        assert_eq!(object.relocations, vec![
+            relocation(Section::Code, 0, "print", RelocationKind::Lo6, 0),
+            relocation(Section::Code, 1, "print", RelocationKind::Hi10, 0),
+            relocation(Section::Code, 3, "table", RelocationKind::Lo6, 2),
+            relocation(Section::Code, 4, ".data", RelocationKind::Lo6, 0),
+            relocation(Section::Code, 5, ".data", RelocationKind::Hi10, 0),
+            relocation(Section::Data, 0, "print", RelocationKind::Full16, 0)
+        ]);
+
+        // an object written before the entry point was recorded is read as having none
+        let legacy = String::from_utf8(bytes).unwrap().replace(",\"entry\":null", "");
+        assert_eq!(ObjectFile::read(legacy.as_bytes()).unwrap(), object);
+        let program = assemble_str(".global main\nNOP\nmain: NOP\n").unwrap();
+        assert_eq!(ObjectFile::from_program(&program).entry.as_deref(), Some("main"));
+    }
+
+
+    #[test]
+    fn test_read_invalid_object() {
+        let err = ObjectFile::read(&b"[1, 2]"[..]).unwrap_err();
+        assert!(err.0.starts_with("Could not read object file"), "{}", err.0);
+
+        let program = assemble_str("NOP\n").unwrap();
+        let mut object = ObjectFile::from_program(&program);
+        object.version = OBJECT_VERSION + 1;
+        let mut bytes:Vec<u8> = Vec::new();
+        object.write(&mut bytes).unwrap();
+        assert_eq!(ObjectFile::read(bytes.as_slice()).unwrap_err().0, format!("Object file has version {} but only version 1 can be read", OBJECT_VERSION + 1));
+    }
+}
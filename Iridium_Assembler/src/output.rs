@@ -0,0 +1,881 @@
+use std::io::{ self, Write };
+use serde::{ Deserialize, Serialize };
+use crate::convert_to_i64;
+use crate::encoder::InstructionFields;
+use crate::parser::{ DUMP_IMM_REGEX, LABEL_REGEX, find_comment_start, is_continued, split_operands };
+use crate::labels::{ LabelKind, Section, SymbolTable };
+
+// the functions which write files are only needed by the command line tool, so they are left out of builds such as WebAssembly which have no filesystem
+#[cfg(feature = "cli")]
+use std::borrow::Cow;
+#[cfg(feature = "cli")]
+use std::error::Error;
+#[cfg(feature = "cli")]
+use std::fs::{ self, File, OpenOptions };
+#[cfg(feature = "cli")]
+use std::io::BufWriter;
+#[cfg(feature = "cli")]
+use crate::AssemblyError;
+#[cfg(feature = "cli")]
+use crate::parser::read_source_lines;
+#[cfg(feature = "cli")]
+use crate::labels::{ RelocationKind, get_section_switch, strip_label_definitions };
+#[cfg(feature = "cli")]
+use crate::encoder::{ decode, parse_instruction };
+#[cfg(feature = "cli")]
+use crate::isa::DEFAULT_ISA_SPEC;
+#[cfg(feature = "cli")]
+use crate::expansion::expand_runs;
+#[cfg(feature = "cli")]
+use crate::stream::WordStream;
+#[cfg(feature = "cli")]
+use crate::layout::{ MemoryRegion, format_memory_map };
+
+
+/// An instruction of the code section as written by `write_encoding_json`, giving its address, the instruction it was encoded from once any pseudo-instruction
+/// was expanded, and the fields of its word alongside them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodingRecord {
+    pub address: usize,
+    pub instruction: String,
+    #[serde(flatten)]
+    pub fields: InstructionFields
+}
+
+
+/// How immediates are shown in the dump of assembled words. `Source` leaves them as they were written, while `Hex` and `Dec` rewrite every numeric immediate in
+/// hexadecimal or decimal. The encoding is the same whichever is chosen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImmRadix {
+    #[default]
+    Source,
+    Hex,
+    Dec
+}
+
+
+/// The order of the two bytes of each word in a binary image, which is the high byte first by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little
+}
+
+impl Endian {
+    /// Splits a word into its two bytes in this order.
+    pub fn to_bytes(&self, word:u16) -> [u8; 2] {
+        match self {
+            Endian::Big => word.to_be_bytes(),
+            Endian::Little => word.to_le_bytes()
+        }
+    }
+
+
+    /// Joins two bytes in this order into a word.
+    pub fn from_bytes(&self, bytes:[u8; 2]) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes)
+        }
+    }
+}
+
+
+/// Collapses every run of whitespace outside string and character literals into a single space and trims the result.
+pub fn collapse_whitespace(text:&str) -> String {
+    let mut result = String::new();
+    let mut quote = None;
+    for c in text.trim().chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, _) if c.is_whitespace() => {
+                if !result.ends_with(' ') {
+                    result.push(' ');
+                }
+
+                continue;
+            },
+            _ => ()
+        };
+
+        result.push(c);
+    }
+
+    result
+}
+
+
+/// Splits a source line into its label (without the colon), mnemonic, normalised operands, and comment (without the `#`), any of which may be empty. Operands are
+/// separated by a comma and a single space, and the elements of a `.space` or `.pattern` array are laid out the same way inside their brackets.
+pub fn split_source_line(line:&str) -> (String, String, String, String) {
+    let (code, comment) = match find_comment_start(line) {
+        Some(index) => (&line[..index], line[index + 1..].trim().to_owned()),
+        None => (line, String::new())
+    };
+
+    let code = code.trim();
+    let (label, rest) = match LABEL_REGEX.find(code) {
+        Some(val) => (val.as_str().trim_end_matches(':').to_owned(), code[val.end()..].trim()),
+        None => (String::new(), code)
+    };
+
+    let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+        Some(index) => (rest[..index].to_owned(), rest[index..].trim()),
+        None => (rest.to_owned(), "")
+    };
+
+    let operands = match (operands.find('['), operands.rfind(']')) {
+        (Some(open), Some(close)) if (mnemonic == ".space" || mnemonic == ".pattern") && open < close => {
+            let elems:Vec<String> = split_operands(&operands[open + 1..close]).iter().map(|elem| collapse_whitespace(elem)).filter(|elem| !elem.is_empty()).collect();
+            format!("{} [{}]{}", collapse_whitespace(&operands[..open]), elems.join(", "), collapse_whitespace(&operands[close + 1..]))
+        },
+
+        _ if mnemonic == ".text" => collapse_whitespace(operands),
+        _ => split_operands(operands).iter().map(|operand| collapse_whitespace(operand)).collect::<Vec<String>>().join(", ")
+    };
+
+    (label, mnemonic, operands, comment)
+}
+
+
+/// Rewrites the lines of a source file in a canonical layout without changing what they assemble to: labels are left-aligned in a column as wide as the longest label,
+/// mnemonics are aligned in the column after it, operands are separated by a comma and a single space, and trailing comments are aligned one column past the longest
+/// instruction with a single space after the `#`. Comment-only lines are left-aligned, and blank lines are kept but emptied of whitespace. Lines continued with a
+/// trailing `\` only have their trailing whitespace removed.
+///
+/// Formatting already formatted lines leaves them unchanged.
+pub fn format_source(lines:&[String]) -> Vec<String> {
+    // lines which are part of a statement continued over several lines are left as they are
+    let continued:Vec<bool> = lines.iter().enumerate().map(|(index, line)| is_continued(line) || (index > 0 && is_continued(&lines[index - 1]))).collect();
+    let parts:Vec<(String, String, String, String)> = lines.iter().zip(continued.iter()).map(|(line, continued)| {
+        if *continued { Default::default() } else { split_source_line(line) }
+    }).collect();
+    let label_width = parts.iter().map(|(label, ..)| if label.is_empty() { 0 } else { label.len() + 2 }).max().unwrap_or(0);
+    let mnemonic_width = parts.iter().map(|(_, mnemonic, ..)| mnemonic.len() + 1).max().unwrap_or(0);
+
+    let code:Vec<String> = parts.iter().map(|(label, mnemonic, operands, _)| {
+        if mnemonic.is_empty() && label.is_empty() {
+            return String::new();
+        }
+
+        let label = if label.is_empty() { String::new() } else { format!("{}:", label) };
+        format!("{:label_width$}{:mnemonic_width$}{}", label, mnemonic, operands).trim_end().to_owned()
+    }).collect();
+
+    let comment_column = code.iter().map(|line| line.len() + 1).max().unwrap_or(0);
+    code.iter().zip(parts.iter()).enumerate().map(|(index, (code, (.., comment)))| {
+        if continued[index] {
+            return lines[index].trim_end().to_owned();
+        }
+
+        match (code.is_empty(), comment.is_empty()) {
+            (_, true) => code.to_owned(),
+            (true, false) => format!("# {}", comment),
+            (false, false) => format!("{:comment_column$}# {}", code, comment)
+        }
+    }).collect()
+}
+
+
+/// Reads the given source file and rewrites it in place in the canonical layout produced by `format_source`, then returns the number of lines which changed.
+///
+/// Returns an `AssemblyError` if the file cannot be read or written.
+#[cfg(feature = "cli")]
+pub fn format_source_file(filename:&str) -> Result<usize, Box<dyn Error>> {
+    let lines = read_source_lines(filename, false)?;
+    let formatted = format_source(&lines);
+    let source:String = formatted.iter().map(|line| format!("{}\n", line)).collect();
+    write_file_atomically(filename, source.as_bytes())?;
+
+    Ok(lines.iter().zip(formatted.iter()).filter(|(old, new)| old != new).count())
+}
+
+
+/// Reads the given source file and returns the number of lines which `format_source` would change, without rewriting it.
+///
+/// Returns an `AssemblyError` if the file cannot be read.
+#[cfg(feature = "cli")]
+pub fn check_source_file(filename:&str) -> Result<usize, Box<dyn Error>> {
+    let lines = read_source_lines(filename, false)?;
+    Ok(lines.iter().zip(format_source(&lines).iter()).filter(|(old, new)| old != new).count())
+}
+
+
+/// Gives the error for a failure writing to the given file.
+#[cfg(feature = "cli")]
+fn write_error(filename:&str, e:io::Error) -> Box<dyn Error> {
+    Box::new(AssemblyError(format!("Could not write to file {}: {}", filename, e)))
+}
+
+
+/// Runs `write` on a temporary file beside the specified file, then syncs it and renames it over the destination, so the destination either keeps its old contents
+/// or holds exactly what was written, with nothing left over from a longer old file. The temporary file is removed if anything fails.
+///
+/// Returns the error of `write`, or an `AssemblyError` naming the file if it cannot be created, synced, or renamed.
+#[cfg(feature = "cli")]
+fn replace_file<T>(filename:&str, write:impl FnOnce(&mut File) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    let temp_filename = format!("{}.tmp", filename);
+    let result = OpenOptions::new().write(true).create(true).truncate(true).open(&temp_filename).map_err(|e| write_error(filename, e))
+        .and_then(|mut temp_file| {
+            let val = write(&mut temp_file)?;
+            temp_file.sync_all().map_err(|e| write_error(filename, e))?;
+            Ok(val)
+        })
+        .and_then(|val| fs::rename(&temp_filename, filename).map(|_| val).map_err(|e| write_error(filename, e)));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_filename);
+    }
+
+    result
+}
+
+
+/// Writes the given bytes to the specified file by first writing them to a temporary file beside it and then renaming that over the destination, so the destination
+/// either keeps its old contents or holds exactly the new bytes, with nothing left over from a longer old file.
+///
+/// Returns an `AssemblyError` naming the file if it cannot be written, in which case any existing file is left untouched.
+#[cfg(feature = "cli")]
+pub fn write_file_atomically(filename:&str, bytes:&[u8]) -> Result<(), Box<dyn Error>> {
+    replace_file(filename, |temp_file| temp_file.write_all(bytes).map_err(|e| write_error(filename, e)))
+}
+
+
+/// The number of words `write_words` converts to bytes at a time.
+const WORDS_PER_CHUNK:usize = 4096;
+
+
+/// Writes each word to any writer, such as a file, a socket, or a `Cursor` in memory, as 2 bytes in the given order, and then returns the number of bytes written.
+/// The words are converted a chunk at a time, so no copy of the whole image is made however large it is.
+///
+/// Returns the error of the writer if it fails.
+pub fn write_words(mut sink:impl Write, words:&[u16], endian:Endian) -> io::Result<usize> {
+    let mut bytes = [0_u8; 2 * WORDS_PER_CHUNK];
+    for chunk in words.chunks(WORDS_PER_CHUNK) {
+        for (pair, word) in bytes.chunks_exact_mut(2).zip(chunk) {
+            pair.copy_from_slice(&endian.to_bytes(*word));
+        }
+
+        sink.write_all(&bytes[..2 * chunk.len()])?;
+    }
+
+    Ok(2 * words.len())
+}
+
+
+/// Writes each word to any writer through a `BufWriter` with `write_words`, flushing it before returning the number of bytes written so that a failure writing the
+/// last of them is reported rather than lost when the buffer is dropped. `filename` is the file the writer leads to, which is only used to say where a problem is.
+///
+/// Returns an `AssemblyError` naming `filename` if the writer fails, in which case some of the words may already have been written.
+#[cfg(feature = "cli")]
+pub fn write_image(sink:impl Write, words:&[u16], endian:Endian, filename:&str) -> Result<usize, Box<dyn Error>> {
+    let mut writer = BufWriter::new(sink);
+    let num_bytes = write_words(&mut writer, words, endian).map_err(|e| write_error(filename, e))?;
+    writer.flush().map_err(|e| write_error(filename, e))?;
+    Ok(num_bytes)
+}
+
+
+/// Takes a vector containing the processed and assembled instructions and writes them to the specified file with `write_image`, replacing any existing file, and
+/// then returns the number of bytes written once they have been flushed and synced to the disk.
+///
+/// Returns an `AssemblyError` naming the file if it cannot be written, in which case any existing file is left untouched.
+#[cfg(feature = "cli")]
+pub fn write_assembled_bytes(filename:&str, instrs:Vec<u16>, endian:Endian) -> Result<usize, Box<dyn Error>> {
+    replace_file(filename, |temp_file| write_image(temp_file, &instrs, endian, filename))
+}
+
+
+/// Writes the words of a `WordStream` to the specified file as each is encoded, through a `BufWriter` which writes them out whenever its buffer fills, so the
+/// words of the program are never all held at once. The file is replaced in the same way as by `write_assembled_bytes`, which gives exactly the same bytes for
+/// the code section of the assembled program, and the number of bytes written is returned once they have been flushed and synced to the disk.
+///
+/// Returns an `AssemblyError` if the program cannot be assembled or the file cannot be written, in which case any existing file is left untouched.
+#[cfg(feature = "cli")]
+pub fn write_stream(filename:&str, stream:WordStream, endian:Endian) -> Result<usize, Box<dyn Error>> {
+    replace_file(filename, |temp_file| {
+        let mut writer = BufWriter::new(temp_file);
+        let num_bytes = stream.write_to(&mut writer, endian)?;
+        writer.flush().map_err(|e| write_error(filename, e))?;
+        Ok(num_bytes)
+    })
+}
+
+
+/// Writes the relocation table to the specified file as text, with one line per relocated word giving its index in the code image and how it holds the address,
+/// such as `0x0004 lo6`, and then returns the number of relocations written.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_relocations(filename:&str, relocations:&[(usize, RelocationKind)]) -> Result<usize, Box<dyn Error>> {
+    let mut table = String::new();
+    for (index, kind) in relocations {
+        let kind = match kind {
+            RelocationKind::Full16 => "full16",
+            RelocationKind::Lo6 => "lo6",
+            RelocationKind::Hi10 => "hi10"
+        };
+
+        table.push_str(&format!("0x{:04X} {}\n", index, kind));
+    }
+
+    write_file_atomically(filename, table.as_bytes())?;
+    Ok(relocations.len())
+}
+
+
+/// Writes a table of golden encodings for checking a simulator's decoder against, with one line per instruction in the code section giving the instruction and its
+/// encoding, such as `ADDI $r1, $zero, 5 -> 0x2805`, and then returns the number of vectors written. Words placed with `.fill` are data rather than instructions, so
+/// they are left out.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_test_vectors(filename:&str, lines:&[String], words:&[u16]) -> Result<usize, Box<dyn Error>> {
+    let mut table = String::new();
+    let mut num_vectors = 0;
+    for (line, word) in expand_runs(lines).zip(words.iter()) {
+        let instr = LABEL_REGEX.replace(&line, "");
+        let instr = instr.trim();
+        if instr.starts_with(".fill") {
+            continue;
+        }
+
+        table.push_str(&format!("{} -> 0x{:04X}\n", instr, word));
+        num_vectors += 1;
+    }
+
+    write_file_atomically(filename, table.as_bytes())?;
+    Ok(num_vectors)
+}
+
+
+/// Writes each instruction of the code section as an `EncodingRecord` to a JSON array, as a precise reference for testing a simulator's decoder against, and then
+/// returns the number of instructions written. A pseudo-instruction is given as each of the instructions it expands to, so a `MOVI` gives an `ADDI` and a `LUI`,
+/// and words placed with `.fill` are data rather than instructions, so they are left out as in `write_test_vectors`.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_encoding_json(filename:&str, lines:&[String], words:&[u16]) -> Result<usize, Box<dyn Error>> {
+    let mut records = Vec::new();
+    for (address, (line, word)) in expand_runs(lines).zip(words.iter()).enumerate() {
+        let instr = LABEL_REGEX.replace(&line, "");
+        let instr = instr.trim();
+        let fields = match decode(*word).fields() {
+            Some(val) if !instr.starts_with(".fill") => val,
+            _ => continue
+        };
+
+        records.push(EncodingRecord { address, instruction: instr.to_owned(), fields });
+    }
+
+    let mut json = serde_json::to_string_pretty(&records)?;
+    json.push('\n');
+
+    write_file_atomically(filename, json.as_bytes())?;
+    Ok(records.len())
+}
+
+
+/// Writes a plain listing of the program for printing, with one line per word giving its address, its encoding, and the instruction it came from in the
+/// canonical form given by `Instruction`'s `Display`, such as `0x0000  2807  ADDI     $r0, $zero, 7        # 0x07`, so the listing does not depend on how the
+/// source was laid out. Labels are given in a column of their own before the instructions, and addresses are given as byte offsets if `byte_addresses` is set, as
+/// in the dump printed while assembling. The lines are those of the code section followed by a `.data` directive and the lines of the data section, if it has
+/// any, with `words` holding the words of both in the same order. The directive is kept in the listing to mark where the data section starts, at `data_base`.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_text_listing(filename:&str, lines:&[String], words:&[u16], data_base:usize, byte_addresses:bool) -> Result<usize, Box<dyn Error>> {
+    let lines:Vec<Cow<str>> = expand_runs(lines).collect();
+    let labels:Vec<&str> = lines.iter().map(|line| LABEL_REGEX.find(line).map_or("", |val| val.as_str())).collect();
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+
+    let mut listing = String::new();
+    let mut remaining = words.iter();
+    let mut address = 0;
+    for (line, label) in lines.iter().zip(labels.iter()) {
+        if let Some(section) = get_section_switch(line) {
+            address = if section == Section::Data { data_base } else { 0 };
+            listing.push_str(line.trim());
+            listing.push('\n');
+            continue;
+        }
+
+        let word = match remaining.next() {
+            Some(val) => *val,
+            None => break
+        };
+
+        let instr = parse_instruction(line, &DEFAULT_ISA_SPEC).unwrap_or(decode(word));
+        let label = match width {
+            0 => String::new(),
+            _ => format!("{:width$}  ", label, width = width)
+        };
+
+        listing.push_str(format!("0x{:04X}  {:04X}  {}{}", get_display_address(address, byte_addresses), word, label, instr).trim_end());
+        listing.push('\n');
+        address += 1;
+    }
+
+    write_file_atomically(filename, listing.as_bytes())?;
+    Ok(words.len())
+}
+
+
+/// Writes the program with its labels resolved to numbers and their definitions removed, one line per word along with the section directives, and then returns the
+/// number of lines written. This is a self-contained source which assembles to the same words as the original.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_resolved_source(filename:&str, lines:&[String]) -> Result<usize, Box<dyn Error>> {
+    let lines:Vec<String> = expand_runs(lines).map(Cow::into_owned).collect();
+    let resolved = strip_label_definitions(&lines);
+    let mut source = resolved.join("\n");
+    source.push('\n');
+
+    write_file_atomically(filename, source.as_bytes())?;
+    Ok(resolved.len())
+}
+
+
+/// Writes the lines of the program as they were encoded, once pseudo-instructions have been expanded and labels substituted but with the label definitions kept,
+/// one line per word along with the section directives, and then returns the number of lines written. This shows exactly what each word was assembled from.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_expanded_lines(filename:&str, lines:&[String]) -> Result<usize, Box<dyn Error>> {
+    let lines:Vec<Cow<str>> = expand_runs(lines).collect();
+    let mut expanded = lines.join("\n");
+    expanded.push('\n');
+
+    write_file_atomically(filename, expanded.as_bytes())?;
+    Ok(lines.len())
+}
+
+
+/// Formats the symbol map, with one line per label giving its name, its address, and whether it points at code or data or is a fixed address given by `.at`, such
+/// as `table  0x0010  DATA`. The labels are in the order the table is iterated in, so the same labels always give the same map, and those of the data section
+/// follow a `.data` line marking where the code section's labels end.
+pub fn format_symbol_map(labels:&SymbolTable) -> String {
+    let name_width = labels.iter().map(|symbol| symbol.name.len()).max().unwrap_or(0);
+    let mut map = String::new();
+    let mut section = Section::Code;
+    for symbol in labels.iter() {
+        if symbol.section != section {
+            section = symbol.section;
+            map.push_str(".data\n");
+        }
+
+        map.push_str(&format!("{:name_width$}  0x{:04X}  {}\n", symbol.name, symbol.address, label_kind_name(symbol.kind)));
+    }
+
+    map
+}
+
+
+/// Gets the name a kind of label is given in the symbol map: `CODE`, `DATA`, or `ABS` for a fixed address given by `.at`.
+pub fn label_kind_name(kind:LabelKind) -> &'static str {
+    match kind {
+        LabelKind::Code => "CODE",
+        LabelKind::Data => "DATA",
+        LabelKind::Absolute => "ABS"
+    }
+}
+
+
+/// Writes the symbol map given by `format_symbol_map` to the specified file, and then returns the number of labels written.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_symbol_map(filename:&str, labels:&SymbolTable) -> Result<usize, Box<dyn Error>> {
+    write_file_atomically(filename, format_symbol_map(labels).as_bytes())?;
+    Ok(labels.len())
+}
+
+
+/// Writes the symbol table to the specified file as a JSON array with one object per label, giving every field of its `Symbol` in the same order as the symbol map,
+/// and then returns the number of labels written. The table can be read back by deserialising a `SymbolTable` from the file.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_symbol_json(filename:&str, labels:&SymbolTable) -> Result<usize, Box<dyn Error>> {
+    let mut json = serde_json::to_string_pretty(labels)?;
+    json.push('\n');
+
+    write_file_atomically(filename, json.as_bytes())?;
+    Ok(labels.len())
+}
+
+
+
+/// Writes the memory map given by `layout::format_memory_map` to the specified file, and then returns the number of regions written.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_memory_map(filename:&str, regions:&[MemoryRegion]) -> Result<usize, Box<dyn Error>> {
+    write_file_atomically(filename, format_memory_map(regions).as_bytes())?;
+    Ok(regions.len())
+}
+
+
+/// Writes the memory map to the specified file as a JSON array with one object per region, giving its section, kind, first and last addresses, size, and the
+/// labels at its start, and then returns the number of regions written.
+///
+/// Returns an `AssemblyError` if the file cannot be written.
+#[cfg(feature = "cli")]
+pub fn write_memory_map_json(filename:&str, regions:&[MemoryRegion]) -> Result<usize, Box<dyn Error>> {
+    let mut json = serde_json::to_string_pretty(regions)?;
+    json.push('\n');
+
+    write_file_atomically(filename, json.as_bytes())?;
+    Ok(regions.len())
+}
+
+/// Rewrites every numeric immediate in a line in the given radix for display, leaving registers, character literals, and strings as they are.
+pub fn render_immediates(line:&str, radix:ImmRadix) -> String {
+    if radix == ImmRadix::Source || line.contains('"') {
+        return line.to_owned();
+    }
+
+    DUMP_IMM_REGEX.replace_all(line, |caps:&regex::Captures| {
+        let imm = match convert_to_i64(&caps[2]) {
+            Ok(val) => val,
+            Err(_) => return caps[0].to_owned()
+        };
+
+        let rendered = match (radix, imm < 0) {
+            (ImmRadix::Hex, false) => format!("0x{:X}", imm),
+            (ImmRadix::Hex, true) => format!("-0x{:X}", -imm),
+            _ => imm.to_string()
+        };
+
+        format!("{}{}", &caps[1], rendered)
+    }).into_owned()
+}
+
+
+/// Gets the address of the word at the given index for display, which is the index itself, or the offset of its first byte if `byte_addresses` is set for tools
+/// which address memory in bytes.
+pub fn get_display_address(index:usize, byte_addresses:bool) -> usize {
+    if byte_addresses { index * 2 } else { index }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    #[cfg(feature = "cli")]
+    use std::env;
+    use crate::parser::{ get_line_vector, validate_assembly_lines };
+    use crate::expansion::substitute_pseudoinstrs;
+    use crate::labels::{ generate_label_table, strip_label_definitions, substitute_labels };
+    use crate::encoder::assemble_section;
+    #[cfg(feature = "cli")]
+    use crate::{ Assembler, assemble_str };
+    #[cfg(feature = "cli")]
+    use crate::labels::{ SourceLoc, Symbol };
+    #[cfg(feature = "cli")]
+    use crate::encoder::Instruction;
+    #[cfg(feature = "cli")]
+    use crate::parser::LineSource;
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_text_listing() {
+        let lines:Vec<String> = vec!["ADDI  $r0,$zero,7".to_owned(), ".fill 0x1234".to_owned()];
+        let words:Vec<u16> = vec![0x2807, 0x1234];
+
+        let filename = env::temp_dir().join("iridium_test_listing.txt").to_str().unwrap().to_owned();
+        assert_eq!(write_text_listing(&filename, &lines, &words, 0, false).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "0x0000  2807  ADDI     $r0, $zero, 7     # 0x07\n0x0001  1234  .fill    4660              # 0x1234\n");
+
+        let lines:Vec<String> = vec!["start: ADDI $r0, $zero, 7".to_owned(), "NAND $r0, $r0, $r0".to_owned()];
+        write_text_listing(&filename, &lines, &[0x2807, 0x4490], 0, true).unwrap();
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "0x0000  2807  start:  ADDI     $r0, $zero, 7     # 0x07\n0x0002  4490          NAND     $r0, $r0, $r0\n");
+
+        // the data section is marked by its directive and counts from its base
+        let lines:Vec<String> = vec!["ADDI $r0, $zero, 7".to_owned(), ".data".to_owned(), "table: .fill 0x1234".to_owned()];
+        assert_eq!(write_text_listing(&filename, &lines, &[0x2807, 0x1234], 1, false).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "0x0000  2807          ADDI     $r0, $zero, 7     # 0x07\n.data\n\
+            0x0001  1234  table:  .fill    4660              # 0x1234\n");
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_symbol_map() {
+        let symbol = |name:&str, address, section, kind| Symbol { name: name.to_owned(), address, section, kind, defined_at: SourceLoc { line: 1 }, exported: false };
+        let labels:SymbolTable = vec![
+            symbol("table", 0x10, Section::Code, LabelKind::Data),
+            symbol("start", 0, Section::Code, LabelKind::Code),
+            symbol("message", 0, Section::Data, LabelKind::Data)
+        ].into();
+
+        let filename = env::temp_dir().join("iridium_test_symbols.txt").to_str().unwrap().to_owned();
+        assert_eq!(write_symbol_map(&filename, &labels).unwrap(), 3);
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "start    0x0000  CODE\ntable    0x0010  DATA\n.data\nmessage  0x0000  DATA\n");
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    fn test_symbol_map_is_stable() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_sections.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+
+        // each label table is a new HashMap with its own random order, so this would differ between tables if the map followed the order of the table
+        let map = format_symbol_map(&generate_label_table(&lines).unwrap());
+        for _ in 0..16 {
+            assert_eq!(format_symbol_map(&generate_label_table(&lines).unwrap()), map);
+        }
+
+        assert_eq!(map, "start    0x0000  CODE\nloop     0x0003  CODE\n.data\ntable    0x0000  DATA\nmessage  0x0002  DATA\n");
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_encoding_json() {
+        let lines:Vec<String> = ["start: ADDI $r0, $zero, 7", "LUI $r0, 1", ".fill 0x2407", ".syscall 6"].iter().map(|line| line.to_string()).collect();
+        let filename = env::temp_dir().join("iridium_test_encoding.json").to_str().unwrap().to_owned();
+        assert_eq!(write_encoding_json(&filename, &lines, &[0x2407, 0x6401, 0x2407, 0xF406]).unwrap(), 3);
+
+        let json = fs::read_to_string(&filename).unwrap();
+        assert!(json.contains(r#""mnemonic": "ADDI""#), "{}", json);
+        let records:Vec<EncodingRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records[1], EncodingRecord { address: 1, instruction: "LUI $r0, 1".to_owned(), fields: Instruction::Lui { rd: 1, imm: 1 }.fields().unwrap() });
+        assert_eq!((records[2].address, records[2].fields.imm), (3, Some(6)));
+
+        // a pseudo-instruction is given as the instructions it expands to
+        let program = crate::assemble_str("start: MOVI $r1, 0x1234\n.syscall 6\n").unwrap();
+        write_encoding_json(&filename, &program.code_lines, &program.code).unwrap();
+        let records:Vec<EncodingRecord> = serde_json::from_str(&fs::read_to_string(&filename).unwrap()).unwrap();
+        let mnemonics:Vec<&str> = records.iter().map(|record| record.fields.mnemonic.as_str()).collect();
+        assert_eq!(mnemonics, vec!["ADDI", "LUI", ".syscall"]);
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_test_vectors() {
+        let lines:Vec<String> = vec!["start: ADDI $r1, $zero, 5".to_owned(), "NAND $r2, $r1, $r1".to_owned(), ".fill 0x1234".to_owned()];
+        let words:Vec<u16> = lines.iter().map(|line| crate::encoder::convert_instr_to_binary(line, &DEFAULT_ISA_SPEC).unwrap()).collect();
+
+        let filename = env::temp_dir().join("iridium_test_vectors.txt").to_str().unwrap().to_owned();
+        assert_eq!(write_test_vectors(&filename, &lines, &words).unwrap(), 2);
+
+        let table = fs::read_to_string(&filename).unwrap();
+        assert_eq!(table, format!("ADDI $r1, $zero, 5 -> 0x2805\nNAND $r2, $r1, $r1 -> 0x{:04X}\n", words[1]));
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    fn test_render_immediates() {
+        assert_eq!(render_immediates("LUI $r1, 0b1010", ImmRadix::Source), "LUI $r1, 0b1010");
+        assert_eq!(render_immediates("LUI $r1, 0b1010", ImmRadix::Dec), "LUI $r1, 10");
+        assert_eq!(render_immediates("ADDI $r1, $r2, -10", ImmRadix::Hex), "ADDI $r1, $r2, -0xA");
+        assert_eq!(render_immediates(".syscall 3", ImmRadix::Hex), ".syscall 0x3");
+        assert_eq!(render_immediates(".space 2 [0x10,'a']", ImmRadix::Dec), ".space 2 [16,'a']");
+    }
+
+
+    #[test]
+    fn test_resolve_labels() {
+        let mut lines:Vec<String> = get_line_vector("test_files/test_label_operands.asm", false).unwrap();
+        lines.retain(|line| !line.is_empty());
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        lines = substitute_pseudoinstrs(&lines);
+        let label_table = generate_label_table(&lines).unwrap();
+        lines = substitute_labels(&lines, &label_table).unwrap();
+
+        let mut resolved = strip_label_definitions(&lines);
+        assert!(resolved.iter().all(|line| !LABEL_REGEX.is_match(line) && !line.contains('@')));
+
+        validate_assembly_lines(&resolved, &DEFAULT_ISA_SPEC).unwrap();
+        resolved = substitute_pseudoinstrs(&resolved);
+        let label_table = generate_label_table(&resolved).unwrap();
+        resolved = substitute_labels(&resolved, &label_table).unwrap();
+        assert_eq!(assemble_section(&resolved, &DEFAULT_ISA_SPEC).unwrap(), assemble_section(&lines, &DEFAULT_ISA_SPEC).unwrap());
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_expanded_lines() {
+        let lines:Vec<String> = vec!["start: ADDI $r1, $zero, 0".to_owned(), "LUI $r1, 0".to_owned(), ".data".to_owned(), "table: .fill 0".to_owned()];
+        let filename = env::temp_dir().join("iridium_test_expanded.asm").to_str().unwrap().to_owned();
+        assert_eq!(write_expanded_lines(&filename, &lines).unwrap(), 4);
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "start: ADDI $r1, $zero, 0\nLUI $r1, 0\n.data\ntable: .fill 0\n");
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    fn test_format_source() {
+        let lines:Vec<String> = std::fs::read_to_string("test_files/test_format_source.asm").unwrap().lines().map(|line| line.to_owned()).collect();
+        let formatted = format_source(&lines);
+        assert_eq!(formatted[0], "# Counts down from 5");
+        assert_eq!(formatted[1], "start: ADDI   $r1, $zero, 5    # initial count");
+        assert_eq!(formatted[2], "loop:  ADDI   $r1, $r1, -1");
+        assert_eq!(formatted[3], "       BEQ    $r1, $zero, @end # done");
+        assert_eq!(formatted[4], "       JALR   $zero, $r2");
+        assert_eq!(formatted[5], "");
+        assert_eq!(formatted[6], "end:   .text  \"a,  b # c\"");
+        assert_eq!(formatted[7], "arr:   .space 2 [1, 2]");
+        assert_eq!(formatted.len(), 8);
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_check_source_file() {
+        assert_eq!(check_source_file("test_files/test_format_source.asm").unwrap(), 6);
+        assert!(check_source_file("test_files/does_not_exist.asm").is_err());
+
+        let filename = env::temp_dir().join("iridium_test_check_format.asm").to_str().unwrap().to_owned();
+        fs::copy("test_files/test_format_source.asm", &filename).unwrap();
+        assert_eq!(format_source_file(&filename).unwrap(), 6);
+        assert_eq!(check_source_file(&filename).unwrap(), 0);
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    fn test_format_source_idempotent() {
+        let lines:Vec<String> = std::fs::read_to_string("test_files/test_format_source.asm").unwrap().lines().map(|line| line.to_owned()).collect();
+        let formatted = format_source(&lines);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_assembled_bytes_truncates() {
+        let filename = env::temp_dir().join("iridium_test_truncate.bin").to_str().unwrap().to_owned();
+        assert_eq!(write_assembled_bytes(&filename, vec![0x1234; 16], Endian::Big).unwrap(), 32);
+        assert_eq!(write_assembled_bytes(&filename, vec![0xABCD, 0x0001], Endian::Big).unwrap(), 4);
+        assert_eq!(fs::read(&filename).unwrap(), vec![0xAB, 0xCD, 0x00, 0x01]);
+
+        write_assembled_bytes(&filename, vec![0xABCD, 0x0001], Endian::Little).unwrap();
+        assert_eq!(fs::read(&filename).unwrap(), vec![0xCD, 0xAB, 0x01, 0x00]);
+        assert!(!std::path::Path::new(&format!("{}.tmp", filename)).exists());
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_short_program_over_longer_file() {
+        let filename = env::temp_dir().join("iridium_test_short_program.bin").to_str().unwrap().to_owned();
+        fs::write(&filename, vec![0xFF; 64]).unwrap();
+        let program = assemble_str("ADDI $r0, $zero, 7\n.syscall 6\n").unwrap();
+        assert_eq!(write_assembled_bytes(&filename, program.code.clone(), Endian::Big).unwrap(), 4);
+
+        // the image is exactly the program, with none of the old file's bytes left after it
+        let image = fs::read(&filename).unwrap();
+        assert_eq!((image.len(), &image[..2]), (4, &[0x24, 0x07][..]));
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_assembled_bytes_failure_keeps_old_file() {
+        let filename = env::temp_dir().join("iridium_test_failure.bin").to_str().unwrap().to_owned();
+        let temp_filename = format!("{}.tmp", filename);
+        let _ = fs::remove_dir(&temp_filename);
+        write_assembled_bytes(&filename, vec![0x1234, 0x5678], Endian::Big).unwrap();
+
+        // a directory in the way of the temporary file makes the write fail before the destination is touched
+        fs::create_dir(&temp_filename).unwrap();
+        let result = write_assembled_bytes(&filename, vec![0xFFFF], Endian::Big);
+        fs::remove_dir(&temp_filename).unwrap();
+
+        assert!(result.unwrap_err().to_string().contains(&filename));
+        assert_eq!(fs::read(&filename).unwrap(), vec![0x12, 0x34, 0x56, 0x78]);
+        fs::remove_file(&filename).unwrap();
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_stream() {
+        let streamed = env::temp_dir().join("iridium_test_stream.bin").to_str().unwrap().to_owned();
+        let batched = env::temp_dir().join("iridium_test_batched.bin").to_str().unwrap().to_owned();
+        let source = "start: MOVI $r1, @end\n.space 3 [1, 2]\nend: JAL $zero, $r1\n";
+        assert_eq!(write_stream(&streamed, Assembler::new().stream(LineSource::Str(source)), Endian::Little).unwrap(), 12);
+        write_assembled_bytes(&batched, assemble_str(source).unwrap().code, Endian::Little).unwrap();
+        assert_eq!(fs::read(&streamed).unwrap(), fs::read(&batched).unwrap());
+
+        // a program which cannot be streamed leaves the old file as it was
+        assert!(write_stream(&streamed, Assembler::new().stream(LineSource::Str(".data\n.fill 1\n")), Endian::Big).is_err());
+        assert_eq!(fs::read(&streamed).unwrap(), fs::read(&batched).unwrap());
+        fs::remove_file(&streamed).unwrap();
+        fs::remove_file(&batched).unwrap();
+    }
+
+
+    /// Accepts the first `limit` bytes written to it and then fails, as a full disk would.
+    #[cfg(feature = "cli")]
+    struct FailingWriter {
+        written: usize,
+        limit: usize
+    }
+
+    #[cfg(feature = "cli")]
+    impl Write for FailingWriter {
+        fn write(&mut self, buf:&[u8]) -> io::Result<usize> {
+            if self.written >= self.limit {
+                return Err(io::Error::other("no space left on device"));
+            }
+
+            let len = buf.len().min(self.limit - self.written);
+            self.written += len;
+            Ok(len)
+        }
+
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+
+    #[test]
+    fn test_write_words_chunks() {
+        let words:Vec<u16> = (0..2 * WORDS_PER_CHUNK as u32 + 3).map(|val| val as u16).collect();
+        for endian in [Endian::Big, Endian::Little] {
+            let mut image:Vec<u8> = Vec::new();
+            assert_eq!(write_words(&mut image, &words, endian).unwrap(), 2 * words.len());
+            assert_eq!(image, words.iter().flat_map(|word| endian.to_bytes(*word)).collect::<Vec<u8>>());
+        }
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_write_image_failing_writer() {
+        // 4 MB, which is far more than the buffer holds at once
+        let words:Vec<u16> = (0..2_000_000_u32).map(|val| val as u16).collect();
+        let mut sink = FailingWriter { written: 0, limit: 3 << 20 };
+        let err = write_image(&mut sink, &words, Endian::Big, "image.bin").unwrap_err().to_string();
+        assert!(err.contains("Could not write to file image.bin: no space left on device"));
+        assert_eq!(sink.written, 3 << 20);
+
+        // the last byte is still in the buffer when the words run out, so only flushing it finds the failure
+        let mut sink = FailingWriter { written: 0, limit: 4_000_000 - 1 };
+        assert!(write_image(&mut sink, &words, Endian::Big, "image.bin").unwrap_err().to_string().contains("image.bin"));
+
+        let mut sink = FailingWriter { written: 0, limit: usize::MAX };
+        assert_eq!(write_image(&mut sink, &words, Endian::Little, "image.bin").unwrap(), 4_000_000);
+        assert_eq!(sink.written, 4_000_000);
+    }
+}
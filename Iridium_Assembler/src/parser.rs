@@ -0,0 +1,1368 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use lazy_static::lazy_static;
+use regex::Regex;
+use ascii_converter::string_to_decimals;
+use crate::{ AssemblyError, convert_to_i64, evaluate_expression, into_assembly_error, parse_immediate };
+use crate::diagnostics::{ Diagnostic, DiagnosticSink, INVALID_UTF8, LogSink };
+use crate::encoder::get_imm_operand;
+use crate::isa::IsaSpec;
+use crate::labels::{ Section, get_section_switch };
+use crate::lexer::{ LineKind, Token, parse_line };
+
+
+/// The symbols provided by the assembler, which cannot be used as label names.
+pub(crate) const PREDEFINED_SYMBOLS:[&str; 4] = ["__LINE__", "__FILE__", "__ADDR__", "__END__"];
+
+/// The mnemonics and register names, which cannot be used as label or constant names in any case. Directives always start with a `.`, which a name cannot, so names
+/// such as `text` are still allowed.
+pub(crate) const RESERVED_WORDS:[&str; 20] = ["ADD", "ADDI", "NAND", "LUI", "SW", "LW", "BEQ", "JAL", "NOP", "LLI", "MOVI", "MASK", "ZERO", "R0", "R1", "R2", "R3", "R4", "R5", "R6"];
+
+
+/// Checks whether a name is one of the `RESERVED_WORDS` or a mnemonic or register name of the given instruction set, ignoring case.
+pub fn is_reserved_word(name:&str, isa:&IsaSpec) -> bool {
+    let upper = name.to_uppercase();
+    RESERVED_WORDS.contains(&upper.as_str()) || isa.instructions.keys().any(|mnemonic| mnemonic.to_uppercase() == upper)
+        || isa.registers.keys().any(|reg| reg[1..].to_uppercase() == upper)
+}
+
+
+/// A register operand.
+#[cfg(test)]
+pub(crate) const REG_FRAGMENT:&str = r"\$(zero|r[0-6])";
+
+/// An expression operand containing at least one label, such as `@table+2`.
+pub(crate) const LABEL_EXPR_FRAGMENT:&str = r"[-+*/%&|^<>~()0-9a-zA-Z_@]*@[a-zA-Z_]+[-+*/%&|^<>~()0-9a-zA-Z_@]*";
+
+
+/// Builds the regex matching a whole line holding the given mnemonic and operands, so every instruction and directive accepts blank space in the same way: an optional
+/// label, at least one blank after the mnemonic if it has operands, any number of blanks (including none) around the commas between operands, and an optional
+/// trailing comment.
+pub fn instr_regex(mnemonic:&str, operands:&[&str]) -> Regex {
+    let operands = match operands.is_empty() {
+        true => String::new(),
+        false => format!("[[:blank:]]+{}", operands.iter().map(|operand| format!("({})", operand)).collect::<Vec<String>>().join("[[:blank:]]*,[[:blank:]]*"))
+    };
+
+    Regex::new(&format!(r"^([a-zA-Z_]+:)?[[:blank:]]*{}{}[[:blank:]]*(#[[:print:]]*)?$", mnemonic, operands)).unwrap()
+}
+
+
+lazy_static! {
+    pub(crate) static ref INT_REGEX:Regex = Regex::new(r"[[:blank:],](0b[01]+|0x[[:xdigit:]]+|((\+|-)?[0-9]+))").unwrap();
+    pub(crate) static ref CHAR_REGEX:Regex = Regex::new(r"'[[:ascii:]]'").unwrap();
+    pub(crate) static ref UINT_REGEX:Regex = Regex::new(r"0b[01]+|0x[[:xdigit:]]+|([0-9]+)").unwrap();
+    pub(crate) static ref LABEL_REGEX:Regex = Regex::new(r"^[a-zA-Z_]+:").unwrap();
+    pub(crate) static ref REGISTER_REGEX:Regex = Regex::new(r"\$[a-zA-Z0-9_]+").unwrap();
+    pub(crate) static ref TEXT_IMM_REGEX:Regex = Regex::new(r#""[[:ascii:]]+""#).unwrap();
+    pub(crate) static ref LABEL_ARG_REGEX:Regex = Regex::new(LABEL_EXPR_FRAGMENT).unwrap();
+    pub(crate) static ref SECTION_REGEX:Regex = Regex::new(r"^\.(code|data|section[[:blank:]]+(text|data))[[:blank:]]*$").unwrap();
+    pub(crate) static ref REGALIAS_REGEX:Regex = Regex::new(r"^\.regalias[[:blank:]]+([a-zA-Z_][a-zA-Z0-9_]*)[[:blank:]]*,[[:blank:]]*(\$[a-zA-Z0-9_]+)[[:blank:]]*$").unwrap();
+    pub(crate) static ref LINKAGE_REGEX:Regex = Regex::new(r"^\.(extern|export|global)[[:blank:]]+([a-zA-Z_]+)[[:blank:]]*$").unwrap();
+    pub(crate) static ref ORG_REGEX:Regex = Regex::new(r"^\.org[[:blank:]]+([^[:blank:]]+)[[:blank:]]*$").unwrap();
+    pub(crate) static ref EQU_REGEX:Regex = Regex::new(r"^\.equ[[:blank:]]+([a-zA-Z_][a-zA-Z0-9_]*)[[:blank:]]*,[[:blank:]]*(.+)$").unwrap();
+    pub(crate) static ref OPERANDS_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+:)?[[:blank:]]*(ADDI|SW|LW|LUI|LLI|MOVI|MASK|\.fill|\.space|\.pattern|\.syscall|\.org)[[:blank:]]+(.*)$").unwrap();
+    pub(crate) static ref LITERAL_REGEX:Regex = Regex::new(r"^(0*((\+|-)?[0-9]+|0b[01]+|0x[[:xdigit:]]+)|'[[:ascii:]]')$").unwrap();
+    pub(crate) static ref PREDEFINED_LABEL_REGEX:Regex = Regex::new(r"(^|[^@a-zA-Z0-9_])(__ADDR__|__END__)").unwrap();
+    pub(crate) static ref LABEL_NAME_REGEX:Regex = Regex::new(r"@([a-zA-Z_]+)").unwrap();
+    pub(crate) static ref CONSTANT_NAME_REGEX:Regex = Regex::new(r"(^|[^@a-zA-Z0-9_])([a-zA-Z_][a-zA-Z0-9_]*)").unwrap();
+    pub(crate) static ref ASSERT_SIZE_REGEX:Regex = Regex::new(r"^\.assert_size[[:blank:]]+(<=|<|==)[[:blank:]]*(.+)$").unwrap();
+    pub(crate) static ref AT_REGEX:Regex = Regex::new(r"^([a-zA-Z_]+):[[:blank:]]*\.at[[:blank:]]+(.+)$").unwrap();
+    pub(crate) static ref DUMP_IMM_REGEX:Regex = Regex::new(r"(^|[[:blank:],\[])((\+|-)?(0x[[:xdigit:]]+|0b[01]+|[0-9]+))\b").unwrap();
+}
+
+
+// The whole-line patterns lines were once checked against one by one, kept only so the tests can check `parse_line` accepts exactly the same lines.
+#[cfg(test)]
+lazy_static! {
+    pub(crate) static ref RI_REGEX:Regex = instr_regex("LUI", &[REG_FRAGMENT, &format!(r"0*([0-9]+|0b[01]+|0x[[:xdigit:]]+|{})", LABEL_EXPR_FRAGMENT)]);
+    pub(crate) static ref RRR_REGEX:Regex = instr_regex("(ADD|NAND|BEQ)", &[REG_FRAGMENT, REG_FRAGMENT, REG_FRAGMENT]);
+    pub(crate) static ref RRI_REGEX:Regex = instr_regex("(ADDI|SW|LW)", &[REG_FRAGMENT, REG_FRAGMENT, &format!(r"(0*((-|\+)?[0-9]+|0b[01]+|0x[[:xdigit:]]+)|{})", LABEL_EXPR_FRAGMENT)]);
+    pub(crate) static ref JAL_REGEX:Regex = instr_regex("JAL", &[REG_FRAGMENT, &format!("{}|{}", REG_FRAGMENT, LABEL_EXPR_FRAGMENT)]);
+    pub(crate) static ref NOP_REGEX:Regex = instr_regex("NOP", &[]);
+    pub(crate) static ref DATA_REGEX:Regex = instr_regex("(LLI|MOVI)", &[REG_FRAGMENT, &format!(r"0*([0-9]+|0b[01]+|0x[[:xdigit:]]+|{})", LABEL_EXPR_FRAGMENT)]);
+    pub(crate) static ref FILL_REGEX:Regex = instr_regex(r"\.fill", &[&format!(r"('[[:ascii:]]'|(0*((\+|-)?[0-9]+|0b[01]+|0x[[:xdigit:]]+))|{})", LABEL_EXPR_FRAGMENT)]);
+    pub(crate) static ref SCALL_REGEX:Regex = instr_regex(r"\.syscall", &["[0-7]"]);
+    pub(crate) static ref PSEUDO_TEXT_REGEX:Regex = instr_regex(r"\.text", &[r#""[[:ascii:]]+""#]);
+}
+
+
+/// Gets the mnemonic of a line, which is its first word after any label, such as `ADDI` or `.fill`, or an empty string if the line has no instruction.
+pub fn get_mnemonic(line:&str) -> &str {
+    let start = LABEL_REGEX.find(line).map_or(0, |val| val.end());
+    line[start..].split_whitespace().next().unwrap_or("")
+}
+
+
+/// Splits the operands of an instruction on the commas between them, ignoring any comma inside a character literal such as `','`.
+pub fn split_operands(operands:&str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut in_char = false;
+    for (index, c) in operands.char_indices() {
+        if c == '\'' {
+            in_char = !in_char;
+        } else if c == ',' && !in_char {
+            result.push(&operands[start..index]);
+            start = index + 1;
+        }
+    }
+
+    result.push(&operands[start..]);
+    result
+}
+
+
+/// Takes an instruction and returns a result containing either any immediate it finds if successful, or an error if it could not find one. If it finds a label immediate,
+/// then it will return `None`.
+///
+/// Panics if an immediate outside the valid range is found.
+pub fn get_imm_from_instr(instr:&str, bits:u32, signed:bool, accept_char:bool, accept_label:bool) -> Result<Option<i16>, Box<dyn Error>> {
+    if let Some(val) = LABEL_ARG_REGEX.find(instr) {
+        if accept_label {
+            return Ok(None);
+        }
+
+        return Err(Box::new(AssemblyError(format!("Found label {} in instruction {} but labels are not accepted", val.as_str(), instr))));
+    };
+
+    // prepended space needed to ensure that regex can tell the difference between a number such as the one6 in "$r6" and an actual immediate as Rust Regex does not support
+    // negative lookbehinds to check for "$r".
+    let instr_with_prepended_space = " ".to_owned() + instr;
+
+    let imm_str:&str = match INT_REGEX.find_iter(&instr_with_prepended_space).map(|num| num.as_str()).collect::<Vec<&str>>().first() {
+        Some(val) => val.trim_start_matches(|c:char| c.is_whitespace() || c == ','),
+        None => {
+            if !accept_char {
+                return Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", instr))))
+            }
+
+            match CHAR_REGEX.find_iter(instr).map(|num| num.as_str()).collect::<Vec<&str>>().first() {
+                Some(val) => return Ok(Some(*string_to_decimals(&val[1..2]).unwrap().first().unwrap() as i16)),
+                None      => return Err(Box::new(AssemblyError(format!("Could not find a valid immediate in instruction {}", instr))))
+            }
+        }
+    };
+
+    let imm:i64 = match parse_immediate(imm_str, bits, signed) {
+        Ok(val) => val,
+        Err(err) => return Err(Box::new(AssemblyError(format!("{} in instruction {}", err.0, instr))))
+    };
+
+    Ok(Some(imm as i16))
+}
+
+
+/// A value given in the brackets of a `.space` or `.pattern`, which is either a number or an expression referring to a label, whose value is only known once the label table has
+/// been generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpaceValue {
+    Value(i64),
+    Expr(String)
+}
+
+
+/// Splits a directive taking a size and an array, such as `.space 4 [1, 'a', 0x10, @table+2]`, into its size and the values given in its brackets. Each value is
+/// an expression, which is evaluated unless it refers to a label. Any amount of blank space is allowed around the size, the brackets, and the values, the last
+/// value may be followed by a comma, and the line may end with a comment.
+///
+/// Returns an `AssemblyError` if the line is not the given directive in that form, if there is an empty value such as in `[1,,2]`, if two values are separated only
+/// by blank space such as in `[1 2, 3]`, or if a value is not a valid expression or does not fit in 16 bits as either a signed or an unsigned number, so from
+/// -32768 to 65535.
+fn parse_array_directive(instr:&str, directive:&str) -> Result<(usize, Vec<SpaceValue>), Box<dyn Error>> {
+    let start = LABEL_REGEX.find(instr).map_or(0, |val| val.end());
+    let end = find_comment_start(instr).unwrap_or(instr.len());
+    let operands = match instr[start..end].trim_start().strip_prefix(directive) {
+        Some(val) if val.starts_with([' ', '\t']) => val,
+        _ => return Err(Box::new(AssemblyError(format!("Expected a {} followed by a blank in instruction {}", directive, instr))))
+    };
+
+    let (open, close) = match (operands.find('['), operands.rfind(']')) {
+        (Some(open), Some(close)) if open < close && operands[close + 1..].trim().is_empty() => (open, close),
+        _ => return Err(Box::new(AssemblyError(format!("Expected the values of a {} to be given in brackets in instruction {}", directive, instr))))
+    };
+
+    let size = match convert_to_i64(operands[..open].trim()) {
+        Ok(val) if val >= 0 => val as usize,
+        _ => return Err(Box::new(AssemblyError(format!("Could not get length of array in instruction {}", instr))))
+    };
+
+    let mut elems:Vec<&str> = split_operands(&operands[open + 1..close]).iter().map(|elem| elem.trim()).collect();
+    if elems.last() == Some(&"") {
+        elems.pop(); // either a trailing comma or no values at all
+    }
+
+    let mut values:Vec<SpaceValue> = Vec::new();
+    for elem in elems {
+        if elem.is_empty() {
+            return Err(Box::new(AssemblyError(format!("Found an empty value in the array in instruction {}", instr))));
+        }
+
+        // an expression with a label is only checked to be well formed, with every label taken to be 0, as its value is not known yet
+        let labels:HashMap<String, i64> = LABEL_NAME_REGEX.captures_iter(elem).map(|caps| (caps[1].to_owned(), 0)).collect();
+        let result = match LITERAL_REGEX.is_match(elem) {
+            true => convert_to_i64(elem),
+            false => evaluate_expression(elem, &HashMap::new(), &labels).map(|val| val.value)
+        };
+
+        let val = match result {
+            Ok(_) if !labels.is_empty() => {
+                values.push(SpaceValue::Expr(elem.to_owned()));
+                continue;
+            },
+
+            Ok(val) => val,
+            Err(err) => return Err(Box::new(AssemblyError(match find_missing_comma(elem, &labels) {
+                Some((before, after)) => format!("Expected a comma between the values {} and {} in the array in instruction {}", before, after, instr),
+                None => format!("{} in instruction {}", err.0, instr)
+            })))
+        };
+
+        if !(-0x8000..=0xFFFF).contains(&val) {
+            return Err(Box::new(AssemblyError(format!("Value {} is out of the range -32768 <= value <= 65535 in instruction {}", val, instr))));
+        }
+
+        values.push(SpaceValue::Value(val));
+    }
+
+    Ok((size, values))
+}
+
+
+/// Finds the first two values run together in an array element which is not a valid expression as a whole but whose blank-separated parts each are, such as the
+/// `1` and `2` of `1 2`, where the comma between them was left out. An element such as `-1 -2`, which is a valid expression, is never split up like this.
+fn find_missing_comma<'a>(elem:&'a str, labels:&HashMap<String, i64>) -> Option<(&'a str, &'a str)> {
+    let parts:Vec<&str> = elem.split_whitespace().collect();
+    if parts.len() > 1 && parts.iter().all(|part| evaluate_expression(part, &HashMap::new(), labels).is_ok()) {
+        return Some((parts[0], parts[1]));
+    }
+
+    None
+}
+
+
+/// Splits a `.space` into its size and the values given in its brackets, as described by `parse_array_directive`.
+///
+/// Returns an `AssemblyError` for the same reasons as `parse_array_directive`, or if there are more values than the size of the `.space`.
+pub fn parse_space(instr:&str) -> Result<(usize, Vec<SpaceValue>), Box<dyn Error>> {
+    let (size, values) = parse_array_directive(instr, ".space")?;
+    if values.len() > size {
+        return Err(Box::new(AssemblyError(format!("Array is not long enough for data in instruction {}", instr))));
+    }
+
+    Ok((size, values))
+}
+
+
+/// Splits a `.pattern` into the number of words it fills and the values it repeats to fill them, such as `.pattern 6 [0xAA, 0x55]`, as described by
+/// `parse_array_directive`. The pattern may be longer than the number of words, in which case only its first values are used.
+///
+/// Returns an `AssemblyError` for the same reasons as `parse_array_directive`, or if the number of words is 0 or there are no values to repeat.
+pub fn parse_pattern(instr:&str) -> Result<(usize, Vec<SpaceValue>), Box<dyn Error>> {
+    let (size, values) = parse_array_directive(instr, ".pattern")?;
+    if size == 0 {
+        return Err(Box::new(AssemblyError(format!("A .pattern must fill at least one word in instruction {}", instr))));
+    } else if values.is_empty() {
+        return Err(Box::new(AssemblyError(format!("A .pattern must have at least one value to repeat in instruction {}", instr))));
+    }
+
+    Ok((size, values))
+}
+
+
+/// Parses a `.text "string"`, optionally followed by `pad N` to pad the string with spaces to `N` characters and then by `nonull` to leave out the null terminator,
+/// into the characters of the string, the number of words they take up before the null terminator, which is `N` if the string is padded, and whether the
+/// terminator is kept.
+///
+/// Returns an `AssemblyError` if the line is not a `.text` of that form, if the padded length is not a non-negative integer, if the string is longer than it, or if
+/// a `nonull` string would take up no words at all.
+pub fn parse_text(instr:&str) -> Result<(String, usize, bool), Box<dyn Error>> {
+    let start = LABEL_REGEX.find(instr).map_or(0, |val| val.end());
+    let end = find_comment_start(instr).unwrap_or(instr.len());
+    let operands = match instr[start..end].trim_start().strip_prefix(".text") {
+        Some(val) if val.starts_with([' ', '\t']) => val,
+        _ => return Err(Box::new(AssemblyError(format!("Expected a .text followed by a blank in instruction {}", instr))))
+    };
+
+    let text = match TEXT_IMM_REGEX.find(operands) {
+        Some(val) if operands[..val.start()].trim().is_empty() => val,
+        _ => return Err(Box::new(AssemblyError(format!("Expected a string in quotes after the .text in instruction {}", instr))))
+    };
+
+    let chars = operands[text.start() + 1..text.end() - 1].to_owned();
+    let mut tail = operands[text.end()..].trim();
+    let null_terminated = match tail.strip_suffix("nonull") {
+        Some(val) if val.is_empty() || val.ends_with([' ', '\t']) => {
+            tail = val.trim_end();
+            false
+        },
+        _ => true
+    };
+
+    let size = match tail.strip_prefix("pad").filter(|val| val.starts_with([' ', '\t'])).map(|val| convert_to_i64(val.trim())) {
+        _ if tail.is_empty() => chars.len(),
+        Some(Ok(val)) if val >= 0 => val as usize,
+        _ => return Err(Box::new(AssemblyError(format!("Expected pad and the length to pad the string to after the string in instruction {}", instr))))
+    };
+
+    if chars.len() > size {
+        return Err(Box::new(AssemblyError(format!("String of length {} is longer than its padded length of {} in instruction {}", chars.len(), size, instr))));
+    }
+
+    if size == 0 && !null_terminated {
+        return Err(Box::new(AssemblyError(format!("A .text without its null terminator must have at least one character in instruction {}", instr))));
+    }
+
+    Ok((chars, size, null_terminated))
+}
+
+
+/// Parses a `.run COUNT 0xVVVV`, which `substitute_pseudoinstrs` writes in place of a long run of words which all hold the same value, such as the zeros at the end
+/// of a large `.space`, into the label before it (including the blank after the colon), the number of words, and their value. A `.run` cannot be written in a source
+/// file, so it is only ever found in lines which have been expanded.
+///
+/// Returns `None` if the line is not a `.run`.
+pub fn parse_run(line:&str) -> Option<(&str, usize, u16)> {
+    let start = LABEL_REGEX.find(line).map_or(0, |val| val.end());
+    let run = line[start..].trim_start();
+    let mut operands = run.strip_prefix(".run ")?.split(' ');
+    let count = operands.next()?.parse().ok()?;
+    let value = u16::from_str_radix(operands.next()?.strip_prefix("0x")?, 16).ok()?;
+    Some((&line[..line.len() - run.len()], count, value))
+}
+
+
+/// Gets the number of words a line will take up once assembled, which is 2 for a `MOVI` or `MASK`, 3 for a `JAL` to a label as it becomes a `MOVI` into the scratch
+/// register and a `JAL` through it, the given size for a `.space` or `.pattern`, the length of the string plus its null terminator for a `.text`, or its padded
+/// length plus the terminator if it is padded, without the terminator if it is `nonull`, the count of a `.run`, none for a section directive, `.assert_size`,
+/// `.at`, or `.org`, whose padding is only written once `expansion::substitute_org` has found where it is, and 1 for anything else.
+pub fn get_word_count(line:&str) -> usize {
+    match get_mnemonic(line) {
+        "" | ".code" | ".data" | ".section" | ".assert_size" | ".at" | ".org" => 0,
+        "MOVI" | "MASK" => 2,
+        "JAL" if line.contains('@') => 3,
+        ".space" => parse_space(line).map_or(1, |(size, _)| size),
+        ".pattern" => parse_pattern(line).map_or(1, |(size, _)| size),
+        ".text" => parse_text(line).map_or(1, |(_, size, null_terminated)| size + null_terminated as usize),
+        ".run" => parse_run(line).map_or(1, |(_, count, _)| count),
+        _ => 1
+    }
+}
+
+
+/// Go line-by-line through each instruction in the file, skips if it is empty, and otherwise checks it with `validate_assembly_line` and that each section fits in
+/// the 16-bit address space. Empty lines still count towards the line numbers, so lines which have not had empty lines removed are numbered as in the source file.
+///
+/// Returns an `AssemblyError` giving the line number if an invalid instruction is found, otherwise returns `Ok()`
+pub fn validate_assembly_lines(lines:&[String], isa:&IsaSpec) -> Result<(), Box<dyn Error>> {
+    validate_and_count_words(lines, isa).map(|_| ())
+}
+
+
+/// Validates the lines in the same way as `validate_assembly_lines`, then returns the number of words in the code and data sections, counted from the same parse of
+/// each line.
+pub(crate) fn validate_and_count_words(lines:&[String], isa:&IsaSpec) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut section = Section::Code;
+    let (mut code_size, mut data_size) = (0, 0);
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+
+        // the number of words is taken from the parse the line is checked with, so a .space or .text is only parsed once here
+        let num_words = match check_assembly_line(line, isa) {
+            Ok(val) => val,
+            Err(err) => return Err(Box::new(AssemblyError(format!("{} on line {}", into_assembly_error(err).0, index + 1))))
+        };
+
+        section = get_section_switch(line).unwrap_or(section);
+        let size = match section {
+            Section::Code => &mut code_size,
+            Section::Data => &mut data_size
+        };
+
+        // each section is its own 16-bit address space, so neither may grow past 0x10000 words
+        if *size + num_words > 0x10000 {
+            return Err(Box::new(AssemblyError(format!("Adding {} words to the {} already in the section would take it past the 65536 words which can be \
+                addressed, in instruction {} on line {}", num_words, size, line, index + 1))));
+        }
+
+        *size += num_words;
+    }
+
+    Ok((code_size, data_size))
+}
+
+
+/// Parses a single line with `parse_line` to determine the type of the instruction or pseudo-instruction, then performs other checks such as validating the range
+/// of immediate values and that no label is named with a reserved word.
+///
+/// Returns an `AssemblyError` if the line is not a valid instruction.
+pub fn validate_assembly_line(line:&str, isa:&IsaSpec) -> Result<(), Box<dyn Error>> {
+    check_assembly_line(line, isa).map(|_| ())
+}
+
+
+/// Checks a line in the same way as `validate_assembly_line`, then returns the number of words it takes up. The immediate is checked from the operand `parse_line`
+/// found rather than by searching the line for it again, and an expression with a label is only checked once the label is substituted.
+fn check_assembly_line(line:&str, isa:&IsaSpec) -> Result<usize, Box<dyn Error>> {
+    if let Some(val) = LABEL_REGEX.find(line) {
+        let label_name = val.as_str().trim_end_matches(':');
+        if is_reserved_word(label_name, isa) {
+            return Err(Box::new(AssemblyError(format!("Cannot define label {} as it is a reserved word: {}", label_name, line))));
+        }
+    }
+
+    let parsed = parse_line(line, isa)?;
+    let check_imm = |bits:u32, signed:bool| match parsed.operands.last() {
+        Some(Token::Expr(_)) | None => Ok(0),
+        Some(token) => get_imm_operand(token, bits, signed, line)
+    };
+
+    match (parsed.kind, parsed.mnemonic) {
+        (LineKind::Rri | LineKind::Ri, mnemonic) => match isa.get_immediate(mnemonic) {
+            Some(field) => check_imm(field.bits, field.signed)?,
+            None => 0
+        },
+        (LineKind::Load, "LLI") => check_imm(6, false)?,
+        // the bit number is needed to expand a MASK, so unlike the other immediates it cannot be an expression with a label
+        (LineKind::Load, "MASK") => match parsed.operands.last() {
+            Some(token) => get_imm_operand(token, 4, false, line)?,
+            None => 0
+        },
+        (LineKind::Load, _) => check_imm(16, false)?,
+        (LineKind::Fill, _) => check_imm(16, true)?,
+        (LineKind::At | LineKind::Org, _) => check_imm(16, false)?,
+        _ => 0
+    };
+
+    Ok(parsed.num_words)
+}
+
+
+/// Where the source of a program is read from, which is either the file with the given name or a string held in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineSource<'a> {
+    File(&'a str),
+    Str(&'a str)
+}
+
+impl LineSource<'_> {
+    /// Gets the name of the source to use in messages and for `__FILE__`, which is the name of the file or `<string>` for a string.
+    pub fn name(&self) -> &str {
+        match self {
+            LineSource::File(filename) => filename,
+            LineSource::Str(_) => "<string>"
+        }
+    }
+}
+
+
+/// The sources the lines of a program were read from, in the order they were joined into one source, so that a line of the joined source, counting from 1, can
+/// be traced back to the source and line it was written on. A program read from a single source has just that one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<(String, usize)>
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+
+    /// Adds a source of the given number of lines, whose lines follow those of the sources already added.
+    pub fn push(&mut self, name:&str, num_lines:usize) {
+        self.files.push((name.to_owned(), num_lines));
+    }
+
+
+    /// Gets the number of sources.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+
+    /// Finds the name of the source holding the given line of the joined source and the line within that source, both counting from 1.
+    pub fn locate(&self, line:usize) -> Option<(&str, usize)> {
+        let mut first = 1;
+        for (name, num_lines) in &self.files {
+            if (first..first + num_lines).contains(&line) {
+                return Some((name, line - first + 1));
+            }
+
+            first += num_lines;
+        }
+
+        None
+    }
+
+
+    /// Describes where a line of the joined source was written, such as `line 3 of kernel.asm`, or just `line 3` if there is only one source.
+    pub fn describe(&self, line:usize) -> String {
+        match self.locate(line) {
+            Some((name, line)) if self.len() > 1 => format!("line {} of {}", line, name),
+            _ => format!("line {}", line)
+        }
+    }
+
+
+    /// Rewrites an error whose message ends by giving a line of the joined source, as errors in an instruction do, to give the line within its source followed by
+    /// the name of the source, such as `on line 3 of kernel.asm`, which `AssemblyError::line` and `AssemblyError::file` read back. Any other error is left as it is.
+    pub fn locate_error(&self, err:AssemblyError) -> AssemblyError {
+        let (name, line) = match err.line().and_then(|line| self.locate(line)) {
+            Some(val) if err.file().is_none() => val,
+            _ => return err
+        };
+
+        let (message, _) = err.0.rsplit_once(" on line ").unwrap();
+        AssemblyError(format!("{} on line {} of {}", message, line, name))
+    }
+
+
+    /// Moves a diagnostic on a line of the joined source to the source and line it was written on.
+    pub fn locate_diagnostic(&self, mut diagnostic:Diagnostic) -> Diagnostic {
+        if let Some((name, line)) = diagnostic.line.and_then(|line| self.locate(line)) {
+            diagnostic.file = name.to_owned();
+            diagnostic.line = Some(line);
+        }
+
+        diagnostic
+    }
+}
+
+
+/// The character encoding a source file is decoded from. Only UTF-8 sources may start with a byte order mark, while every byte of a Latin-1 source is a character,
+/// so decoding one cannot fail.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputEncoding {
+    #[default]
+    Utf8,
+    Latin1
+}
+
+
+/// Reads the lines of a program from any reader, such as a file, a network stream, or a `Cursor` over bytes in memory. A leading UTF-8 byte order mark is skipped,
+/// and both `\n` and `\r\n` line endings are accepted, with or without a final newline, by removing every `\r` from the end of each line before anything else sees
+/// it. If `lossy` is set, bytes which are not valid UTF-8 are replaced with U+FFFD and a warning naming the line is logged. A Latin-1 source is decoded byte by
+/// byte instead, so `lossy` has no effect on it. `name` is only used to say where a problem is, and is the name of the file or `<string>` when called by
+/// `read_lines`.
+///
+/// Returns an `AssemblyError` if the reader fails, or if the source contains invalid UTF-8 and `lossy` is not set, in which case every line is still read so that
+/// the error names each line with invalid UTF-8 and the byte offset within it, rather than only the first.
+pub fn read_lines_from(reader:impl BufRead, name:&str, lossy:bool, encoding:InputEncoding) -> Result<Vec<String>, Box<dyn Error>> {
+    read_lines_from_reporting(reader, name, lossy, encoding, &mut LogSink)
+}
+
+
+/// Reads the lines of a program from any reader in the same way as `read_lines_from`, giving the warning for each line with invalid UTF-8 replaced to `sink`
+/// rather than logging it.
+///
+/// Returns an `AssemblyError` if the reader fails, or if the source contains invalid UTF-8 and `lossy` is not set.
+pub fn read_lines_from_reporting(mut reader:impl BufRead, name:&str, lossy:bool, encoding:InputEncoding, sink:&mut dyn DiagnosticSink)
+        -> Result<Vec<String>, Box<dyn Error>> {
+    let mut bytes:Vec<u8> = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut bytes) {
+        return Err(Box::new(AssemblyError(format!("Could not read {}: {}", name, e))));
+    }
+
+    let bytes = match encoding {
+        InputEncoding::Utf8 => bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes),
+        InputEncoding::Latin1 => &bytes
+    };
+
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut lines:Vec<String> = Vec::new();
+    let mut invalid_lines:Vec<String> = Vec::new();
+    for (line_num, line) in bytes.split(|byte| *byte == b'\n').enumerate() {
+        let end = line.iter().rposition(|byte| *byte != b'\r').map_or(0, |index| index + 1);
+        let line = &line[..end];
+        if encoding == InputEncoding::Latin1 {
+            lines.push(line.iter().map(|byte| *byte as char).collect());
+            continue;
+        }
+
+        match std::str::from_utf8(line) {
+            Ok(val) => lines.push(val.to_owned()),
+            Err(_) if lossy => {
+                sink.report(Diagnostic::warning(INVALID_UTF8, "Replaced invalid UTF-8 with U+FFFD".to_owned(), name, Some(line_num + 1)));
+                lines.push(String::from_utf8_lossy(line).into_owned());
+            },
+
+            Err(e) => {
+                let offset = e.valid_up_to();
+                invalid_lines.push(format!("Invalid UTF-8 byte 0x{:02X} on line {} at byte offset {} of {}", line[offset], line_num + 1, offset, name));
+            }
+        };
+    }
+
+    if !invalid_lines.is_empty() {
+        invalid_lines.push("The source may have been saved in another encoding, such as Latin-1, which can be read as such, or the invalid bytes replaced".to_owned());
+        return Err(Box::new(AssemblyError(invalid_lines.join("\n"))));
+    }
+
+    Ok(lines)
+}
+
+
+/// Reads the lines of the given source with `read_lines_from`, opening the file if it is one.
+///
+/// Returns an `AssemblyError` if the file cannot be read, or if it contains invalid UTF-8 and `lossy` is not set, naming the line and byte offset within it.
+pub fn read_lines(source:LineSource, lossy:bool, encoding:InputEncoding) -> Result<Vec<String>, Box<dyn Error>> {
+    read_lines_reporting(source, lossy, encoding, &mut LogSink)
+}
+
+
+/// Reads the lines of the given source with `read_lines_from_reporting`, opening the file if it is one, giving any warnings to `sink`.
+///
+/// Returns an `AssemblyError` if the file cannot be read, or if it contains invalid UTF-8 and `lossy` is not set.
+pub fn read_lines_reporting(source:LineSource, lossy:bool, encoding:InputEncoding, sink:&mut dyn DiagnosticSink) -> Result<Vec<String>, Box<dyn Error>> {
+    match source {
+        LineSource::File(filename) => match File::open(filename) {
+            Ok(file) => read_lines_from_reporting(BufReader::new(file), filename, lossy, encoding, sink),
+            Err(e) => Err(Box::new(AssemblyError(format!("Could not read file {}: {}", filename, e))))
+        },
+
+        LineSource::Str(text) => read_lines_from_reporting(text.as_bytes(), source.name(), lossy, encoding, sink)
+    }
+}
+
+
+/// Reads the lines of the given UTF-8 file with `read_lines`.
+///
+/// Returns an `AssemblyError` if the file cannot be read, or if it contains invalid UTF-8 and `lossy` is not set.
+pub fn read_source_lines(filename:&str, lossy:bool) -> Result<Vec<String>, Box<dyn Error>> {
+    read_lines(LineSource::File(filename), lossy, InputEncoding::Utf8)
+}
+
+
+/// Checks whether a line ends with a `\` continuing it onto the next line. A `\` inside a string or character literal or a comment does not count.
+pub fn is_continued(line:&str) -> bool {
+    find_comment_start(line).is_none() && line.trim_end().ends_with('\\') && line.chars().filter(|c| *c == '"').count() % 2 == 0
+}
+
+
+/// Joins every line ending in a `\` with the line after it, separated by a space, so a statement can be split over several physical lines. The joined line takes the
+/// place of the first physical line and the others are left empty, so each line keeps its original index for error messages and `__LINE__`.
+///
+/// Returns an `AssemblyError` if the last line of the file is continued.
+pub fn join_continued_lines(lines:&[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut result:Vec<String> = Vec::new();
+    let mut start = None;
+    for line in lines {
+        let (text, continued) = match is_continued(line) {
+            true => (line.trim_end().trim_end_matches('\\').trim_end(), true),
+            false => (line.as_str(), false)
+        };
+
+        match start {
+            Some(index) => {
+                let joined:&mut String = &mut result[index];
+                joined.push(' ');
+                joined.push_str(text.trim());
+                result.push(String::new());
+            },
+
+            None => result.push(text.to_owned())
+        };
+
+        start = match (start, continued) {
+            (_, false) => None,
+            (Some(index), true) => Some(index),
+            (None, true) => Some(result.len() - 1)
+        };
+    }
+
+    if let Some(index) = start {
+        return Err(Box::new(AssemblyError(format!("The line continued with a \\ on line {} runs past the end of the file", index + 1))));
+    }
+
+    Ok(result)
+}
+
+
+/// Joins any lines continued with a trailing `\`, then removes any '#' symbols outside a string or character literal and everything after them, and finally trims
+/// each resulting string. Every line keeps its index, so comment-only and blank lines are left empty rather than removed.
+///
+/// Returns an `AssemblyError` if the last line is continued.
+pub fn clean_source_lines(lines:&[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(join_continued_lines(lines)?.iter().map(|line| {
+        line[..find_comment_start(line).unwrap_or(line.len())].trim().to_owned() // strip comments out of all lines
+    }).collect())
+}
+
+
+/// The words a line of the source is annotated with by a comment starting with `#=`, such as `ADDI $r0, $zero, 7  #= 0x2807`, which the line is checked to
+/// assemble to. `line` counts from 1 as in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodingAnnotation {
+    pub line: usize,
+    pub words: Vec<u16>
+}
+
+
+/// Finds every line whose comment starts with `#=` and reads the words after it, separated by spaces, in any of the bases an immediate can be written in. Lines
+/// continued with a `\` are joined first, so an annotation at the end of the last physical line belongs to the statement as a whole.
+///
+/// Returns an `AssemblyError` if an annotation gives no words, or gives something other than a number from 0 to 0xFFFF.
+pub fn find_encoding_annotations(lines:&[String]) -> Result<Vec<EncodingAnnotation>, Box<dyn Error>> {
+    let mut annotations:Vec<EncodingAnnotation> = Vec::new();
+    for (index, line) in join_continued_lines(lines)?.iter().enumerate() {
+        let annotation = match find_comment_start(line).and_then(|start| line[start..].strip_prefix("#=")) {
+            Some(annotation) => annotation,
+            None => continue
+        };
+
+        let words:Option<Vec<u16>> = annotation.split_whitespace().map(|word| {
+            convert_to_i64(word).ok().filter(|value| (0..=0xFFFF).contains(value)).map(|value| value as u16)
+        }).collect();
+
+        match words {
+            Some(words) if !words.is_empty() => annotations.push(EncodingAnnotation { line: index + 1, words }),
+            _ => return Err(Box::new(AssemblyError(format!("Expected the words the line assembles to, such as 0x2807, after #= on line {}", index + 1))))
+        };
+    }
+
+    Ok(annotations)
+}
+
+
+/// Checks that each annotated line assembled to the words it is annotated with, finding the words of each line by walking the `source_lines` as they were before
+/// their pseudo-instructions were expanded, with the line numbered `n` at index `n - 1`, and counting the words of each with `get_word_count` in the section it is in.
+///
+/// Returns an `AssemblyError` giving the line and both sets of words for the first line which does not match its annotation.
+pub fn check_encoding_annotations(source_lines:&[String], annotations:&[EncodingAnnotation], code:&[u16], data:&[u16]) -> Result<(), Box<dyn Error>> {
+    if annotations.is_empty() {
+        return Ok(());
+    }
+
+    let mut section = Section::Code;
+    let (mut code_address, mut data_address) = (0, 0);
+    let mut annotations = annotations.iter().peekable();
+    for (index, line) in source_lines.iter().enumerate() {
+        if let Some(next_section) = get_section_switch(line) {
+            section = next_section;
+        }
+
+        let num_words = get_word_count(line);
+        let (words, address) = match section {
+            Section::Code => (code, &mut code_address),
+            Section::Data => (data, &mut data_address)
+        };
+
+        let line_words = words.get(*address..*address + num_words).unwrap_or(&[]);
+        *address += num_words;
+        while let Some(annotation) = annotations.next_if(|annotation| annotation.line <= index + 1) {
+            if annotation.line == index + 1 && annotation.words != line_words {
+                return Err(Box::new(AssemblyError(format!("Line assembles to {} but is annotated with #= {} on line {}", format_words(line_words),
+                    format_words(&annotation.words), annotation.line))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Formats words as they are written in an encoding annotation, or as `no words` if there are none.
+fn format_words(words:&[u16]) -> String {
+    match words.is_empty() {
+        true => "no words".to_owned(),
+        false => words.iter().map(|word| format!("0x{:04X}", word)).collect::<Vec<String>>().join(" ")
+    }
+}
+
+
+/// Checks that no line of a source contains a tab, for projects which only allow blank space to be written with spaces. This includes tabs in comments and string
+/// literals, and lines are numbered as in the source.
+///
+/// Returns an `AssemblyError` giving the first line with a tab.
+pub fn check_no_tabs(lines:&[String]) -> Result<(), Box<dyn Error>> {
+    match lines.iter().position(|line| line.contains('\t')) {
+        Some(index) => Err(Box::new(AssemblyError(format!("Found a tab where only spaces are allowed on line {}: {}", index + 1, lines[index])))),
+        None => Ok(())
+    }
+}
+
+
+/// Reads each line of the given source and cleans them with `clean_source_lines`. Invalid UTF-8 is replaced rather than rejected if `lossy` is set, and the source
+/// is decoded as Latin-1 rather than UTF-8 if `encoding` says so.
+///
+/// Returns an `AssemblyError` if the source cannot be read or the last line is continued.
+pub fn get_source_lines(source:LineSource, lossy:bool, encoding:InputEncoding) -> Result<Vec<String>, Box<dyn Error>> {
+    clean_source_lines(&read_lines(source, lossy, encoding)?)
+}
+
+
+/// Gets the lines of the given UTF-8 file with `get_source_lines`.
+///
+/// Returns an `AssemblyError` if the file cannot be read or the last line is continued.
+pub fn get_line_vector(filename:&str, lossy:bool) -> Result<Vec<String>, Box<dyn Error>> {
+    get_source_lines(LineSource::File(filename), lossy, InputEncoding::Utf8)
+}
+
+
+/// Finds the `#` starting the comment on a line, ignoring any `#` inside a string literal such as `"#1"` or a character literal such as `'#'`.
+pub fn find_comment_start(line:&str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '#') => return Some(index),
+            _ => ()
+        };
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::DEFAULT_ISA_SPEC;
+    use crate::expansion::substitute_pseudoinstrs;
+    use crate::encoder::assemble_section;
+    use std::fs;
+
+
+    /// Validates a line as it was before the word count and immediate were taken from its parse, counting its words separately with `get_word_count` and searching
+    /// the line again for its immediate with `get_imm_from_instr`.
+    fn check_line_by_rescanning(line:&str) -> Result<usize, Box<dyn Error>> {
+        let num_words = get_word_count(line);
+        if let Some(val) = LABEL_REGEX.find(line) {
+            if is_reserved_word(val.as_str().trim_end_matches(':'), &DEFAULT_ISA_SPEC) {
+                return Err(Box::new(AssemblyError(format!("Cannot define label {}", line))));
+            }
+        }
+
+        let parsed = parse_line(line, &DEFAULT_ISA_SPEC)?;
+        match (parsed.kind, parsed.mnemonic) {
+            (LineKind::Rri | LineKind::Ri, mnemonic) => match DEFAULT_ISA_SPEC.get_immediate(mnemonic) {
+                Some(field) => get_imm_from_instr(line, field.bits, field.signed, false, true)?,
+                None => None
+            },
+            (LineKind::Load, "LLI") => get_imm_from_instr(line, 6, false, false, true)?,
+            (LineKind::Load, _) => get_imm_from_instr(line, 16, false, false, true)?,
+            (LineKind::Fill, _) => get_imm_from_instr(line, 16, true, true, true)?,
+            (LineKind::At | LineKind::Org, _) => get_imm_from_instr(line, 16, false, false, false)?,
+            _ => None
+        };
+
+        Ok(num_words)
+    }
+
+
+    #[test]
+    fn test_check_line_matches_rescanning() {
+        let mut lines:Vec<String> = Vec::new();
+        for entry in fs::read_dir("test_files").unwrap() {
+            lines.extend(get_line_vector(entry.unwrap().path().to_str().unwrap(), true).unwrap());
+        }
+
+        lines.extend(["ADDI $r0, $r1, 63", "ADDI $r0, $r1, 64", "SW $r0, $r1, -65", "LLI $r0, 64", "MOVI $r0, 65535", "MOVI $r0, 65536", "LUI $r0, 0x400",
+            ".fill 0xFFFF", ".fill -32769", ".fill 'a'", ".fill @x+1", "ADDI $r0, $r1, @x", "IO: .at 0x10000", "x: .space 3 [1]", "y: .text \"ab\" pad 4",
+            "NOP: ADD $r0, $r1, $r2", ".data", "JAL $r0"].iter().map(|line| line.to_string()));
+
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            match (check_assembly_line(line, &DEFAULT_ISA_SPEC), check_line_by_rescanning(line)) {
+                (Ok(num_words), Ok(expected)) => assert_eq!(num_words, expected, "{}", line),
+                (Err(_), Err(_)) => (),
+                (result, expected) => panic!("{} gave {:?} rather than {:?}", line, result.is_ok(), expected.is_ok())
+            };
+        }
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_validate_assembly_lines_large_input() {
+        let sample = ["start: ADDI $r0, $zero, 5", "ADD $r1, $r0, $r2 # sum", "LUI $r3, 0x3FF", "loop: BEQ $r0, $r1, $r2", "SW $r0, $r6, -3", "JAL $zero, $r6",
+            "NOP", ".fill 'x'", ".text \"hello\"", ".syscall 3", "MOVI $r4, @start+2", "table: .space 4 [1, 2, 3]"];
+        let lines:Vec<String> = sample.iter().cycle().take(20_000).map(|line| line.to_string()).collect();
+        let rescanned:usize = lines.iter().map(|line| check_line_by_rescanning(line).unwrap()).sum();
+        let (code_size, _) = validate_and_count_words(&lines, &DEFAULT_ISA_SPEC).unwrap();
+        assert_eq!(code_size, rescanned);
+    }
+
+
+    #[test]
+    fn test_line_vector_generation() {
+        let lines = get_line_vector("test_files/test_line_vec_gen.asm", false).unwrap();
+        assert_eq!(lines[0], "start: ADDI $r0, $r0, 5");
+        assert_eq!(lines[1], "ADDI $r0, $r1, 2");
+        assert_eq!(lines[2], "NAND $r0, $r0, $r0");
+        assert_eq!(lines[3], "NOP");
+        assert_eq!(lines[4], "ADDI $r0, $r6, 1");
+        assert_eq!(lines[5], "ADD $r0, $r0, $r1");
+        assert_eq!(lines[6], "MOVI $r0, @start");
+        assert_eq!(lines.len(), 7);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_line_vector_gen_invalid_file() {
+        let _lines = get_line_vector("test_files/does_not_exist.asm", false).unwrap();
+    }
+
+
+    #[test]
+    fn test_line_vector_bom() {
+        let lines = get_line_vector("test_files/test_bom.asm", false).unwrap();
+        assert_eq!(lines, vec!["start: ADDI $r1, $zero, 5", "JALR $zero, $r2"]);
+    }
+
+
+    #[test]
+    fn test_line_vector_crlf() {
+        let lines = get_line_vector("test_files/test_crlf.asm", false).unwrap();
+        assert_eq!(lines, vec!["ADDI $r1, $zero, 5", "", "JALR $zero, $r2"]);
+
+        let lines = get_line_vector("test_files/test_line_vec_gen_crlf.asm", false).unwrap();
+        assert_eq!(lines, get_line_vector("test_files/test_line_vec_gen.asm", false).unwrap());
+    }
+
+
+    #[test]
+    fn test_read_source_lines_invalid_utf8() {
+        let err = read_source_lines("test_files/test_invalid_utf8.asm", false).unwrap_err().to_string();
+        assert!(err.contains("0xE9 on line 2 at byte offset 24"));
+        assert!(err.contains("Latin-1"));
+
+        let err = get_line_vector("test_files/test_invalid_utf8.asm", false).unwrap_err().to_string();
+        assert!(err.contains("on line 2"));
+
+        let bytes:&[u8] = b"NOP\nADDI $r1, $zero, 1 # \xFF\nNOP\n.fill 2 # caf\xE9\n";
+        let err = read_lines_from(bytes, "<bytes>", false, InputEncoding::Utf8).unwrap_err().to_string();
+        assert!(err.contains("0xFF on line 2 at byte offset 21 of <bytes>"));
+        assert!(err.contains("0xE9 on line 4 at byte offset 13 of <bytes>"));
+
+        let lines = read_source_lines("test_files/test_invalid_utf8.asm", true).unwrap();
+        assert_eq!(lines[1], "ADDI $r2, $zero, 1 # caf\u{FFFD}");
+    }
+
+
+    #[test]
+    fn test_read_latin1_source() {
+        let lines = read_lines(LineSource::File("test_files/test_invalid_utf8.asm"), false, InputEncoding::Latin1).unwrap();
+        assert_eq!(lines[1], "ADDI $r2, $zero, 1 # caf\u{E9}");
+
+        let lines = get_source_lines(LineSource::File("test_files/test_invalid_utf8.asm"), false, InputEncoding::Latin1).unwrap();
+        assert_eq!(lines[1], "ADDI $r2, $zero, 1");
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_named_mnemonic() {
+        let lines = vec!["ADD: ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_named_pseudoinstr() {
+        let lines = vec!["movi: NOP".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_named_register() {
+        let lines = vec!["zero: NOP".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_label_containing_mnemonic() {
+        let lines = vec!["adder: ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_uniform_whitespace() {
+        let instrs = [
+            ("ADD", "$r0, $r1, $r2"), ("NAND", "$r0, $r1, $r2"), ("BEQ", "$r0, $r1, $r2"), ("ADDI", "$r0, $r1, -5"), ("SW", "$r0, $r1, 5"),
+            ("LW", "$r0, $r1, 5"), ("LUI", "$r0, 5"), ("JAL", "$zero, $r6"), ("LLI", "$r0, 5"), ("MOVI", "$r0, 0x1234"), ("MASK", "$r0, 5"), (".fill", "0x1234"),
+            (".syscall", "3"), (".text", "\"hi\""), (".space", "2 [1, 2]")
+        ];
+
+        for (mnemonic, operands) in instrs {
+            let canonical = vec![format!("{} {}", mnemonic, operands)];
+            validate_assembly_lines(&canonical, &DEFAULT_ISA_SPEC).unwrap();
+            let expected = assemble_section(&substitute_pseudoinstrs(&canonical), &DEFAULT_ISA_SPEC).unwrap();
+
+            let variants = [
+                format!("{} {}", mnemonic, operands.replace(", ", ",")),
+                format!("{}\t{}", mnemonic, operands.replace(", ", ",\t")),
+                format!("lbl:  {}   {} # comment", mnemonic, operands.replace(", ", " ,  ")),
+            ];
+
+            for variant in variants {
+                let lines = vec![variant.clone()];
+                assert!(validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).is_ok(), "{} was rejected", variant);
+                assert_eq!(assemble_section(&substitute_pseudoinstrs(&lines), &DEFAULT_ISA_SPEC).unwrap(), expected, "{} encoded differently", variant);
+            }
+
+            let glued = vec![format!("{}{}", mnemonic, operands)];
+            assert!(validate_assembly_lines(&glued, &DEFAULT_ISA_SPEC).is_err(), "{} was accepted", glued[0]);
+        }
+    }
+
+
+    #[test]
+    fn test_valid_instrs() {
+        let lines = get_line_vector("test_files/test_valid_instrs.asm", false).unwrap();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_rrr() {
+        let lines = vec!["ADD $zero $r1 $r1".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_get_imm_from_instr() {
+        let mut imm = get_imm_from_instr("ADDI $r0, $r1, 10", 7, true, true, true).unwrap();
+        assert_eq!(imm.unwrap(), 10);
+
+        imm = get_imm_from_instr("ADDI $r0, $r1, -10", 7, true, true, true).unwrap();
+        assert_eq!(imm.unwrap(), -10);
+
+        imm = get_imm_from_instr("ADDI $r0, $r1, 0x03A", 7, true, true, true).unwrap();
+        assert_eq!(imm.unwrap(), 0x3A);
+
+        imm = get_imm_from_instr("ADDI $r0, $r1, 0b011010", 7, true, true, true).unwrap();
+        assert_eq!(imm.unwrap(), 0b11010);
+
+        imm = get_imm_from_instr(".fill 'a'", 16, true, true, false).unwrap();
+        assert_eq!(imm.unwrap(), 97);
+
+        imm = get_imm_from_instr("ADDI $r0, $r1, @label", 16, true, true, true).unwrap();
+        assert_eq!(imm, None);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_label_imm() {
+        let imm = get_imm_from_instr("ADDI $r0, $r1, @label", 16, true, true, false).unwrap();
+        assert_eq!(imm, None);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_negative_unsigned_imm() {
+        let _imm = get_imm_from_instr("ADDI $r0, $r1, -10", 7, false, false, true).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn unsigned_imm_out_of_range() {
+        let _imm = get_imm_from_instr("ADDI $r0, $r1, 128", 7, false, false, true).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn signed_imm_to_large() {
+        let _imm = get_imm_from_instr("ADDI $r0, $r1, 64", 7, true, false, true).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn signed_imm_too_small() {
+        let _imm = get_imm_from_instr("ADDI $r0, $r1, -65", 7, true, false, true).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_unsigned_imm_too_large() {
+        let lines = vec!["ADDI $r0, $r1, 100000".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_signed_imm_too_large() {
+        let lines = vec!["ADDI $r0, $r1, 100".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_non_ascii_char_fill() {
+        let lines = vec![".fill 'д'".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_fill_integer() {
+        let lines = vec![".fill -100000".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_validate_space() {
+        parse_space(".space 10 [100, 200, 0xFF, 0b001100, 'a', 'b']").unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_validate_invalid_space() {
+        parse_space(".space 10 [100, 200, 0xFFFFF, 0b001100, 'a', 'b']").unwrap();
+    }
+
+
+    #[test]
+    fn test_parse_space_blanks() {
+        let values = |values:&[i64]| values.iter().map(|val| SpaceValue::Value(*val)).collect::<Vec<SpaceValue>>();
+        assert_eq!(parse_space(".space 3 [1,2,3]").unwrap(), (3, values(&[1, 2, 3])));
+        assert_eq!(parse_space(".space 3 [ 1 ,2,  3 ]").unwrap(), (3, values(&[1, 2, 3])));
+        assert_eq!(parse_space("arr:\t.space\t3[1,\t2 ,3]").unwrap(), (3, values(&[1, 2, 3])));
+        assert_eq!(parse_space(".space 3 [1, 2, 3,]").unwrap(), (3, values(&[1, 2, 3])));
+        assert_eq!(parse_space(".space 3 [1, 2, 3 , ]").unwrap(), (3, values(&[1, 2, 3])));
+        assert_eq!(parse_space(".space 2 [',', 'a']").unwrap(), (2, values(&[44, 97])));
+        assert_eq!(parse_space(".space 2 [ ]").unwrap(), (2, values(&[])));
+    }
+
+
+    #[test]
+    fn test_parse_space_range() {
+        assert_eq!(parse_space(".space 3 [-32768, -1, 65535]").unwrap().1, vec![SpaceValue::Value(-32768), SpaceValue::Value(-1), SpaceValue::Value(65535)]);
+        assert!(parse_space(".space 1 [-32769]").unwrap_err().to_string().contains("Value -32769 is out of the range -32768 <= value <= 65535"));
+        assert!(parse_space(".space 1 [65536]").is_err());
+    }
+
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(parse_pattern("buf: .pattern 6 [0xAA, 0x55] # test").unwrap(), (6, vec![SpaceValue::Value(0xAA), SpaceValue::Value(0x55)]));
+        assert_eq!(parse_pattern(".pattern 1 [1, 2, 3]").unwrap().0, 1);
+        assert_eq!(get_word_count(".pattern 6 [0xAA, 0x55]"), 6);
+        assert!(parse_pattern(".pattern 0 [1]").unwrap_err().to_string().contains("must fill at least one word"));
+        assert!(parse_pattern(".pattern 4 []").unwrap_err().to_string().contains("at least one value to repeat"));
+        assert!(parse_pattern(".pattern -4 [1]").is_err());
+        assert!(parse_pattern(".pattern 4 [65536]").unwrap_err().to_string().contains("out of the range"));
+        assert!(parse_pattern(".pattern 4 0xAA").unwrap_err().to_string().contains("Expected the values of a .pattern to be given in brackets"));
+        assert!(validate_assembly_lines(&[".pattern 4 [1,,2]".to_owned()], &DEFAULT_ISA_SPEC).is_err());
+    }
+
+
+    #[test]
+    fn test_parse_run() {
+        assert_eq!(parse_run("buffer: .run 40000 0x0000"), Some(("buffer: ", 40000, 0)));
+        assert_eq!(parse_run(".run 20 0x0020"), Some(("", 20, 0x20)));
+        assert_eq!(get_word_count("buffer: .run 40000 0x0000"), 40000);
+        assert_eq!(parse_run(".fill 0x0000"), None);
+        assert_eq!(parse_run(".run 20"), None);
+    }
+
+
+    #[test]
+    fn test_parse_space_expressions() {
+        assert_eq!(parse_space(".space 4 [2*3, (1+1)<<4, @table+1, @end-@start]").unwrap(), (4, vec![SpaceValue::Value(6), SpaceValue::Value(32),
+            SpaceValue::Expr("@table+1".to_owned()), SpaceValue::Expr("@end-@start".to_owned())]));
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_space_invalid_expression() {
+        parse_space(".space 2 [@table+]").unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_space_empty_value() {
+        parse_space(".space 3 [1,,2]").unwrap();
+    }
+
+
+    #[test]
+    fn test_parse_space_missing_comma() {
+        let err = into_assembly_error(parse_space(".space 3 [1 2, 3]").unwrap_err()).0;
+        assert_eq!(err, "Expected a comma between the values 1 and 2 in the array in instruction .space 3 [1 2, 3]");
+        let err = into_assembly_error(parse_space(".space 3 [1, 0x10\t@table, 3]").unwrap_err()).0;
+        assert!(err.starts_with("Expected a comma between the values 0x10 and @table"), "{}", err);
+        assert!(validate_assembly_lines(&[".pattern 4 ['a' 'b']".to_owned()], &DEFAULT_ISA_SPEC).unwrap_err().to_string().contains("Expected a comma between the values 'a' and 'b'"));
+
+        // blank space inside a single value is still allowed, and a value which is a valid expression is not split
+        assert_eq!(parse_space(".space 2 [1 + 2, -1 -2]").unwrap().1, vec![SpaceValue::Value(3), SpaceValue::Value(-3)]);
+        assert!(!parse_space(".space 2 [1 +, 2]").unwrap_err().to_string().contains("Expected a comma"));
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_parse_space_only_comma() {
+        parse_space(".space 3 [,]").unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_array_too_small() {
+        parse_space(".space 3 [100, 200, 50, 20]").unwrap();
+    }
+
+
+    #[test]
+    fn test_trailing_comments() {
+        let lines:Vec<String> = [".text \"hi\"  # greeting", ".syscall 3 # print", ".space 2 [1, 2] # pair", "msg: .text \"a # b\"#note"].iter()
+            .map(|line| line.to_string()).collect();
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+
+        let lines = substitute_pseudoinstrs(&lines);
+        assert_eq!(lines[0], ".fill 0x0068");
+        assert_eq!(lines[3], ".syscall 3 # print");
+        assert_eq!(lines[4], ".fill 0x0001");
+        assert_eq!(lines[6], "msg: .fill 0x0061");
+        assert_eq!(lines[8], ".fill 0x0023");
+        assert_eq!(lines.len(), 12);
+    }
+
+
+    #[test]
+    fn test_line_vector_quoted_hash() {
+        let lines = get_line_vector("test_files/test_quoted_hash.asm", false).unwrap();
+        assert_eq!(lines, vec![".text \"a # b\"", ".fill '#'", ".syscall 3"]);
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_syscall_code() {
+        let lines = vec![".syscall 18".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_with_space() {
+        let lines = vec!["hello world: ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_label_with_non_alphabet_char() {
+        let lines = vec!["he**world: ADD $r0, $r1, $r2".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_line_continuation() {
+        let lines = get_line_vector("test_files/test_line_continuation.asm", false).unwrap();
+        assert_eq!(lines[1], "start:   .space 5 [100, -2, 'a']");
+        assert_eq!(lines[2], "");
+        assert_eq!(lines[3], "");
+        assert_eq!(lines[4], "ADD $r0, $r1, $r3");
+
+        let mut continued = lines.clone();
+        continued.retain(|line| !line.is_empty());
+        let mut single = get_line_vector("test_files/test_space_sub.asm", false).unwrap();
+        validate_assembly_lines(&continued, &DEFAULT_ISA_SPEC).unwrap();
+        continued = substitute_pseudoinstrs(&continued);
+        single = substitute_pseudoinstrs(&single);
+
+        assert_eq!(assemble_section(&continued, &DEFAULT_ISA_SPEC).unwrap(), assemble_section(&single, &DEFAULT_ISA_SPEC).unwrap());
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_line_continuation_at_end_of_file() {
+        let lines = vec!["ADD $r0, $r1, $r2".to_owned(), "ADD $r0, $r1, \\".to_owned()];
+        join_continued_lines(&lines).unwrap();
+    }
+
+
+    #[test]
+    fn test_find_encoding_annotations() {
+        let lines:Vec<String> = ["ADDI $r0, $zero, 7  #= 0x2407", "NOP # #= 0x0000", "MOVI $r0, 7 \\", "#=0x2407 0x6400", "LUI $r0, 1 #=25601"].iter()
+            .map(|line| line.to_string()).collect();
+        assert_eq!(find_encoding_annotations(&lines).unwrap(), vec![
+            EncodingAnnotation { line: 1, words: vec![0x2407] },
+            EncodingAnnotation { line: 3, words: vec![0x2407, 0x6400] },
+            EncodingAnnotation { line: 5, words: vec![0x6401] }
+        ]);
+
+        let err = into_assembly_error(find_encoding_annotations(&["NOP".to_owned(), "NOP #= 0x10000".to_owned()]).unwrap_err()).0;
+        assert_eq!(err, "Expected the words the line assembles to, such as 0x2807, after #= on line 2");
+        assert!(find_encoding_annotations(&["NOP #=".to_owned()]).is_err());
+    }
+
+
+    #[test]
+    fn test_check_encoding_annotations() {
+        let source_lines:Vec<String> = ["ADDI $r0, $zero, 7", "", ".data", ".fill 0x1234", ".code", "MOVI $r0, 7"].iter().map(|line| line.to_string()).collect();
+        let (code, data) = (vec![0x2407, 0x2407, 0x6400], vec![0x1234]);
+        let annotation = |line, words:&[u16]| EncodingAnnotation { line, words: words.to_vec() };
+        check_encoding_annotations(&source_lines, &[annotation(1, &[0x2407]), annotation(4, &[0x1234]), annotation(6, &[0x2407, 0x6400])], &code, &data).unwrap();
+
+        let err = into_assembly_error(check_encoding_annotations(&source_lines, &[annotation(1, &[0x2407]), annotation(6, &[0x2407])], &code, &data).unwrap_err()).0;
+        assert_eq!(err, "Line assembles to 0x2407 0x6400 but is annotated with #= 0x2407 on line 6");
+        let err = into_assembly_error(check_encoding_annotations(&source_lines, &[annotation(2, &[0x0000])], &code, &data).unwrap_err()).0;
+        assert_eq!(err, "Line assembles to no words but is annotated with #= 0x0000 on line 2");
+    }
+
+
+    #[test]
+    fn test_address_space_full() {
+        let lines = vec![".space 65535 []".to_owned(), "NOP".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn test_address_space_exceeded() {
+        let lines = vec![".space 65535 []".to_owned(), "MOVI $r0, 1".to_owned()];
+        validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap();
+    }
+
+
+    #[test]
+    fn test_address_space_exceeded_by_space() {
+        let lines = vec!["NOP".to_owned(), ".space 70000 []".to_owned()];
+        let err = validate_assembly_lines(&lines, &DEFAULT_ISA_SPEC).unwrap_err().to_string();
+        assert!(err.contains("Adding 70000 words to the 1 already in the section"));
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use crate::assemble_str;
+
+
+create_exception!(iridium_assembler, AssemblyError, PyException, "Raised when a program cannot be assembled, with the line it failed on in `line`, or `None`.");
+
+
+/// A program assembled by `assemble` as given to Python, with the words of its code and data sections and the address of each label within its section.
+#[pyclass(name = "AssembledProgram", get_all, frozen)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyAssembledProgram {
+    pub words: Vec<u16>,
+    pub data: Vec<u16>,
+    pub labels: HashMap<String, u16>
+}
+
+#[pymethods]
+impl PyAssembledProgram {
+    fn __repr__(&self) -> String {
+        format!("AssembledProgram(words={}, data={}, labels={})", self.words.len(), self.data.len(), self.labels.len())
+    }
+}
+
+
+/// Assembles a program in the same way as `assemble_str`.
+///
+/// Raises an `AssemblyError` giving the message and line of the first error if the program cannot be assembled.
+#[pyfunction]
+fn assemble(py:Python<'_>, source:&str) -> PyResult<PyAssembledProgram> {
+    let program = match assemble_str(source) {
+        Ok(val) => val,
+        Err(err) => {
+            let exception = AssemblyError::new_err(err.0.clone());
+            exception.value_bound(py).setattr("line", err.line())?;
+            return Err(exception);
+        }
+    };
+
+    let labels = program.labels.iter().map(|symbol| (symbol.name.to_owned(), symbol.address)).collect();
+    Ok(PyAssembledProgram { words: program.code, data: program.data, labels })
+}
+
+
+/// The `iridium_assembler` Python module, built with `maturin develop --features python`.
+#[pymodule]
+fn iridium_assembler(m:&Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(assemble, m)?)?;
+    m.add_class::<PyAssembledProgram>()?;
+    m.add("AssemblyError", m.py().get_type_bound::<AssemblyError>())?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::io::{ self, BufRead, Write };
+use crate::AssemblyError;
+use crate::assembler::Assembler;
+use crate::expansion::expand_runs;
+use crate::encoder::{ Instruction, decode, register_name };
+
+
+/// Breaks a word down into the fields of the instruction it encodes, such as `opcode=001 rd=$r1 ra=$zero imm=5`, or `data` if it is not an instruction.
+pub fn describe_fields(word:u16) -> String {
+    let opcode = format!("opcode={:03b}", word >> 13);
+    match decode(word) {
+        Instruction::Add { rd, ra, rb } | Instruction::Nand { rd, ra, rb } | Instruction::Beq { rd, ra, rb } =>
+            format!("{} rd={} ra={} rb={}", opcode, register_name(rd), register_name(ra), register_name(rb)),
+        Instruction::Addi { rd, ra, imm } | Instruction::Sw { rd, ra, imm } | Instruction::Lw { rd, ra, imm } =>
+            format!("{} rd={} ra={} imm={}", opcode, register_name(rd), register_name(ra), imm),
+        Instruction::Lui { rd, imm } => format!("{} rd={} imm=0x{:03X}", opcode, register_name(rd), imm),
+        Instruction::Jal { rd, ra } => format!("{} rd={} ra={}", opcode, register_name(rd), register_name(ra)),
+        Instruction::Syscall(code) => format!("{} syscall={}", opcode, code),
+        Instruction::Data(_) => "data".to_owned()
+    }
+}
+
+
+/// Assembles a single line on its own, such as one given on the command line, giving the words of the code section it assembles to. A pseudo-instruction gives
+/// each of the words it expands to.
+///
+/// Returns an `AssemblyError` if the line cannot be assembled, such as if it refers to a label it does not define.
+pub fn assemble_instr(line:&str, assembler:&Assembler) -> Result<Vec<u16>, AssemblyError> {
+    Ok(assembler.assemble_str(&format!("{}\n", line))?.code)
+}
+
+
+/// Reads one line at a time from `input` and writes the words it assembles to, each with its address, the line it was encoded from, and its fields, or the error
+/// if it cannot be assembled, prompting for the next line with `> ` until the input ends. Each line is assembled after every line accepted before it, so a label
+/// defined on an earlier line or a constant defined with `.equ` can be used, and a pseudo-instruction which expands to several words shows all of them. A line
+/// with an error is forgotten, so the next line takes its place.
+///
+/// Returns the number of lines which were assembled, or the error if reading from `input` or writing to `output` fails.
+pub fn run_repl(input:impl BufRead, mut output:impl Write, assembler:&Assembler) -> io::Result<usize> {
+    let mut session = String::new();
+    let (mut code_len, mut data_len) = (0, 0);
+    let mut num_lines = 0;
+
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            let source = format!("{}{}\n", session, line);
+            match assembler.assemble_str(&source) {
+                Ok(program) => {
+                    for (address, (word, instr)) in program.code.iter().zip(expand_runs(&program.code_lines)).enumerate().skip(code_len) {
+                        writeln!(output, "0x{:04X}: 0x{:04X}  {:32} {}", address, word, instr, describe_fields(*word))?;
+                    }
+
+                    for (address, (word, instr)) in program.data.iter().zip(expand_runs(&program.data_lines)).enumerate().skip(data_len) {
+                        writeln!(output, "data 0x{:04X}: 0x{:04X}  {:32} {}", address, word, instr, describe_fields(*word))?;
+                    }
+
+                    (code_len, data_len) = (program.code.len(), program.data.len());
+                    session = source;
+                    num_lines += 1;
+                },
+
+                Err(err) => writeln!(output, "Error: {}", err.0)?
+            };
+        }
+
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+
+    writeln!(output)?;
+    Ok(num_lines)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_describe_fields() {
+        assert_eq!(describe_fields(0x2805), "opcode=001 rd=$r1 ra=$zero imm=5");
+        assert_eq!(describe_fields(0x4D20), "opcode=010 rd=$r2 ra=$r1 rb=$r1");
+        assert_eq!(describe_fields(0x6C00), "opcode=011 rd=$r2 imm=0x000");
+        assert_eq!(describe_fields(0xF405), "opcode=111 syscall=5");
+        assert_eq!(describe_fields(0x0001), "data");
+    }
+
+
+    #[test]
+    fn test_assemble_instr() {
+        assert_eq!(assemble_instr("ADDI $r0, $zero, 7", &Assembler::new()).unwrap(), vec![0x2407]);
+        assert_eq!(assemble_instr("MOVI $r0, 32", &Assembler::new()).unwrap(), vec![0x2420, 0x6400]);
+        assert_eq!(assemble_instr("start: MOVI $r0, @start", &Assembler::new()).unwrap(), vec![0x2400, 0x6400]);
+        assert!(assemble_instr("MOVI $r0, @end", &Assembler::new()).is_err());
+        assert!(assemble_instr("ADDI $r0, $zero", &Assembler::new()).is_err());
+    }
+
+
+    #[test]
+    fn test_run_repl() {
+        let input = "ADDI $r1, $zero, 5\n\nstart: MOVI $r2, @start\nNAND $r0\nBEQ $r1, $r2, $r3\n";
+        let mut output:Vec<u8> = Vec::new();
+        assert_eq!(run_repl(input.as_bytes(), &mut output, &Assembler::new()).unwrap(), 3);
+
+        let output = String::from_utf8(output).unwrap();
+        let lines:Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("> 0x0000: 0x2805  ADDI $r1, $zero, 5") && lines[0].ends_with("opcode=001 rd=$r1 ra=$zero imm=5"));
+        assert!(lines[1].starts_with("> > 0x0001: 0x2C01  start: ADDI $r2, $zero, 1"));
+        assert!(lines[2].starts_with("0x0002: 0x6C00  LUI $r2, 0") && lines[2].ends_with("opcode=011 rd=$r2 imm=0x000"));
+        assert!(lines[3].starts_with("> Error: "));
+        assert!(lines[4].starts_with("> 0x0003: 0xC9C0  BEQ $r1, $r2, $r3"));
+        assert_eq!(lines[5], "> ");
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::rc::Rc;
+use std::slice;
+use crate::{ AssemblerOptions, AssemblyError, encoder, expansion, into_assembly_error, labels, parser };
+use crate::isa::{ DEFAULT_ISA_SPEC, IsaSpec };
+use crate::labels::{ LabelResolver, LabelTableBuilder, get_section_switch };
+use crate::output::Endian;
+
+
+/// The words of the code section of a program as `(address, word)` pairs, given by `Assembler::stream`. Each line is only expanded, resolved, and encoded once the
+/// words before it have been taken, so the expanded lines and the words of the program are never all held at once.
+///
+/// Some things still need the whole program before the first word can be given, so are done when the stream is created:
+/// - the source is read and kept in full, as `.equ` constants may be used before they are defined and the lines are needed a second time;
+/// - every line is expanded once to build the label table, as a label may be used before it is defined, so nothing needs to be backpatched later;
+/// - the size of each section is counted, for `@__END__`, `.assert_size`, and `.at`.
+///
+/// A `.space`, `.pattern`, or `.text` is expanded into all of its words at once when it is reached, so the words of a single very large one are held in full,
+/// though the zeros filling out a large `.space` are encoded from a single `.run` line rather than a line each. Only the code section can be streamed, as the
+/// words have no section, so a program with a `.data` section gives an error instead of any words.
+pub struct WordStream {
+    lines: Vec<String>,
+    index: usize,
+    resolver: Option<LabelResolver>,
+    address: usize,
+    pending: VecDeque<(u16, u16)>,
+    error: Option<AssemblyError>,
+    isa: Option<Rc<IsaSpec>>
+}
+
+impl WordStream {
+    /// Runs the first pass over the lines of a program as returned by `parser::get_source_lines`, substituting the constants, validating each line, and building the
+    /// label table, so that the words can then be given one line at a time, with the given options. An error in the first pass is given as the only item of the
+    /// stream.
+    pub(crate) fn new(lines:&[String], filename:&str, options:&AssemblerOptions) -> WordStream {
+        let mut stream = WordStream { lines: Vec::new(), index: 0, resolver: None, address: 0, pending: VecDeque::new(), error: None, isa: options.isa.clone() };
+        match first_pass(lines, filename, options) {
+            Ok((lines, resolver)) => {
+                stream.lines = lines;
+                stream.resolver = Some(resolver);
+            },
+
+            Err(err) => stream.error = Some(err)
+        };
+
+        stream
+    }
+
+
+    /// A stream which gives the error as its only item, for when the source could not be read.
+    pub(crate) fn from_error(err:AssemblyError) -> WordStream {
+        WordStream { lines: Vec::new(), index: 0, resolver: None, address: 0, pending: VecDeque::new(), error: Some(err), isa: None }
+    }
+
+
+    /// Expands, resolves, and encodes the next line, adding its words to those waiting to be given.
+    fn encode_next_line(&mut self) -> Result<(), AssemblyError> {
+        let resolver = self.resolver.as_mut().unwrap();
+        let isa = self.isa.as_deref().unwrap_or(&DEFAULT_ISA_SPEC);
+        for line in expansion::substitute_pseudoinstrs(slice::from_ref(&self.lines[self.index])) {
+            let resolved = resolver.resolve(&line).map_err(into_assembly_error)?;
+            if get_section_switch(&resolved).is_some() {
+                continue;
+            }
+
+            let (word, count) = encoder::encode_line(&resolved, isa)?;
+            for _ in 0..count {
+                self.pending.push_back((self.address as u16, word));
+                self.address += 1;
+            }
+        }
+
+        self.index += 1;
+        Ok(())
+    }
+
+
+    /// Writes each word to any writer as 2 bytes in the given order as it is encoded, giving the same bytes as `output::write_words` does for the code section of
+    /// the whole program, and then returns the number of bytes written.
+    ///
+    /// Returns an `AssemblyError` if the program cannot be assembled or the writer fails, in which case the words before the error have already been written.
+    pub fn write_to(self, mut sink:impl Write, endian:Endian) -> Result<usize, AssemblyError> {
+        let mut num_bytes = 0;
+        for word in self {
+            let bytes = endian.to_bytes(word?.1);
+            if let Err(e) = sink.write_all(&bytes) {
+                return Err(AssemblyError(format!("Could not write the assembled words: {}", e)));
+            }
+
+            num_bytes += bytes.len();
+        }
+
+        Ok(num_bytes)
+    }
+}
+
+impl Iterator for WordStream {
+    type Item = Result<(u16, u16), AssemblyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.index = self.lines.len();
+            return Some(Err(err));
+        }
+
+        while self.pending.is_empty() && self.index < self.lines.len() {
+            if let Err(err) = self.encode_next_line() {
+                self.index = self.lines.len();
+                return Some(Err(err));
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+
+/// Runs every stage of the assembler which needs the whole program, in the same order as `assemble_lines`, giving the lines to encode once the constants have been
+/// substituted and the size assertions and fixed labels taken out, along with the resolver for their labels.
+///
+/// Returns an `AssemblyError` if any stage fails, a `.assert_size` does not hold, or the program has a `.data` section.
+fn first_pass(lines:&[String], filename:&str, options:&AssemblerOptions) -> Result<(Vec<String>, LabelResolver), AssemblyError> {
+    let isa = options.isa_spec();
+    let mut lines = expansion::substitute_source_symbols(lines, filename);
+    lines = expansion::substitute_register_aliases(&lines, isa).map_err(into_assembly_error)?;
+    lines = expansion::substitute_constants(&lines, isa).map_err(into_assembly_error)?;
+    parser::validate_assembly_lines(&lines, isa).map_err(into_assembly_error)?;
+    lines = expansion::substitute_org(&lines, options.allow_overlap).map_err(into_assembly_error)?.0;
+    lines = expansion::substitute_build_info(&lines, options.build_id);
+
+    lines.retain(|line| !line.is_empty());
+    let (lines, size_assertions) = expansion::take_size_assertions(&lines);
+    let (lines, fixed_labels) = labels::take_fixed_labels(&lines);
+    let lines = expansion::substitute_label_jumps(&lines, options.scratch_register.as_deref(), isa).map_err(into_assembly_error)?;
+
+    let mut builder = LabelTableBuilder::new();
+    for line in &lines {
+        for expanded in expansion::substitute_pseudoinstrs(slice::from_ref(line)) {
+            builder.add_line(&expanded).map_err(into_assembly_error)?;
+        }
+    }
+
+    let (code_size, data_size) = builder.section_sizes();
+    if data_size > 0 {
+        return Err(AssemblyError("The program has a .data section, which cannot be streamed as only the words of the code section are given".to_owned()));
+    }
+
+    let mut label_table = builder.finish();
+    labels::add_fixed_labels(&mut label_table, &fixed_labels, code_size, data_size, 0).map_err(into_assembly_error)?;
+    expansion::check_size_assertions(&size_assertions, code_size, data_size).map_err(into_assembly_error)?;
+
+    let resolver = LabelResolver::new(&label_table, code_size, data_size);
+    Ok((lines, resolver))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ Assembler, assemble_str };
+    use crate::output::{ Endian, write_words };
+    use crate::parser::LineSource;
+
+
+    #[test]
+    fn test_stream_matches_batch() {
+        let source = ".equ COUNT, 3\nstart: MOVI $r1, @end\n.space COUNT [1, @start]\nloop: BEQ $r1, $zero, $r2\n.text \"hi\"\nend: .fill __END__-@loop\n";
+        let words:Vec<(u16, u16)> = Assembler::new().stream(LineSource::Str(source)).collect::<Result<_, _>>().unwrap();
+        let program = assemble_str(source).unwrap();
+        assert_eq!(words.iter().map(|(_, word)| *word).collect::<Vec<u16>>(), program.code);
+        assert!(words.iter().enumerate().all(|(index, (address, _))| *address as usize == index));
+
+        let mut streamed:Vec<u8> = Vec::new();
+        let mut batch:Vec<u8> = Vec::new();
+        assert_eq!(Assembler::new().stream(LineSource::Str(source)).write_to(&mut streamed, Endian::Little).unwrap(), 2 * program.code.len());
+        write_words(&mut batch, &program.code, Endian::Little).unwrap();
+        assert_eq!(streamed, batch);
+    }
+
+
+    #[test]
+    fn test_stream_errors() {
+        let mut stream = Assembler::new().stream(LineSource::Str("NOP\nBEQ $r1, $zero, @missing\nNOP\n"));
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+
+        // the value of an expression is only known once its line is resolved, so the words before it are given first
+        let mut stream = Assembler::new().stream(LineSource::Str("NOP\n.fill @far*1000\n.space 100 []\nfar: NOP\n"));
+        assert_eq!(stream.next().unwrap().unwrap(), (0, 0x0000));
+        assert!(stream.next().unwrap().unwrap_err().0.contains(".fill"));
+        assert!(stream.next().is_none());
+
+        assert!(Assembler::new().stream(LineSource::Str("NOP\n.data\n.fill 1\n")).next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{ Deserialize, Serialize };
+use wasm_bindgen::prelude::*;
+use crate::assemble_str;
+use crate::diagnostics::Diagnostic;
+use crate::labels::Section;
+
+
+/// A label of an assembled program as given to JavaScript, with its section as `"code"` or `"data"`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasmLabel {
+    pub name: String,
+    pub address: u16,
+    pub section: String
+}
+
+
+/// The result of `assemble` as given to JavaScript. If there are any diagnostics the program could not be assembled, and the words and labels are empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasmProgram {
+    pub code: Vec<u16>,
+    pub data: Vec<u16>,
+    pub labels: Vec<WasmLabel>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl WasmProgram {
+    /// Assembles a program in the same way as `assemble_str`, with the labels in the same order as the symbol map: the code section's first, each section in order
+    /// of address. An error is given as a diagnostic rather than returned.
+    pub fn assemble(source:&str) -> WasmProgram {
+        let program = match assemble_str(source) {
+            Ok(val) => val,
+            Err(err) => return WasmProgram { diagnostics: vec![Diagnostic::from_error(&err, "<string>")], ..Default::default() }
+        };
+
+        let labels:Vec<WasmLabel> = program.labels.iter().map(|symbol| WasmLabel {
+            name: symbol.name.to_owned(),
+            address: symbol.address,
+            section: match symbol.section {
+                Section::Code => "code".to_owned(),
+                Section::Data => "data".to_owned()
+            }
+        }).collect();
+
+        WasmProgram { code: program.code, data: program.data, labels, diagnostics: Vec::new() }
+    }
+}
+
+
+/// Assembles a program for JavaScript, giving an object with the `code` and `data` words, the `labels`, and the `diagnostics` as described by `WasmProgram`.
+#[wasm_bindgen]
+pub fn assemble(source:&str) -> JsValue {
+    serde_wasm_bindgen::to_value(&WasmProgram::assemble(source)).unwrap_or(JsValue::NULL)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_wasm_program() {
+        let program = WasmProgram::assemble("start: ADDI $r1, $zero, 5\nloop: NAND $r2, $r1, $r1\n.data\ntable: .fill 0x10\n");
+        assert_eq!(program.code[0], 0x2805);
+        assert_eq!(program.data, vec![0x0010]);
+        assert_eq!(program.labels.iter().map(|label| label.name.as_str()).collect::<Vec<&str>>(), vec!["start", "loop", "table"]);
+        assert_eq!(program.labels[2], WasmLabel { name: "table".to_owned(), address: 0, section: "data".to_owned() });
+        assert!(program.diagnostics.is_empty());
+    }
+
+
+    #[test]
+    fn test_wasm_program_error() {
+        let program = WasmProgram::assemble("ADDI $r0, $zero, 1\nNAND $r0, $r1\n");
+        assert!(program.code.is_empty());
+        assert_eq!(program.diagnostics.len(), 1);
+        assert_eq!(program.diagnostics[0].line, Some(2));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::io;
+use crate::{ AssembledProgram, AssemblyError };
+use crate::object::ObjectWriter;
+use crate::output::{ Endian, write_words };
+
+
+/// A format an assembled program can be written out in, such as a raw binary image. Writers are looked up by name in a `WriterRegistry`, which is how the
+/// command line tool chooses one with `--format`.
+pub trait OutputWriter {
+    /// Writes the program to `sink` in this format, and then returns the number of bytes written.
+    fn write(&self, program:&AssembledProgram, sink:&mut dyn io::Write) -> io::Result<u64>;
+
+    /// The extension usually given to files in this format, without the leading dot.
+    fn extension(&self) -> &str;
+
+    /// Whether the format keeps what is needed to link the program with others, which lets the program refer to labels declared with `.extern`. Such a format holds
+    /// the data section along with the code, so it needs no separate data image.
+    fn is_relocatable(&self) -> bool {
+        false
+    }
+
+    /// Whether the format records the program's entry point, so a loader can start running it from there rather than from word 0.
+    fn records_entry(&self) -> bool {
+        false
+    }
+}
+
+
+/// Writes the code section as a raw binary image, with each word as 2 bytes in the given order. This is the same image `output::write_assembled_bytes` writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RawBinaryWriter {
+    pub endian: Endian
+}
+
+impl OutputWriter for RawBinaryWriter {
+    fn write(&self, program:&AssembledProgram, sink:&mut dyn io::Write) -> io::Result<u64> {
+        Ok(write_words(sink, &program.code, self.endian)? as u64)
+    }
+
+
+    fn extension(&self) -> &str {
+        "bin"
+    }
+}
+
+
+/// The output formats which can be chosen by name. `WriterRegistry::with_builtin_writers` gives the formats the command line tool supports, and other crates can
+/// add their own with `register`.
+#[derive(Default)]
+pub struct WriterRegistry {
+    writers: BTreeMap<String, Box<dyn OutputWriter>>
+}
+
+impl WriterRegistry {
+    /// Creates a registry without any formats.
+    pub fn new() -> WriterRegistry {
+        WriterRegistry::default()
+    }
+
+
+    /// Creates a registry holding the built-in formats, which write words in the given byte order: `bin`, the raw binary image of the code section, and `obj`, the
+    /// relocatable object file described by `object::ObjectFile`, which holds both sections.
+    pub fn with_builtin_writers(endian:Endian) -> WriterRegistry {
+        let mut registry = WriterRegistry::new();
+        registry.register("bin", Box::new(RawBinaryWriter { endian }));
+        registry.register("obj", Box::new(ObjectWriter));
+        registry
+    }
+
+
+    /// Adds a format under the given name, replacing and returning any format which already has that name.
+    pub fn register(&mut self, name:&str, writer:Box<dyn OutputWriter>) -> Option<Box<dyn OutputWriter>> {
+        self.writers.insert(name.to_owned(), writer)
+    }
+
+
+    /// Gets the names of every format in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.writers.keys().map(String::as_str)
+    }
+
+
+    /// Gets the format with the given name.
+    ///
+    /// Returns an `AssemblyError` listing the formats which are available if there is none with that name.
+    pub fn get(&self, name:&str) -> Result<&dyn OutputWriter, AssemblyError> {
+        match self.writers.get(name) {
+            Some(writer) => Ok(writer.as_ref()),
+            None => Err(AssemblyError(format!("Unknown output format {}, expected one of: {}", name, self.names().collect::<Vec<&str>>().join(", "))))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+
+    struct HexWriter;
+
+    impl OutputWriter for HexWriter {
+        fn write(&self, program:&AssembledProgram, sink:&mut dyn io::Write) -> io::Result<u64> {
+            let text:String = program.code.iter().map(|word| format!("{:04X}\n", word)).collect();
+            sink.write_all(text.as_bytes())?;
+            Ok(text.len() as u64)
+        }
+
+
+        fn extension(&self) -> &str {
+            "hex"
+        }
+    }
+
+
+    #[test]
+    fn test_writer_lookup() {
+        let mut registry = WriterRegistry::with_builtin_writers(Endian::Big);
+        assert_eq!(registry.get("bin").unwrap().extension(), "bin");
+        assert!(registry.register("hex", Box::new(HexWriter)).is_none());
+        assert_eq!(registry.names().collect::<Vec<&str>>(), vec!["bin", "hex", "obj"]);
+
+        let program = assemble_str("ADDI $r1, $zero, 5\n.syscall 5\n").unwrap();
+        let mut text:Vec<u8> = Vec::new();
+        assert_eq!(registry.get("hex").unwrap().write(&program, &mut text).unwrap(), 10);
+        assert_eq!(text, b"2805\nF405\n");
+
+        let err = registry.get("elf").err().unwrap();
+        assert_eq!(err.0, "Unknown output format elf, expected one of: bin, hex, obj");
+    }
+
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_raw_writer_matches_write_assembled_bytes() {
+        use std::{ env, fs };
+        use crate::output::write_assembled_bytes;
+
+        let program = assemble_str("start: ADDI $r1, $zero, 5\nMOVI $r2, @start\nLW $r3, $r2, -1\n.fill 0x1234\n").unwrap();
+        let filename = env::temp_dir().join("iridium_test_raw_writer.bin").to_str().unwrap().to_owned();
+        for endian in [Endian::Big, Endian::Little] {
+            let mut bytes:Vec<u8> = Vec::new();
+            let num_bytes = RawBinaryWriter { endian }.write(&program, &mut bytes).unwrap();
+            assert_eq!(num_bytes as usize, write_assembled_bytes(&filename, program.code.clone(), endian).unwrap());
+            assert_eq!(bytes, fs::read(&filename).unwrap());
+        }
+
+        fs::remove_file(&filename).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+﻿start: ADDI $r1, $zero, 5 # count
+JALR $zero, $r2
\ No newline at end of file
@@ -0,0 +1,8 @@
+.equ BUF_SIZE, 8
+.equ DOUBLE_BUF, BUF_SIZE << 1
+ADDI $r0, $zero, (BUF_SIZE*2)+1
+LUI $r1, 0x10 | 0b1
+MOVI $r2, @buffer + BUF_SIZE - 1
+.syscall 2 + 1
+buffer: .space DOUBLE_BUF / 2 []
+.fill (@buffer+BUF_SIZE) * 1
@@ -0,0 +1,3 @@
+ADDI $r1, $zero, 5
+# comment
+JALR $zero, $r2
@@ -0,0 +1,2 @@
+start: ADDI $r1, $zero, 5 # caf
+NAND $r2, $r1
@@ -0,0 +1,8 @@
+# Counts down from 5
+start:   ADDI $r1,$zero,   5   #initial count
+loop: ADDI   $r1, $r1, -1
+	BEQ $r1 , $zero,  @end      # done
+JALR $zero,$r2
+
+end: .text   "a,  b # c"
+  arr:  .space 2 [ 1,2 ]
@@ -0,0 +1,2 @@
+ADDI $r1, $zero, 5
+ADDI $r2, $zero, 1 # caf
@@ -0,0 +1,7 @@
+# a table of handler addresses, indexed by the number of the handler to jump to
+table: .fill @reset
+       .fill @handler
+
+reset: NOP
+       .space 300 []
+handler: ADDI $r0, $r0, 1
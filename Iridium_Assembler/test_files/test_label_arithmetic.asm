@@ -0,0 +1,7 @@
+MOVI $r0, @buf+0x10
+LW $r1, $r0, @table-1
+table: .fill 0x0001
+.fill 0x0002
+.fill @table+2
+buf: .space 100 []
+MOVI $r0, @buf-4
@@ -0,0 +1,11 @@
+MOVI $r1, @msg_end-@msg_start
+ADDI $r0, $zero, 0
+loop: ADDI $r0, $r0, 1
+MOVI $r6, @done
+BEQ $r0, $r1, $r6
+MOVI $r6, @loop
+JAL $zero, $r6
+done: .syscall 6
+msg_start: .text "hello"
+msg_end: .fill @msg_end-@msg_start
+.fill @msg_start-@msg_end
@@ -0,0 +1,5 @@
+ADD $r0, $r1, $r2
+start:   .space 5 [100, \
+    -2, \
+    'a']
+ADD $r0, $r1, $r3
@@ -0,0 +1,7 @@
+start: ADDI $r0, $r0, 5 # hello world
+ADDI $r0, $r1, 2
+NAND $r0, $r0, $r0
+NOP
+ADDI $r0, $r6, 1
+ADD $r0, $r0, $r1 
+MOVI $r0, @start
\ No newline at end of file
@@ -0,0 +1,18 @@
+# two routines separated by a table, with the handler placed at 0x20 by an .org
+start:   MOVI $r6, @next
+         ADDI $r1, $zero, 5
+         ADD $r2, $r1, $r1
+         JAL $zero, $r6
+table:   .fill 1
+         .fill 2
+         .fill 3
+next:    LW $r3, $zero, @table
+         ADDI $r3, $r3, 1
+         .syscall 6
+
+         .org 0x20
+handler: ADDI $r4, $zero, 1
+         JAL $zero, $r5
+
+.data
+buffer:  .space 10 [1, 2]
@@ -0,0 +1,4 @@
+# boot loader, which calls into the kernel
+boot: MOVI $r6, @kernel
+JAL $r5, $r6
+.syscall 6
@@ -0,0 +1,3 @@
+# defines the label of the boot loader again
+NOP
+boot: NOP
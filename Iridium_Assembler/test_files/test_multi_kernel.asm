@@ -0,0 +1,4 @@
+# kernel, which prints the message held in the data file
+kernel: MOVI $r6, @message
+.syscall 1
+JAL $zero, $r5
@@ -0,0 +1,6 @@
+start: ADDI $r0, $zero, __LINE__
+MOVI $r6, __END__
+
+here: .fill __ADDR__
+MOVI $r1, __ADDR__ + 1
+.text __FILE__
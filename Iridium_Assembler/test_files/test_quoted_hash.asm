@@ -0,0 +1,3 @@
+.text "a # b" # a string with a hash
+.fill '#'   # a hash
+.syscall 3 # print
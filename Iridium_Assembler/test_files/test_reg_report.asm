@@ -0,0 +1,12 @@
+main: MOVI $r0, 5
+MOVI $r6, @leaf
+JAL $r5, $r6
+MOVI $r6, @uses_garbage
+JAL $r5, $r6
+.syscall 6
+
+leaf: ADDI $r0, $r0, 1
+JAL $zero, $r5
+
+uses_garbage: ADD $r1, $r0, $r2
+JAL $zero, $r5
@@ -0,0 +1,10 @@
+start: MOVI $r0, @table
+LW $r1, $r0, @table+1
+MOVI $r2, @end-@start
+table: .fill @start
+.fill 0x1234
+LUI $r3, @value
+end: NOP
+
+.data
+value: .fill @table
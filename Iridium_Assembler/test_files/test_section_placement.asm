@@ -0,0 +1,17 @@
+.section text
+start: MOVI $r6, @table
+LW $r1, $r6, 0
+
+.section data
+table: .fill @message
+
+.section text
+loop: ADD $r1, $r1, $r1
+BEQ $r1, $zero, $r6
+
+.section data
+message: .text "hi"
+end: .fill @__END__
+
+.section text
+.syscall 6
@@ -0,0 +1,14 @@
+start: MOVI $r0, @table
+LW $r1, $r0, 0
+
+.data
+table: .fill 0x0010
+.fill 0x0020
+
+.code
+loop: ADD $r1, $r1, $r1
+MOVI $r6, @loop
+JAL $zero, $r6
+
+.data
+message: .text "hi"
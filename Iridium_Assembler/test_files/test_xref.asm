@@ -0,0 +1,11 @@
+start: MOVI $r6, @loop
+JAL $zero, $r6
+loop: ADDI $r1, $r1, 1
+MOVI $r6, @loop
+BEQ $r1, $r2, $r6
+.syscall 6
+
+.data
+table: .fill @loop
+.fill @table+@table
+end: .fill 0
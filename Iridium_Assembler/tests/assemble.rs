@@ -0,0 +1,393 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use iridium_assembler::{ Assembler, AssemblerOptions, assemble_file, assemble_reader, assemble_source, assemble_source_reporting, assemble_source_timed, assemble_sources,
+    assemble_str, assemble_str_with_diagnostics, count_words, list_unresolved_labels };
+use iridium_assembler::diagnostics::{ ASSEMBLY_ERROR, DATA_IN_CODE, Diagnostic, ENTRY_NOT_RECORDED, INVALID_UTF8, OVERLAPPING_REGIONS, Severity, UNUSED_LABEL };
+use iridium_assembler::parser::{ InputEncoding, LineSource, SourceMap, read_source_lines };
+use iridium_assembler::labels::{ DataPlacement, LabelKind, Section, SourceLoc, Symbol };
+use iridium_assembler::lint::find_unused_labels;
+use iridium_assembler::output::{ Endian, format_source, write_words };
+
+
+#[test]
+fn test_assemble_file() {
+    let program = assemble_file(Path::new("test_files/test_file_bios.asm")).unwrap();
+    assert_eq!(program.code[2], 0x280B);
+    assert_eq!(program.code[3], 0x6800);
+    assert_eq!(program.code.len(), program.code_lines.len());
+    assert!(program.data.is_empty());
+}
+
+
+#[test]
+fn test_assemble_file_sections() {
+    let program = assemble_file(Path::new("test_files/test_sections.asm")).unwrap();
+    assert_eq!(program.code.len(), 7);
+    assert_eq!(program.data, vec![0x0010, 0x0020, 0x0068, 0x0069, 0x0000]);
+    assert_eq!(program.data_lines[0], "table: .fill 0x0010");
+    assert_eq!(program.labels["loop"], Symbol { name: "loop".to_owned(), address: 3, section: Section::Code, kind: LabelKind::Code, defined_at: SourceLoc { line: 9 },
+        exported: false });
+    assert_eq!(program.labels["message"], Symbol { name: "message".to_owned(), address: 2, section: Section::Data, kind: LabelKind::Data,
+        defined_at: SourceLoc { line: 14 }, exported: false });
+}
+
+
+#[test]
+fn test_assemble_file_xref() {
+    let program = assemble_file(Path::new("test_files/test_xref.asm")).unwrap();
+    let lines:Vec<usize> = program.xref["loop"].iter().map(|reference| reference.line).collect();
+    assert_eq!(lines, vec![1, 4, 9]);
+    assert!(program.xref.values().flatten().all(|reference| reference.file == "test_files/test_xref.asm"));
+    assert_eq!(find_unused_labels(&program.xref, &program.labels), vec!["end", "start"]);
+}
+
+
+#[test]
+fn test_assemble_source_reporting() {
+    let filename = "test_files/test_diagnostics.asm";
+    let mut diagnostics:Vec<Diagnostic> = Vec::new();
+    let program = assemble_source_reporting(LineSource::File(filename), &AssemblerOptions { lossy: true, ..Default::default() }, &mut diagnostics);
+    assert!(program.is_none());
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0], Diagnostic::warning(INVALID_UTF8, "Replaced invalid UTF-8 with U+FFFD".to_owned(), filename, Some(1)));
+    assert_eq!((diagnostics[1].severity, diagnostics[1].code.as_str(), diagnostics[1].file.as_str()), (Severity::Error, ASSEMBLY_ERROR, filename));
+    assert_eq!(diagnostics[1].line, Some(2));
+    assert!(diagnostics[1].message.contains("NAND $r2, $r1"), "{}", diagnostics[1].message);
+}
+
+
+#[test]
+fn test_assemble_str_with_diagnostics() {
+    let (program, diagnostics) = assemble_str_with_diagnostics("MOVI $r6, @main\nBEQ $r0, $r1, $r6\ntable: .fill 1\nmain: NOP\n");
+    assert_eq!(program.unwrap().code.len(), 5);
+    let codes:Vec<(&str, Option<usize>)> = diagnostics.iter().map(|diagnostic| (diagnostic.code.as_str(), diagnostic.line)).collect();
+    assert_eq!(codes, vec![(DATA_IN_CODE, None), (UNUSED_LABEL, Some(3))]);
+    assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Warning && diagnostic.file == "<string>"));
+
+    let (program, diagnostics) = assemble_str_with_diagnostics("ADDI $r0, $zero, 1\nNAND $r0, $r1\n");
+    assert!(program.is_none());
+    assert_eq!(diagnostics, vec![Diagnostic::from_error(&assemble_str("ADDI $r0, $zero, 1\nNAND $r0, $r1\n").unwrap_err(), "<string>")]);
+}
+
+
+#[test]
+fn test_assemble_missing_file() {
+    let err = assemble_file(Path::new("test_files/does_not_exist.asm")).unwrap_err();
+    assert!(err.0.contains("does_not_exist.asm"));
+}
+
+
+#[test]
+fn test_assemble_str() {
+    let source = "start: MOVI $r0, @table
+LW $r1, $r0, 0
+
+.data
+table: .fill 0x0010
+.fill 0x0020
+
+.code
+loop: ADD $r1, $r1, $r1
+MOVI $r6, @loop
+JAL $zero, $r6
+
+.data
+message: .text \"hi\"
+";
+
+    let program = assemble_str(source).unwrap();
+    let mut expected = assemble_file(Path::new("test_files/test_sections.asm")).unwrap();
+    for reference in expected.xref.values_mut().flatten() {
+        reference.file = "<string>".to_owned();
+    }
+    expected.sources = SourceMap::new();
+    expected.sources.push("<string>", 14);
+
+    assert_eq!(program, expected);
+    assert_eq!(program.code[3], 0x0920);
+}
+
+
+#[test]
+fn test_assemble_str_error_line() {
+    let source = "ADDI $r0, $zero, 1
+# a comment
+.equ LIMIT, 4
+
+NAND $r0, $r1";
+
+    let err = assemble_str(source).unwrap_err();
+    assert!(err.0.ends_with("NAND $r0, $r1 on line 5"), "{}", err.0);
+    assert_eq!(err.line(), Some(5));
+}
+
+
+#[test]
+fn test_assemble_no_tabs() {
+    let source = LineSource::Str("ADDI $r0, $zero, 1\n\tNOP # indented with a tab\n");
+    assert!(assemble_source(source, &AssemblerOptions::default()).is_ok());
+
+    let err = assemble_source(source, &AssemblerOptions { no_tabs: true, ..Default::default() }).unwrap_err();
+    assert!(err.0.contains("on line 2"));
+}
+
+
+#[test]
+fn test_assemble_source_timed() {
+    let source = LineSource::File("test_files/test_sections.asm");
+    let (program, timings) = assemble_source_timed(source, &AssemblerOptions::default()).unwrap();
+    assert_eq!(program, assemble_file(Path::new("test_files/test_sections.asm")).unwrap());
+
+    let stages:Vec<&str> = timings.iter().map(|(stage, _)| *stage).collect();
+    assert_eq!(stages, vec!["reading", "validation", "pseudo-instruction expansion", "label table generation", "label substitution", "encoding"]);
+}
+
+
+#[test]
+fn test_assemble_sources() {
+    let files = ["test_files/test_multi_boot.asm", "test_files/test_multi_kernel.asm", "test_files/test_multi_data.asm"];
+    let sources:Vec<LineSource> = files.iter().map(|file| LineSource::File(file)).collect();
+    let program = assemble_sources(&sources, &AssemblerOptions::default()).unwrap();
+    let joined:String = files.iter().map(|file| fs::read_to_string(file).unwrap()).collect();
+    let whole = assemble_str(&joined).unwrap();
+    assert_eq!((program.code.clone(), program.data.clone()), (whole.code, whole.data));
+    assert_eq!(program.code[0], 0x3C04); // ADDI $r6, $zero, 4, the address of the kernel in the next file
+
+    // the line each label is defined on and each reference to it are traced back to their own file
+    let kernel = &program.xref["kernel"][0];
+    assert_eq!((kernel.file.as_str(), kernel.line), ("test_files/test_multi_boot.asm", 2));
+    assert_eq!(program.labels["message"].defined_at.line, 10);
+    assert_eq!(program.sources.describe(10), "line 2 of test_files/test_multi_data.asm");
+    assert_eq!(program.sources.locate(2), Some(("test_files/test_multi_boot.asm", 2)));
+}
+
+
+#[test]
+fn test_assemble_sources_errors() {
+    let boot = LineSource::File("test_files/test_multi_boot.asm");
+    let duplicate = LineSource::File("test_files/test_multi_duplicate.asm");
+    let err = assemble_sources(&[boot, duplicate], &AssemblerOptions::default()).unwrap_err();
+    assert_eq!(err.0, "Label boot is defined on line 2 of test_files/test_multi_boot.asm and again on line 3 of test_files/test_multi_duplicate.asm");
+
+    // an error on a line names the file it is in, as does the diagnostic given for it
+    let err = Assembler::new().assemble_sources(&[boot, LineSource::Str("kernel: NOP
+NAND $r0, $r1
+")]).unwrap_err();
+    assert_eq!((err.line(), err.file()), (Some(2), Some("<string>")));
+    assert_eq!(Diagnostic::from_error(&err, "test_files/test_multi_boot.asm").file, "<string>");
+
+    let err = assemble_sources(&[boot], &AssemblerOptions::default()).unwrap_err();
+    assert_eq!(err.0, "Found undefined label @kernel on line 2 of test_files/test_multi_boot.asm");
+}
+
+
+#[test]
+fn test_list_unresolved_labels() {
+    let source = LineSource::Str("start: BEQ $r1, $zero, @done # line 1\n\nMOVI $r2, @table\nJAL $zero, $r2\n.fill @start\nMOVI $r3, @done");
+    let unresolved = list_unresolved_labels(source, false, InputEncoding::Utf8).unwrap();
+    assert_eq!(unresolved, vec![(1, "done".to_owned()), (3, "table".to_owned()), (6, "done".to_owned())]);
+}
+
+
+#[test]
+fn test_count_words() {
+    for filename in ["test_files/test_file_bios.asm", "test_files/test_sections.asm", "test_files/test_space_sub.asm", "test_files/test_valid_pseudo_subs.asm"] {
+        let program = assemble_file(Path::new(filename)).unwrap();
+        assert_eq!(count_words(LineSource::File(filename), &AssemblerOptions::default()).unwrap(), (program.code.len(), program.data.len()), "{}", filename);
+    }
+
+    let source = ".equ SIZE, 4\nstart: MOVI $r0, @table\n.assert_size <= 100\nIO: .at 0xF000\n.data\ntable: .space SIZE [1]\n.text \"hi\" pad 3\n";
+    assert_eq!(Assembler::new().count_words(LineSource::Str(source)).unwrap(), (2, 8));
+
+    // the labels are never resolved, so an undefined one is not found
+    assert_eq!(count_words(LineSource::Str("JAL $r0, $r1\nMOVI $r1, @missing\n"), &AssemblerOptions::default()).unwrap(), (3, 0));
+    assert!(count_words(LineSource::Str("NAND $r0\n"), &AssemblerOptions::default()).is_err());
+}
+
+
+#[test]
+fn test_assemble_section_placement() {
+    let path = Path::new("test_files/test_section_placement.asm");
+    let addresses = |program:&iridium_assembler::AssembledProgram| -> Vec<(String, u16)> {
+        program.labels.iter().map(|symbol| (symbol.name.to_owned(), symbol.address)).collect()
+    };
+
+    // the sections are switched between three times, but each collects its lines in order into one contiguous block
+    let program = assemble_file(path).unwrap();
+    let code = assemble_str("MOVI $r6, 0\nLW $r1, $r6, 0\nADD $r1, $r1, $r1\nBEQ $r1, $zero, $r6\n.syscall 6\n").unwrap().code;
+    assert_eq!(program.code, code);
+    assert_eq!(program.data, vec![1, 0x68, 0x69, 0, 5]);
+    assert_eq!(program.data_base, 0);
+
+    // placed after the code, the data and the labels in it follow the 6 words of code
+    let program = Assembler::new().data_placement(DataPlacement::AfterCode).assemble_file(path).unwrap();
+    let code = assemble_str("MOVI $r6, 6\nLW $r1, $r6, 0\nADD $r1, $r1, $r1\nBEQ $r1, $zero, $r6\n.syscall 6\n").unwrap().code;
+    assert_eq!(program.code, code);
+    assert_eq!(program.data, vec![7, 0x68, 0x69, 0, 11]);
+    assert_eq!(program.data_base, 6);
+    assert_eq!(addresses(&program), vec![("start".to_owned(), 0), ("loop".to_owned(), 3), ("table".to_owned(), 6), ("message".to_owned(), 7),
+        ("end".to_owned(), 10)]);
+
+    let program = Assembler::new().data_placement(DataPlacement::At(0x4000)).assemble_file(path).unwrap();
+    assert_eq!(program.labels["table"].address, 0x4000);
+    assert_eq!(program.data, vec![0x4001, 0x68, 0x69, 0, 0x4005]);
+
+    // a fixed address is checked against where the data section is placed, which must fit below 0x10000
+    let source = "NOP\n.section data\n.fill 1\n.fill 2\nIO_PORT: .at 1\n";
+    assert!(assemble_str(source).is_err());
+    assert_eq!(Assembler::new().data_placement(DataPlacement::At(0x10)).assemble_str(source).unwrap().labels["IO_PORT"].address, 1);
+    assert!(Assembler::new().data_placement(DataPlacement::AfterCode).assemble_str(source).is_err());
+    assert!(Assembler::new().data_placement(DataPlacement::At(0xFFFF)).assemble_str(".data\n.fill 1\n.fill 2\n").is_err());
+}
+
+
+#[test]
+fn test_assemble_register_aliases() {
+    let program = assemble_str(".regalias counter, $r3\nstart: MOVI $counter, 0x1234\nADDI $counter, $counter, -1\nBEQ $counter, $zero, $counter\n").unwrap();
+    assert_eq!(program.code, assemble_str("start: MOVI $r3, 0x1234\nADDI $r3, $r3, -1\nBEQ $r3, $zero, $r3\n").unwrap().code);
+
+    let err = assemble_str("NOP\n.regalias r0, $r3\n").unwrap_err();
+    assert_eq!(err.0, "Cannot define register alias $r0 as it is the name of a register");
+    assert!(assemble_str(".regalias counter, $r3\nADDI $count, $zero, 1\n").is_err());
+}
+
+
+#[test]
+fn test_assemble_fixed_labels() {
+    let source = ".equ UART, 0xF000\nIO_PORT: .at UART\nSTATUS: .at UART+1\nstart: MOVI $r1, @STATUS\nLW $r2, $r1, 0\nMOVI $r3, @start\nJAL $zero, $r3\n";
+    let program = assemble_str(source).unwrap();
+    assert_eq!(program.code.len(), 6);
+    assert_eq!(program.code_lines[..2], ["start: ADDI $r1, $zero, 1".to_owned(), "LUI $r1, 960".to_owned()]);
+    assert_eq!(program.labels["IO_PORT"], Symbol { name: "IO_PORT".to_owned(), address: 0xF000, section: Section::Code, kind: LabelKind::Absolute,
+        defined_at: SourceLoc { line: 2 }, exported: false });
+    assert_eq!(program.relocations.iter().map(|(index, _)| *index).collect::<Vec<usize>>(), vec![3, 4]);
+
+    let err = assemble_str("start: NOP\nNOP\nEARLY: .at 1\n").unwrap_err();
+    assert!(err.0.contains("EARLY"), "{}", err.0);
+}
+
+
+#[test]
+fn test_assemble_in_memory() {
+    let source = "start: ADDI $r1, $zero, 5\r\nJAL $zero, $zero\r\n.text __FILE__\r\n";
+    let program = assemble_reader(Cursor::new(source.as_bytes()), "<stdin>", &AssemblerOptions::default()).unwrap();
+    assert_eq!(program.code[2..], "<stdin>\0".bytes().map(|byte| byte as u16).collect::<Vec<u16>>()[..]);
+
+    let mut image = Cursor::new(Vec::new());
+    assert_eq!(write_words(&mut image, &program.code, Endian::Little).unwrap(), 20);
+    assert_eq!(&image.get_ref()[..2], &[0x05, 0x28]);
+
+    let err = Assembler::new().assemble_reader(Cursor::new(b"NOP\n.fill 'a' # caf\xE9\n".to_vec()), "net://program").unwrap_err();
+    assert_eq!(err.0.lines().next(), Some("Invalid UTF-8 byte 0xE9 on line 2 at byte offset 15 of net://program"));
+    assert!(err.0.lines().last().unwrap().contains("Latin-1"));
+}
+
+
+#[test]
+fn test_format_source_keeps_assembly() {
+    let mut num_files = 0;
+    for entry in fs::read_dir("test_files").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.to_str().unwrap();
+        let lines = match read_source_lines(name, false) {
+            Ok(val) => val,
+            Err(_) => continue
+        };
+
+        // the formatted source is given the same name so that __FILE__ is the same
+        let formatted = format_source(&lines).join("\n") + "\n";
+        let reformatted = Assembler::new().assemble_reader(Cursor::new(formatted.as_bytes()), name);
+        match Assembler::new().assemble_source(LineSource::File(name)) {
+            Ok(program) => {
+                let reformatted = reformatted.unwrap();
+                let to_bytes = |words:&[u16]| Assembler::new().to_bytes(words);
+                assert_eq!(to_bytes(&reformatted.code), to_bytes(&program.code), "formatting {} changed its code image", name);
+                assert_eq!(to_bytes(&reformatted.data), to_bytes(&program.data), "formatting {} changed its data image", name);
+                num_files += 1;
+            },
+
+            Err(_) => assert!(reformatted.is_err(), "{} only assembles once it is formatted", name)
+        };
+    }
+
+    assert!(num_files > 10);
+}
+
+
+#[test]
+fn test_assemble_encoding_annotations() {
+    let program = assemble_str("start: ADDI $r0, $zero, 7  #= 0x2407\nMOVI $r1, 7  #= 0x2807 0x6800\nNOP\n.data\n.fill 0x1234  #= 0x1234\n").unwrap();
+    assert_eq!(program.code[..3], [0x2407, 0x2807, 0x6800]);
+
+    let err = assemble_str("NOP\nADDI $r0, $zero, 7  #= 0x2408\n").unwrap_err();
+    assert_eq!(err.0, "Line assembles to 0x2407 but is annotated with #= 0x2408 on line 2");
+    assert_eq!(err.line(), Some(2));
+}
+
+
+#[test]
+fn test_assemble_merged_strings() {
+    let source = "LW $r1, $zero, @first\nLW $r2, $zero, @second\nLW $r3, $zero, @third\nLW $r4, $zero, @other\n.data\nfirst: .text \"OK!\"\n\
+        second: .text \"OK!\"\nother: .text \"No\"\nthird: .text \"OK!\"\n";
+    let program = assemble_str(source).unwrap();
+    assert_eq!(program.data.len(), 15);
+    assert!(program.merged_strings.is_empty());
+
+    let merged = Assembler::new().merge_strings(true).assemble_str(source).unwrap();
+    assert_eq!(merged.data, vec![0x004F, 0x004B, 0x0021, 0x0000, 0x004E, 0x006F, 0x0000]);
+    let addresses:Vec<u16> = ["first", "second", "other", "third"].iter().map(|name| merged.labels.get(name).unwrap().address).collect();
+    assert_eq!(addresses, vec![0, 0, 4, 0]);
+    assert_eq!(merged.labels.get("third").unwrap().defined_at.line, 9);
+    assert_eq!(merged.merged_strings.iter().map(|string| string.words).sum::<usize>(), 8);
+    assert_eq!(merged.code[0], program.code[0]);
+    assert_ne!(merged.code[1], program.code[1]);
+}
+
+
+#[test]
+fn test_assemble_overlapping_org() {
+    let program = assemble_str("start: NOP\n.org 4\nhandler: NOP\n").unwrap();
+    assert_eq!((program.code.len(), program.labels.get("handler").unwrap().address), (5, 4));
+    assert!(program.overlaps.is_empty());
+
+    let source = "start: ADDI $r0, $zero, 7\nADDI $r1, $zero, 7\n.org 1\nhandler: LUI $r0, 0\n";
+    let err = assemble_str(source).unwrap_err();
+    assert_eq!(err.0, "Word 0x0001 of the code section is written by both the region from the start of the code section, labelled start, and the region from \
+        .org 0x0001 on line 3, labelled handler");
+
+    // with the overlap allowed, the later word takes the place of the earlier one and a warning is given on the .org
+    let program = Assembler::new().allow_overlap(true).assemble_str(source).unwrap();
+    assert_eq!(program.code, vec![0x2407, 0x6400]);
+    assert_eq!(program.labels.get("handler").unwrap().address, 1);
+    let warnings = program.overlap_warnings("overlap.asm");
+    assert_eq!(warnings.iter().map(|warning| (warning.code.as_str(), warning.line)).collect::<Vec<_>>(), vec![(OVERLAPPING_REGIONS, Some(3))]);
+    assert!(warnings[0].message.ends_with("so the later words are written over the earlier ones"));
+}
+
+
+#[test]
+fn test_assemble_entry_point() {
+    let program = assemble_str(".global start\n.data\ncount: .fill 3\n.code\nhandler: NOP\nstart: ADDI $r0, $zero, 7\n").unwrap();
+    let entry = program.entry.as_ref().unwrap();
+    assert_eq!((entry.name.as_str(), entry.address, entry.section), ("start", 1, Section::Code));
+
+    // a raw binary image is always started from word 0, so an entry point anywhere else is lost
+    let warning = program.entry_warning("entry.asm").unwrap();
+    assert_eq!((warning.code.as_str(), warning.line), (ENTRY_NOT_RECORDED, Some(6)));
+    assert_eq!(warning.message, "Entry point start is at 0x0001 rather than word 0, which the output format does not record, so the program will not start there");
+    assert!(assemble_str(".global start\nstart: NOP\n").unwrap().entry_warning("entry.asm").is_none());
+    assert!(assemble_str("NOP\n").unwrap().entry.is_none());
+
+    let mut program = assemble_str("handler: NOP\nstart: NOP\n").unwrap();
+    program.set_entry("handler").unwrap();
+    assert_eq!(program.entry.as_ref().map(|entry| entry.address), Some(0));
+
+    let err = assemble_str(".global start\nmain: NOP\n").unwrap_err();
+    assert_eq!(err.0, "Entry point start is not defined in the program");
+    let err = assemble_str(".global table\nNOP\ntable: .fill 5\n").unwrap_err();
+    assert_eq!(err.0, "Entry point table points at data at 0x0001 rather than an instruction on line 3");
+    let err = assemble_str(".global count\nNOP\n.data\ncount: .fill 3\n").unwrap_err();
+    assert_eq!(err.0, "Entry point count points at data at 0x0000 rather than an instruction on line 4");
+    assert!(program.set_entry("missing").is_err());
+}
@@ -0,0 +1,91 @@
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::borrow::Cow;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Instant;
+use iridium_assembler::{ Assembler, assemble_str };
+use iridium_assembler::expansion::expand_runs;
+use iridium_assembler::parser::LineSource;
+
+
+/// Counts the bytes allocated by this test binary, keeping the most held at once since `reset_peak` was last called.
+struct CountingAllocator;
+
+static CURRENT:AtomicUsize = AtomicUsize::new(0);
+static PEAK:AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout:Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+
+    unsafe fn dealloc(&self, ptr:*mut u8, layout:Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR:CountingAllocator = CountingAllocator;
+
+
+/// Sets the peak to what is held now, and gives that as the baseline to measure from.
+fn reset_peak() -> usize {
+    let current = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(current, Ordering::SeqCst);
+    current
+}
+
+
+/// A program with a `.space` of `size` words, and the same program with the `.space` written out as a `.fill` for each of its words.
+fn space_program(size:usize) -> (String, String) {
+    let end = format!("end: BEQ $r1, $zero, $r2\n.fill @__END__-@buffer\n.assert_size == {}\n", size + 4);
+    let compact = format!("start: MOVI $r1, @end\nbuffer: .space {} [1, @start]\n{}", size, end);
+
+    let mut naive = String::from("start: MOVI $r1, @end\nbuffer: .fill 0x0001\n.fill @start\n");
+    naive.push_str(&".fill 0x0000\n".repeat(size - 2));
+    naive.push_str(&end);
+    (compact, naive)
+}
+
+
+// This is the only test in the file, as the allocator counts every thread of the test binary.
+#[test]
+fn test_large_space_is_compact() {
+    // both forms are assembled once before measuring, so that anything allocated once, such as the caches of the regexes, is not counted
+    let (compact, naive) = space_program(100);
+    assert_eq!(assemble_str(&compact).unwrap().code, assemble_str(&naive).unwrap().code);
+
+    let (compact, naive) = space_program(60_000);
+    let baseline = reset_peak();
+    let start = Instant::now();
+    let program = assemble_str(&compact).unwrap();
+    let compact_time = start.elapsed();
+    let compact_peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    let baseline = reset_peak();
+    let start = Instant::now();
+    let expected = assemble_str(&naive).unwrap();
+    let naive_time = start.elapsed();
+    let naive_peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    assert_eq!(program.code.len(), 60_004);
+    assert_eq!(program.code, expected.code);
+    assert_eq!(program.relocations, expected.relocations);
+    assert_eq!(program.labels.addresses(), expected.labels.addresses());
+    assert_eq!(expand_runs(&program.code_lines).map(Cow::into_owned).collect::<Vec<String>>(), expected.code_lines);
+    assert!(program.code_lines.len() < 10);
+
+    let streamed:Vec<u16> = Assembler::new().stream(LineSource::Str(&compact)).map(|word| word.unwrap().1).collect();
+    assert_eq!(streamed, expected.code);
+
+    assert!(compact_peak < 1 << 20, "assembling the .space held {} bytes at once", compact_peak);
+    assert!(naive_peak > 4 * compact_peak, "assembling the .space held {} bytes at once against {} for the written out words", compact_peak, naive_peak);
+    assert!(compact_time < naive_time, "assembling the .space took {:?} against {:?} for the written out words", compact_time, naive_time);
+}
@@ -0,0 +1,84 @@
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use iridium_assembler::{ Assembler, assemble_str };
+use iridium_assembler::parser::LineSource;
+
+
+/// Counts the bytes allocated by this test binary, keeping the most held at once since `reset_peak` was last called.
+struct CountingAllocator;
+
+static CURRENT:AtomicUsize = AtomicUsize::new(0);
+static PEAK:AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout:Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+
+    unsafe fn dealloc(&self, ptr:*mut u8, layout:Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR:CountingAllocator = CountingAllocator;
+
+
+/// Sets the peak to what is held now, and gives that as the baseline to measure from.
+fn reset_peak() -> usize {
+    let current = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(current, Ordering::SeqCst);
+    current
+}
+
+
+// This is the only test in the file, as the allocator counts every thread of the test binary.
+#[test]
+fn test_stream_memory_ceiling() {
+    // a section can hold at most 65536 words, so this is the largest program there can be, rather than the million words which could be streamed in principle
+    let mut source = String::from("start: ADDI $r1, $zero, 1\n");
+    for _ in 0..16383 {
+        source.push_str("ADDI $r1, $r1, 1\nMOVI $r2, @end\nNAND $r3, $r1, $r2\n");
+    }
+
+    source.push_str("loop: BEQ $r1, $r2, $r3\nend: .fill @start\n.fill @loop\n");
+
+    // the first words are taken before measuring, so that anything allocated once, such as the caches of the regexes, is not counted
+    let mut stream = Assembler::new().stream(LineSource::Str(&source));
+    let mut sample:Vec<(u16, u16)> = Vec::with_capacity(128);
+    for word in stream.by_ref().take(1000) {
+        sample.push(word.unwrap());
+    }
+
+    let baseline = reset_peak();
+    let mut num_words = 1000;
+    for word in stream.by_ref() {
+        let (address, word) = word.unwrap();
+        if address % 997 == 0 {
+            sample.push((address, word));
+        }
+
+        num_words += 1;
+    }
+
+    let stream_peak = PEAK.load(Ordering::SeqCst) - baseline;
+    drop(stream);
+
+    let baseline = reset_peak();
+    let program = assemble_str(&source).unwrap();
+    let batch_peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    assert_eq!(num_words, 0x10000);
+    assert_eq!(program.code.len(), 0x10000);
+    assert!(sample.iter().all(|(address, word)| program.code[*address as usize] == *word));
+    assert!(stream_peak < 1 << 20, "streaming held {} bytes at once", stream_peak);
+    assert!(batch_peak > 4 * stream_peak, "streaming held {} bytes at once against {} for the batch API", stream_peak, batch_peak);
+}
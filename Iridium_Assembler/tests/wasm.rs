@@ -0,0 +1,22 @@
+// Runs in a JavaScript engine with `wasm-pack test --node -- --no-default-features --features wasm`, checking the object JavaScript receives from `assemble`.
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use wasm_bindgen_test::wasm_bindgen_test;
+use iridium_assembler::wasm::{ WasmProgram, assemble };
+
+
+#[wasm_bindgen_test]
+fn test_wasm_assemble() {
+    let program:WasmProgram = serde_wasm_bindgen::from_value(assemble("start: ADDI $r1, $zero, 5\nLUI $r0, 500\n")).unwrap();
+    assert_eq!(program.code, vec![0x2805, 0x65F4]);
+    assert_eq!(program.labels[0].name, "start");
+    assert!(program.diagnostics.is_empty());
+}
+
+
+#[wasm_bindgen_test]
+fn test_wasm_assemble_error() {
+    let program:WasmProgram = serde_wasm_bindgen::from_value(assemble("ADDI $r0, $zero, 1\nNAND $r0, $r1\n")).unwrap();
+    assert!(program.code.is_empty());
+    assert_eq!(program.diagnostics[0].line, Some(2));
+}